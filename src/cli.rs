@@ -0,0 +1,541 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+use giants_core_rust::pipeline::{print_status, StateStore};
+use giants_core_rust::cleaning::{clean_books, Voice};
+use giants_core_rust::chunking::chunk_books;
+use giants_core_rust::embedding::embed_books;
+use giants_core_rust::answering::{
+    answer_as_author, answer_question, compare_authors, debate, load_backend, Answer, AnsweringConfig, Debate, FaithfulnessChecker,
+    FaithfulnessReport, LlmBackend, OnText, TokenUsage,
+};
+use giants_core_rust::retrieval::{print_passages, Passage, Retriever};
+use giants_core_rust::quotations::{QuoteFinder, QuoteMatch};
+use giants_core_rust::concordance::{concordance_pattern, BookCount, Concordance, ConcordanceLine, ConcordanceOrder, ConcordanceText, CSV_HEADER};
+use giants_core_rust::indexing::{collection_name, export_collections, Filter, index_books, print_sync_reports, verify_collections, SyncReport, COMBINED_COLLECTION};
+use giants_core_rust::segmentation::{evaluate, Evaluation, EVALUATION_FIXTURE};
+use giants_core_rust::setup::config::Config;
+use giants_core_rust::setup::paths::Directories;
+use giants_core_rust::extraction::{extract_books, ocr_books};
+use giants_core_rust::catalog::{acquired_books, lint_catalog, prepare_sources, Author, Book, Selection};
+use giants_core_rust::acquisition::{download_all_texts, verify_downloads, DownloadOptions, SourceKind};
+
+
+#[derive(Parser)]
+#[command(about = "Builds retrieval pipelines over the works of history's most influential figures")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    #[command(flatten)]
+    pub filters: Filters,
+}
+
+
+#[derive(Args)]
+pub struct Filters {
+    /// Only work on this author (may be repeated)
+    #[arg(long = "author", global = true)]
+    pub authors: Vec<String>,
+
+    /// Only work on the book with this slug, e.g. capital-vol-i (may be repeated)
+    #[arg(long = "book", global = true)]
+    pub books: Vec<String>,
+
+    /// Only work on books acquired from this kind of source (may be repeated)
+    #[arg(long = "source", value_enum, global = true)]
+    pub sources: Vec<SourceKind>,
+
+    /// Report what would be done without doing it
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Redo work even if its outputs already exist and are valid
+    #[arg(long, global = true)]
+    pub force: bool,
+}
+
+
+impl Filters {
+
+    pub fn selection(&self) -> Selection {
+        Selection {
+            authors: self.authors.clone(),
+            books: self.books.clone(),
+            sources: self.sources.clone(),
+        }
+    }
+}
+
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Acquire texts over HTTP, by scraping, or by torrenting
+    Download,
+    /// Extract plain text from the acquired files
+    Extract,
+    /// Run OCR on the scanned books that need it
+    Ocr,
+    /// Remove running headers, page numbers, and broken hyphenation from extracted text
+    Clean,
+    /// Split cleaned text into retrieval passages
+    Chunk,
+    /// Embed each passage
+    Embed,
+    /// Add embedded passages to the search indices
+    Index,
+    /// Push the local collections to a Chroma server
+    ExportChroma,
+    /// Compare the local collections with their copies on a Chroma server
+    VerifyChroma,
+    /// Find the passages that best match a query, with how each stage of retrieval scored them
+    Search {
+        query: String,
+
+        /// How many passages to show
+        #[arg(short, default_value_t = 10)]
+        k: usize,
+    },
+    /// Ask a question of an author's works
+    Query {
+        question: String,
+
+        /// Refuse to answer unless retrieval finds convincing passages and the answer cites them
+        #[arg(long)]
+        strict: bool,
+
+        /// The LLM profile to answer with, instead of the configured one
+        #[arg(long)]
+        profile: Option<String>,
+
+        /// Print the answer as it is written, citing passages by number
+        #[arg(long)]
+        stream: bool,
+
+        /// Answer in the author's own voice, from their own writing
+        #[arg(long)]
+        persona: bool,
+    },
+    /// Compare what two or more authors say about a question, or have them debate it
+    Compare {
+        question: String,
+
+        /// Have the authors debate the question in turns, each in their own voice, instead
+        #[arg(long)]
+        debate: bool,
+
+        /// How many times each author speaks in the debate, instead of the configured number
+        #[arg(long, requires = "debate")]
+        rounds: Option<usize>,
+
+        /// Refuse to answer unless retrieval finds convincing passages and the answer cites them
+        #[arg(long)]
+        strict: bool,
+
+        /// The LLM profile to answer with, instead of the configured one
+        #[arg(long)]
+        profile: Option<String>,
+
+        /// Print the comparison as it is written, citing passages by number
+        #[arg(long, conflicts_with = "debate")]
+        stream: bool,
+    },
+    /// Find where a quotation comes from in the cleaned texts, or show that it is not there
+    Quote {
+        quotation: String,
+
+        /// How many matches to show
+        #[arg(short, default_value_t = 5)]
+        k: usize,
+    },
+    /// List every occurrence of a word, phrase, or pattern, with the text on either side of it
+    Concordance {
+        term: String,
+
+        /// Treat the term as a regular expression
+        #[arg(long)]
+        regex: bool,
+
+        /// How many characters of context to show on each side
+        #[arg(long, default_value_t = 60)]
+        width: usize,
+
+        /// How to order the lines
+        #[arg(long, value_enum, default_value_t = ConcordanceOrder::Position)]
+        sort: ConcordanceOrder,
+
+        /// Which copy of each book to search
+        #[arg(long, value_enum, default_value_t = ConcordanceText::Cleaned)]
+        text: ConcordanceText,
+
+        /// Write the lines to this CSV file instead of printing them
+        #[arg(long)]
+        csv: Option<PathBuf>,
+    },
+    /// Show how far each book has progressed through the pipeline
+    Status,
+    /// Check the catalog for mistakes
+    Lint,
+    /// Measure sentence segmentation against sample passages from the catalog's authors
+    EvaluateSegmentation,
+    /// Rehash every acquired file and compare it against the manifest
+    Verify,
+}
+
+
+fn selected_authors(selection: &Selection) -> Vec<Author> {
+    prepare_sources()
+        .into_iter()
+        .filter(|author| selection.includes_author(&author.name))
+        .collect()
+}
+
+
+fn selected_books(directories: &Directories, selection: &Selection) -> Vec<Book> {
+    selected_authors(selection)
+        .iter()
+        .flat_map(|author| acquired_books(directories, author, selection))
+        .collect()
+}
+
+
+// The selected authors' collections, and the combined one unless particular authors were asked for
+fn selected_collections(selection: &Selection) -> Vec<String> {
+    let mut names: Vec<String> = selected_authors(selection).iter().map(|author| collection_name(&author.name)).collect();
+    if selection.authors.is_empty() {
+        names.push(COMBINED_COLLECTION.to_string());
+    }
+    names
+}
+
+
+// A streamed answer has already been printed as it was written, so only its sources are left to print
+fn print_answer(answer: &Answer, was_streamed: bool) {
+    if let Some(reason) = &answer.refusal {
+        if was_streamed {
+            println!();
+        }
+        println!("{} cannot be answered from the works of {}: {}", answer.question, answer.author, reason);
+        return
+    }
+
+    if was_streamed {
+        println!("\n");
+    } else {
+        println!("{}\n", answer.with_citations());
+    }
+    for (number, passage) in answer.cited_passages() {
+        println!("[{}] {}", number, passage.citation());
+    }
+    if let Some(report) = &answer.faithfulness {
+        print_faithfulness(report);
+    }
+
+    if answer.interpretive {
+        println!("\nNone of the passages answers this directly, so the answer is an interpretation of them rather than what {} wrote.", answer.author);
+    }
+    if let Some(disclaimer) = &answer.disclaimer {
+        println!("\n{}", disclaimer);
+    }
+}
+
+
+// As print_answer, with the sources listed under each author
+fn print_comparison(answer: &Answer, authors: &[Author], was_streamed: bool) {
+    if answer.is_refused() {
+        print_answer(answer, was_streamed);
+        return
+    }
+
+    if was_streamed {
+        println!("\n");
+    } else {
+        println!("{}\n", answer.with_citations());
+    }
+    for author in authors {
+        let cited: Vec<(usize, &Passage)> = answer.cited_passages().filter(|(_, passage)| passage.metadata.author == author.name).collect();
+        println!("{}:{}", author.name, if cited.is_empty() {" none of their passages are cited"} else {""});
+        for (number, passage) in cited {
+            println!("    [{}] {}", number, passage.citation());
+        }
+    }
+    if let Some(report) = &answer.faithfulness {
+        print_faithfulness(report);
+    }
+}
+
+
+// Only what did not pass is shown
+fn print_faithfulness(report: &FaithfulnessReport) {
+    if report.is_faithful() {
+        return
+    }
+
+    println!("\nNot borne out by the sources:");
+    for claim in report.unsupported_claims() {
+        println!("    \"{}\" ({})", claim.text, claim.problem.as_deref().unwrap_or_default());
+    }
+    for quotation in report.missing_quotations() {
+        println!("    \"{}\" (quoted, but not found word for word in the corpus)", quotation.quotation);
+    }
+}
+
+
+fn load_checker(directories: &Directories, config: &Config) -> Result<Option<FaithfulnessChecker>, anyhow::Error> {
+    if config.faithfulness.enabled {Ok(Some(FaithfulnessChecker::new(directories, config)?))} else {Ok(None)}
+}
+
+
+fn print_turn(turn: &Answer) {
+    match &turn.refusal {
+        Some(reason) => println!("{}: (passes, since {})\n", turn.author, reason.to_lowercase()),
+        None => println!("{}: {}\n", turn.author, turn.with_citations()),
+    }
+}
+
+
+fn print_usage(backend: &dyn LlmBackend) {
+    let usage: TokenUsage = backend.total_usage();
+    log::info!(
+        "{} used {} prompt and {} completion tokens{}",
+        backend.name(), usage.prompt_tokens, usage.completion_tokens, if usage.estimated {" (estimated)"} else {""},
+    );
+}
+
+
+// Selected authors are matched by the names that their chunks carry, and books by slug
+fn search_filter(selection: &Selection) -> Filter {
+    let authors: Vec<String> = if selection.authors.is_empty() {
+        Vec::new()
+    } else {
+        selected_authors(selection).into_iter().map(|author| author.name).collect()
+    };
+
+    Filter { authors, books: selection.books.clone(), ..Filter::default() }
+}
+
+
+pub async fn run(cli: Cli, config: &Config, directories: &Directories) -> Result<(), anyhow::Error> {
+    let selection: Selection = cli.filters.selection();
+    let dry_run: bool = cli.filters.dry_run;
+    let force: bool = cli.filters.force;
+
+    match &cli.command {
+        Command::Download => {
+            let options = DownloadOptions { selection: selection.clone(), force, dry_run };
+            download_all_texts(directories, &options).await;
+        },
+
+        Command::Extract => {
+            let state = StateStore::open(directories)?;
+            extract_books(directories, &state, &selected_books(directories, &selection), force, dry_run);
+        },
+
+        Command::Ocr => {
+            let state = StateStore::open(directories)?;
+            ocr_books(directories, &state, &selected_books(directories, &selection), force, dry_run);
+        },
+
+        Command::Clean => {
+            let state = StateStore::open(directories)?;
+            clean_books(directories, &state, &config.cleaning, &selected_books(directories, &selection), force, dry_run);
+        },
+
+        Command::Chunk => {
+            let state = StateStore::open(directories)?;
+            chunk_books(directories, &state, &config.chunking, &config.segmentation, &selected_books(directories, &selection), force, dry_run);
+        },
+
+        Command::Embed => {
+            let state = StateStore::open(directories)?;
+            embed_books(directories, &state, &config.embedding, &selected_books(directories, &selection), force, dry_run);
+        },
+
+        Command::Index => {
+            let state = StateStore::open(directories)?;
+            index_books(directories, &state, config, &selected_books(directories, &selection), force, dry_run);
+        },
+
+        Command::ExportChroma => export_collections(directories, &config.chroma, &selected_collections(&selection), dry_run).await?,
+
+        Command::VerifyChroma => {
+            let reports: Vec<SyncReport> = verify_collections(directories, &config.chroma, &selected_collections(&selection)).await?;
+            print_sync_reports(&reports);
+
+            let out_of_sync: usize = reports.iter().filter(|report| !report.is_in_sync()).count();
+            if out_of_sync > 0 {
+                anyhow::bail!("{} collections differ from their copies on {}", out_of_sync, config.chroma.url);
+            }
+        },
+
+        Command::Search { query, k } => {
+            let mut retriever = Retriever::new(directories, config)?;
+            let passages: Vec<Passage> = retriever.retrieve(query, &search_filter(&selection), *k)?;
+            print_passages(&passages);
+        },
+
+        Command::Quote { quotation, k } => {
+            let finder = QuoteFinder::new(&config.quotes, &config.segmentation);
+            let matches: Vec<QuoteMatch> = finder.find(directories, &selected_books(directories, &selection), quotation, *k)?;
+            if matches.is_empty() {
+                println!("Nothing in the selected books matches this quotation closely enough");
+            }
+
+            for (index, found) in matches.iter().enumerate() {
+                let exact: &str = if found.is_exact {", exact"} else {""};
+                let voice: &str = if found.voice == Voice::Secondary {", not in the author's own words"} else {""};
+                println!("{}. {}  (score {:.3}{}{})", index + 1, found.citation(), found.score, exact, voice);
+                println!("    {}\n", found.text.split_whitespace().collect::<Vec<&str>>().join(" "));
+            }
+        },
+
+        Command::Concordance { term, regex, width, sort, text, csv } => {
+            let concordance = Concordance::new(concordance_pattern(term, *regex)?, *width, *text);
+            let books: Vec<Book> = selected_books(directories, &selection);
+
+            let counts: Vec<BookCount> = match csv {
+                Some(path) => {
+                    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+                    writeln!(file, "{}", CSV_HEADER)?;
+                    let mut result: Result<(), std::io::Error> = Ok(());
+                    let counts: Vec<BookCount> = concordance.search(directories, &books, *sort, |line: ConcordanceLine| {
+                        if result.is_ok() {
+                            result = writeln!(file, "{}", line.to_csv());
+                        }
+                    })?;
+                    result?;
+                    file.flush()?;
+                    log::info!("Wrote the concordance to {}", path.display());
+                    counts
+                },
+                None => concordance.search(directories, &books, *sort, |line: ConcordanceLine| {
+                    println!("{:>width$}  {}  {:<width$}  {}, p. {}", line.left, line.keyword, line.right, line.title, line.page, width = *width);
+                })?,
+            };
+
+            println!("\n{:>11}  {:>16}  Book", "Occurrences", "Per 10,000 words");
+            for count in &counts {
+                println!("{:>11}  {:>16.2}  {} ({})", count.occurrences, count.per_ten_thousand_words(), count.title, count.author);
+            }
+            println!("{:>11}  {:>16}  In all", counts.iter().map(|count| count.occurrences).sum::<usize>(), "");
+        },
+
+        Command::Status => print_status(directories, &StateStore::open(directories)?, &selection),
+
+        Command::Lint => {
+            let problems: Vec<String> = lint_catalog(&selected_authors(&selection));
+            for problem in &problems {
+                log::warn!("{}", problem);
+            }
+
+            if !problems.is_empty() {
+                anyhow::bail!("The catalog has {} problems", problems.len());
+            }
+        },
+
+        Command::EvaluateSegmentation => {
+            let evaluation: Evaluation = evaluate(&config.segmentation, EVALUATION_FIXTURE);
+            for (passage, sentences) in &evaluation.mistakes {
+                println!("{}", passage);
+                for sentence in sentences {
+                    println!("    | {}", sentence);
+                }
+            }
+
+            println!(
+                "{} passages, {} sentences expected, {} found, {} correct (precision {:.3}, recall {:.3})",
+                evaluation.passages, evaluation.expected, evaluation.found, evaluation.correct,
+                evaluation.precision(), evaluation.recall(),
+            );
+        },
+
+        Command::Verify => {
+            let problems: usize = verify_downloads(directories, &selection);
+            if problems > 0 {
+                anyhow::bail!("{} acquired files no longer match the manifest", problems);
+            }
+        },
+
+        Command::Query { question, strict, profile, stream, persona } => {
+            let authors: Vec<Author> = selected_authors(&selection);
+            let [author] = authors.as_slice() else {
+                anyhow::bail!("Choose the one author to ask with --author");
+            };
+            anyhow::ensure!(selection.authors.len() == 1, "Choose the one author to ask with --author");
+
+            let answering = AnsweringConfig { strict: *strict || config.answering.strict, ..config.answering.clone() };
+            let backend: Box<dyn LlmBackend> = load_backend(&config.llm, profile.as_deref())?;
+            let mut retriever = Retriever::new(directories, config)?;
+            let checker: Option<FaithfulnessChecker> = load_checker(directories, config)?;
+
+            let mut print_text = |text: &str| {
+                print!("{}", text);
+                let _ = std::io::stdout().flush();
+            };
+            let on_text: Option<&mut OnText> = if *stream {Some(&mut print_text)} else {None};
+
+            let mut answer: Answer = if *persona {
+                answer_as_author(&mut retriever, backend.as_ref(), on_text, &answering, author, question).await?
+            } else {
+                answer_question(&mut retriever, backend.as_ref(), on_text, &answering, &author.name, question).await?
+            };
+            if let Some(checker) = &checker {
+                answer = checker.check_answer(&mut retriever, answer, answering.strict)?;
+            }
+            print_answer(&answer, *stream);
+            print_usage(backend.as_ref());
+        },
+
+        Command::Compare { question, debate: is_debate, rounds, strict, profile, stream } => {
+            let authors: Vec<Author> = selected_authors(&selection);
+            anyhow::ensure!(
+                !selection.authors.is_empty() && authors.len() >= 2,
+                "Choose at least two authors to compare with --author",
+            );
+            let chosen: Vec<&Author> = authors.iter().collect();
+
+            let answering = AnsweringConfig {
+                strict: *strict || config.answering.strict,
+                debate_rounds: rounds.unwrap_or(config.answering.debate_rounds),
+                ..config.answering.clone()
+            };
+            let backend: Box<dyn LlmBackend> = load_backend(&config.llm, profile.as_deref())?;
+            let mut retriever = Retriever::new(directories, config)?;
+            let checker: Option<FaithfulnessChecker> = load_checker(directories, config)?;
+
+            if *is_debate {
+                let debate: Debate = debate(&mut retriever, backend.as_ref(), print_turn, &answering, &chosen, question).await?;
+                if let Some(checker) = &checker {
+                    for (number, turn) in debate.turns.iter().enumerate() {
+                        let report: FaithfulnessReport = checker.check(&mut retriever, turn)?;
+                        if !report.is_faithful() {
+                            print!("In turn {} ({}):", number + 1, turn.author);
+                            print_faithfulness(&report);
+                            println!();
+                        }
+                    }
+                }
+                for disclaimer in &debate.disclaimers {
+                    println!("{}", disclaimer);
+                }
+            } else {
+                let mut print_text = |text: &str| {
+                    print!("{}", text);
+                    let _ = std::io::stdout().flush();
+                };
+                let on_text: Option<&mut OnText> = if *stream {Some(&mut print_text)} else {None};
+
+                let mut answer: Answer = compare_authors(&mut retriever, backend.as_ref(), on_text, &answering, &chosen, question).await?;
+                if let Some(checker) = &checker {
+                    answer = checker.check_answer(&mut retriever, answer, answering.strict)?;
+                }
+                print_comparison(&answer, &authors, *stream);
+            }
+            print_usage(backend.as_ref());
+        },
+    }
+
+    Ok(())
+}
@@ -0,0 +1,270 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::setup::paths::Directories;
+use crate::data_preparation::books::Book;
+use crate::data_preparation::cleaning::{is_heading, paragraphs};
+
+// Headings that are never the author's own words
+const SECONDARY_HEADINGS: [&str; 11] = [
+    "editor's note", "editorial note", "editor's introduction", "translator's note", "translator's preface",
+    "translator's introduction", "publisher's note", "biographical sketch", "biographical introduction",
+    "memoir", "chronology",
+];
+
+// Headings that authors write as often as their editors do. These only count as secondary when
+// they name someone else, or when one of the author's biographers or compilers signs them.
+const AMBIGUOUS_HEADINGS: [&str; 5] = ["preface", "introduction", "foreword", "prefatory note", "introductory note"];
+
+const CHAPTER_HEADINGS: [&str; 5] = ["chapter ", "book ", "part ", "lecture ", "letter "];
+
+// How much of the start and end of a section is searched for the name of whoever wrote it
+const SIGNATURE_WINDOW: usize = 400;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Voice {
+    // The author's own words
+    Primary,
+    // Prefaces, editorial matter, and biographical sketches written by someone else
+    Secondary,
+}
+
+
+// A stretch of the cleaned text between two headings (or page ranges from the catalog)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Section {
+    pub heading: Option<String>,
+    pub voice: Voice,
+    // The biographer, compiler, or editor who wrote a secondary section, if they could be named
+    pub contributor: Option<String>,
+    // Offsets in characters into the cleaned text, end exclusive
+    pub start: usize,
+    pub end: usize,
+    pub first_page: i64,
+    pub last_page: i64,
+}
+
+
+pub fn sections_path(directories: &Directories, book: &Book) -> PathBuf {
+    directories.cleaned.join(&book.author).join(format!("{}.sections.json", book.slug))
+}
+
+
+pub fn save_sections(directories: &Directories, book: &Book, sections: &[Section]) -> Result<(), anyhow::Error> {
+    fs::write(sections_path(directories, book), serde_json::to_string_pretty(sections)?)?;
+    Ok(())
+}
+
+
+// Books that were cleaned before sections were labelled are treated as the author's throughout
+pub fn load_sections(directories: &Directories, book: &Book) -> Result<Vec<Section>, anyhow::Error> {
+    let path: PathBuf = sections_path(directories, book);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+
+// The section that a character offset falls into
+pub fn section_at(sections: &[Section], offset: usize) -> Option<&Section> {
+    sections.iter().find(|section| section.start <= offset && offset < section.end)
+}
+
+
+fn heading_starts_with(heading: &str, prefixes: &[&str]) -> bool {
+    let heading: String = heading.to_lowercase().replace('’', "'");
+    prefixes.iter().any(|prefix| heading.starts_with(prefix))
+}
+
+
+fn is_section_heading(paragraph: &str) -> bool {
+    let paragraph: &str = paragraph.trim();
+    let is_short: bool = paragraph.chars().count() < 80 && !paragraph.contains('\n');
+
+    is_short && (
+        is_heading(paragraph)
+        || heading_starts_with(paragraph, &SECONDARY_HEADINGS)
+        || heading_starts_with(paragraph, &AMBIGUOUS_HEADINGS)
+        || heading_starts_with(paragraph, &CHAPTER_HEADINGS)
+    )
+}
+
+
+// Catalog entries list people as "Surname, Forename", "Surname H.E. (ed.)", or "Forename Surname".
+// Any word of the name other than an initial is enough to recognise them.
+fn name_words(name: &str) -> Vec<&str> {
+    let name: &str = name.split('(').next().unwrap_or(name);
+
+    name.split([' ', ','])
+        .filter(|word| word.chars().count() > 2 && !word.contains('.'))
+        .collect()
+}
+
+
+fn find_contributor(text: &str, biographers_and_compilers: &[String]) -> Option<String> {
+    let words: Vec<&str> = text.split(|c: char| !c.is_alphanumeric() && c != '-').collect();
+
+    biographers_and_compilers.iter()
+        .find(|name| name_words(name).iter().any(|word| words.contains(word)))
+        .cloned()
+}
+
+
+// "Introduction by Francis Darwin" names whoever wrote it, unless that is the author (as in "by C. Darwin")
+fn named_in_heading(heading: &str, author: &str) -> Option<String> {
+    let (_, name) = heading.split_once(" by ").or_else(|| heading.split_once(" BY "))?;
+    let name: &str = name.trim().trim_end_matches(['.', ':']);
+    let author_words: Vec<String> = name_words(author).iter().map(|word| word.to_lowercase()).collect();
+    let is_author: bool = name_words(name).iter().all(|word| author_words.contains(&word.to_lowercase()));
+
+    if name.is_empty() || is_author {None} else {Some(name.to_string())}
+}
+
+
+fn classify(section: &mut Section, text: &[char], book: &Book, in_secondary_pages: bool) {
+    let heading: String = section.heading.clone().unwrap_or_default();
+    let body: &[char] = &text[section.start..section.end];
+    let opening: String = body.iter().take(SIGNATURE_WINDOW).collect();
+    let closing: String = body[body.len().saturating_sub(SIGNATURE_WINDOW)..].iter().collect();
+    let signed_by: Option<String> = find_contributor(&format!("{} {} {}", heading, opening, closing), &book.biographers_and_compilers);
+
+    let (voice, contributor): (Voice, Option<String>) = if let Some(name) = named_in_heading(&heading, &book.author) {
+        (Voice::Secondary, signed_by.or(Some(name)))
+    } else if in_secondary_pages
+        || heading_starts_with(&heading, &SECONDARY_HEADINGS)
+        || (heading_starts_with(&heading, &AMBIGUOUS_HEADINGS) && signed_by.is_some()) {
+        (Voice::Secondary, signed_by)
+    } else {
+        (Voice::Primary, None)
+    };
+
+    section.voice = voice;
+    section.contributor = contributor;
+}
+
+
+// Splits the cleaned text into sections at each heading, and wherever the catalog says that a range
+// of pages was written by someone else, then works out whose words each section is
+pub fn label_sections(text: &str, first_page: i64, book: &Book) -> Vec<Section> {
+    let characters: Vec<char> = text.chars().collect();
+    let is_secondary_page = |page: i64| book.secondary_pages.iter().any(|(start, end)| *start <= page && page <= *end);
+
+    let mut boundaries: Vec<(usize, i64, Option<String>, bool)> = Vec::new();
+    let mut page_starts: Vec<usize> = Vec::new();
+
+    for (offset, index, paragraph) in paragraphs(text) {
+        let page: i64 = first_page + index as i64;
+        let in_secondary_pages: bool = is_secondary_page(page);
+
+        if page_starts.len() == index {
+            page_starts.push(offset);
+        }
+
+        if boundaries.last().is_none_or(|(_, _, _, secondary)| *secondary != in_secondary_pages) {
+            boundaries.push((offset, page, None, in_secondary_pages));
+        }
+
+        if is_section_heading(paragraph) {
+            boundaries.push((offset, page, Some(paragraph.trim().to_string()), in_secondary_pages));
+        }
+    }
+
+    let page_of = |offset: usize| first_page + page_starts.partition_point(|start| *start <= offset) as i64 - 1;
+
+    let mut sections: Vec<Section> = Vec::new();
+    for (index, (start, page, heading, in_secondary_pages)) in boundaries.iter().enumerate() {
+        let end: usize = boundaries.get(index + 1)
+            .map(|(end, _, _, _)| *end)
+            .unwrap_or(characters.len())
+            .min(characters.len());
+
+        if end <= *start {
+            continue
+        }
+
+        let mut section = Section {
+            heading: heading.clone(),
+            voice: Voice::Primary,
+            contributor: None,
+            start: *start,
+            end,
+            first_page: *page,
+            last_page: page_of(end - 1),
+        };
+
+        classify(&mut section, &characters, book, *in_secondary_pages);
+        sections.push(section);
+    }
+
+    sections
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indices::testing::book;
+
+    const PAGES: [&str; 6] = [
+        "PREFACE\n\nI have been asked to say how this volume came to be written, and I do so gladly.\n\nC. Darwin",
+        "INTRODUCTION\n\nThese letters were gathered by his daughter after his death, and are given here as he wrote them.\n\nH. E. Litchfield",
+        "EDITOR'S NOTE\n\nThe spelling of the letters has been left as it was.",
+        "CHAPTER I\n\nWhen we look to the individuals of the same variety or sub-variety of our older cultivated plants and animals, one of the first points which strikes us is that they generally differ more from each other.",
+        "Darwin was born at Shrewsbury on the twelfth of February, and was sent to school there.",
+        "CHAPTER II\n\nBefore applying the principles arrived at in the last chapter to organic beings in a state of nature, we must briefly discuss whether these latter are subject to any variation.",
+    ];
+
+    fn sections() -> Vec<Section> {
+        let mut book: Book = book("Charles Darwin", "More Letters");
+        book.secondary_pages = vec![(5, 5)];
+        book.biographers_and_compilers = vec![String::from("Litchfield H.E. (ed.)"), String::from("Barrett, Paul (ed.)")];
+
+        label_sections(&PAGES.join("\x0c"), 1, &book)
+    }
+
+    // The voice and contributor of the section that begins on the given page
+    fn labelled(sections: &[Section], page: i64) -> (Voice, Option<&str>) {
+        let section: &Section = sections.iter().find(|section| section.first_page == page).unwrap();
+        (section.voice, section.contributor.as_deref())
+    }
+
+    #[test]
+    fn an_authors_own_preface_is_primary() {
+        let sections: Vec<Section> = sections();
+
+        assert_eq!(sections[0].heading.as_deref(), Some("PREFACE"));
+        assert_eq!(labelled(&sections, 1), (Voice::Primary, None));
+    }
+
+    #[test]
+    fn a_preface_signed_by_a_compiler_is_theirs() {
+        assert_eq!(labelled(&sections(), 2), (Voice::Secondary, Some("Litchfield H.E. (ed.)")));
+    }
+
+    #[test]
+    fn an_editors_note_is_secondary() {
+        assert_eq!(labelled(&sections(), 3), (Voice::Secondary, None));
+    }
+
+    #[test]
+    fn the_catalogs_page_ranges_override_the_headings() {
+        let sections: Vec<Section> = sections();
+
+        assert_eq!(labelled(&sections, 4), (Voice::Primary, None));
+        assert_eq!(labelled(&sections, 5), (Voice::Secondary, None));
+        assert_eq!(labelled(&sections, 6), (Voice::Primary, None));
+        assert_eq!(sections.iter().map(|section| (section.first_page, section.last_page)).collect::<Vec<_>>(), [(1, 1), (2, 2), (3, 3), (4, 4), (5, 5), (6, 6)]);
+    }
+
+    #[test]
+    fn a_heading_that_names_someone_else_is_secondary() {
+        assert_eq!(named_in_heading("Introduction by Francis Darwin", "Charles Darwin").as_deref(), Some("Francis Darwin"));
+        assert_eq!(named_in_heading("Preface by C. Darwin", "Charles Darwin"), None);
+    }
+}
@@ -0,0 +1,107 @@
+use std::path::PathBuf;
+
+use crate::setup::paths::Directories;
+use crate::sources::authors::{get_author_root, Author};
+use crate::sources::manifest::{Manifest, SourceIdentity, SourceKind};
+
+
+// Everything that the later stages need to know about a single book, whichever way it was acquired.
+// Torrents are collections, so each of the files that they produced is treated as a book of its own.
+#[derive(Clone, Debug)]
+pub struct Book {
+    pub author: String,
+    pub title: String,
+    pub slug: String,
+    pub file_name: String,
+    pub source: SourceIdentity,
+    pub needs_ocr: bool,
+    pub start_page: Option<i64>,
+    pub end_page: Option<i64>,
+    pub secondary_pages: Vec<(i64, i64)>,
+    pub biographers_and_compilers: Vec<String>,
+    pub is_interview: bool,
+}
+
+
+impl Book {
+
+    pub fn path(&self, directories: &Directories) -> PathBuf {
+        get_author_root(directories, &self.author).join(&self.file_name)
+    }
+}
+
+
+// Narrows a run down to particular authors, books (by slug), and kinds of source.
+// An empty filter selects everything.
+#[derive(Clone, Default)]
+pub struct Selection {
+    pub authors: Vec<String>,
+    pub books: Vec<String>,
+    pub sources: Vec<SourceKind>,
+}
+
+
+impl Selection {
+
+    pub fn includes_author(&self, author_name: &str) -> bool {
+        self.authors.is_empty() || self.authors.iter().any(
+            |name| name.eq_ignore_ascii_case(author_name)
+        )
+    }
+
+    pub fn includes_book(&self, title: &str) -> bool {
+        let slug: String = slugify(title);
+        self.books.is_empty() || self.books.iter().any(|book| slugify(book) == slug)
+    }
+
+    pub fn includes_source(&self, kind: SourceKind) -> bool {
+        self.sources.is_empty() || self.sources.contains(&kind)
+    }
+
+    pub fn includes(&self, title: &str, kind: SourceKind) -> bool {
+        self.includes_source(kind) && self.includes_book(title)
+    }
+}
+
+
+pub fn slugify(title: &str) -> String {
+    let mut slug: String = String::new();
+
+    for character in title.chars().flat_map(char::to_lowercase) {
+        if character.is_alphanumeric() {
+            slug.push(character);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    slug.trim_end_matches('-').to_string()
+}
+
+
+// Lists the author's books as the catalog describes them. Torrented books can only be listed once
+// the manifest knows which files the torrent produced.
+pub fn list_books(author: &Author, manifest: &Manifest) -> Vec<Book> {
+    author.sources.iter()
+        .flat_map(|source| source.books(&author.name, manifest))
+        .map(|book| Book { biographers_and_compilers: author.biographers_and_compilers.clone().unwrap_or_default(), ..book })
+        .collect()
+}
+
+
+// The books that have been acquired, and still match what the manifest recorded for them.
+pub fn acquired_books(directories: &Directories, author: &Author, selection: &Selection) -> Vec<Book> {
+    let manifest: Manifest = Manifest::load(&get_author_root(directories, &author.name));
+
+    list_books(author, &manifest)
+        .into_iter()
+        .filter(|book| selection.includes_book(&book.title) && selection.includes_source(book.source.kind()))
+        .filter(|book| {
+            let is_valid: bool = manifest.is_valid(&book.file_name, &book.source);
+            if !is_valid {
+                log::warn!("{} has not been acquired, so it will be skipped", book.title);
+            }
+            is_valid
+        })
+        .collect()
+}
@@ -0,0 +1,575 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::setup::paths::Directories;
+use crate::data_preparation::books::{slugify, Book};
+use crate::sources::manifest::{hash_bytes, hash_file};
+use crate::data_preparation::state::{hash_config, Stage, StateStore};
+use crate::data_preparation::dialogue::{load_turns, turn_at, Role, Turn};
+use crate::data_preparation::notes::{load_annotations, Annotation};
+use crate::data_preparation::attribution::{load_sections, section_at, Section, Voice};
+use crate::data_preparation::cleaning::{cleaned_text_path, paragraphs};
+use crate::data_preparation::segmentation::{SegmentationConfig, Segmenter};
+use crate::data_preparation::extraction::{extracted_metadata_path, ExtractedDocument, PAGE_SEPARATOR};
+
+// Written into every chunk. Bumped whenever the meaning of a field changes, so that readers of old
+// files can tell, and so that every book is chunked again.
+pub const CHUNK_FORMAT_VERSION: u32 = 2;
+
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SizeUnit {
+    Characters,
+    // Approximated by counting words, which errs on the small side for most tokenizers
+    Tokens,
+}
+
+
+// The [chunking] section of giants.toml
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChunkingConfig {
+    pub unit: SizeUnit,
+    pub maximum_size: usize,
+    // How much of the end of each chunk is repeated at the start of the next one
+    pub overlap: usize,
+}
+
+
+impl Default for ChunkingConfig {
+    fn default() -> Self {
+        Self {
+            unit: SizeUnit::Tokens,
+            maximum_size: 300,
+            overlap: 40,
+        }
+    }
+}
+
+
+impl ChunkingConfig {
+
+    pub fn size(&self, text: &str) -> usize {
+        match self.unit {
+            SizeUnit::Characters => text.chars().count(),
+            SizeUnit::Tokens => text.split_whitespace().count(),
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkKind {
+    // Running text from the body of the book
+    Text,
+    // A footnote or endnote, which is almost always the editor's
+    Note,
+}
+
+
+// One retrieval passage, as written to the chunk files (one JSON object per line)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Chunk {
+    pub id: String,
+    pub format_version: u32,
+    pub author: String,
+    pub title: String,
+    pub slug: String,
+    pub source_url: String,
+    pub kind: ChunkKind,
+    pub chapter: Option<String>,
+    pub first_page: i64,
+    pub last_page: i64,
+    // Offsets in characters into the cleaned text, or into the note's own text for notes
+    pub start: usize,
+    pub end: usize,
+    pub voice: Voice,
+    pub contributor: Option<String>,
+    pub speakers: Vec<String>,
+    pub role: Option<Role>,
+    // Where in the cleaned text a note is referred to
+    pub anchor: Option<usize>,
+    pub text: String,
+}
+
+
+impl Chunk {
+
+    // Derived only from what the chunk contains, and not from where it is, so that an edit early in a
+    // book leaves the ids of the chunks after it alone. A text that recurs word for word is told apart
+    // by how many times it has come before.
+    fn make_id(author: &str, slug: &str, scope: &str, text: &str, occurrences: &mut HashMap<String, usize>) -> String {
+        let content: String = hash_bytes(format!("{}\n{}", scope, text).as_bytes());
+        let occurrence: &mut usize = occurrences.entry(content.clone()).or_insert(0);
+        *occurrence += 1;
+
+        let digest: String = hash_bytes(format!("{}\n{}", content, occurrence).as_bytes());
+        format!("{}:{}:{}", slugify(author), slug, &digest[..16])
+    }
+}
+
+
+pub fn chunks_path(directories: &Directories, book: &Book) -> PathBuf {
+    directories.chunks.join(&book.author).join(format!("{}.jsonl", book.slug))
+}
+
+
+pub fn load_chunks(directories: &Directories, book: &Book) -> Result<Vec<Chunk>, anyhow::Error> {
+    let contents: String = fs::read_to_string(chunks_path(directories, book))?;
+
+    contents.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+
+pub fn chunk_books(
+    directories: &Directories,
+    state: &StateStore,
+    config: &ChunkingConfig,
+    segmentation: &SegmentationConfig,
+    books: &[Book],
+    force: bool,
+    dry_run: bool,
+) {
+    let config_hash: String = hash_config(&(config, segmentation, CHUNK_FORMAT_VERSION));
+
+    for book in kdam::tqdm!(books.iter(), desc="Chunking") {
+        let output_path: PathBuf = chunks_path(directories, book);
+
+        let input_hash: String = match state.input_hash(book, Stage::Chunking, &cleaned_text_path(directories, book)) {
+            Ok(hash) => hash,
+            Err(e) => {
+                log::error!("Unable to chunk {} (has it been cleaned?): {}", book.title, e);
+                continue
+            }
+        };
+
+        if !force && output_path.exists() && state.is_current(book, Stage::Chunking, &input_hash, &config_hash) {
+            continue
+        }
+
+        if dry_run {
+            log::info!("Would chunk {} into {}", book.title, output_path.display());
+            continue
+        }
+
+        let result: Result<(), anyhow::Error> = chunk_book(directories, book, config, &segmentation.segmenter_for(&book.author)).and_then(
+            |_| state.record(book, Stage::Chunking, &input_hash, &config_hash, &hash_file(&output_path)?)
+        );
+
+        if let Err(e) = result {
+            log::error!("Unable to chunk {}: {}", book.title, e);
+        }
+    }
+}
+
+
+pub fn chunk_book(directories: &Directories, book: &Book, config: &ChunkingConfig, segmenter: &Segmenter) -> Result<(), anyhow::Error> {
+    let text: String = fs::read_to_string(cleaned_text_path(directories, book))?;
+    let document: ExtractedDocument = serde_json::from_str(
+        &fs::read_to_string(extracted_metadata_path(directories, book))?
+    )?;
+
+    let sections: Vec<Section> = load_sections(directories, book)?;
+    let turns: Vec<Turn> = load_turns(directories, book)?;
+    let annotations: Vec<Annotation> = load_annotations(directories, book)?;
+
+    let characters: Vec<char> = text.chars().collect();
+    let page_starts: Vec<usize> = characters.iter()
+        .enumerate()
+        .filter(|(_, c)| **c == PAGE_SEPARATOR)
+        .map(|(index, _)| index + 1)
+        .collect();
+
+    let chunker = Chunker { book, document: &document, config, segmenter, text: characters, page_starts, sections, turns };
+    let mut occurrences: HashMap<String, usize> = HashMap::new();
+    let mut chunks: Vec<Chunk> = chunker.chunk_text(&mut occurrences);
+    for note in &annotations {
+        chunks.extend(chunker.chunk_note(note, &mut occurrences));
+    }
+
+    // Written to a partial file first, so that an interrupted run never leaves half a book behind
+    let output_path: PathBuf = chunks_path(directories, book);
+    fs::create_dir_all(output_path.parent().unwrap())?;
+    let partial_path: PathBuf = output_path.with_extension("jsonl.part");
+
+    let mut file = std::io::BufWriter::new(fs::File::create(&partial_path)?);
+    for chunk in &chunks {
+        writeln!(file, "{}", serde_json::to_string(chunk)?)?;
+    }
+    file.flush()?;
+    drop(file);
+    fs::rename(&partial_path, &output_path)?;
+
+    log::info!("Split {} into {} chunks", book.title, chunks.len());
+    Ok(())
+}
+
+
+// Which chunks a stretch of text may share with its neighbours. Chunks never cross from one section
+// to another, nor between what the author said and what their interviewer said.
+#[derive(Clone, PartialEq)]
+struct Segment {
+    section: Option<usize>,
+    role: Option<Role>,
+}
+
+
+struct Chunker<'a> {
+    book: &'a Book,
+    document: &'a ExtractedDocument,
+    config: &'a ChunkingConfig,
+    segmenter: &'a Segmenter,
+    text: Vec<char>,
+    // Where each page after the first begins
+    page_starts: Vec<usize>,
+    sections: Vec<Section>,
+    turns: Vec<Turn>,
+}
+
+
+impl Chunker<'_> {
+
+    fn slice(&self, start: usize, end: usize) -> String {
+        self.text[start..end].iter()
+            .map(|c| if *c == PAGE_SEPARATOR {' '} else {*c})
+            .collect()
+    }
+
+    fn size(&self, start: usize, end: usize) -> usize {
+        self.config.size(&self.slice(start, end))
+    }
+
+    fn page_of(&self, offset: usize) -> i64 {
+        self.document.first_page + self.page_starts.partition_point(|start| *start <= offset) as i64
+    }
+
+    fn segment_of(&self, offset: usize) -> Segment {
+        let role: Option<Role> = turn_at(&self.turns, offset).map(|turn| turn.role);
+
+        Segment {
+            section: self.sections.iter().position(|section| section.start <= offset && offset < section.end),
+            // Everyone in a dialogue may share a chunk, but the author never shares one with an interviewer
+            role: role.map(|role| if role == Role::Subject {Role::Subject} else {Role::Other}),
+        }
+    }
+
+    // The chapter is the nearest heading at or before the offset
+    fn chapter_at(&self, offset: usize) -> Option<String> {
+        self.sections.iter()
+            .filter(|section| section.start <= offset)
+            .filter_map(|section| section.heading.clone())
+            .next_back()
+    }
+
+    // Paragraphs, with paragraphs that a page break cut short joined back onto their continuation
+    fn units(&self) -> Vec<(usize, usize)> {
+        let mut units: Vec<(usize, usize)> = Vec::new();
+        let mut previous_page: usize = 0;
+
+        for (offset, page, paragraph) in paragraphs(&self.text.iter().collect::<String>()) {
+            let leading: usize = paragraph.chars().take_while(|c| c.is_whitespace()).count();
+            let trimmed: &str = paragraph.trim();
+            if trimmed.is_empty() {
+                continue
+            }
+
+            let (start, end) = (offset + leading, offset + leading + trimmed.chars().count());
+
+            let continues_previous: bool = page != previous_page && units.last().is_some_and(|(last_start, last_end)| {
+                let unfinished: bool = !self.text[*last_end - 1].is_ascii_punctuation() || self.text[*last_end - 1] == ',';
+                unfinished && self.segment_of(*last_start) == self.segment_of(start)
+            });
+
+            match units.last_mut() {
+                Some(last) if continues_previous => last.1 = end,
+                _ => units.push((start, end)),
+            }
+            previous_page = page;
+        }
+
+        units
+    }
+
+    // Paragraphs that are too long are broken into sentences, and sentences that are still too
+    // long into runs of words
+    fn pieces(&self, start: usize, end: usize) -> Vec<(usize, usize)> {
+        if self.size(start, end) <= self.config.maximum_size {
+            return vec![(start, end)];
+        }
+
+        let mut pieces: Vec<(usize, usize)> = Vec::new();
+        let mut sentence_start: usize = start;
+
+        for sentence_end in self.segmenter.sentence_ends(&self.text, start, end) {
+            sentence_start += self.text[sentence_start..sentence_end].iter().take_while(|c| c.is_whitespace()).count();
+            if sentence_start == sentence_end {
+                continue
+            }
+
+            if self.size(sentence_start, sentence_end) <= self.config.maximum_size {
+                pieces.push((sentence_start, sentence_end));
+            } else {
+                pieces.extend(self.word_runs(sentence_start, sentence_end));
+            }
+            sentence_start = sentence_end;
+        }
+
+        pieces
+    }
+
+    fn word_runs(&self, start: usize, end: usize) -> Vec<(usize, usize)> {
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        let mut run_start: usize = start;
+        let mut last_break: usize = start;
+
+        for index in start..end {
+            if !self.text[index].is_whitespace() || index == last_break {
+                continue
+            }
+
+            if self.size(run_start, index) > self.config.maximum_size && last_break > run_start {
+                runs.push((run_start, last_break));
+                run_start = last_break + 1;
+            }
+            last_break = index;
+        }
+
+        runs.push((run_start, end));
+        runs
+    }
+
+    // The latest sentence boundary from which the rest of the chunk fits within the overlap
+    fn overlap_start(&self, start: usize, end: usize) -> usize {
+        if self.config.overlap == 0 {
+            return end;
+        }
+
+        self.segmenter.sentence_ends(&self.text, start, end)
+            .into_iter()
+            .map(|boundary| boundary + self.text[boundary..end].iter().take_while(|c| c.is_whitespace()).count())
+            .find(|boundary| *boundary < end && self.size(*boundary, end) <= self.config.overlap)
+            .unwrap_or(end)
+    }
+
+    // Packs whole pieces into chunks of up to the maximum size, carrying each chunk's last sentences
+    // over into the next as overlap. Pieces always end at a paragraph or a sentence, so no turn in a
+    // dialogue is ever split in the middle of a sentence.
+    fn pack(&self, pieces: &[(usize, usize)]) -> Vec<(usize, usize)> {
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        let mut current: Option<(usize, usize)> = None;
+
+        for (piece_start, piece_end) in pieces {
+            current = match current {
+                None => Some((*piece_start, *piece_end)),
+                Some((start, _)) if self.size(start, *piece_end) <= self.config.maximum_size => Some((start, *piece_end)),
+                Some((start, end)) => {
+                    ranges.push((start, end));
+                    let overlap: usize = self.overlap_start(start, end);
+                    let fits: bool = overlap < end && self.size(overlap, *piece_end) <= self.config.maximum_size;
+                    Some((if fits {overlap} else {*piece_start}, *piece_end))
+                },
+            };
+        }
+
+        ranges.extend(current);
+        ranges
+    }
+
+    fn chunk_text(&self, occurrences: &mut HashMap<String, usize>) -> Vec<Chunk> {
+        let mut chunks: Vec<Chunk> = Vec::new();
+        let mut segment_pieces: Vec<(usize, usize)> = Vec::new();
+        let mut current_segment: Option<Segment> = None;
+
+        let units: Vec<(usize, usize)> = self.units();
+        for (index, (start, end)) in units.iter().enumerate() {
+            let segment: Segment = self.segment_of(*start);
+
+            if current_segment.as_ref().is_some_and(|current| *current != segment) {
+                chunks.extend(self.pack(&segment_pieces).into_iter().map(|(start, end)| self.text_chunk(start, end, occurrences)));
+                segment_pieces.clear();
+            }
+
+            segment_pieces.extend(self.pieces(*start, *end));
+            current_segment = Some(segment);
+
+            if index == units.len() - 1 {
+                chunks.extend(self.pack(&segment_pieces).into_iter().map(|(start, end)| self.text_chunk(start, end, occurrences)));
+            }
+        }
+
+        chunks
+    }
+
+    fn text_chunk(&self, start: usize, end: usize, occurrences: &mut HashMap<String, usize>) -> Chunk {
+        let text: String = self.slice(start, end);
+        let section: Option<&Section> = section_at(&self.sections, start);
+
+        let mut speakers: Vec<String> = Vec::new();
+        for turn in self.turns.iter().filter(|turn| turn.start < end && start < turn.end) {
+            if !speakers.contains(&turn.speaker) {
+                speakers.push(turn.speaker.clone());
+            }
+        }
+
+        Chunk {
+            id: Chunk::make_id(&self.book.author, &self.book.slug, "text", &text, occurrences),
+            format_version: CHUNK_FORMAT_VERSION,
+            author: self.book.author.clone(),
+            title: self.book.title.clone(),
+            slug: self.book.slug.clone(),
+            source_url: self.document.source_url.clone(),
+            kind: ChunkKind::Text,
+            chapter: self.chapter_at(start),
+            first_page: self.page_of(start),
+            last_page: self.page_of(end.saturating_sub(1)),
+            start,
+            end,
+            voice: section.map(|section| section.voice).unwrap_or(Voice::Primary),
+            contributor: section.and_then(|section| section.contributor.clone()),
+            speakers,
+            role: turn_at(&self.turns, start).map(|turn| turn.role),
+            anchor: None,
+            text,
+        }
+    }
+
+    // Notes are chunked on their own, and are always counted as someone other than the author's
+    fn chunk_note(&self, note: &Annotation, occurrences: &mut HashMap<String, usize>) -> Vec<Chunk> {
+        let characters: Vec<char> = note.text.chars().collect();
+        let note_chunker = Chunker { text: characters, page_starts: Vec::new(), sections: Vec::new(), turns: Vec::new(), ..*self };
+        let pieces: Vec<(usize, usize)> = note_chunker.pieces(0, note_chunker.text.len());
+
+        note_chunker.pack(&pieces)
+            .into_iter()
+            .map(|(start, end)| {
+                let text: String = note_chunker.slice(start, end);
+
+                Chunk {
+                    id: Chunk::make_id(&self.book.author, &self.book.slug, &format!("note {}", note.id), &text, occurrences),
+                    format_version: CHUNK_FORMAT_VERSION,
+                    author: self.book.author.clone(),
+                    title: self.book.title.clone(),
+                    slug: self.book.slug.clone(),
+                    source_url: self.document.source_url.clone(),
+                    kind: ChunkKind::Note,
+                    chapter: note.anchor.and_then(|anchor| self.chapter_at(anchor)),
+                    first_page: note.page,
+                    last_page: note.page,
+                    start,
+                    end,
+                    voice: Voice::Secondary,
+                    contributor: None,
+                    speakers: Vec::new(),
+                    role: None,
+                    anchor: note.anchor,
+                    text,
+                }
+            })
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indices::testing::book;
+    use crate::data_preparation::dialogue::parse_turns;
+    use crate::data_preparation::extraction::DocumentFormat;
+
+    const CAPITAL: &str = "The wealth of those societies in which the capitalist mode of production prevails, presents itself as an immense accumulation of commodities. Its unit is a single commodity.\n\n\
+        A commodity is, in the first place, an object outside us, a thing that by its properties satisfies human wants of some sort or another.\n\n\
+        The utility of a thing makes it a use value. But this utility is not a thing of air.";
+
+    fn chunk(text: &str) -> Vec<Chunk> {
+        chunk_turns(text, &book("Karl Marx", "Capital Vol I"), 20)
+    }
+
+    fn chunk_turns(text: &str, book: &Book, maximum_size: usize) -> Vec<Chunk> {
+        let document = ExtractedDocument {
+            author: book.author.clone(),
+            title: book.title.clone(),
+            slug: book.slug.clone(),
+            source_url: String::from("https://example.org"),
+            format: DocumentFormat::Text,
+            first_page: 1,
+            is_interview: false,
+        };
+        let config = ChunkingConfig { unit: SizeUnit::Tokens, maximum_size, overlap: 0 };
+        let segmenter: Segmenter = Segmenter::new("en", &[]);
+
+        let chunker = Chunker {
+            book, document: &document, config: &config, segmenter: &segmenter,
+            text: text.chars().collect(), page_starts: Vec::new(), sections: Vec::new(), turns: parse_turns(text, book),
+        };
+        chunker.chunk_text(&mut HashMap::new())
+    }
+
+    fn ids(chunks: &[Chunk]) -> Vec<String> {
+        chunks.iter().map(|chunk| chunk.id.clone()).collect()
+    }
+
+    #[test]
+    fn unchanged_text_keeps_its_ids() {
+        let chunks: Vec<Chunk> = chunk(CAPITAL);
+
+        assert!(chunks.len() > 2);
+        assert_eq!(ids(&chunks), ids(&chunk(CAPITAL)));
+    }
+
+    #[test]
+    fn inserting_a_prefix_keeps_the_later_ids() {
+        let before: Vec<Chunk> = chunk(CAPITAL);
+        let after: Vec<Chunk> = chunk(&format!("Preface to the first German edition.\n\n{}", CAPITAL));
+
+        assert_eq!(after.len(), before.len() + 1);
+        assert_eq!(ids(&after[1..]), ids(&before));
+        // The offsets still move with the text
+        assert!(after[1..].iter().zip(&before).all(|(after, before)| after.start > before.start && after.text == before.text));
+    }
+
+    #[test]
+    fn repeated_text_gets_an_id_for_each_occurrence() {
+        let chunks: Vec<Chunk> = chunk(&format!("{}\n\n{}", CAPITAL, CAPITAL));
+        let (first, second): (&[Chunk], &[Chunk]) = chunks.split_at(chunks.len() / 2);
+        assert!(first.iter().zip(second).all(|(first, second)| first.text == second.text));
+
+        let mut unique: Vec<String> = ids(&chunks);
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), chunks.len());
+    }
+
+    #[test]
+    fn turns_are_only_split_between_sentences() {
+        let mut interview: Book = book("Mahatma Gandhi", "An Interview");
+        interview.is_interview = true;
+        let text: &str = "Q. What is your view of the machine? Many say that you would have us go back to the plough.\n\n\
+            A. I am not against machinery as such. What I object to is the craze for machinery. Men go on saving labour till \
+            thousands are without work and thrown on the open streets to die of starvation. I want to save time and labour, \
+            not for a fraction of mankind, but for all.\n\n\
+            Q. And the spinning wheel? Is that not a machine too?\n\n\
+            A. It is. But it is one that every cottage can own.";
+
+        let chunks: Vec<Chunk> = chunk_turns(text, &interview, 24);
+
+        assert!(chunks.len() > 4);
+        for chunk in &chunks {
+            assert!(chunk.text.ends_with(['.', '?']), "{:?} ends in the middle of a sentence", chunk.text);
+            assert!(chunk.speakers.len() == 1, "{:?} mixes the interviewer with the subject", chunk.text);
+        }
+        let roles: Vec<Option<Role>> = chunks.iter().map(|chunk| chunk.role).collect();
+        assert_eq!(roles.first(), Some(&Some(Role::Interviewer)));
+        assert!(roles.contains(&Some(Role::Subject)));
+    }
+}
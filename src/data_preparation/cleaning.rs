@@ -0,0 +1,601 @@
+use std::fs;
+use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::setup::paths::Directories;
+use crate::sources::manifest::{hash_bytes, hash_file};
+use crate::data_preparation::books::Book;
+use crate::data_preparation::state::{hash_config, Stage, StateStore};
+use crate::data_preparation::notes::{annotations_path, resolve_anchors, save_annotations, separate_notes, Annotation};
+use crate::data_preparation::attribution::{label_sections, save_sections, sections_path, Section, Voice};
+use crate::data_preparation::dialogue::{parse_turns, save_turns, speaker_label, turns_path, Turn};
+use crate::data_preparation::extraction::{extracted_metadata_path, extracted_text_path, ExtractedDocument, PAGE_SEPARATOR};
+
+// Bumped whenever a change to cleaning should cause every book to be cleaned again
+pub const CLEANING_VERSION: u32 = 5;
+
+// Words that keep their hyphen when it falls at the end of a line, as in "self-government"
+const HYPHENATED_PREFIXES: [&str; 12] = ["self", "non", "anti", "semi", "pre", "post", "co", "ex", "well", "ill", "half", "quasi"];
+
+
+// How aggressively a book is cleaned. The defaults suit most PDFs, but scans of a particular
+// archive often need their own rules, which is what the diff reports are for.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CleaningRules {
+    // How many lines at the top and bottom of each page may be running headers, footers, or page numbers
+    pub edge_lines: usize,
+    // The share of pages that a line must recur on to count as a running header or footer
+    pub minimum_recurrence: f64,
+    // Books with fewer pages than this are too short for recurrence to mean anything
+    pub minimum_pages: usize,
+    pub remove_page_numbers: bool,
+    // Moves footnotes and endnotes out of the text, into an annotation stream of their own
+    pub separate_notes: bool,
+    pub rejoin_hyphenation: bool,
+    pub reflow: bool,
+}
+
+
+impl Default for CleaningRules {
+    fn default() -> Self {
+        Self {
+            edge_lines: 3,
+            minimum_recurrence: 0.3,
+            minimum_pages: 5,
+            remove_page_numbers: true,
+            separate_notes: true,
+            rejoin_hyphenation: true,
+            reflow: true,
+        }
+    }
+}
+
+
+// The [cleaning] section of giants.toml. Rules for a host (such as "marxists.org") replace the
+// default rules entirely for every book acquired from it or from one of its subdomains.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CleaningConfig {
+    #[serde(default)]
+    pub rules: CleaningRules,
+    #[serde(default)]
+    pub hosts: BTreeMap<String, CleaningRules>,
+}
+
+
+impl CleaningConfig {
+
+    pub fn rules_for(&self, source_url: &str) -> &CleaningRules {
+        let host: String = reqwest::Url::parse(source_url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default();
+
+        self.hosts.iter()
+            .find(|(name, _)| host == **name || host.ends_with(&format!(".{}", name)))
+            .map(|(_, rules)| rules)
+            .unwrap_or(&self.rules)
+    }
+}
+
+
+// Everything that cleaning took out of a book, so that the rules can be checked against what they did
+#[derive(Clone, Debug, Default)]
+pub struct CleaningReport {
+    // Keyed by the line with its digits masked, as in "selected works of mao tse-tung #"
+    pub recurring_lines: BTreeMap<String, usize>,
+    pub removed_lines: Vec<(i64, String)>,
+    pub page_numbers: usize,
+    pub rejoined_words: Vec<(String, String)>,
+    pub reflowed_lines: usize,
+    pub annotations: Vec<Annotation>,
+    pub sections: Vec<Section>,
+    pub turns: Vec<Turn>,
+}
+
+
+impl CleaningReport {
+
+    pub fn render(&self, title: &str) -> String {
+        let mut lines: Vec<String> = vec![format!("Cleaning report for {}", title), String::new()];
+
+        lines.push(format!("Running headers and footers ({}):", self.recurring_lines.len()));
+        for (line, count) in &self.recurring_lines {
+            lines.push(format!("  {:>5} x {}", count, line));
+        }
+
+        lines.push(format!("Page numbers removed: {}", self.page_numbers));
+        lines.push(format!("Line breaks reflowed: {}", self.reflowed_lines));
+
+        lines.push(format!("Hyphenated words rejoined ({}):", self.rejoined_words.len()));
+        for (broken, rejoined) in &self.rejoined_words {
+            lines.push(format!("  {} -> {}", broken, rejoined));
+        }
+
+        lines.push(format!("Notes moved to the annotations ({}):", self.annotations.len()));
+        for note in &self.annotations {
+            let anchor: &str = if note.anchor.is_some() {""} else {" (anchor not found)"};
+            let text: String = note.text.chars().take(80).collect();
+            lines.push(format!("  page {}: [{}] {}{}", note.page, note.marker, text, anchor));
+        }
+
+        let secondary: Vec<&Section> = self.sections.iter().filter(|section| section.voice == Voice::Secondary).collect();
+        lines.push(format!("Sections by someone other than the author ({}):", secondary.len()));
+        for section in secondary {
+            lines.push(format!(
+                "  pages {}-{}: {} ({})",
+                section.first_page,
+                section.last_page,
+                section.heading.as_deref().unwrap_or("untitled"),
+                section.contributor.as_deref().unwrap_or("unnamed"),
+            ));
+        }
+
+        let mut turns_per_speaker: BTreeMap<&str, usize> = BTreeMap::new();
+        for turn in &self.turns {
+            *turns_per_speaker.entry(turn.speaker.as_str()).or_insert(0) += 1;
+        }
+
+        lines.push(format!("Speaker turns ({}):", self.turns.len()));
+        for (speaker, count) in turns_per_speaker {
+            lines.push(format!("  {:>5} x {}", count, speaker));
+        }
+
+        lines.push(String::new());
+        lines.push(String::from("Removed lines:"));
+        for (page, line) in &self.removed_lines {
+            lines.push(format!("  page {}: - {}", page, line));
+        }
+
+        lines.join("\n") + "\n"
+    }
+}
+
+
+pub fn cleaned_text_path(directories: &Directories, book: &Book) -> PathBuf {
+    directories.cleaned.join(&book.author).join(format!("{}.txt", book.slug))
+}
+
+
+pub fn cleaning_report_path(directories: &Directories, book: &Book) -> PathBuf {
+    directories.cleaned.join(&book.author).join(format!("{}.report.txt", book.slug))
+}
+
+
+// Cleaning writes the notes, sections, and turns beside the text, and chunking depends on all of them
+pub fn cleaned_output_hash(directories: &Directories, book: &Book) -> Result<String, anyhow::Error> {
+    let paths: [PathBuf; 4] = [
+        cleaned_text_path(directories, book),
+        annotations_path(directories, book),
+        sections_path(directories, book),
+        turns_path(directories, book),
+    ];
+
+    let mut hashes: Vec<String> = Vec::new();
+    for path in paths {
+        hashes.push(hash_file(&path)?);
+    }
+
+    Ok(hash_bytes(hashes.join("").as_bytes()))
+}
+
+
+pub fn clean_books(directories: &Directories, state: &StateStore, config: &CleaningConfig, books: &[Book], force: bool, dry_run: bool) {
+
+    for book in kdam::tqdm!(books.iter(), desc="Cleaning text") {
+        let input_path: PathBuf = extracted_text_path(directories, book);
+        let output_path: PathBuf = cleaned_text_path(directories, book);
+        let rules: &CleaningRules = config.rules_for(book.source.url());
+        let config_hash: String = hash_config(
+            &(rules, &book.secondary_pages, &book.biographers_and_compilers, book.is_interview, CLEANING_VERSION)
+        );
+
+        let input_hash: String = match state.input_hash(book, Stage::Cleaning, &input_path) {
+            Ok(hash) => hash,
+            Err(e) => {
+                log::error!("Unable to clean {} (has it been extracted?): {}", book.title, e);
+                continue
+            }
+        };
+
+        if !force && output_path.exists() && state.is_current(book, Stage::Cleaning, &input_hash, &config_hash) {
+            continue
+        }
+
+        if dry_run {
+            log::info!("Would clean {} into {}", book.title, output_path.display());
+            continue
+        }
+
+        let result: Result<(), anyhow::Error> = clean_book(directories, book, rules).and_then(
+            |_| state.record(book, Stage::Cleaning, &input_hash, &config_hash, &cleaned_output_hash(directories, book)?)
+        );
+
+        if let Err(e) = result {
+            log::error!("Unable to clean {}: {}", book.title, e);
+        }
+    }
+}
+
+
+pub fn clean_book(directories: &Directories, book: &Book, rules: &CleaningRules) -> Result<(), anyhow::Error> {
+    let text: String = fs::read_to_string(extracted_text_path(directories, book))?;
+    let document: ExtractedDocument = serde_json::from_str(
+        &fs::read_to_string(extracted_metadata_path(directories, book))?
+    )?;
+
+    let (cleaned, mut report) = clean_text(&text, document.first_page, rules);
+    report.sections = label_sections(&cleaned, document.first_page, book);
+    report.turns = parse_turns(&cleaned, book);
+
+    let output_path: PathBuf = cleaned_text_path(directories, book);
+    fs::create_dir_all(output_path.parent().unwrap())?;
+    fs::write(&output_path, cleaned)?;
+    fs::write(cleaning_report_path(directories, book), report.render(&book.title))?;
+    save_annotations(directories, book, &report.annotations)?;
+    save_sections(directories, book, &report.sections)?;
+    save_turns(directories, book, &report.turns)?;
+
+    log::info!(
+        "Cleaned {}: {} header and footer lines, {} page numbers, {} notes, {} hyphenated words",
+        book.title,
+        report.removed_lines.len() - report.page_numbers,
+        report.page_numbers,
+        report.annotations.len(),
+        report.rejoined_words.len(),
+    );
+    Ok(())
+}
+
+
+// Cleans each page on its own, so that the page separators (and with them the page numbers of the
+// original) survive. The pages are numbered from first_page in the report. Notes are taken out
+// before the text is reflowed, and each keeps the offset into the cleaned text of its anchor.
+pub fn clean_text(text: &str, first_page: i64, rules: &CleaningRules) -> (String, CleaningReport) {
+    let mut report = CleaningReport::default();
+    let pages: Vec<Vec<&str>> = text.split(PAGE_SEPARATOR).map(|page| page.lines().collect()).collect();
+    let recurring: HashSet<String> = find_recurring_lines(&pages, rules);
+
+    let mut kept_pages: Vec<Vec<String>> = Vec::new();
+    for (index, page) in pages.iter().enumerate() {
+        let edges: HashSet<usize> = edge_line_indices(page, rules.edge_lines);
+        let mut kept: Vec<String> = Vec::new();
+
+        for (line_index, line) in page.iter().enumerate() {
+            let is_edge: bool = edges.contains(&line_index);
+
+            let normalised: String = normalise_line(line);
+
+            if is_edge && recurring.contains(&normalised) {
+                *report.recurring_lines.entry(normalised).or_insert(0) += 1;
+                report.removed_lines.push((first_page + index as i64, line.trim().to_string()));
+            } else if is_edge && rules.remove_page_numbers && is_page_number(line) {
+                report.page_numbers += 1;
+                report.removed_lines.push((first_page + index as i64, line.trim().to_string()));
+            } else {
+                kept.push(line.trim_end().to_string());
+            }
+        }
+
+        kept_pages.push(trim_blank_lines(kept));
+    }
+
+    if rules.separate_notes {
+        report.annotations = separate_notes(&mut kept_pages, first_page);
+    }
+
+    if rules.rejoin_hyphenation {
+        rejoin_hyphenation(&mut kept_pages, &mut report);
+    }
+
+    let typical_width: usize = typical_line_width(&kept_pages);
+    let cleaned_pages: Vec<String> = kept_pages.iter()
+        .map(|page| if rules.reflow {reflow(page, typical_width, &mut report)} else {page.join("\n")})
+        .collect();
+
+    let cleaned: String = resolve_anchors(&cleaned_pages.join(&PAGE_SEPARATOR.to_string()), &mut report.annotations);
+    (cleaned, report)
+}
+
+
+// Each paragraph of cleaned text, with its offset in characters and the index of its page
+pub fn paragraphs(text: &str) -> Vec<(usize, usize, &str)> {
+    let mut paragraphs: Vec<(usize, usize, &str)> = Vec::new();
+    let mut offset: usize = 0;
+
+    for (page, page_text) in text.split(PAGE_SEPARATOR).enumerate() {
+        for paragraph in page_text.split("\n\n") {
+            paragraphs.push((offset, page, paragraph));
+            offset += paragraph.chars().count() + 2;
+        }
+
+        // The last paragraph on a page is followed by a page separator rather than a blank line
+        offset = offset - 2 + 1;
+    }
+
+    paragraphs
+}
+
+
+// Page numbers change from page to page, so digits are masked before lines are compared
+fn normalise_line(line: &str) -> String {
+    let mut normalised: String = String::new();
+
+    for word in line.split_whitespace() {
+        if !normalised.is_empty() {
+            normalised.push(' ');
+        }
+
+        let mut previous_was_digit: bool = false;
+        for character in word.chars().flat_map(char::to_lowercase) {
+            if character.is_ascii_digit() {
+                if !previous_was_digit {
+                    normalised.push('#');
+                }
+                previous_was_digit = true;
+            } else {
+                normalised.push(character);
+                previous_was_digit = false;
+            }
+        }
+    }
+
+    normalised
+}
+
+
+// The first and last few non-blank lines of a page, which is where headers, footers, and page numbers live
+fn edge_line_indices(page: &[&str], edge_lines: usize) -> HashSet<usize> {
+    let non_blank: Vec<usize> = page.iter()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, _)| index)
+        .collect();
+
+    non_blank.iter()
+        .take(edge_lines)
+        .chain(non_blank.iter().rev().take(edge_lines))
+        .copied()
+        .collect()
+}
+
+
+fn find_recurring_lines(pages: &[Vec<&str>], rules: &CleaningRules) -> HashSet<String> {
+    if pages.len() < rules.minimum_pages {
+        return HashSet::new();
+    }
+
+    let mut pages_per_line: HashMap<String, usize> = HashMap::new();
+    for page in pages {
+        let lines: HashSet<String> = edge_line_indices(page, rules.edge_lines)
+            .into_iter()
+            .map(|index| normalise_line(page[index]))
+            // A masked page number on its own is left for is_page_number to deal with
+            .filter(|line| line.chars().any(char::is_alphabetic))
+            .collect();
+
+        for line in lines {
+            *pages_per_line.entry(line).or_insert(0) += 1;
+        }
+    }
+
+    let threshold: f64 = (rules.minimum_recurrence * pages.len() as f64).max(2.0);
+    pages_per_line.into_iter()
+        .filter(|(_, count)| *count as f64 >= threshold)
+        .map(|(line, _)| line)
+        .collect()
+}
+
+
+// Arabic or Roman numerals, optionally decorated as "- 12 -", "[12]", "p. 12", or "Page 12"
+fn is_page_number(line: &str) -> bool {
+    let mut number: &str = line.trim().trim_matches(|c: char| "-–—[]().".contains(c) || c.is_whitespace());
+
+    for prefix in ["page", "Page", "PAGE", "p.", "P."] {
+        if let Some(rest) = number.strip_prefix(prefix) {
+            number = rest.trim();
+        }
+    }
+
+    let is_arabic: bool = !number.is_empty() && number.len() <= 4 && number.chars().all(|c| c.is_ascii_digit());
+    let is_roman: bool = roman_numeral(number).is_some();
+
+    is_arabic || is_roman
+}
+
+
+// The value of a roman numeral written the usual way, in one case, such as "xiv" or "LXII". Words
+// made of the same letters, such as "civil" or "did", are not numerals, and nor is anything past
+// the length of front matter, such as "mix".
+fn roman_numeral(text: &str) -> Option<usize> {
+    const NUMERALS: [(usize, &str); 13] = [
+        (1000, "m"), (900, "cm"), (500, "d"), (400, "cd"), (100, "c"), (90, "xc"),
+        (50, "l"), (40, "xl"), (10, "x"), (9, "ix"), (5, "v"), (4, "iv"), (1, "i"),
+    ];
+
+    let is_one_case: bool = text.chars().all(|c| "ivxlcdm".contains(c)) || text.chars().all(|c| "IVXLCDM".contains(c));
+    if text.is_empty() || !is_one_case {
+        return None;
+    }
+
+    let lower: String = text.to_lowercase();
+    let mut rest: &str = &lower;
+    let mut value: usize = 0;
+    for (amount, numeral) in NUMERALS {
+        // Only m, c, x, and i may be repeated, and at most three times
+        let repeats: usize = if numeral.len() == 1 && "mcxi".contains(numeral) {3} else {1};
+        for _ in 0..repeats {
+            match rest.strip_prefix(numeral) {
+                Some(after) => {
+                    value += amount;
+                    rest = after;
+                },
+                None => break,
+            }
+        }
+    }
+
+    (rest.is_empty() && value < 1000).then_some(value)
+}
+
+
+fn trim_blank_lines(mut lines: Vec<String>) -> Vec<String> {
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+
+    let leading: usize = lines.iter().take_while(|line| line.trim().is_empty()).count();
+    lines.split_off(leading)
+}
+
+
+// Words that were broken across lines are put back together on the first of the two lines.
+// This also works across pages, since the headers that separated the halves are gone by now.
+fn rejoin_hyphenation(pages: &mut [Vec<String>], report: &mut CleaningReport) {
+    let positions: Vec<(usize, usize)> = pages.iter()
+        .enumerate()
+        .flat_map(|(page, lines)| (0..lines.len()).map(move |line| (page, line)))
+        .collect();
+
+    for pair in positions.windows(2) {
+        let ((page, line), (next_page, next_line)) = (pair[0], pair[1]);
+
+        let current: &str = pages[page][line].trim_end();
+        let Some(stem) = current.strip_suffix('-') else { continue };
+        // A dash set off with a space, as in "this -", does not break a word
+        if stem.ends_with(char::is_whitespace) {
+            continue
+        }
+        let Some(fragment) = stem.split_whitespace().last() else { continue };
+
+        let following: String = pages[next_page][next_line].trim_start().to_string();
+        let Some(continuation) = following.split_whitespace().next() else { continue };
+
+        let ends_in_letter: bool = fragment.chars().last().is_some_and(char::is_alphabetic);
+        let continues_in_lowercase: bool = continuation.chars().next().is_some_and(char::is_lowercase);
+        if !ends_in_letter || !continues_in_lowercase {
+            continue
+        }
+
+        let keeps_hyphen: bool = HYPHENATED_PREFIXES.contains(&fragment.to_lowercase().as_str());
+        let rejoined: String = if keeps_hyphen {
+            format!("{}-{}", fragment, continuation)
+        } else {
+            format!("{}{}", fragment, continuation)
+        };
+
+        report.rejoined_words.push((format!("{}-{}", fragment, continuation), rejoined.clone()));
+        pages[page][line] = format!("{}{}", &stem[..stem.len() - fragment.len()], rejoined);
+        pages[next_page][next_line] = following[continuation.len()..].trim_start().to_string();
+    }
+}
+
+
+// The median length of the lines that make up running text, which is how wide a full line is
+fn typical_line_width(pages: &[Vec<String>]) -> usize {
+    let mut widths: Vec<usize> = pages.iter()
+        .flatten()
+        .map(|line| line.trim().chars().count())
+        .filter(|width| *width > 20)
+        .collect();
+
+    widths.sort_unstable();
+    widths.get(widths.len() / 2).copied().unwrap_or(0)
+}
+
+
+pub fn is_heading(line: &str) -> bool {
+    line.chars().count() < 60
+        && line.chars().any(char::is_alphabetic)
+        && !line.chars().any(char::is_lowercase)
+}
+
+
+// Hard-wrapped lines are joined into paragraphs. A paragraph ends at a blank line, at a heading, before
+// a speaker's label, or after a line that finishes a sentence well short of the full width of the page.
+fn reflow(lines: &[String], typical_width: usize, report: &mut CleaningReport) -> String {
+    let mut paragraphs: Vec<String> = Vec::new();
+    let mut current: String = String::new();
+
+    for line in lines {
+        let trimmed: &str = line.trim();
+
+        if trimmed.is_empty() || is_heading(trimmed) {
+            if !current.is_empty() {
+                paragraphs.push(std::mem::take(&mut current));
+            }
+            if !trimmed.is_empty() {
+                paragraphs.push(trimmed.to_string());
+            }
+            continue
+        }
+
+        // Each speaker in a dialogue starts a paragraph of their own
+        if speaker_label(trimmed).is_some() && !current.is_empty() {
+            paragraphs.push(std::mem::take(&mut current));
+        }
+
+        if current.is_empty() {
+            current.push_str(trimmed);
+        } else {
+            current.push(' ');
+            current.push_str(trimmed);
+            report.reflowed_lines += 1;
+        }
+
+        let ends_sentence: bool = trimmed.ends_with(['.', '?', '!', ':', '"', '”', ')']);
+        let is_short: bool = (trimmed.chars().count() as f64) < typical_width as f64 * 0.8;
+        if ends_sentence && is_short {
+            paragraphs.push(std::mem::take(&mut current));
+        }
+    }
+
+    if !current.is_empty() {
+        paragraphs.push(current);
+    }
+
+    paragraphs.join("\n\n")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejoined(text: &str) -> Vec<String> {
+        let mut pages: Vec<Vec<String>> = vec![text.lines().map(String::from).collect()];
+        rejoin_hyphenation(&mut pages, &mut CleaningReport::default());
+        pages.remove(0)
+    }
+
+    #[test]
+    fn rejoins_words_broken_across_lines() {
+        assert_eq!(rejoined("the revo-\nlution is here"), vec!["the revolution", "is here"]);
+        assert_eq!(rejoined("a self-\nevident truth"), vec!["a self-evident", "truth"]);
+    }
+
+    #[test]
+    fn leaves_spaced_dashes_alone() {
+        assert_eq!(rejoined("some text -\nand more here"), vec!["some text -", "and more here"]);
+        assert_eq!(rejoined("x the élite -\nand more here"), vec!["x the élite -", "and more here"]);
+    }
+
+    #[test]
+    fn rejoins_words_with_accented_letters() {
+        assert_eq!(rejoined("the proletariat's élé-\nments are"), vec!["the proletariat's éléments", "are"]);
+        assert_eq!(rejoined("x the éli-\nte of them"), vec!["x the élite", "of them"]);
+    }
+
+    #[test]
+    fn recognizes_page_numbers() {
+        for line in ["12", "- 12 -", "[304]", "Page 7", "p. 18", "xiv", "lxii", "XLIX", "(iii)"] {
+            assert!(is_page_number(line), "{} is a page number", line);
+        }
+        for line in ["mix", "civil", "did", "mild", "dim", "lid", "iiii", "vv", "Xiv", "mcm", "12345", "chapter"] {
+            assert!(!is_page_number(line), "{} is not a page number", line);
+        }
+    }
+}
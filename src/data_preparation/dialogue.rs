@@ -0,0 +1,246 @@
+use std::fs;
+use std::path::PathBuf;
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::setup::paths::Directories;
+use crate::data_preparation::books::Book;
+use crate::data_preparation::cleaning::{is_heading, paragraphs};
+
+const INTERVIEWER_LABELS: [&str; 4] = ["q", "question", "interviewer", "journalist"];
+const SUBJECT_LABELS: [&str; 2] = ["a", "answer"];
+
+// Capitalised words that end in a colon at the start of a paragraph without being anyone's name
+const NOT_SPEAKERS: [&str; 10] = ["note", "notes", "chapter", "book", "part", "section", "source", "translator", "editor", "see"];
+
+// Outside of interviews, a label has to recur this often before it is taken to be a speaker
+const MINIMUM_TURNS_PER_SPEAKER: usize = 3;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    // The figure whose works these are, answering questions or speaking in their own name
+    Subject,
+    Interviewer,
+    // Anyone else, which includes every character in a dialogue such as Plato's
+    Other,
+}
+
+
+// One speaker's uninterrupted contribution to a dialogue or an interview
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Turn {
+    pub speaker: String,
+    pub role: Role,
+    // Offsets in characters into the cleaned text, end exclusive. The speaker's label is included.
+    pub start: usize,
+    pub end: usize,
+}
+
+
+pub fn turns_path(directories: &Directories, book: &Book) -> PathBuf {
+    directories.cleaned.join(&book.author).join(format!("{}.turns.json", book.slug))
+}
+
+
+pub fn save_turns(directories: &Directories, book: &Book, turns: &[Turn]) -> Result<(), anyhow::Error> {
+    fs::write(turns_path(directories, book), serde_json::to_string_pretty(turns)?)?;
+    Ok(())
+}
+
+
+// Books that are not dialogues, or were cleaned before turns were parsed, have no turns
+pub fn load_turns(directories: &Directories, book: &Book) -> Result<Vec<Turn>, anyhow::Error> {
+    let path: PathBuf = turns_path(directories, book);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+
+// The turn that a character offset falls into
+pub fn turn_at(turns: &[Turn], offset: usize) -> Option<&Turn> {
+    turns.iter().find(|turn| turn.start <= offset && offset < turn.end)
+}
+
+
+// The speaker that a paragraph (or line) opens with, as in "SOCRATES: ...", "GLAUCON. ...", "Q: ...",
+// or "Edgar Snow: ...", together with whatever they said
+pub fn speaker_label(line: &str) -> Option<(String, &str)> {
+    let line: &str = line.trim_start();
+    let end: usize = line.find([':', '.'])?;
+    let (label, rest) = (line[..end].trim(), &line[end + 1..]);
+
+    if !rest.starts_with(char::is_whitespace) || rest.trim().is_empty() {
+        return None;
+    }
+
+    let words: Vec<&str> = label.split_whitespace().collect();
+    let lowercase: String = label.to_lowercase();
+
+    let is_question_or_answer: bool = INTERVIEWER_LABELS.contains(&lowercase.as_str()) || SUBJECT_LABELS.contains(&lowercase.as_str());
+    let is_uppercase_name: bool = label.chars().filter(|c| c.is_alphabetic()).count() >= 3
+        && !label.chars().any(char::is_lowercase)
+        && !label.chars().all(|c| "IVXLCDM ".contains(c));
+    let is_titlecase_name: bool = line.as_bytes()[end] == b':'
+        && (1..=3).contains(&words.len())
+        && words.iter().all(|word| word.starts_with(char::is_uppercase) && word.chars().all(|c| c.is_alphabetic() || c == '-'));
+
+    // A full stop only ends a label that is a single word, since names do not run on into sentences
+    let ends_properly: bool = line.as_bytes()[end] == b':' || words.len() == 1;
+    let is_excluded: bool = words.first().is_some_and(|word| NOT_SPEAKERS.contains(&word.to_lowercase().as_str()));
+
+    if ends_properly && !is_excluded && (is_question_or_answer || is_uppercase_name || is_titlecase_name) {
+        Some((label.to_string(), rest.trim_start()))
+    } else {
+        None
+    }
+}
+
+
+fn role_of(speaker: &str, author: &str) -> Role {
+    let speaker: String = speaker.to_lowercase();
+    let author_words: Vec<String> = author.split_whitespace().map(str::to_lowercase).collect();
+
+    if INTERVIEWER_LABELS.contains(&speaker.as_str()) {
+        Role::Interviewer
+    } else if SUBJECT_LABELS.contains(&speaker.as_str()) || speaker.split_whitespace().any(|word| author_words.iter().any(|name| name == word)) {
+        Role::Subject
+    } else {
+        Role::Other
+    }
+}
+
+
+// Splits a dialogue or an interview into turns. A turn runs from a paragraph that opens with a
+// speaker's label until the next such paragraph or the next heading. Books that are not marked as
+// interviews in the catalog are still parsed if they read like dialogues (as Plato's do), but only
+// if at least two speakers recur.
+pub fn parse_turns(text: &str, book: &Book) -> Vec<Turn> {
+    let paragraphs: Vec<(usize, usize, &str)> = paragraphs(text);
+    let labels: Vec<Option<String>> = paragraphs.iter()
+        .map(|(_, _, paragraph)| speaker_label(paragraph).map(|(label, _)| label))
+        .collect();
+
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for label in labels.iter().flatten() {
+        *counts.entry(label.as_str()).or_insert(0) += 1;
+    }
+
+    let minimum: usize = if book.is_interview {1} else {MINIMUM_TURNS_PER_SPEAKER};
+    let recurring: usize = counts.values().filter(|count| **count >= minimum).count();
+
+    if recurring == 0 || (!book.is_interview && recurring < 2) {
+        return Vec::new();
+    }
+
+    // Once it is clear that this is a dialogue, minor characters who only speak once or twice are
+    // let in too, as long as their labels are in capitals and so unlikely to be anything else
+    let speakers: Vec<&str> = counts.iter()
+        .filter(|(label, count)| **count >= minimum || !label.chars().any(char::is_lowercase))
+        .map(|(label, _)| *label)
+        .collect();
+
+    let mut turns: Vec<Turn> = Vec::new();
+    let end_of_text: usize = text.chars().count();
+
+    for ((start, _, paragraph), label) in paragraphs.iter().zip(&labels) {
+        let speaker: Option<&String> = label.as_ref().filter(|label| speakers.contains(&label.as_str()));
+
+        if speaker.is_some() || is_heading(paragraph.trim()) {
+            if let Some(turn) = turns.last_mut().filter(|turn| turn.end == end_of_text) {
+                turn.end = *start;
+            }
+        }
+
+        if let Some(speaker) = speaker {
+            turns.push(Turn { speaker: speaker.clone(), role: role_of(speaker, &book.author), start: *start, end: end_of_text });
+        }
+    }
+
+    // Trailing separators are not part of what anyone said
+    let characters: Vec<char> = text.chars().collect();
+    for turn in turns.iter_mut() {
+        while turn.end > turn.start && characters[turn.end - 1].is_whitespace() {
+            turn.end -= 1;
+        }
+    }
+
+    turns
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indices::testing::book;
+
+    const REPUBLIC: &str = "BOOK I\n\n\
+        SOCRATES: I went down yesterday to the Piraeus with Glaucon the son of Ariston.\n\n\
+        GLAUCON: And what did you see there?\n\n\
+        SOCRATES: I was delighted with the procession of the inhabitants.\n\n\
+        GLAUCON. Then you must stay with us.\n\n\
+        POLEMARCHUS: You see that we are many, and we will not let you go.\n\n\
+        SOCRATES: Very well, I said.\n\n\
+        GLAUCON: And so we shall remain.\n\n\
+        BOOK II\n\n\
+        With these words I was thinking that I had made an end of the discussion.";
+
+    fn label(line: &str) -> Option<String> {
+        speaker_label(line).map(|(label, _)| label)
+    }
+
+    #[test]
+    fn finds_the_speakers_of_dialogues_and_interviews() {
+        assert_eq!(label("SOCRATES: I went down yesterday").as_deref(), Some("SOCRATES"));
+        assert_eq!(label("GLAUCON. And what did you see there?").as_deref(), Some("GLAUCON"));
+        assert_eq!(label("Q. What do you think of nonviolence?").as_deref(), Some("Q"));
+        assert_eq!(label("A: It is the law of our species.").as_deref(), Some("A"));
+        assert_eq!(label("Edgar Snow: Where were you born?").as_deref(), Some("Edgar Snow"));
+        assert_eq!(speaker_label("SOCRATES: I went down").map(|(_, rest)| rest), Some("I went down"));
+    }
+
+    #[test]
+    fn headings_are_not_speakers() {
+        assert_eq!(label("CHAPTER I. The Commodity"), None);
+        assert_eq!(label("NOTE: The text follows the first edition."), None);
+        assert_eq!(label("XIV. Of the division of labour"), None);
+        assert_eq!(label("BOOK II"), None);
+        assert_eq!(label("In the morning. We left early"), None);
+    }
+
+    #[test]
+    fn splits_a_dialogue_into_turns() {
+        let turns: Vec<Turn> = parse_turns(REPUBLIC, &book("Plato", "The Republic"));
+        let characters: Vec<char> = REPUBLIC.chars().collect();
+
+        let speakers: Vec<&str> = turns.iter().map(|turn| turn.speaker.as_str()).collect();
+        assert_eq!(speakers, ["SOCRATES", "GLAUCON", "SOCRATES", "GLAUCON", "POLEMARCHUS", "SOCRATES", "GLAUCON"]);
+        assert!(turns.iter().all(|turn| turn.role == Role::Other));
+
+        // The heading ends the last turn, and the narration after it belongs to no one
+        let last: String = characters[turns[6].start..turns[6].end].iter().collect();
+        assert_eq!(last, "GLAUCON: And so we shall remain.");
+        assert!(turn_at(&turns, REPUBLIC.chars().count() - 1).is_none());
+    }
+
+    #[test]
+    fn a_dialogue_needs_recurring_speakers() {
+        let text: &str = "SOCRATES: I went down yesterday to the Piraeus.\n\nThe procession was a fine one.";
+        assert!(parse_turns(text, &book("Plato", "The Republic")).is_empty());
+    }
+
+    #[test]
+    fn tells_the_interviewer_from_the_subject() {
+        let mut interview: Book = book("Mahatma Gandhi", "An Interview");
+        interview.is_interview = true;
+        let text: &str = "Q. What is your view of the machine?\n\nA. I am not against machinery as such.\n\nQ: And the spinning wheel?\n\nA: It is a symbol of self-reliance.";
+
+        let roles: Vec<Role> = parse_turns(text, &interview).iter().map(|turn| turn.role).collect();
+        assert_eq!(roles, [Role::Interviewer, Role::Subject, Role::Interviewer, Role::Subject]);
+    }
+}
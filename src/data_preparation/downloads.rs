@@ -1,17 +1,30 @@
+
 // Learning Note: The structs that I have created for each source needs to implement
 // the Clone trait because I will be dealing with a "level 2" aggregate type that will need 
 // to implement the trait. 
 
-use crate::sources::authors::prepare_sources;
-use crate::setup::paths::make_fundamental_directories;
+use crate::sources::authors::{prepare_sources, DownloadOptions};
+use crate::data_preparation::planning::{print_plan, AuthorPlan};
+use crate::setup::paths::{make_fundamental_directories, Directories};
+
+
+pub async fn download_all_texts(directories: &Directories, options: &DownloadOptions) {
 
+    if options.dry_run {
+        let mut plans: Vec<AuthorPlan> = Vec::new();
+        for author in prepare_sources().iter().filter(|author| options.selection.includes_author(&author.name)) {
+            plans.push(author.plan_books(directories, options).await);
+        }
 
-pub async fn download_all_texts() {
+        print_plan(&plans);
+        return
+    }
     
-    make_fundamental_directories();
+    make_fundamental_directories(directories);
     for author in prepare_sources() {
-        author.download_books().await;
+        if options.selection.includes_author(&author.name) {
+            author.download_books(directories, options).await;
+        }
     }
 
 }
-
@@ -0,0 +1,456 @@
+use std::sync::Mutex;
+use std::path::{Path, PathBuf};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use serde::{Deserialize, Serialize};
+use rusqlite::{params, Connection, OptionalExtension};
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config as BertConfig, DTYPE};
+use ort::session::Session;
+use ort::value::Tensor as OrtTensor;
+use tokenizers::{PaddingParams, PaddingStrategy, Tokenizer, TruncationParams};
+
+use crate::setup::paths::Directories;
+use crate::data_preparation::books::Book;
+use crate::sources::manifest::{hash_bytes, hash_file};
+use crate::data_preparation::chunking::{chunks_path, load_chunks, Chunk};
+use crate::data_preparation::state::{hash_config, Stage, StateStore};
+
+pub static EMBEDDINGS_FILE_NAME: &str = "embeddings.sqlite";
+
+// Bumped whenever the way that vectors are computed changes, so that every book is embedded again
+pub const EMBEDDING_VERSION: u32 = 2;
+
+const MODEL_CONFIG_FILE_NAME: &str = "config.json";
+const TOKENIZER_FILE_NAME: &str = "tokenizer.json";
+const WEIGHTS_FILE_NAME: &str = "model.safetensors";
+const ONNX_FILE_NAME: &str = "model.onnx";
+
+
+// The [embedding] section of giants.toml. The model is the name of a directory beneath the models
+// directory, holding a BERT-style sentence embedding model as exported by sentence-transformers.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmbeddingConfig {
+    pub model: String,
+    pub batch_size: usize,
+    // How many threads inference may use, or 0 for one per core
+    pub threads: usize,
+    // Longer passages are truncated, in the model's own tokens
+    pub maximum_length: usize,
+    pub normalize: bool,
+    // Some models (such as E5) expect passages and queries to be marked as such
+    pub passage_prefix: String,
+    pub query_prefix: String,
+}
+
+
+impl Default for EmbeddingConfig {
+    fn default() -> Self {
+        Self {
+            model: String::from("all-MiniLM-L6-v2"),
+            batch_size: 32,
+            threads: 0,
+            maximum_length: 256,
+            normalize: true,
+            passage_prefix: String::new(),
+            query_prefix: String::new(),
+        }
+    }
+}
+
+
+pub fn model_directory(directories: &Directories, config: &EmbeddingConfig) -> PathBuf {
+    directories.models.join(&config.model)
+}
+
+
+// Safetensors weights are preferred to ONNX ones when a model has both
+fn weights_path(directory: &Path) -> Result<PathBuf, anyhow::Error> {
+    [WEIGHTS_FILE_NAME, ONNX_FILE_NAME].iter()
+        .map(|name| directory.join(name))
+        .find(|path| path.exists())
+        .ok_or_else(|| anyhow::anyhow!("There is no embedding model at {} (it needs {}, {}, and either {} or {})",
+            directory.display(), MODEL_CONFIG_FILE_NAME, TOKENIZER_FILE_NAME, WEIGHTS_FILE_NAME, ONNX_FILE_NAME))
+}
+
+
+// Identifies the model by its name and its weights, so that replacing the files under the same
+// name still invalidates every cached vector
+pub fn model_id(directories: &Directories, config: &EmbeddingConfig) -> Result<String, anyhow::Error> {
+    let weights: PathBuf = weights_path(&model_directory(directories, config))?;
+    Ok(format!("{}@{}", config.model, &hash_file(&weights)?[..12]))
+}
+
+
+// What vectors are cached under: the model, and the settings that change what it computes from the
+// same text
+pub fn vectors_id(model_id: &str, config: &EmbeddingConfig) -> String {
+    let settings: String = hash_config(&(config.maximum_length, config.normalize, &config.passage_prefix, EMBEDDING_VERSION));
+    format!("{}+{}", model_id, &settings[..12])
+}
+
+
+// Safetensors weights run on candle. ONNX weights run on ONNX Runtime, which is loaded when the
+// model is, from the library that ORT_DYLIB_PATH names or else from the system's libonnxruntime.
+enum Network {
+    Candle(Box<BertModel>),
+    // Running a session needs it exclusively
+    Onnx(Mutex<Session>),
+}
+
+
+fn load_onnx(path: &Path, threads: usize) -> Result<Session, anyhow::Error> {
+    // ort panics rather than failing when it cannot find the runtime
+    let session = catch_unwind(AssertUnwindSafe(|| -> Result<Session, ort::Error> {
+        let mut builder = Session::builder()?;
+        if threads > 0 {
+            builder = builder.with_intra_threads(threads)?;
+        }
+        builder.commit_from_file(path)
+    }));
+
+    match session {
+        Ok(session) => Ok(session?),
+        Err(_) => anyhow::bail!("Unable to load ONNX Runtime for {} (is ORT_DYLIB_PATH set?)", path.display()),
+    }
+}
+
+
+// A sentence embedding model running on the CPU. Everything is read from the models directory, so
+// nothing is downloaded at inference time.
+pub struct EmbeddingModel {
+    pub id: String,
+    // The model and its settings, as its vectors are cached under
+    pub vectors_id: String,
+    pub dimensions: usize,
+    config: EmbeddingConfig,
+    network: Network,
+    tokenizer: Tokenizer,
+    device: Device,
+    // Inference runs on a pool of its own when the number of threads is configured
+    pool: Option<rayon::ThreadPool>,
+}
+
+
+impl EmbeddingModel {
+
+    pub fn load(directories: &Directories, config: &EmbeddingConfig) -> Result<Self, anyhow::Error> {
+        let id: String = model_id(directories, config)?;
+        let directory: PathBuf = model_directory(directories, config);
+
+        let pool: Option<rayon::ThreadPool> = match config.threads {
+            0 => None,
+            threads => Some(rayon::ThreadPoolBuilder::new().num_threads(threads).thread_name(|index| format!("embedding-{}", index)).build()?),
+        };
+
+        let device = Device::Cpu;
+        let bert_config: BertConfig = serde_json::from_str(&std::fs::read_to_string(directory.join(MODEL_CONFIG_FILE_NAME))?)?;
+
+        let weights: PathBuf = weights_path(&directory)?;
+        let network: Network = if weights.ends_with(ONNX_FILE_NAME) {
+            Network::Onnx(Mutex::new(load_onnx(&weights, config.threads)?))
+        } else {
+            // Safety: the weights are memory mapped, and must not be modified while the model is loaded
+            let variables = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DTYPE, &device)? };
+            Network::Candle(Box::new(BertModel::load(variables, &bert_config)?))
+        };
+
+        let mut tokenizer: Tokenizer = Tokenizer::from_file(directory.join(TOKENIZER_FILE_NAME)).map_err(anyhow::Error::msg)?;
+        tokenizer.with_padding(Some(PaddingParams { strategy: PaddingStrategy::BatchLongest, ..PaddingParams::default() }));
+        tokenizer.with_truncation(Some(TruncationParams { max_length: config.maximum_length, ..TruncationParams::default() }))
+            .map_err(anyhow::Error::msg)?;
+
+        log::info!("Loaded embedding model {}", id);
+        let vectors_id: String = vectors_id(&id, config);
+        Ok(Self { id, vectors_id, dimensions: bert_config.hidden_size, config: config.clone(), network, tokenizer, device, pool })
+    }
+
+    // Candle's kernels run on whichever rayon pool they are called from
+    fn run<T: Send>(&self, work: impl FnOnce() -> T + Send) -> T {
+        match &self.pool {
+            Some(pool) => pool.install(work),
+            None => work(),
+        }
+    }
+
+    pub fn embed_passages(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, anyhow::Error> {
+        let texts: Vec<String> = texts.iter().map(|text| format!("{}{}", self.config.passage_prefix, text)).collect();
+
+        self.run(|| {
+            let mut vectors: Vec<Vec<f32>> = Vec::with_capacity(texts.len());
+            for batch in texts.chunks(self.config.batch_size.max(1)) {
+                vectors.extend(self.embed_batch(batch)?);
+            }
+            Ok(vectors)
+        })
+    }
+
+    pub fn embed_query(&self, query: &str) -> Result<Vec<f32>, anyhow::Error> {
+        let query: String = format!("{}{}", self.config.query_prefix, query);
+        self.run(|| Ok(self.embed_batch(&[query])?.remove(0)))
+    }
+
+    // Mean pooling over the tokens that are not padding, as sentence-transformers does
+    fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, anyhow::Error> {
+        let encodings = self.tokenizer.encode_batch(texts.to_vec(), true).map_err(anyhow::Error::msg)?;
+
+        let rows = |values: fn(&tokenizers::Encoding) -> &[u32]| -> Result<Tensor, anyhow::Error> {
+            let rows: Vec<Tensor> = encodings.iter()
+                .map(|encoding| Tensor::new(values(encoding), &self.device))
+                .collect::<Result<_, _>>()?;
+            Ok(Tensor::stack(&rows, 0)?)
+        };
+
+        let input_ids: Tensor = rows(|encoding| encoding.get_ids())?;
+        let type_ids: Tensor = rows(|encoding| encoding.get_type_ids())?;
+        let attention_mask: Tensor = rows(|encoding| encoding.get_attention_mask())?;
+
+        let hidden: Tensor = match &self.network {
+            Network::Candle(model) => model.forward(&input_ids, &type_ids, Some(&attention_mask))?,
+            Network::Onnx(session) => self.run_onnx(&mut session.lock().unwrap(), &input_ids, &type_ids, &attention_mask)?,
+        };
+        let mask: Tensor = attention_mask.to_dtype(DType::F32)?.unsqueeze(2)?;
+        let summed: Tensor = hidden.broadcast_mul(&mask)?.sum(1)?;
+        let mut pooled: Tensor = summed.broadcast_div(&mask.sum(1)?.clamp(1e-9, f32::MAX)?)?;
+
+        if self.config.normalize {
+            let norms: Tensor = pooled.sqr()?.sum_keepdim(1)?.sqrt()?.clamp(1e-12, f32::MAX)?;
+            pooled = pooled.broadcast_div(&norms)?;
+        }
+
+        Ok(pooled.to_vec2::<f32>()?)
+    }
+
+    // The hidden states of the model's first output. Only the inputs that the model declares are
+    // given to it, since not every export takes token type ids.
+    fn run_onnx(&self, session: &mut Session, input_ids: &Tensor, type_ids: &Tensor, attention_mask: &Tensor) -> Result<Tensor, anyhow::Error> {
+        let mut inputs: Vec<(String, OrtTensor<i64>)> = Vec::new();
+        for input in &session.inputs {
+            let values: &Tensor = match input.name.as_str() {
+                "input_ids" => input_ids,
+                "token_type_ids" => type_ids,
+                "attention_mask" => attention_mask,
+                name => anyhow::bail!("The ONNX model takes an input, {}, that is not known", name),
+            };
+            let shape: Vec<usize> = values.dims().to_vec();
+            let values: Vec<i64> = values.to_dtype(DType::I64)?.flatten_all()?.to_vec1::<i64>()?;
+            inputs.push((input.name.clone(), OrtTensor::from_array((shape, values))?));
+        }
+
+        let outputs = session.run(inputs)?;
+        let (shape, values) = outputs[0].try_extract_tensor::<f32>()?;
+        let shape: Vec<usize> = shape.iter().map(|dimension| *dimension as usize).collect();
+        anyhow::ensure!(shape.len() == 3, "The ONNX model returned {} dimensions instead of token states", shape.len());
+
+        Ok(Tensor::from_slice(values, shape, &self.device)?)
+    }
+}
+
+
+// Every vector that has been computed, keyed by chunk id and vectors id. A chunk's id changes with its
+// text, so only new or changed chunks need to be embedded when a book is chunked again.
+pub struct EmbeddingStore {
+    connection: Connection,
+}
+
+
+impl EmbeddingStore {
+
+    pub fn open(directories: &Directories) -> Result<Self, anyhow::Error> {
+        let path: PathBuf = directories.data.join(EMBEDDINGS_FILE_NAME);
+        std::fs::create_dir_all(&directories.data)?;
+        let connection = Connection::open(&path)?;
+
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS embeddings (
+                chunk_id TEXT NOT NULL,
+                model_id TEXT NOT NULL,
+                dimensions INTEGER NOT NULL,
+                vector BLOB NOT NULL,
+                PRIMARY KEY (chunk_id, model_id)
+            );"
+        )?;
+
+        Ok(Self { connection })
+    }
+
+    pub fn contains(&self, chunk_id: &str, vectors_id: &str) -> Result<bool, anyhow::Error> {
+        let found: Option<i64> = self.connection.query_row(
+            "SELECT 1 FROM embeddings WHERE chunk_id = ?1 AND model_id = ?2",
+            params![chunk_id, vectors_id],
+            |row| row.get(0),
+        ).optional()?;
+
+        Ok(found.is_some())
+    }
+
+    pub fn get(&self, chunk_id: &str, vectors_id: &str) -> Result<Option<Vec<f32>>, anyhow::Error> {
+        let bytes: Option<Vec<u8>> = self.connection.query_row(
+            "SELECT vector FROM embeddings WHERE chunk_id = ?1 AND model_id = ?2",
+            params![chunk_id, vectors_id],
+            |row| row.get(0),
+        ).optional()?;
+
+        Ok(bytes.map(|bytes| decode_vector(&bytes)))
+    }
+
+    pub fn insert(&mut self, vectors_id: &str, vectors: &[(&str, Vec<f32>)]) -> Result<(), anyhow::Error> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT OR REPLACE INTO embeddings (chunk_id, model_id, dimensions, vector) VALUES (?1, ?2, ?3, ?4)"
+            )?;
+            for (chunk_id, vector) in vectors {
+                statement.execute(params![chunk_id, vectors_id, vector.len() as i64, encode_vector(vector)])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+
+fn encode_vector(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+
+fn decode_vector(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4)
+        .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+        .collect()
+}
+
+
+// What the embedding stage produced for a book: its chunks, under a particular model and settings
+fn embedded_output_hash(chunks: &[Chunk], vectors_id: &str) -> String {
+    let ids: Vec<&str> = chunks.iter().map(|chunk| chunk.id.as_str()).collect();
+    hash_bytes(format!("{}\n{}", vectors_id, ids.join("\n")).as_bytes())
+}
+
+
+pub fn embed_books(directories: &Directories, state: &StateStore, config: &EmbeddingConfig, books: &[Book], force: bool, dry_run: bool) {
+    let model_id: String = match model_id(directories, config) {
+        Ok(id) => id,
+        Err(e) => {
+            log::error!("Unable to embed anything: {}", e);
+            return
+        }
+    };
+
+    let mut store: EmbeddingStore = match EmbeddingStore::open(directories) {
+        Ok(store) => store,
+        Err(e) => {
+            log::error!("Unable to open the embedding cache: {}", e);
+            return
+        }
+    };
+
+    // The batch size and thread count only affect speed, so they are left out of the hash
+    let config_hash: String = hash_config(&(&model_id, config.maximum_length, config.normalize, &config.passage_prefix, EMBEDDING_VERSION));
+    // Loaded on first use, so that a run with nothing to do does not pay for it
+    let mut model: Option<EmbeddingModel> = None;
+
+    for book in kdam::tqdm!(books.iter(), desc="Embedding") {
+        let input_path: PathBuf = chunks_path(directories, book);
+
+        let input_hash: String = match state.input_hash(book, Stage::Embedding, &input_path) {
+            Ok(hash) => hash,
+            Err(e) => {
+                log::error!("Unable to embed {} (has it been chunked?): {}", book.title, e);
+                continue
+            }
+        };
+
+        if !force && state.is_current(book, Stage::Embedding, &input_hash, &config_hash) {
+            continue
+        }
+
+        if dry_run {
+            log::info!("Would embed the chunks of {} with {}", book.title, model_id);
+            continue
+        }
+
+        if model.is_none() {
+            match EmbeddingModel::load(directories, config) {
+                Ok(loaded) => model = Some(loaded),
+                Err(e) => {
+                    log::error!("Unable to load the embedding model: {}", e);
+                    return
+                }
+            }
+        }
+
+        let result: Result<(), anyhow::Error> = embed_book(directories, &mut store, model.as_ref().unwrap(), book, &input_path, force)
+            .and_then(|output_hash| state.record(book, Stage::Embedding, &input_hash, &config_hash, &output_hash));
+
+        if let Err(e) = result {
+            log::error!("Unable to embed {}: {}", book.title, e);
+        }
+    }
+}
+
+
+// Embeds whichever of the book's chunks are not already in the cache, and returns the stage's output hash
+fn embed_book(
+    directories: &Directories,
+    store: &mut EmbeddingStore,
+    model: &EmbeddingModel,
+    book: &Book,
+    input_path: &Path,
+    force: bool,
+) -> Result<String, anyhow::Error> {
+    anyhow::ensure!(input_path.exists(), "{} has not been chunked", book.title);
+    let chunks: Vec<Chunk> = load_chunks(directories, book)?;
+
+    let mut missing: Vec<&Chunk> = Vec::new();
+    for chunk in &chunks {
+        if force || !store.contains(&chunk.id, &model.vectors_id)? {
+            missing.push(chunk);
+        }
+    }
+
+    // Written a batch at a time, so that an interrupted run keeps what it had finished
+    let batch_size: usize = model.config.batch_size.max(1);
+    for batch in missing.chunks(batch_size) {
+        let texts: Vec<&str> = batch.iter().map(|chunk| chunk.text.as_str()).collect();
+        let vectors: Vec<Vec<f32>> = model.embed_passages(&texts)?;
+        let rows: Vec<(&str, Vec<f32>)> = batch.iter().map(|chunk| chunk.id.as_str()).zip(vectors).collect();
+        store.insert(&model.vectors_id, &rows)?;
+    }
+
+    log::info!("Embedded {} of the {} chunks of {}", missing.len(), chunks.len(), book.title);
+    Ok(embedded_output_hash(&chunks, &model.vectors_id))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indices::testing::{scratch_directories, write_model};
+
+    #[test]
+    fn prefers_safetensors_to_onnx() {
+        let (directories, config) = scratch_directories("embedding-weights");
+        write_model(&directories, &[String::from("value")]);
+        let directory: PathBuf = model_directory(&directories, &config.embedding);
+        let safetensors_id: String = model_id(&directories, &config.embedding).unwrap();
+
+        std::fs::write(directory.join(ONNX_FILE_NAME), b"not a model").unwrap();
+        assert_eq!(model_id(&directories, &config.embedding).unwrap(), safetensors_id);
+        assert!(EmbeddingModel::load(&directories, &config.embedding).is_ok());
+
+        std::fs::remove_file(directory.join(WEIGHTS_FILE_NAME)).unwrap();
+        let onnx_id: String = model_id(&directories, &config.embedding).unwrap();
+        assert_ne!(onnx_id, safetensors_id);
+        assert!(onnx_id.starts_with("tiny@"));
+
+        // Whether or not ONNX Runtime is installed, a broken model is an error rather than a crash
+        assert!(EmbeddingModel::load(&directories, &config.embedding).is_err());
+
+        std::fs::remove_file(directory.join(ONNX_FILE_NAME)).unwrap();
+        assert!(model_id(&directories, &config.embedding).unwrap_err().to_string().contains(ONNX_FILE_NAME));
+    }
+}
@@ -0,0 +1,228 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+
+use crate::setup::paths::Directories;
+use crate::sources::manifest::hash_file;
+use crate::data_preparation::books::Book;
+use crate::data_preparation::state::{hash_config, Stage, StateStore};
+
+// Pages are kept apart with form feeds (as pdftotext and ocrmypdf do), so that later stages can
+// still work out which page of the original any piece of text came from.
+pub const PAGE_SEPARATOR: char = '\x0c';
+
+// Bumped whenever a change to extraction should cause every book to be extracted again
+pub const EXTRACTION_VERSION: u32 = 1;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentFormat {
+    Pdf,
+    Epub,
+    Html,
+    Text,
+}
+
+
+// Written next to each extracted text, so that later stages know where the text came from
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExtractedDocument {
+    pub author: String,
+    pub title: String,
+    pub slug: String,
+    pub source_url: String,
+    pub format: DocumentFormat,
+    pub first_page: i64,
+    pub is_interview: bool,
+}
+
+
+pub fn extracted_text_path(directories: &Directories, book: &Book) -> PathBuf {
+    directories.extracted.join(&book.author).join(format!("{}.txt", book.slug))
+}
+
+
+pub fn extracted_metadata_path(directories: &Directories, book: &Book) -> PathBuf {
+    directories.extracted.join(&book.author).join(format!("{}.json", book.slug))
+}
+
+
+// Books that needed OCR are read from the text that OCR produced, rather than from the original file
+pub fn extraction_input(directories: &Directories, book: &Book) -> PathBuf {
+    if book.needs_ocr {
+        directories.txt_after_ocr.join(&book.author).join(format!("{}.txt", book.slug))
+    } else {
+        book.path(directories)
+    }
+}
+
+
+pub fn extract_books(directories: &Directories, state: &StateStore, books: &[Book], force: bool, dry_run: bool) {
+
+    for book in kdam::tqdm!(books.iter(), desc="Extracting text") {
+        let output_path: PathBuf = extracted_text_path(directories, book);
+        let config_hash: String = hash_config(&(book.start_page, book.end_page, EXTRACTION_VERSION));
+
+        let input_hash: String = match state.input_hash(book, Stage::Extraction, &extraction_input(directories, book)) {
+            Ok(hash) => hash,
+            Err(e) => {
+                log::error!("Unable to extract the text of {}: {}", book.title, e);
+                continue
+            }
+        };
+
+        if !force && output_path.exists() && state.is_current(book, Stage::Extraction, &input_hash, &config_hash) {
+            continue
+        }
+
+        if dry_run {
+            log::info!("Would extract {} to {}", book.title, output_path.display());
+            continue
+        }
+
+        let result: Result<(), anyhow::Error> = extract(directories, book).and_then(
+            |_| state.record(book, Stage::Extraction, &input_hash, &config_hash, &hash_file(&output_path)?)
+        );
+
+        if let Err(e) = result {
+            log::error!("Unable to extract the text of {}: {}", book.title, e);
+        }
+    }
+}
+
+
+pub fn extract(directories: &Directories, book: &Book) -> Result<(), anyhow::Error> {
+    let input_path: PathBuf = extraction_input(directories, book);
+    if !input_path.exists() {
+        anyhow::bail!("{} does not exist (has the OCR stage been run?)", input_path.display());
+    }
+
+    let format: DocumentFormat = detect_format(&input_path)?;
+    let pages: Vec<String> = match format {
+        DocumentFormat::Pdf => read_pdf_pages(&input_path)?,
+        DocumentFormat::Epub => read_epub_chapters(&input_path)?,
+        DocumentFormat::Html => vec![html_to_text(&fs::read_to_string(&input_path)?)],
+        DocumentFormat::Text => fs::read_to_string(&input_path)?
+            .split(PAGE_SEPARATOR)
+            .map(str::to_string)
+            .collect(),
+    };
+
+    let (first_page, selected_pages) = select_pages(book, pages);
+    let text: String = selected_pages.join(&PAGE_SEPARATOR.to_string());
+
+    if text.trim().is_empty() {
+        anyhow::bail!("no text could be found in {}", input_path.display());
+    }
+
+    let document = ExtractedDocument {
+        author: book.author.clone(),
+        title: book.title.clone(),
+        slug: book.slug.clone(),
+        source_url: book.source.url().to_string(),
+        format,
+        first_page,
+        is_interview: book.is_interview,
+    };
+
+    let output_path: PathBuf = extracted_text_path(directories, book);
+    fs::create_dir_all(output_path.parent().unwrap())?;
+    fs::write(&output_path, text)?;
+    fs::write(extracted_metadata_path(directories, book), serde_json::to_string_pretty(&document)?)?;
+    Ok(())
+}
+
+
+// Keeps only the pages between the book's (one-based and inclusive) start and end pages, which
+// is how the catalog leaves out covers, tables of contents, and indices.
+fn select_pages(book: &Book, pages: Vec<String>) -> (i64, Vec<String>) {
+    let page_count: i64 = pages.len() as i64;
+    let start: i64 = book.start_page.unwrap_or(1).max(1);
+    let mut end: i64 = book.end_page.unwrap_or(page_count);
+
+    if end > page_count {
+        log::warn!("{} only has {} pages, but its end page is {}", book.title, page_count, end);
+        end = page_count;
+    }
+
+    let selected: Vec<String> = pages.into_iter()
+        .skip((start - 1) as usize)
+        .take((end - start + 1).max(0) as usize)
+        .collect();
+
+    (start, selected)
+}
+
+
+// Many of the files in the catalog were saved without an extension, so the format is sniffed from the contents
+pub fn detect_format(path: &Path) -> Result<DocumentFormat, anyhow::Error> {
+    let mut header: Vec<u8> = vec![0u8; 512];
+    let bytes_read: usize = fs::File::open(path)?.read(&mut header)?;
+    header.truncate(bytes_read);
+
+    let format: DocumentFormat = if header.starts_with(b"%PDF") {
+        DocumentFormat::Pdf
+    } else if header.starts_with(b"PK") {
+        DocumentFormat::Epub
+    } else if String::from_utf8_lossy(&header).to_lowercase().contains("<html") {
+        DocumentFormat::Html
+    } else {
+        DocumentFormat::Text
+    };
+
+    Ok(format)
+}
+
+
+fn read_pdf_pages(path: &Path) -> Result<Vec<String>, anyhow::Error> {
+    // pdf-extract panics on some malformed PDFs, and one bad book should not bring down the whole run
+    let owned_path: PathBuf = path.to_path_buf();
+    let result = std::panic::catch_unwind(move || pdf_extract::extract_text_by_pages(owned_path));
+
+    match result {
+        Ok(pages) => Ok(pages?),
+        Err(_) => anyhow::bail!("the PDF could not be parsed"),
+    }
+}
+
+
+fn read_epub_chapters(path: &Path) -> Result<Vec<String>, anyhow::Error> {
+    let mut document = epub::doc::EpubDoc::new(path)?;
+    let mut chapters: Vec<String> = Vec::new();
+
+    loop {
+        if let Some((contents, _mime)) = document.get_current_str() {
+            chapters.push(html_to_text(&contents));
+        }
+
+        if !document.go_next() {
+            break
+        }
+    }
+
+    Ok(chapters)
+}
+
+
+// Block-level elements are kept on lines of their own, so that paragraphs survive the conversion
+pub fn html_to_text(html: &str) -> String {
+    let document = Html::parse_document(html);
+    let block_selector = Selector::parse("h1, h2, h3, h4, h5, h6, p, li, pre").unwrap();
+    let mut text: String = String::new();
+
+    for element in document.select(&block_selector) {
+        let block_text: String = element.text().collect();
+        let trimmed: &str = block_text.trim();
+
+        if !trimmed.is_empty() {
+            text.push_str(trimmed);
+            text.push_str("\n\n");
+        }
+    }
+
+    text
+}
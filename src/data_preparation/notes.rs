@@ -0,0 +1,390 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::setup::paths::Directories;
+use crate::data_preparation::books::Book;
+
+// While a book is being cleaned, each anchor that a note was linked to is replaced by its note's id
+// between these two (private use) characters. They are stripped out again once the text is final,
+// which is when the anchor's offset into the text becomes known.
+pub const ANCHOR_START: char = '\u{E000}';
+pub const ANCHOR_END: char = '\u{E001}';
+
+const NOTE_HEADINGS: [&str; 7] = ["notes", "endnotes", "footnotes", "editor's notes", "editorial notes", "notes and references", "references"];
+const SYMBOL_MARKERS: [char; 4] = ['*', '†', '‡', '§'];
+const SUPERSCRIPT_DIGITS: [char; 10] = ['⁰', '¹', '²', '³', '⁴', '⁵', '⁶', '⁷', '⁸', '⁹'];
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoteKind {
+    // Printed at the bottom of the page that refers to it
+    Footnote,
+    // Collected under a heading such as "Notes" at the end of a chapter or of the book
+    Endnote,
+}
+
+
+// A note that was moved out of the author's text. Most of them are the editor's, not the author's.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Annotation {
+    pub id: usize,
+    pub kind: NoteKind,
+    pub marker: String,
+    pub page: i64,
+    pub text: String,
+    // How many characters into the cleaned text the note's marker was, if it could be found
+    pub anchor: Option<usize>,
+}
+
+
+pub fn annotations_path(directories: &Directories, book: &Book) -> PathBuf {
+    directories.cleaned.join(&book.author).join(format!("{}.notes.json", book.slug))
+}
+
+
+pub fn save_annotations(directories: &Directories, book: &Book, annotations: &[Annotation]) -> Result<(), anyhow::Error> {
+    fs::write(annotations_path(directories, book), serde_json::to_string_pretty(annotations)?)?;
+    Ok(())
+}
+
+
+// Books that were cleaned before notes were separated simply have none
+pub fn load_annotations(directories: &Directories, book: &Book) -> Result<Vec<Annotation>, anyhow::Error> {
+    let path: PathBuf = annotations_path(directories, book);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+
+fn marker_digit(character: char) -> Option<char> {
+    if character.is_ascii_digit() {
+        return Some(character);
+    }
+
+    SUPERSCRIPT_DIGITS.iter()
+        .position(|digit| *digit == character)
+        .and_then(|position| char::from_digit(position as u32, 10))
+}
+
+
+fn is_marker(marker: &str) -> bool {
+    let is_number: bool = !marker.is_empty() && marker.len() <= 3 && marker.chars().all(|c| c.is_ascii_digit());
+    let is_symbol: bool = !marker.is_empty() && marker.chars().all(|c| SYMBOL_MARKERS.contains(&c));
+    is_number || is_symbol
+}
+
+
+// The marker that a note begins with, as in "12 See ...", "12. See ...", "[12] See ...", "¹² See ..."
+// or "* See ...", together with the rest of the line
+fn note_start(line: &str) -> Option<(String, &str)> {
+    let trimmed: &str = line.trim_start();
+
+    let (marker, rest): (String, &str) = if let Some(bracketed) = trimmed.strip_prefix('[') {
+        let end: usize = bracketed.find(']')?;
+        (bracketed[..end].trim().to_string(), &bracketed[end + 1..])
+    } else {
+        let end: usize = trimmed.char_indices()
+            .find(|(_, c)| marker_digit(*c).is_none() && !SYMBOL_MARKERS.contains(c))
+            .map(|(index, _)| index)
+            .unwrap_or(trimmed.len());
+
+        let marker: String = trimmed[..end].chars().map(|c| marker_digit(c).unwrap_or(c)).collect();
+        let rest: &str = trimmed[end..].strip_prefix(['.', ')']).unwrap_or(&trimmed[end..]);
+
+        // Without the space, "1917" or "2nd" would count as a marker
+        if !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        (marker, rest)
+    };
+
+    if is_marker(&marker) && rest.chars().any(char::is_alphabetic) {
+        Some((marker, rest.trim()))
+    } else {
+        None
+    }
+}
+
+
+// Where notes are referred to from within a line. An anchor is a bracketed marker ("[12]"), a run
+// of superscript digits, a symbol right after a word ("word*"), or a short run of digits right
+// after a word or a closing punctuation mark ("word.12"). Returns the byte range and the marker.
+fn find_anchors(line: &str) -> Vec<(usize, usize, String)> {
+    let characters: Vec<(usize, char)> = line.char_indices().collect();
+    let mut anchors: Vec<(usize, usize, String)> = Vec::new();
+    let mut index: usize = 0;
+
+    while index < characters.len() {
+        let (start, character) = characters[index];
+        let previous: Option<char> = index.checked_sub(1).map(|previous| characters[previous].1);
+        let follows_word: bool = previous.is_some_and(|c| c.is_lowercase() || ".,;:’”)\"'".contains(c));
+
+        let run_end: usize = characters[index..].iter()
+            .position(|(_, c)| !c.is_ascii_digit())
+            .map(|length| index + length)
+            .unwrap_or(characters.len());
+        let byte_end = |end: usize| characters.get(end).map(|(offset, _)| *offset).unwrap_or(line.len());
+
+        if character == '[' {
+            let close: Option<usize> = characters[index..].iter().position(|(_, c)| *c == ']').map(|length| index + length);
+            if let Some(close) = close {
+                let marker: String = line[byte_end(index + 1)..byte_end(close)].trim().to_string();
+                if is_marker(&marker) {
+                    // Take the space before a bracketed marker with it, so as not to leave two behind
+                    let start: usize = if previous == Some(' ') {characters[index - 1].0} else {start};
+                    anchors.push((start, byte_end(close + 1), marker));
+                    index = close + 1;
+                    continue
+                }
+            }
+        } else if SUPERSCRIPT_DIGITS.contains(&character) {
+            let end: usize = characters[index..].iter()
+                .position(|(_, c)| !SUPERSCRIPT_DIGITS.contains(c))
+                .map(|length| index + length)
+                .unwrap_or(characters.len());
+            let marker: String = characters[index..end].iter().filter_map(|(_, c)| marker_digit(*c)).collect();
+            anchors.push((start, byte_end(end), marker));
+            index = end;
+            continue
+        } else if SYMBOL_MARKERS.contains(&character) && previous.is_some_and(|c| !c.is_whitespace()) {
+            anchors.push((start, byte_end(index + 1), character.to_string()));
+        } else if character.is_ascii_digit() && follows_word && run_end - index <= 3 {
+            let next: Option<char> = characters.get(run_end).map(|(_, c)| *c);
+            if next.is_none_or(|c| c.is_whitespace() || ".,;:)".contains(c)) {
+                anchors.push((start, byte_end(run_end), line[start..byte_end(run_end)].to_string()));
+                index = run_end;
+                continue
+            }
+        }
+
+        index += 1;
+    }
+
+    anchors
+}
+
+
+fn is_note_heading(line: &str) -> bool {
+    let heading: String = line.trim().trim_end_matches(':').to_lowercase();
+    NOTE_HEADINGS.contains(&heading.as_str())
+}
+
+
+// The line at which the page's notes begin, and what kind of notes they are. Endnotes follow a
+// heading of their own. Footnotes are only recognised in the lower part of a page, and only if
+// their marker also appears above them, so that numbered lists are left alone.
+fn find_notes(lines: &[String]) -> Option<(usize, NoteKind)> {
+    let heading: Option<usize> = lines.iter().rposition(|line| is_note_heading(line));
+
+    if let Some(heading) = heading {
+        let first_note: Option<&String> = lines[heading + 1..].iter().find(|line| !line.trim().is_empty());
+        if first_note.is_some_and(|line| note_start(line).is_some()) {
+            return Some((heading, NoteKind::Endnote));
+        }
+    }
+
+    (lines.len() / 3..lines.len())
+        .find(|index| {
+            let Some((marker, _)) = note_start(&lines[*index]) else { return false };
+            lines[..*index].iter().any(|line| find_anchors(line).iter().any(|(_, _, anchor)| *anchor == marker))
+        })
+        .map(|index| (index, NoteKind::Footnote))
+}
+
+
+fn parse_notes(lines: &[String], kind: NoteKind, page: i64, next_id: &mut usize) -> Vec<Annotation> {
+    let mut notes: Vec<Annotation> = Vec::new();
+
+    for line in lines.iter().filter(|line| !line.trim().is_empty()) {
+        match (note_start(line), notes.last_mut()) {
+            (Some((marker, rest)), _) => {
+                notes.push(Annotation { id: *next_id, kind, marker, page, text: rest.to_string(), anchor: None });
+                *next_id += 1;
+            },
+            (None, Some(note)) => {
+                note.text.push(' ');
+                note.text.push_str(line.trim());
+            },
+            (None, None) => {},
+        }
+    }
+
+    notes
+}
+
+
+// Replaces the note's anchor with a placeholder carrying its id. Footnotes are anchored on their
+// own page; endnotes on the nearest earlier anchor with the same marker that is still unclaimed.
+fn link_anchor(pages: &mut [Vec<String>], page: usize, note: &Annotation) {
+    let candidates: Vec<(usize, usize)> = match note.kind {
+        NoteKind::Footnote => (0..pages[page].len()).map(|line| (page, line)).collect(),
+        NoteKind::Endnote => (0..=page).rev()
+            .flat_map(|earlier| (0..pages[earlier].len()).rev().map(move |line| (earlier, line)))
+            .collect(),
+    };
+
+    for (page, line) in candidates {
+        let anchors: Vec<(usize, usize, String)> = find_anchors(&pages[page][line]);
+        let matching = match note.kind {
+            NoteKind::Footnote => anchors.into_iter().find(|(_, _, marker)| *marker == note.marker),
+            NoteKind::Endnote => anchors.into_iter().rev().find(|(_, _, marker)| *marker == note.marker),
+        };
+
+        if let Some((start, end, _)) = matching {
+            let placeholder: String = format!("{}{}{}", ANCHOR_START, note.id, ANCHOR_END);
+            pages[page][line].replace_range(start..end, &placeholder);
+            return
+        }
+    }
+}
+
+
+// Moves footnotes and endnotes out of the pages, leaving placeholders where their anchors were
+pub fn separate_notes(pages: &mut [Vec<String>], first_page: i64) -> Vec<Annotation> {
+    let mut annotations: Vec<Annotation> = Vec::new();
+    let mut next_id: usize = 1;
+
+    for page in 0..pages.len() {
+        let Some((start, kind)) = find_notes(&pages[page]) else { continue };
+
+        let note_lines: Vec<String> = pages[page].split_off(start);
+        let note_lines: &[String] = if kind == NoteKind::Endnote {&note_lines[1..]} else {&note_lines};
+
+        for note in parse_notes(note_lines, kind, first_page + page as i64, &mut next_id) {
+            link_anchor(pages, page, &note);
+            annotations.push(note);
+        }
+
+        while pages[page].last().is_some_and(|line| line.trim().is_empty()) {
+            pages[page].pop();
+        }
+    }
+
+    annotations
+}
+
+
+// Strips the placeholders out of the finished text, recording where each of them was
+pub fn resolve_anchors(text: &str, annotations: &mut [Annotation]) -> String {
+    let mut resolved: String = String::with_capacity(text.len());
+    let mut offset: usize = 0;
+    let mut characters = text.chars();
+
+    while let Some(character) = characters.next() {
+        if character != ANCHOR_START {
+            resolved.push(character);
+            offset += 1;
+            continue
+        }
+
+        let id: String = characters.by_ref().take_while(|c| *c != ANCHOR_END).collect();
+        if let Some(note) = annotations.iter_mut().find(|note| id.parse() == Ok(note.id)) {
+            note.anchor = Some(offset);
+        }
+    }
+
+    resolved
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    // Three pages in the manner of Capital: a footnote at the foot of the first, a bracketed anchor on
+    // the second, and the endnote that it refers to under a heading on the third
+    fn capital() -> Vec<Vec<String>> {
+        vec![
+            page(&[
+                "The wealth of those societies in which the capitalist mode of production",
+                "prevails, presents itself as “an immense accumulation of commodities,”1 its unit",
+                "being a single commodity. Our investigation must therefore begin with the",
+                "analysis of a commodity.",
+                "",
+                "1 Karl Marx, “Zur Kritik der Politischen Oekonomie.” Berlin, 1859, p. 3.",
+            ]),
+            page(&[
+                "A commodity is, in the first place, an object outside us, a thing that by its",
+                "properties satisfies human wants of some sort or another. [2] The nature of",
+                "such wants makes no difference.",
+            ]),
+            page(&[
+                "Every useful thing may be looked at from the two points of view of quality",
+                "and quantity.",
+                "Notes",
+                "2. Desire implies want; it is the appetite of the mind.",
+            ]),
+        ]
+    }
+
+    fn join(pages: &[Vec<String>]) -> String {
+        pages.iter().map(|page| page.join("\n")).collect::<Vec<String>>().join("\x0c")
+    }
+
+    #[test]
+    fn moves_footnotes_and_endnotes_out_of_the_text() {
+        let mut pages: Vec<Vec<String>> = capital();
+        let annotations: Vec<Annotation> = separate_notes(&mut pages, 10);
+
+        let found: Vec<(usize, NoteKind, &str, i64)> = annotations.iter()
+            .map(|note| (note.id, note.kind, note.marker.as_str(), note.page))
+            .collect();
+        assert_eq!(found, [(1, NoteKind::Footnote, "1", 10), (2, NoteKind::Endnote, "2", 12)]);
+        assert_eq!(annotations[0].text, "Karl Marx, “Zur Kritik der Politischen Oekonomie.” Berlin, 1859, p. 3.");
+        assert_eq!(annotations[1].text, "Desire implies want; it is the appetite of the mind.");
+
+        let text: String = join(&pages);
+        assert!(!text.contains("Zur Kritik") && !text.contains("Notes") && !text.contains("appetite"));
+        assert!(text.ends_with("and quantity."));
+    }
+
+    #[test]
+    fn resolves_anchors_to_their_offsets() {
+        let mut pages: Vec<Vec<String>> = capital();
+        let mut annotations: Vec<Annotation> = separate_notes(&mut pages, 1);
+        let text: String = resolve_anchors(&join(&pages), &mut annotations);
+
+        assert!(!text.contains(ANCHOR_START) && !text.contains(ANCHOR_END));
+        assert!(text.contains("commodities,” its unit") && text.contains("another. The nature"));
+
+        let before = |note: &Annotation| -> String { text.chars().take(note.anchor.unwrap()).collect() };
+        assert!(before(&annotations[0]).ends_with("accumulation of commodities,”"));
+        assert!(before(&annotations[1]).ends_with("some sort or another."));
+    }
+
+    #[test]
+    fn leaves_numbers_in_running_text_alone() {
+        let mut pages: Vec<Vec<String>> = vec![page(&[
+            "In 1867 the first volume appeared, and chapter 2 of it was printed with",
+            "£100 of capital; the second edition followed in 1873. The workers toiled",
+            "for 12 hours a day,",
+            "3 days a week at the mill, and 10 hours on the others.",
+        ])];
+        let original: Vec<Vec<String>> = pages.clone();
+
+        assert!(separate_notes(&mut pages, 1).is_empty());
+        assert_eq!(pages, original);
+        assert!(original[0].iter().all(|line| find_anchors(line).is_empty()));
+    }
+
+    #[test]
+    fn recognises_the_kinds_of_anchor() {
+        let markers = |line: &str| -> Vec<String> { find_anchors(line).into_iter().map(|(_, _, marker)| marker).collect() };
+
+        assert_eq!(markers("the value¹² of labour-power"), ["12"]);
+        assert_eq!(markers("the value* of labour-power"), ["*"]);
+        assert_eq!(markers("as Ricardo says.3 But"), ["3"]);
+        assert_eq!(markers("as Ricardo says [14] and"), ["14"]);
+        assert!(markers("in 1848 and [sic] in 2024").is_empty());
+    }
+}
@@ -0,0 +1,80 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::setup::paths::Directories;
+use crate::sources::manifest::hash_file;
+use crate::data_preparation::books::Book;
+use crate::data_preparation::state::{hash_config, Stage, StateStore};
+
+// OCR is delegated to ocrmypdf (which drives tesseract), because there is no OCR engine written in Rust
+// that comes close to it. The sidecar text file is what the extraction stage later reads.
+pub static OCR_PROGRAM: &str = "ocrmypdf";
+
+
+pub fn ocr_pdf_path(directories: &Directories, book: &Book) -> PathBuf {
+    directories.pdfs_after_ocr.join(&book.author).join(format!("{}.pdf", book.slug))
+}
+
+
+pub fn ocr_text_path(directories: &Directories, book: &Book) -> PathBuf {
+    directories.txt_after_ocr.join(&book.author).join(format!("{}.txt", book.slug))
+}
+
+
+pub fn ocr_books(directories: &Directories, state: &StateStore, books: &[Book], force: bool, dry_run: bool) {
+    let config_hash: String = hash_config(&(OCR_PROGRAM, "--force-ocr"));
+
+    for book in books.iter().filter(|book| book.needs_ocr) {
+        let text_path: PathBuf = ocr_text_path(directories, book);
+
+        let input_hash: String = match state.input_hash(book, Stage::Ocr, &book.path(directories)) {
+            Ok(hash) => hash,
+            Err(e) => {
+                log::error!("Unable to run OCR on {}: {}", book.title, e);
+                continue
+            }
+        };
+
+        if !force && text_path.exists() && state.is_current(book, Stage::Ocr, &input_hash, &config_hash) {
+            continue
+        }
+
+        if dry_run {
+            log::info!("Would run OCR on {}, writing its text to {}", book.title, text_path.display());
+            continue
+        }
+
+        log::info!("Running OCR on {}", book.title);
+        let result: Result<(), anyhow::Error> = run_ocr(directories, book).and_then(
+            |_| state.record(book, Stage::Ocr, &input_hash, &config_hash, &hash_file(&text_path)?)
+        );
+
+        if let Err(e) = result {
+            log::error!("Unable to run OCR on {}: {}", book.title, e);
+        }
+    }
+}
+
+
+fn run_ocr(directories: &Directories, book: &Book) -> Result<(), anyhow::Error> {
+    let pdf_path: PathBuf = ocr_pdf_path(directories, book);
+    let text_path: PathBuf = ocr_text_path(directories, book);
+    fs::create_dir_all(pdf_path.parent().unwrap())?;
+    fs::create_dir_all(text_path.parent().unwrap())?;
+
+    let status = Command::new(OCR_PROGRAM)
+        .arg("--force-ocr")
+        .arg("--sidecar")
+        .arg(&text_path)
+        .arg(book.path(directories))
+        .arg(&pdf_path)
+        .status()
+        .map_err(|e| anyhow::anyhow!("could not start {} ({}). Is it installed?", OCR_PROGRAM, e))?;
+
+    if !status.success() {
+        anyhow::bail!("{} exited with {}", OCR_PROGRAM, status);
+    }
+
+    Ok(())
+}
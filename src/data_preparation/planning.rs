@@ -0,0 +1,116 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::setup::paths::Directories;
+use crate::sources::manifest::SourceKind;
+use crate::data_preparation::books::Book;
+use crate::data_preparation::state::Stage;
+use crate::data_preparation::ocr::ocr_text_path;
+use crate::data_preparation::extraction::extracted_text_path;
+
+
+// What a run would do for one artifact, without having done any of it
+#[derive(Clone, Debug)]
+pub struct PlannedArtifact {
+    pub title: String,
+    pub kind: SourceKind,
+    pub output_path: PathBuf,
+    pub is_present: bool,
+    pub will_acquire: bool,
+    pub expected_size: Option<u64>,
+    pub later_stages: Vec<Stage>,
+}
+
+
+#[derive(Clone, Debug)]
+pub struct AuthorPlan {
+    pub author: String,
+    pub artifacts: Vec<PlannedArtifact>,
+}
+
+
+pub async fn head_content_length(url: &str) -> Option<u64> {
+    let client = reqwest::Client::builder().timeout(Duration::from_secs(20)).build().ok()?;
+    let response = client.head(url).send().await.ok()?.error_for_status().ok()?;
+    response.content_length().filter(|length| *length > 0)
+}
+
+
+// Works out which of the later stages each artifact would trigger, from the books that it produced
+pub fn add_later_stages(directories: &Directories, books: &[Book], artifacts: &mut [PlannedArtifact]) {
+
+    for artifact in artifacts.iter_mut() {
+        let produced_books: Vec<&Book> = books.iter()
+            .filter(|book| book.source.kind() == artifact.kind)
+            .filter(|book| artifact.kind == SourceKind::Torrent || artifact.output_path.ends_with(&book.file_name))
+            .collect();
+
+        artifact.later_stages = later_stages(directories, artifact, &produced_books);
+    }
+}
+
+
+// A stage is triggered when its output is missing, or when anything upstream of it is going to be redone
+fn later_stages(directories: &Directories, artifact: &PlannedArtifact, books: &[&Book]) -> Vec<Stage> {
+    let mut stages: Vec<Stage> = Vec::new();
+
+    let needs_ocr: bool = books.iter().any(
+        |book| book.needs_ocr && (artifact.will_acquire || !ocr_text_path(directories, book).exists())
+    );
+
+    if needs_ocr {
+        stages.push(Stage::Ocr);
+    }
+
+    let needs_extraction: bool = artifact.will_acquire || needs_ocr || books.is_empty() || books.iter().any(
+        |book| !extracted_text_path(directories, book).exists()
+    );
+
+    if needs_extraction {
+        stages.push(Stage::Extraction);
+        stages.push(Stage::Cleaning);
+        stages.push(Stage::Chunking);
+        stages.push(Stage::Embedding);
+        stages.push(Stage::Indexing);
+    }
+
+    stages
+}
+
+
+fn format_size(size: Option<u64>) -> String {
+    match size {
+        Some(bytes) if bytes >= 1 << 20 => format!("{:.1} MiB", bytes as f64 / (1 << 20) as f64),
+        Some(bytes) => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        None => String::from("unknown"),
+    }
+}
+
+
+pub fn print_plan(plans: &[AuthorPlan]) {
+    let mut total_to_acquire: u64 = 0;
+
+    for plan in plans {
+        println!("{}", plan.author);
+
+        for artifact in &plan.artifacts {
+            let action: &str = match (artifact.will_acquire, artifact.is_present) {
+                (true, true) => "reacquire",
+                (true, false) => "acquire",
+                (false, _) => "keep",
+            };
+
+            let stages: Vec<&str> = artifact.later_stages.iter().map(Stage::name).collect();
+            println!("  {} [{:?}] {}", action, artifact.kind, artifact.title);
+            println!("      path:   {}", artifact.output_path.display());
+            println!("      size:   {}", format_size(artifact.expected_size));
+            println!("      stages: {}", if stages.is_empty() {String::from("none")} else {stages.join(", ")});
+
+            if artifact.will_acquire {
+                total_to_acquire += artifact.expected_size.unwrap_or(0);
+            }
+        }
+    }
+
+    println!("At least {} would be acquired", format_size(Some(total_to_acquire)));
+}
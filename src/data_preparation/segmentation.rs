@@ -0,0 +1,365 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+// Sample passages from the authors in the catalog, one expected sentence per line. See the file
+// itself for its format.
+pub const EVALUATION_FIXTURE: &str = include_str!("../../fixtures/segmentation.txt");
+
+const TERMINATORS: [char; 4] = ['.', '?', '!', '…'];
+const CLOSING_PUNCTUATION: [char; 7] = ['"', '\'', '”', '’', ')', ']', '»'];
+const OPENING_PUNCTUATION: [char; 7] = ['"', '\'', '“', '‘', '(', '[', '«'];
+
+// Abbreviations after which a Roman numeral is a reference ("Vol. II. The") rather than the end of a sentence
+const REFERENCE_WORDS: [&str; 10] = ["vol", "vols", "bk", "ch", "chap", "pt", "no", "art", "sec", "bd"];
+
+// Abbreviations that are also ordinary words, as in "he said no. Then", so they only count before a number
+const NUMBER_ABBREVIATIONS: [&str; 1] = ["no"];
+
+// How far back from a full stop to look for the words that it might belong to
+const ABBREVIATION_WINDOW: usize = 60;
+
+// Lines of verse are each treated as ending a sentence. A paragraph is taken to be verse when it has
+// at least this many lines, and they are short on average.
+const MINIMUM_VERSE_LINES: usize = 3;
+const MAXIMUM_VERSE_LINE_LENGTH: usize = 60;
+
+const ENGLISH_ABBREVIATIONS: [&str; 57] = [
+    "mr", "mrs", "messrs", "dr", "st", "mt", "gen", "col", "capt", "lieut", "gov", "rev", "hon", "esq", "jr", "sr",
+    "vol", "vols", "ch", "chap", "bk", "pt", "p", "pp", "ed", "eds", "trans", "nos", "fig", "art", "sec", "ff",
+    "cf", "viz", "vs", "ibid", "op", "cit", "loc", "i.e", "e.g", "ca", "c", "esp", "approx",
+    "jan", "feb", "apr", "aug", "sept", "oct", "nov", "dec", "co", "ltd", "inc", "ms",
+];
+const GERMAN_ABBREVIATIONS: [&str; 22] = [
+    "z.b", "bzw", "d.h", "vgl", "s", "bd", "kap", "hrsg", "nr", "ff", "ca", "dr", "hr", "fr", "u.a", "evtl", "ggf",
+    "sog", "jh", "abs", "anm", "str",
+];
+const FRENCH_ABBREVIATIONS: [&str; 14] = ["m", "mm", "mme", "mlle", "cf", "p", "pp", "vol", "chap", "éd", "t", "av", "apr", "j.-c"];
+const RUSSIAN_ABBREVIATIONS: [&str; 17] = ["т.е", "т.д", "т.п", "г", "гг", "см", "стр", "т", "тт", "др", "пр", "проф", "им", "ул", "тов", "с", "гл"];
+
+
+// The [segmentation] section of giants.toml. Each author's language decides which abbreviations
+// are recognised, and further abbreviations can be added to any language.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SegmentationConfig {
+    pub default_language: String,
+    // The language of each author's texts (as they appear in the corpus, so usually that of a translation)
+    pub languages: BTreeMap<String, String>,
+    pub abbreviations: BTreeMap<String, Vec<String>>,
+}
+
+
+impl Default for SegmentationConfig {
+    fn default() -> Self {
+        Self {
+            default_language: String::from("en"),
+            languages: BTreeMap::new(),
+            abbreviations: BTreeMap::new(),
+        }
+    }
+}
+
+
+impl SegmentationConfig {
+
+    pub fn language_of(&self, author: &str) -> &str {
+        self.languages.get(author).unwrap_or(&self.default_language)
+    }
+
+    pub fn segmenter(&self, language: &str) -> Segmenter {
+        Segmenter::new(language, self.abbreviations.get(language).map(Vec::as_slice).unwrap_or_default())
+    }
+
+    pub fn segmenter_for(&self, author: &str) -> Segmenter {
+        self.segmenter(self.language_of(author))
+    }
+}
+
+
+pub fn built_in_abbreviations(language: &str) -> &'static [&'static str] {
+    match language {
+        "en" => &ENGLISH_ABBREVIATIONS,
+        "de" => &GERMAN_ABBREVIATIONS,
+        "fr" => &FRENCH_ABBREVIATIONS,
+        "ru" => &RUSSIAN_ABBREVIATIONS,
+        _ => &[],
+    }
+}
+
+
+// Finds where sentences end in prose that is full of abbreviations, initials, citations of
+// numbered volumes and chapters, ellipses, and verse
+#[derive(Clone, Debug)]
+pub struct Segmenter {
+    // Lowercase, without their final full stop
+    abbreviations: HashSet<String>,
+}
+
+
+impl Segmenter {
+
+    pub fn new(language: &str, extra_abbreviations: &[String]) -> Self {
+        let abbreviations: HashSet<String> = built_in_abbreviations(language).iter()
+            .map(|abbreviation| abbreviation.to_string())
+            .chain(extra_abbreviations.iter().map(|abbreviation| abbreviation.trim_end_matches('.').to_lowercase()))
+            .collect();
+
+        Self { abbreviations }
+    }
+
+    // The sentences of a text, with the whitespace around them trimmed
+    pub fn split<'t>(&self, text: &'t str) -> Vec<&'t str> {
+        let characters: Vec<char> = text.chars().collect();
+        let byte_offsets: Vec<usize> = text.char_indices().map(|(offset, _)| offset).chain([text.len()]).collect();
+
+        let mut sentences: Vec<&str> = Vec::new();
+        let mut start: usize = 0;
+        for end in self.sentence_ends(&characters, 0, characters.len()) {
+            let sentence: &str = text[byte_offsets[start]..byte_offsets[end]].trim();
+            if !sentence.is_empty() {
+                sentences.push(sentence);
+            }
+            start = end;
+        }
+
+        sentences
+    }
+
+    // Where sentences end within characters[start..end], as offsets just past each sentence (and any
+    // closing quotes). The end of the range always counts as the end of a sentence.
+    pub fn sentence_ends(&self, characters: &[char], start: usize, end: usize) -> Vec<usize> {
+        let mut ends: Vec<usize> = Vec::new();
+
+        for (paragraph_start, paragraph_end) in paragraph_ranges(characters, start, end) {
+            if is_verse(&characters[paragraph_start..paragraph_end]) {
+                ends.extend(
+                    (paragraph_start..paragraph_end).filter(|index| characters[*index] == '\n')
+                );
+            }
+
+            ends.extend(self.prose_ends(characters, paragraph_start, paragraph_end));
+            ends.push(paragraph_end);
+        }
+
+        ends.sort_unstable();
+        ends.dedup();
+        ends.retain(|offset| *offset > start);
+
+        if ends.last() != Some(&end) {
+            ends.push(end);
+        }
+        ends
+    }
+
+    fn prose_ends(&self, characters: &[char], start: usize, end: usize) -> Vec<usize> {
+        let mut ends: Vec<usize> = Vec::new();
+        let mut index: usize = start;
+
+        while index < end {
+            if !TERMINATORS.contains(&characters[index]) {
+                index += 1;
+                continue
+            }
+
+            // Runs such as "?!" and "..." end a sentence together
+            let mut boundary: usize = index;
+            while boundary < end && TERMINATORS.contains(&characters[boundary]) {
+                boundary += 1;
+            }
+            let terminator_length: usize = boundary - index;
+
+            while boundary < end && CLOSING_PUNCTUATION.contains(&characters[boundary]) {
+                boundary += 1;
+            }
+
+            let followed_by_space: bool = boundary < end && characters[boundary].is_whitespace();
+            let next: Option<char> = characters[boundary..end].iter().find(|c| !c.is_whitespace()).copied();
+
+            if followed_by_space && next.is_some_and(starts_sentence) {
+                let is_single_stop: bool = terminator_length == 1 && characters[index] == '.';
+                if !is_single_stop || !self.is_abbreviation(characters, start, index) {
+                    ends.push(boundary);
+                }
+            }
+
+            index = boundary;
+        }
+
+        ends
+    }
+
+    // Whether the full stop at characters[stop] belongs to the word before it rather than ending a sentence
+    fn is_abbreviation(&self, characters: &[char], paragraph_start: usize, stop: usize) -> bool {
+        let window_start: usize = stop.saturating_sub(ABBREVIATION_WINDOW).max(paragraph_start);
+        let words: Vec<String> = characters[window_start..stop].iter()
+            .collect::<String>()
+            .split_whitespace()
+            .map(|word| word.trim_start_matches(OPENING_PUNCTUATION).to_string())
+            .collect();
+
+        let Some(word) = words.last() else { return false };
+        let lowercase: String = word.to_lowercase();
+        // Only an abbreviated reference word counts, since "in Part VII. There" ends a sentence
+        let previous: Option<String> = words.len().checked_sub(2)
+            .map(|index| &words[index])
+            .filter(|previous| previous.ends_with('.'))
+            .map(|previous| previous.trim_end_matches('.').to_lowercase());
+
+        let is_listed: bool = self.abbreviations.contains(&lowercase);
+        let is_numbered: bool = NUMBER_ABBREVIATIONS.contains(&lowercase.as_str())
+            && characters[stop + 1..].iter().find(|c| !c.is_whitespace()).is_some_and(char::is_ascii_digit);
+        // Initials, as in "V. I. Lenin" or "H. E. Litchfield"
+        let is_initial: bool = word.chars().count() == 1 && word.chars().all(char::is_uppercase);
+        // Abbreviations with stops of their own, as in "U.S.A" or "i.e"
+        let is_dotted: bool = word.contains('.') && word.split('.').all(|part| part.chars().count() <= 2);
+
+        let is_numeral: bool = is_roman_numeral(word) || (!word.is_empty() && word.chars().all(|c| c.is_ascii_digit()));
+        // "Vol. II. p. 34" cites a volume, and "IV. The Party" at the start of a paragraph numbers a section
+        let is_reference: bool = is_numeral && previous.as_deref().is_some_and(|previous| REFERENCE_WORDS.contains(&previous));
+        let is_enumeration: bool = is_numeral && words.len() == 1 && window_start == paragraph_start;
+
+        is_listed || is_numbered || is_initial || is_dotted || is_reference || is_enumeration
+    }
+}
+
+
+fn starts_sentence(character: char) -> bool {
+    character.is_uppercase() || character.is_ascii_digit() || OPENING_PUNCTUATION.contains(&character)
+}
+
+
+fn is_roman_numeral(word: &str) -> bool {
+    !word.is_empty() && word.chars().count() <= 6 && word.chars().all(|c| "IVXLCDM".contains(c))
+}
+
+
+// Paragraphs are separated by blank lines (and by page separators that follow a blank line), and a
+// sentence never runs from one paragraph into the next
+fn paragraph_ranges(characters: &[char], start: usize, end: usize) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    let mut paragraph_start: usize = start;
+    let mut index: usize = start;
+
+    while index + 1 < end {
+        if characters[index] == '\n' && characters[index + 1] == '\n' {
+            if index > paragraph_start {
+                ranges.push((paragraph_start, index));
+            }
+            paragraph_start = index + 2;
+            index += 2;
+        } else {
+            index += 1;
+        }
+    }
+
+    if end > paragraph_start {
+        ranges.push((paragraph_start, end));
+    }
+    ranges
+}
+
+
+fn is_verse(paragraph: &[char]) -> bool {
+    let text: String = paragraph.iter().collect();
+    let lines: Vec<&str> = text.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+
+    lines.len() >= MINIMUM_VERSE_LINES
+        && lines.iter().map(|line| line.chars().count()).sum::<usize>() / lines.len() < MAXIMUM_VERSE_LINE_LENGTH
+}
+
+
+// How well the segmenter agrees with the sentences in the evaluation fixture
+#[derive(Clone, Debug, Default)]
+pub struct Evaluation {
+    pub passages: usize,
+    pub expected: usize,
+    pub found: usize,
+    pub correct: usize,
+    // The passages that were not split exactly as expected, with the sentences that were found
+    pub mistakes: Vec<(String, Vec<String>)>,
+}
+
+
+impl Evaluation {
+
+    pub fn precision(&self) -> f64 {
+        if self.found == 0 {0.0} else {self.correct as f64 / self.found as f64}
+    }
+
+    pub fn recall(&self) -> f64 {
+        if self.expected == 0 {0.0} else {self.correct as f64 / self.expected as f64}
+    }
+}
+
+
+// The fixture is made of passages separated by blank lines. Each passage opens with a header such as
+// "# en | Karl Marx, Capital" (with "verse" after the language for poetry), and then has one expected
+// sentence per line. Prose sentences are joined with spaces, and lines of verse with line breaks.
+pub fn evaluate(config: &SegmentationConfig, fixture: &str) -> Evaluation {
+    let mut evaluation = Evaluation::default();
+
+    for block in fixture.split("\n\n").map(str::trim).filter(|block| !block.is_empty()) {
+        let mut lines = block.lines();
+        let Some(header) = lines.next().and_then(|line| line.strip_prefix('#')) else { continue };
+
+        let settings: Vec<&str> = header.split('|').next().unwrap_or_default().split_whitespace().collect();
+        let language: &str = settings.first().copied().unwrap_or(&config.default_language);
+        let separator: &str = if settings.contains(&"verse") {"\n"} else {" "};
+
+        let expected: Vec<&str> = lines.map(str::trim).filter(|line| !line.is_empty()).collect();
+        let passage: String = expected.join(separator);
+        let found: Vec<&str> = config.segmenter(language).split(&passage);
+
+        // A sentence is found correctly when it matches an expected one in full
+        let correct: usize = found.iter().filter(|sentence| expected.contains(sentence)).count();
+
+        evaluation.passages += 1;
+        evaluation.expected += expected.len();
+        evaluation.found += found.len();
+        evaluation.correct += correct;
+
+        if correct != expected.len() || found.len() != expected.len() {
+            evaluation.mistakes.push((header.trim().to_string(), found.iter().map(|sentence| sentence.to_string()).collect()));
+        }
+    }
+
+    evaluation
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Below these, a change to the segmenter has broken passages that it used to split correctly
+    const MINIMUM_PRECISION: f64 = 0.95;
+    const MINIMUM_RECALL: f64 = 0.95;
+
+    #[test]
+    fn splits_the_evaluation_fixture() {
+        let evaluation: Evaluation = evaluate(&SegmentationConfig::default(), EVALUATION_FIXTURE);
+        let mistakes: Vec<String> = evaluation.mistakes.iter().map(|(passage, sentences)| format!("{}\n    | {}", passage, sentences.join("\n    | "))).collect();
+
+        assert!(evaluation.passages > 0 && evaluation.expected > 0, "The fixture has no passages");
+        assert!(
+            evaluation.precision() >= MINIMUM_PRECISION && evaluation.recall() >= MINIMUM_RECALL,
+            "Precision {:.3} and recall {:.3} are below the minimum, with these mistakes:\n{}",
+            evaluation.precision(), evaluation.recall(), mistakes.join("\n"),
+        );
+    }
+
+    #[test]
+    fn keeps_abbreviations_and_references_within_sentences() {
+        let segmenter: Segmenter = SegmentationConfig::default().segmenter("en");
+        let sentences: Vec<&str> = segmenter.split("See Vol. II. The argument of Mr. Smith is weak. Is it?");
+        assert_eq!(sentences, vec!["See Vol. II. The argument of Mr. Smith is weak.", "Is it?"]);
+    }
+
+    #[test]
+    fn only_takes_no_for_an_abbreviation_before_a_number() {
+        let segmenter: Segmenter = SegmentationConfig::default().segmenter("en");
+        let sentences: Vec<&str> = segmenter.split("It is printed in No. 12 of the journal. He said no. Then he left.");
+        assert_eq!(sentences, vec!["It is printed in No. 12 of the journal.", "He said no.", "Then he left."]);
+    }
+}
@@ -0,0 +1,234 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::setup::paths::Directories;
+use crate::data_preparation::books::Book;
+use crate::sources::manifest::{hash_bytes, hash_file};
+
+pub static STATE_FILE_NAME: &str = "pipeline_state.sqlite";
+
+
+// The stages that each book passes through, in the order in which they run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    Acquisition,
+    Ocr,
+    Extraction,
+    Cleaning,
+    Chunking,
+    Embedding,
+    Indexing,
+}
+
+
+impl Stage {
+
+    pub const ALL: [Stage; 7] = [
+        Stage::Acquisition,
+        Stage::Ocr,
+        Stage::Extraction,
+        Stage::Cleaning,
+        Stage::Chunking,
+        Stage::Embedding,
+        Stage::Indexing,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Acquisition => "acquisition",
+            Stage::Ocr => "ocr",
+            Stage::Extraction => "extraction",
+            Stage::Cleaning => "cleaning",
+            Stage::Chunking => "chunking",
+            Stage::Embedding => "embedding",
+            Stage::Indexing => "indexing",
+        }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct StageRecord {
+    pub stage: Stage,
+    pub input_hash: String,
+    pub config_hash: String,
+    pub output_hash: String,
+    pub completed_at: u64,
+}
+
+
+// Remembers, for every book and stage, which inputs and which configuration the stage last ran with.
+// A stage only needs to run again if either of them has changed since. Each stage's input hash is
+// normally the output hash of the stage before it, so a change anywhere ripples down the pipeline.
+pub struct StateStore {
+    connection: Connection,
+}
+
+
+impl StateStore {
+
+    pub fn open(directories: &Directories) -> Result<Self, anyhow::Error> {
+        let path: PathBuf = directories.data.join(STATE_FILE_NAME);
+        std::fs::create_dir_all(&directories.data)?;
+        let connection = Connection::open(&path)?;
+
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS stages (
+                author TEXT NOT NULL,
+                book TEXT NOT NULL,
+                stage TEXT NOT NULL,
+                input_hash TEXT NOT NULL,
+                config_hash TEXT NOT NULL,
+                output_hash TEXT NOT NULL,
+                completed_at INTEGER NOT NULL,
+                PRIMARY KEY (author, book, stage)
+            );"
+        )?;
+
+        Ok(Self { connection })
+    }
+
+    pub fn get(&self, book: &Book, stage: Stage) -> Result<Option<StageRecord>, anyhow::Error> {
+        let record = self.connection.query_row(
+            "SELECT input_hash, config_hash, output_hash, completed_at FROM stages
+             WHERE author = ?1 AND book = ?2 AND stage = ?3",
+            params![book.author, book.slug, stage.name()],
+            |row| Ok(
+                StageRecord {
+                    stage,
+                    input_hash: row.get(0)?,
+                    config_hash: row.get(1)?,
+                    output_hash: row.get(2)?,
+                    completed_at: row.get::<_, i64>(3)? as u64,
+                }
+            )
+        ).optional()?;
+
+        Ok(record)
+    }
+
+    pub fn is_current(&self, book: &Book, stage: Stage, input_hash: &str, config_hash: &str) -> bool {
+        match self.get(book, stage) {
+            Ok(Some(record)) => record.input_hash == input_hash && record.config_hash == config_hash,
+            Ok(None) => false,
+            Err(e) => {
+                log::error!("Could not read the state of {} for {}: {}", stage.name(), book.title, e);
+                false
+            }
+        }
+    }
+
+    pub fn record(&self, book: &Book, stage: Stage, input_hash: &str, config_hash: &str, output_hash: &str) -> Result<(), anyhow::Error> {
+        let completed_at: u64 = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        self.connection.execute(
+            "INSERT OR REPLACE INTO stages (author, book, stage, input_hash, config_hash, output_hash, completed_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![book.author, book.slug, stage.name(), input_hash, config_hash, output_hash, completed_at as i64],
+        )?;
+
+        Ok(())
+    }
+
+    // The output of the stage, which is what the next stage takes as its input
+    pub fn output_hash(&self, book: &Book, stage: Stage) -> Option<String> {
+        self.get(book, stage).ok().flatten().map(|record| record.output_hash)
+    }
+
+    // The output of the latest stage before this one that has run, which is this stage's input
+    pub fn upstream_hash(&self, book: &Book, stage: Stage) -> Option<String> {
+        Stage::ALL.iter()
+            .rev()
+            .filter(|earlier| **earlier < stage)
+            .find_map(|earlier| self.output_hash(book, *earlier))
+    }
+
+    // Falls back on hashing the stage's input file when no earlier stage has been recorded,
+    // as happens for books that were acquired before the state was being kept.
+    pub fn input_hash(&self, book: &Book, stage: Stage, input_path: &Path) -> Result<String, anyhow::Error> {
+        match self.upstream_hash(book, stage) {
+            Some(hash) => Ok(hash),
+            None => hash_file(input_path),
+        }
+    }
+
+    // A stage counts as complete if it ran on what the stage before it most recently produced
+    pub fn is_complete(&self, book: &Book, stage: Stage) -> bool {
+        let Ok(Some(record)) = self.get(book, stage) else { return false };
+
+        match self.upstream_hash(book, stage) {
+            Some(hash) => hash == record.input_hash,
+            None => true,
+        }
+    }
+}
+
+
+// Configuration is hashed through its serialised form, so that any change to it invalidates a stage
+pub fn hash_config<T: Serialize>(config: &T) -> String {
+    hash_bytes(serde_json::to_string(config).unwrap_or_default().as_bytes())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::data_preparation::books::Book;
+    use crate::indices::testing::{book, scratch_directories};
+    use crate::sources::manifest::{hash_bytes, hash_file};
+    use super::{Stage, StateStore};
+
+    #[test]
+    fn a_new_upstream_output_invalidates_the_next_stage() {
+        let (directories, _) = scratch_directories("state-chain");
+        let store: StateStore = StateStore::open(&directories).unwrap();
+        let book: Book = book("Marx", "Capital");
+
+        store.record(&book, Stage::Extraction, "pdf", "extraction", "extracted").unwrap();
+        let input: String = store.input_hash(&book, Stage::Cleaning, &directories.extracted).unwrap();
+        assert_eq!(input, "extracted");
+
+        store.record(&book, Stage::Cleaning, &input, "cleaning", "cleaned").unwrap();
+        assert!(store.is_complete(&book, Stage::Cleaning));
+        assert!(store.is_current(&book, Stage::Cleaning, "extracted", "cleaning"));
+        assert!(!store.is_current(&book, Stage::Cleaning, "extracted", "changed cleaning"));
+
+        store.record(&book, Stage::Extraction, "pdf", "extraction", "extracted again").unwrap();
+        assert!(!store.is_complete(&book, Stage::Cleaning));
+        assert_eq!(store.upstream_hash(&book, Stage::Cleaning).as_deref(), Some("extracted again"));
+        assert!(!store.is_current(&book, Stage::Cleaning, &store.input_hash(&book, Stage::Cleaning, &directories.extracted).unwrap(), "cleaning"));
+    }
+
+    #[test]
+    fn skipped_stages_are_passed_over() {
+        let (directories, _) = scratch_directories("state-skip");
+        let store: StateStore = StateStore::open(&directories).unwrap();
+        let book: Book = book("Marx", "Capital");
+
+        store.record(&book, Stage::Acquisition, "url", "acquisition", "downloaded").unwrap();
+        assert_eq!(store.upstream_hash(&book, Stage::Extraction).as_deref(), Some("downloaded"));
+        assert_eq!(store.upstream_hash(&book, Stage::Acquisition), None);
+    }
+
+    #[test]
+    fn an_untracked_book_is_hashed_from_its_input_file() {
+        let (directories, _) = scratch_directories("state-untracked");
+        let store: StateStore = StateStore::open(&directories).unwrap();
+        let book: Book = book("Marx", "Capital");
+
+        let path: PathBuf = directories.data.join("capital.txt");
+        std::fs::write(&path, "Commodities").unwrap();
+        let input: String = store.input_hash(&book, Stage::Cleaning, &path).unwrap();
+        assert_eq!(input, hash_file(&path).unwrap());
+        assert_eq!(input, hash_bytes(b"Commodities"));
+
+        // Nothing upstream has been recorded, so the recorded run is all there is to go on
+        assert!(!store.is_complete(&book, Stage::Cleaning));
+        store.record(&book, Stage::Cleaning, &input, "cleaning", "cleaned").unwrap();
+        assert!(store.is_complete(&book, Stage::Cleaning));
+    }
+}
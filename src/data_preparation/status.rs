@@ -0,0 +1,79 @@
+use std::path::PathBuf;
+
+use crate::setup::paths::Directories;
+use crate::data_preparation::state::{Stage, StateStore};
+use crate::sources::authors::{get_author_root, prepare_sources};
+use crate::sources::manifest::{Manifest, MANIFEST_FILE_NAME};
+use crate::data_preparation::books::{list_books, Book, Selection};
+
+
+fn mark(done: bool) -> &'static str {
+    if done {"yes"} else {"-"}
+}
+
+
+// A stage only shows as done if it ran on what the stage before it most recently produced,
+// so anything that is out of date shows up as not done
+pub fn print_status(directories: &Directories, state: &StateStore, selection: &Selection) {
+
+    print!("{:<28} {:<48}", "AUTHOR", "BOOK");
+    for stage in Stage::ALL {
+        print!(" {:<12}", stage.name().to_uppercase());
+    }
+    println!();
+
+    for author in prepare_sources().iter().filter(|author| selection.includes_author(&author.name)) {
+        let manifest: Manifest = Manifest::load(&get_author_root(directories, &author.name));
+
+        for book in list_books(author, &manifest) {
+            if !selection.includes(&book.title, book.source.kind()) {
+                continue
+            }
+
+            print!("{:<28} {:<48}", author.name, book.slug);
+
+            for stage in Stage::ALL {
+                let done: &str = match stage {
+                    Stage::Acquisition => mark(manifest.is_valid(&book.file_name, &book.source)),
+                    Stage::Ocr if !book.needs_ocr => "n/a",
+                    _ => mark(state.is_complete(&book, stage)),
+                };
+                print!(" {:<12}", done);
+            }
+            println!();
+        }
+    }
+}
+
+
+// Rehashes every acquired file and reports the ones that no longer match the manifest.
+// Returns the number of problems that were found.
+pub fn verify_downloads(directories: &Directories, selection: &Selection) -> usize {
+    let mut problems: usize = 0;
+
+    for author in prepare_sources().iter().filter(|author| selection.includes_author(&author.name)) {
+        let author_root: PathBuf = get_author_root(directories, &author.name);
+        if !author_root.join(MANIFEST_FILE_NAME).exists() {
+            continue
+        }
+
+        let manifest: Manifest = Manifest::load(&author_root);
+        let books: Vec<Book> = list_books(author, &manifest);
+
+        for book in books.iter().filter(|book| selection.includes(&book.title, book.source.kind())) {
+            if !manifest.entries.contains_key(&book.file_name) {
+                continue
+            }
+
+            match manifest.verify(&book.file_name) {
+                Ok(_) => log::info!("{} is intact", book.title),
+                Err(e) => {
+                    log::error!("{}: {}", author.name, e);
+                    problems += 1;
+                }
+            }
+        }
+    }
+
+    problems
+}
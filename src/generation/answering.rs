@@ -0,0 +1,421 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data_preparation::attribution::Voice;
+use crate::indices::filters::{ChunkMetadata, Filter};
+use crate::indices::retrieval::{Passage, Retriever};
+use crate::generation::llm::{ChatMessage, Completion, LlmBackend, OnText, TokenUsage};
+use crate::generation::faithfulness::FaithfulnessReport;
+
+
+// The [answering] section of giants.toml
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnsweringConfig {
+    // How many passages the model is given to answer from
+    pub passages: usize,
+    // How many passages of each author's a comparison, or each turn of a debate, is given
+    pub passages_per_author: usize,
+    // How many times each author speaks in a debate
+    pub debate_rounds: usize,
+    // Leaves out prefaces, editorial notes, and other matter that the author did not write
+    pub own_words_only: bool,
+    // Refuses to answer when retrieval finds nothing convincing, or when the answer cites nothing
+    pub strict: bool,
+    // In strict mode, the best passage must be at least this similar to the question...
+    pub minimum_similarity: f32,
+    // ...or, when a reranker is configured, score at least this with it
+    pub minimum_rerank_score: f32,
+}
+
+
+impl Default for AnsweringConfig {
+    fn default() -> Self {
+        Self {
+            passages: 8,
+            passages_per_author: 4,
+            debate_rounds: 2,
+            own_words_only: true,
+            strict: false,
+            minimum_similarity: 0.35,
+            minimum_rerank_score: 0.0,
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Answer {
+    pub author: String,
+    pub question: String,
+    // Cites passages by their number, as in [2]
+    pub text: String,
+    // Numbered from 1, in the order that the model was given them
+    pub passages: Vec<Passage>,
+    // The numbers of the passages that the answer cites
+    pub cited: Vec<usize>,
+    // Why the question was not answered, in strict mode
+    pub refusal: Option<String>,
+    // Given in the author's voice without a passage that answers the question directly, so reasoned
+    // from the passages rather than taken from them
+    pub interpretive: bool,
+    // Shown with answers given in the author's voice
+    pub disclaimer: Option<String>,
+    // How well the passages bear the answer out, once it has been checked
+    pub faithfulness: Option<FaithfulnessReport>,
+    pub usage: TokenUsage,
+}
+
+
+impl Answer {
+
+    pub(crate) fn refused(author: &str, question: &str, passages: Vec<Passage>, reason: String) -> Self {
+        Self {
+            author: author.to_string(),
+            question: question.to_string(),
+            text: String::new(),
+            passages,
+            cited: Vec::new(),
+            refusal: Some(reason),
+            interpretive: false,
+            disclaimer: None,
+            faithfulness: None,
+            usage: TokenUsage::default(),
+        }
+    }
+
+    pub fn is_refused(&self) -> bool {
+        self.refusal.is_some()
+    }
+
+    // The answer with each passage number replaced by the book, chapter, and page that it cites
+    pub fn with_citations(&self) -> String {
+        replace_markers(&self.text, |number| {
+            number.checked_sub(1).and_then(|index| self.passages.get(index)).map(|passage| format!("[{}]", inline_citation(&passage.metadata)))
+        })
+    }
+
+    pub fn cited_passages(&self) -> impl Iterator<Item = (usize, &Passage)> {
+        self.cited.iter().map(|number| (*number, &self.passages[number - 1]))
+    }
+}
+
+
+// Such as "The State and Revolution, Chapter I, p. 12", since the author goes without saying
+fn inline_citation(metadata: &ChunkMetadata) -> String {
+    let mut parts: Vec<String> = vec![metadata.title.clone()];
+    if let Some(chapter) = &metadata.chapter {
+        parts.push(chapter.clone());
+    }
+    if metadata.first_page == metadata.last_page {
+        parts.push(format!("p. {}", metadata.first_page));
+    } else {
+        parts.push(format!("pp. {}–{}", metadata.first_page, metadata.last_page));
+    }
+    parts.join(", ")
+}
+
+
+// Rewrites every citation marker, such as [2] or [1, 3], with what the closure returns for each of
+// its numbers. Numbers that it returns nothing for are dropped, along with markers left empty.
+pub(crate) fn replace_markers(text: &str, mut replacement: impl FnMut(usize) -> Option<String>) -> String {
+    let mut result: String = String::with_capacity(text.len());
+    let mut rest: &str = text;
+
+    while let Some(start) = rest.find('[') {
+        result.push_str(&rest[..start]);
+        let after: &str = &rest[start + 1..];
+
+        let numbers: Option<Vec<usize>> = after.find(']').and_then(|end| {
+            after[..end].split(',').map(|number| number.trim().parse::<usize>().ok()).collect()
+        });
+
+        match numbers {
+            Some(numbers) => {
+                let replaced: String = numbers.into_iter().filter_map(&mut replacement).collect();
+                rest = &after[after.find(']').unwrap() + 1..];
+                // A space left before a dropped marker is dropped too, unless another marker follows it
+                if replaced.is_empty() && result.ends_with(' ') && !rest.starts_with('[') {
+                    result.pop();
+                }
+                result.push_str(&replaced);
+            },
+            None => {
+                result.push('[');
+                rest = after;
+            },
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+
+// The numbers in every citation marker in the text, whether or not they refer to a passage
+pub(crate) fn cited_numbers(text: &str) -> BTreeSet<usize> {
+    let mut cited: BTreeSet<usize> = BTreeSet::new();
+    replace_markers(text, |number| {
+        cited.insert(number);
+        None
+    });
+    cited
+}
+
+
+// Why retrieval is not to be trusted for this question, if it is not
+pub(crate) fn low_confidence(passages: &[Passage], config: &AnsweringConfig) -> Option<String> {
+    let Some(best) = passages.first() else {
+        return Some(String::from("No passages were found for this question"));
+    };
+
+    if let Some(score) = best.scores.rerank {
+        return (score < config.minimum_rerank_score).then(|| format!(
+            "The best passage only scored {:.3} with the reranker, below the minimum of {:.3}", score, config.minimum_rerank_score,
+        ));
+    }
+
+    // Passages found only by their keywords have no similarity to judge them by
+    let Some(similarity) = passages.iter().filter_map(|passage| passage.scores.vector).reduce(f32::max) else {
+        log::debug!("None of the passages were found by the vector index, so their similarity to the question is unknown");
+        return None;
+    };
+    (similarity < config.minimum_similarity).then(|| format!(
+        "The best passage is only {:.3} similar to the question, below the minimum of {:.3}", similarity.max(0.0), config.minimum_similarity,
+    ))
+}
+
+
+// Each passage under its number and source, as the model is given them, numbered from first
+pub(crate) fn numbered_passages(passages: &[Passage], first: usize) -> String {
+    let mut context: String = String::new();
+    for (index, passage) in passages.iter().enumerate() {
+        context.push_str(&format!("[{}] ({})\n{}\n\n", first + index, passage.citation(), passage.text.trim()));
+    }
+    context
+}
+
+
+pub fn build_prompt(author: &str, question: &str, passages: &[Passage], strict: bool) -> Vec<ChatMessage> {
+    let mut instructions: String = format!(
+        "You answer questions about the works of {author}, using only the numbered passages from those works that you are given. \
+        Cite the passage that supports each claim with its number in square brackets, such as [2] or [1, 3], straight after the claim. \
+        Do not rely on anything that you know about {author} beyond the passages, and do not invent quotations. \
+        Where the passages do not settle the question, say so."
+    );
+    if strict {
+        instructions.push_str(" If the passages do not answer the question at all, reply only that the sources do not answer it, and cite nothing.");
+    }
+
+    vec![
+        ChatMessage::system(&instructions),
+        ChatMessage::user(&format!("Passages:\n\n{}Question: {}", numbered_passages(passages, 1), question)),
+    ]
+}
+
+
+// The author's passages that best match the question, leaving out what they did not write if so configured
+pub(crate) fn retrieve_passages(retriever: &mut Retriever, config: &AnsweringConfig, author: &str, question: &str) -> Result<Vec<Passage>, anyhow::Error> {
+    let filter = Filter {
+        authors: vec![author.to_string()],
+        voice: if config.own_words_only {Some(Voice::Primary)} else {None},
+        ..Filter::default()
+    };
+    retriever.retrieve(question, &filter, config.passages)
+}
+
+
+// Streams the reply to on_text as it is written, if it is given
+pub(crate) async fn generate(backend: &dyn LlmBackend, messages: &[ChatMessage], on_text: Option<&mut OnText<'_>>) -> Result<Completion, anyhow::Error> {
+    match on_text {
+        Some(on_text) => backend.stream(messages, on_text).await,
+        None => backend.complete(messages).await,
+    }
+}
+
+
+// Turns the model's reply into an answer, taking out citations of passages that it was not given
+pub(crate) fn cited_answer(author: &str, question: &str, passages: Vec<Passage>, completion: Completion) -> Answer {
+    let numbers: BTreeSet<usize> = cited_numbers(&completion.text);
+    let cited: Vec<usize> = numbers.iter().copied().filter(|number| (1..=passages.len()).contains(number)).collect();
+
+    if cited.len() < numbers.len() {
+        log::warn!("The answer cites passages that it was not given, and those citations were dropped");
+    }
+
+    let text: String = replace_markers(&completion.text, |number| cited.contains(&number).then(|| format!("[{}]", number)));
+
+    Answer {
+        author: author.to_string(),
+        question: question.to_string(),
+        text: text.trim().to_string(),
+        passages,
+        cited,
+        refusal: None,
+        interpretive: false,
+        disclaimer: None,
+        faithfulness: None,
+        usage: completion.usage,
+    }
+}
+
+
+// Answers the question from the author's own works, citing the passages that the answer rests on.
+// The reply is streamed to on_text as it is written, if it is given.
+pub async fn answer_question(
+    retriever: &mut Retriever,
+    backend: &dyn LlmBackend,
+    on_text: Option<&mut OnText<'_>>,
+    config: &AnsweringConfig,
+    author: &str,
+    question: &str,
+) -> Result<Answer, anyhow::Error> {
+    let passages: Vec<Passage> = retrieve_passages(retriever, config, author, question)?;
+
+    if config.strict {
+        if let Some(reason) = low_confidence(&passages, config) {
+            return Ok(Answer::refused(author, question, passages, reason));
+        }
+    } else if passages.is_empty() {
+        anyhow::bail!("No passages by {} were found. Have their books been indexed?", author);
+    }
+
+    let messages: Vec<ChatMessage> = build_prompt(author, question, &passages, config.strict);
+    let completion: Completion = generate(backend, &messages, on_text).await?;
+    let answer: Answer = cited_answer(author, question, passages, completion);
+
+    if answer.cited.is_empty() {
+        if config.strict {
+            let reason: String = String::from("The answer did not cite any of the passages");
+            return Ok(Answer { usage: answer.usage, ..Answer::refused(author, question, answer.passages, reason) });
+        }
+        log::warn!("The answer does not cite any of the passages");
+    }
+
+    Ok(answer)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setup::config::Config;
+    use crate::setup::paths::Directories;
+    use crate::indices::testing::{build_corpus, metadata};
+    use crate::generation::mock::{MockBackend, MockConfig};
+    use crate::generation::faithfulness::FaithfulnessChecker;
+    use crate::indices::retrieval::StageScores;
+
+    fn corpus(name: &str) -> (Directories, Config) {
+        build_corpus(name, &[
+            ("marx-1", metadata("Karl Marx", "Capital", 1), "Labour is the substance of value, and its measure is labour time."),
+            ("marx-2", metadata("Karl Marx", "Capital", 2), "The capitalist buys labour power and sells its product for more than its value."),
+            ("lenin-1", metadata("Vladimir Lenin", "The State and Revolution", 1), "The state is an organ of class rule."),
+        ])
+    }
+
+    fn mock(replies: &[&str]) -> MockBackend {
+        MockBackend::new(&MockConfig { replies: replies.iter().map(|reply| reply.to_string()).collect() })
+    }
+
+    fn passage(number: usize, scores: StageScores) -> Passage {
+        Passage { id: format!("marx-{}", number), text: String::from("Labour is the substance of value."), metadata: metadata("Karl Marx", "Capital", 1), scores }
+    }
+
+    fn completion(text: &str) -> Completion {
+        Completion { text: text.to_string(), usage: TokenUsage::default() }
+    }
+
+    #[test]
+    fn replaces_every_number_in_a_marker() {
+        let renumbered: String = replace_markers("Value [1] and price [2, 3].", |number| Some(format!("<{}>", number * 10)));
+        assert_eq!(renumbered, "Value <10> and price <20><30>.");
+    }
+
+    #[test]
+    fn drops_empty_markers_with_the_space_before_them() {
+        let dropped: String = replace_markers("Value [1]. Price [2, 3]. Rent [3].", |number| (number == 2).then(|| format!("[{}]", number)));
+        assert_eq!(dropped, "Value. Price [2]. Rent.");
+        assert_eq!(replace_markers("Value [1][2][3].", |number| (number == 3).then(|| format!("[{}]", number))), "Value [3].");
+    }
+
+    #[test]
+    fn leaves_brackets_that_are_not_markers_alone() {
+        let text: &str = "He [Marx] wrote [sic] in [1867, and] of [ 2 ] things [unclosed";
+        assert_eq!(replace_markers(text, |_| None), "He [Marx] wrote [sic] in [1867, and] of things [unclosed");
+        assert_eq!(cited_numbers(text), BTreeSet::from([2]));
+    }
+
+    #[test]
+    fn keeps_only_citations_of_the_passages_that_were_given() {
+        let passages: Vec<Passage> = (1..=3).map(|number| passage(number, StageScores::default())).collect();
+        let answer: Answer = cited_answer("Karl Marx", "What is value?", passages, completion("Value [3, 1] is labour [0]. Price [4][2]. Rent [3]."));
+
+        assert_eq!(answer.cited, vec![1, 2, 3]);
+        assert_eq!(answer.text, "Value [3][1] is labour. Price [2]. Rent [3].");
+    }
+
+    #[test]
+    fn judges_confidence_by_the_best_similarity_or_rerank_score() {
+        let config = AnsweringConfig { minimum_similarity: 0.5, minimum_rerank_score: 0.0, ..AnsweringConfig::default() };
+        let similar = |score: f32| StageScores { vector: Some(score), ..StageScores::default() };
+
+        assert!(low_confidence(&[], &config).is_some());
+        assert!(low_confidence(&[passage(1, similar(0.2)), passage(2, similar(0.6))], &config).is_none());
+        assert!(low_confidence(&[passage(1, similar(0.2)), passage(2, similar(0.4))], &config).is_some());
+
+        // The reranker's score is used instead whenever there is one
+        let reranked = StageScores { vector: Some(0.9), rerank: Some(-1.0), ..StageScores::default() };
+        assert!(low_confidence(&[passage(1, reranked)], &config).is_some());
+    }
+
+    #[test]
+    fn does_not_judge_passages_found_only_by_their_keywords() {
+        let config = AnsweringConfig { minimum_similarity: 0.5, ..AnsweringConfig::default() };
+        let keyword_only = StageScores { keyword_rank: Some(1), keyword: Some(3.2), fused: 0.016, ..StageScores::default() };
+        assert!(low_confidence(&[passage(1, keyword_only.clone()), passage(2, keyword_only)], &config).is_none());
+    }
+
+    #[tokio::test]
+    async fn answers_from_the_authors_passages_with_the_mock() {
+        let (directories, config) = corpus("answer-mock");
+        let mut retriever = Retriever::new(&directories, &config).unwrap();
+        let question: &str = "What is the substance of value?";
+
+        let answer: Answer = answer_question(&mut retriever, &mock(&[]), None, &config.answering, "Karl Marx", question).await.unwrap();
+        assert!(!answer.is_refused());
+        assert_eq!(answer.cited, vec![1]);
+        assert_eq!(answer.passages.len(), 2);
+        assert!(answer.passages.iter().all(|passage| passage.metadata.author == "Karl Marx"));
+        assert!(answer.text.contains(question) && !answer.text.contains('"'), "{}", answer.text);
+
+        // The question is repeated without quotation marks, so it is not taken for a quotation missing from the corpus
+        let checker = FaithfulnessChecker::new(&directories, &config).unwrap();
+        let checked: Answer = checker.check_answer(&mut retriever, answer, true).unwrap();
+        assert!(!checked.is_refused());
+        assert!(checked.faithfulness.unwrap().quotations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn drops_citations_of_passages_that_were_not_given() {
+        let (directories, config) = corpus("answer-citations");
+        let mut retriever = Retriever::new(&directories, &config).unwrap();
+        let backend: MockBackend = mock(&["Labour is the substance of value [1, 9]. Nothing else is [7]."]);
+
+        let answer: Answer = answer_question(&mut retriever, &backend, None, &config.answering, "Karl Marx", "What is value?").await.unwrap();
+        assert_eq!(answer.cited, vec![1]);
+        assert_eq!(answer.text, "Labour is the substance of value [1]. Nothing else is.");
+    }
+
+    #[tokio::test]
+    async fn strict_mode_refuses_an_answer_that_cites_nothing() {
+        let (directories, config) = corpus("answer-uncited");
+        let mut retriever = Retriever::new(&directories, &config).unwrap();
+        let strict = AnsweringConfig { strict: true, minimum_similarity: -1.0, ..config.answering.clone() };
+
+        let answer: Answer = answer_question(&mut retriever, &mock(&["The sources do not answer it."]), None, &strict, "Karl Marx", "What is value?").await.unwrap();
+        assert!(answer.is_refused());
+        assert!(answer.text.is_empty() && answer.cited.is_empty());
+    }
+}
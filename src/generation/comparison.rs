@@ -0,0 +1,319 @@
+use std::collections::BTreeSet;
+
+use serde::Serialize;
+
+use crate::sources::authors::Author;
+use crate::indices::retrieval::{Passage, Retriever};
+use crate::generation::persona::speak_as_author;
+use crate::generation::llm::{ChatMessage, Completion, LlmBackend, OnText, TokenUsage};
+use crate::generation::answering::{
+    cited_answer, cited_numbers, generate, low_confidence, numbered_passages, replace_markers, retrieve_passages, Answer, AnsweringConfig,
+};
+
+
+// A debate in turns, each given in one author's voice from their own passages
+#[derive(Clone, Debug, Serialize)]
+pub struct Debate {
+    pub question: String,
+    pub authors: Vec<String>,
+    // Each turn's author is the one speaking
+    pub turns: Vec<Answer>,
+    // One for each author, given once for the whole debate rather than after every turn
+    pub disclaimers: Vec<String>,
+    pub usage: TokenUsage,
+}
+
+
+// "A", "A and B", or "A, B, and C"
+fn list_names(names: &[String]) -> String {
+    match names {
+        [] => String::new(),
+        [name] => name.clone(),
+        [first, second] => format!("{} and {}", first, second),
+        [rest @ .., last] => format!("{}, and {}", rest.join(", "), last),
+    }
+}
+
+
+// Each author's passages, cut to the same number for every author so that none of them dominates
+fn balanced_passages(
+    retriever: &mut Retriever,
+    config: &AnsweringConfig,
+    authors: &[&Author],
+    question: &str,
+) -> Result<Vec<Vec<Passage>>, anyhow::Error> {
+    let per_author = AnsweringConfig { passages: config.passages_per_author, ..config.clone() };
+
+    let mut passages: Vec<Vec<Passage>> = Vec::with_capacity(authors.len());
+    for author in authors {
+        let found: Vec<Passage> = retrieve_passages(retriever, &per_author, &author.name, question)?;
+        anyhow::ensure!(!found.is_empty(), "No passages by {} were found. Have their books been indexed?", author.name);
+        passages.push(found);
+    }
+
+    let fewest: usize = passages.iter().map(Vec::len).min().unwrap_or_default();
+    if passages.iter().any(|found| found.len() > fewest) {
+        log::info!("Every author is given {} passages, since that is all that was found for one of them", fewest);
+    }
+    for found in &mut passages {
+        found.truncate(fewest);
+    }
+    Ok(passages)
+}
+
+
+// Citations are only kept under the heading of the author whose passage they cite, so that no
+// author is credited with another's words. Lines before the first heading may cite anyone.
+fn drop_misattributed(text: &str, passages: &[Passage], authors: &[&Author]) -> (String, usize) {
+    let mut speaker: Option<&str> = None;
+    let mut dropped: usize = 0;
+    let mut lines: Vec<String> = Vec::new();
+
+    for line in text.lines() {
+        if line.trim_start().starts_with('#') {
+            let heading: String = line.to_lowercase();
+            speaker = authors.iter().map(|author| author.name.as_str()).find(|name| heading.contains(&name.to_lowercase()));
+            // Headings that name no author, such as a closing comparison, may cite anyone
+            lines.push(line.to_string());
+            continue
+        }
+
+        lines.push(replace_markers(line, |number| {
+            let passage: &Passage = passages.get(number.checked_sub(1)?)?;
+            match speaker {
+                Some(name) if passage.metadata.author != name => {
+                    dropped += 1;
+                    None
+                },
+                _ => Some(format!("[{}]", number)),
+            }
+        }));
+    }
+
+    (lines.join("\n"), dropped)
+}
+
+
+pub fn build_comparison_prompt(authors: &[&Author], question: &str, passages: &[Vec<Passage>]) -> Vec<ChatMessage> {
+    let names: Vec<String> = authors.iter().map(|author| author.name.clone()).collect();
+    let instructions: String = format!(
+        "You compare the views of {} using only the numbered passages from their own works that you are given. \
+        Give each author a section of their own, headed with \"## \" and their name, that sets out their position from their passages. \
+        Then end with a section headed \"## Comparison\" that sets out where they agree and where they differ. \
+        Cite the passage that supports each claim with its number in square brackets, such as [2], straight after it, and only ever \
+        cite an author's own passages in their section. Do not rely on anything that you know about them beyond the passages, \
+        do not invent quotations, and do not favour any of them. Where the passages do not show an author's view, say so.",
+        list_names(&names),
+    );
+
+    // Numbered straight through, in one block for each author
+    let mut context: String = String::new();
+    let mut first: usize = 1;
+    for (author, found) in authors.iter().zip(passages) {
+        context.push_str(&format!("Passages by {}:\n\n{}", author.name, numbered_passages(found, first)));
+        first += found.len();
+    }
+
+    vec![
+        ChatMessage::system(&instructions),
+        ChatMessage::user(&format!("{}Question: {}", context, question)),
+    ]
+}
+
+
+// Compares what each author says about the question, from the same number of each one's passages,
+// in a section for each author followed by a comparison
+pub async fn compare_authors(
+    retriever: &mut Retriever,
+    backend: &dyn LlmBackend,
+    on_text: Option<&mut OnText<'_>>,
+    config: &AnsweringConfig,
+    authors: &[&Author],
+    question: &str,
+) -> Result<Answer, anyhow::Error> {
+    anyhow::ensure!(authors.len() >= 2, "A comparison needs at least two authors");
+    let names: Vec<String> = authors.iter().map(|author| author.name.clone()).collect();
+
+    let by_author: Vec<Vec<Passage>> = balanced_passages(retriever, config, authors, question)?;
+    if config.strict {
+        for (author, found) in authors.iter().zip(&by_author) {
+            if let Some(reason) = low_confidence(found, config) {
+                let reason: String = format!("{} for {}", reason, author.name);
+                return Ok(Answer::refused(&list_names(&names), question, by_author.concat(), reason));
+            }
+        }
+    }
+
+    let messages: Vec<ChatMessage> = build_comparison_prompt(authors, question, &by_author);
+    let completion: Completion = generate(backend, &messages, on_text).await?;
+    let passages: Vec<Passage> = by_author.concat();
+
+    let (text, dropped) = drop_misattributed(&completion.text, &passages, authors);
+    if dropped > 0 {
+        log::warn!("{} citations were made under the wrong author's heading, and were dropped", dropped);
+    }
+    let mut answer: Answer = cited_answer(&list_names(&names), question, passages, Completion { text, usage: completion.usage });
+
+    // Every author should be represented by at least one of their own passages
+    let cited_authors: BTreeSet<&str> = answer.cited.iter().map(|number| answer.passages[number - 1].metadata.author.as_str()).collect();
+    let uncited: Vec<String> = names.iter().filter(|name| !cited_authors.contains(name.as_str())).cloned().collect();
+
+    if !uncited.is_empty() {
+        let reason: String = format!("The answer cites none of the passages by {}", list_names(&uncited));
+        if config.strict {
+            return Ok(Answer { usage: answer.usage, ..Answer::refused(&list_names(&names), question, answer.passages, reason) });
+        }
+        log::warn!("{}", reason);
+    }
+
+    answer.text = answer.text.trim().to_string();
+    Ok(answer)
+}
+
+
+// A debate between the authors, each taking a turn in every round in their own voice. Every turn is
+// grounded in that author's passages, found for the question and for what the last speaker said.
+pub async fn debate(
+    retriever: &mut Retriever,
+    backend: &dyn LlmBackend,
+    mut on_turn: impl FnMut(&Answer),
+    config: &AnsweringConfig,
+    authors: &[&Author],
+    question: &str,
+) -> Result<Debate, anyhow::Error> {
+    anyhow::ensure!(authors.len() >= 2, "A debate needs at least two authors");
+    let names: Vec<String> = authors.iter().map(|author| author.name.clone()).collect();
+    let per_turn = AnsweringConfig { passages: config.passages_per_author, ..config.clone() };
+
+    let mut turns: Vec<Answer> = Vec::new();
+    let mut usage: TokenUsage = TokenUsage::default();
+
+    for _ in 0..config.debate_rounds.max(1) {
+        for author in authors {
+            let others: Vec<String> = names.iter().filter(|name| **name != author.name).cloned().collect();
+            let transcript: String = turns.iter()
+                .filter(|turn| !turn.is_refused())
+                .map(|turn| format!("{}: {}", turn.author, turn.with_citations()))
+                .collect::<Vec<String>>()
+                .join("\n\n");
+
+            let prompt: String = if transcript.is_empty() {
+                format!("You are opening a debate with {} on this question: {}", list_names(&others), question)
+            } else {
+                format!(
+                    "You are in a debate with {} on this question: {}\n\nThe debate so far:\n\n{}\n\n\
+                    Give your next turn, replying to what has been said, in a few paragraphs.",
+                    list_names(&others), question, transcript,
+                )
+            };
+
+            // Passages are found for the question and the last thing said, rather than the whole transcript
+            let query: String = match turns.last() {
+                Some(last) if !last.is_refused() => format!("{} {}", question, last.text),
+                _ => question.to_string(),
+            };
+
+            let mut turn: Answer = speak_as_author(retriever, backend, None, &per_turn, author, &query, &prompt).await?;
+            turn.question = question.to_string();
+            turn.disclaimer = None;
+
+            usage += turn.usage;
+            on_turn(&turn);
+            turns.push(turn);
+        }
+    }
+
+    // The passages of every turn are numbered from 1, so the citations in the transcript are kept inline
+    let cited: usize = turns.iter().map(|turn| cited_numbers(&turn.text).len()).sum();
+    log::info!("The debate took {} turns, citing {} passages", turns.len(), cited);
+
+    let disclaimers: Vec<String> = authors.iter().map(|author| author.persona.disclaimer(&author.name)).collect();
+    Ok(Debate { question: question.to_string(), authors: names, turns, disclaimers, usage })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setup::config::Config;
+    use crate::setup::paths::Directories;
+    use crate::generation::persona::Persona;
+    use crate::indices::testing::{build_corpus, metadata};
+    use crate::generation::mock::{MockBackend, MockConfig};
+
+    fn corpus(name: &str) -> (Directories, Config) {
+        build_corpus(name, &[
+            ("marx-1", metadata("Karl Marx", "Capital", 1), "The state is the executive committee of the bourgeoisie."),
+            ("marx-2", metadata("Karl Marx", "Capital", 2), "Labour is the substance of value."),
+            ("lenin-1", metadata("Vladimir Lenin", "The State and Revolution", 1), "The state is an organ of class rule."),
+            ("lenin-2", metadata("Vladimir Lenin", "The State and Revolution", 2), "The state will wither away after the revolution."),
+            ("lenin-3", metadata("Vladimir Lenin", "The State and Revolution", 3), "The proletariat needs the state only for a time."),
+        ])
+    }
+
+    fn author(name: &str) -> Author {
+        Author { name: name.to_string(), sources: Vec::new(), biographers_and_compilers: None, persona: Persona::default() }
+    }
+
+    fn mock(replies: &[&str]) -> MockBackend {
+        MockBackend::new(&MockConfig { replies: replies.iter().map(|reply| reply.to_string()).collect() })
+    }
+
+    #[tokio::test]
+    async fn compares_from_the_same_number_of_passages_and_drops_misattributed_citations() {
+        let (directories, config) = corpus("compare");
+        let mut retriever = Retriever::new(&directories, &config).unwrap();
+        let (marx, lenin) = (author("Karl Marx"), author("Vladimir Lenin"));
+        let settings = AnsweringConfig { passages_per_author: 3, ..config.answering.clone() };
+
+        // Marx only has two passages, so Lenin is given two as well: 1 and 2 are Marx's, and 3 and 4 Lenin's
+        let reply: &str = "## Karl Marx\nThe state serves a class [1][3].\n\n## Vladimir Lenin\nIt will wither away [3].\n\n## Comparison\nThey agree [2, 4].";
+        let answer: Answer = compare_authors(&mut retriever, &mock(&[reply]), None, &settings, &[&marx, &lenin], "What is the state?").await.unwrap();
+
+        assert_eq!(answer.author, "Karl Marx and Vladimir Lenin");
+        assert_eq!(answer.passages.len(), 4);
+        assert!(answer.passages[..2].iter().all(|passage| passage.metadata.author == "Karl Marx"));
+        assert!(answer.passages[2..].iter().all(|passage| passage.metadata.author == "Vladimir Lenin"));
+        assert!(answer.text.contains("The state serves a class [1].\n"), "{}", answer.text);
+        assert!(answer.text.contains("They agree [2][4]."), "{}", answer.text);
+        assert_eq!(answer.cited, vec![1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn strict_comparison_refuses_when_an_author_is_not_cited() {
+        let (directories, config) = corpus("compare-uncited");
+        let mut retriever = Retriever::new(&directories, &config).unwrap();
+        let (marx, lenin) = (author("Karl Marx"), author("Vladimir Lenin"));
+        let settings = AnsweringConfig { strict: true, minimum_similarity: -1.0, ..config.answering.clone() };
+
+        let reply: &str = "## Karl Marx\nThe state serves a class [1].\n\n## Vladimir Lenin\nThe passages do not say.";
+        let answer: Answer = compare_authors(&mut retriever, &mock(&[reply]), None, &settings, &[&marx, &lenin], "What is the state?").await.unwrap();
+
+        assert!(answer.is_refused());
+        assert!(answer.refusal.unwrap().contains("Vladimir Lenin"));
+    }
+
+    #[tokio::test]
+    async fn debates_in_turns_from_each_authors_own_passages() {
+        let (directories, config) = corpus("debate");
+        let mut retriever = Retriever::new(&directories, &config).unwrap();
+        let (marx, lenin) = (author("Karl Marx"), author("Vladimir Lenin"));
+        let settings = AnsweringConfig { debate_rounds: 2, ..config.answering.clone() };
+
+        let mut seen: usize = 0;
+        let debate: Debate = debate(&mut retriever, &mock(&[]), |_| seen += 1, &settings, &[&marx, &lenin], "What is the state?").await.unwrap();
+
+        assert_eq!(seen, 4);
+        let speakers: Vec<&str> = debate.turns.iter().map(|turn| turn.author.as_str()).collect();
+        assert_eq!(speakers, vec!["Karl Marx", "Vladimir Lenin", "Karl Marx", "Vladimir Lenin"]);
+
+        for turn in &debate.turns {
+            assert_eq!(turn.question, "What is the state?");
+            assert!(turn.disclaimer.is_none());
+            assert!(turn.passages.iter().all(|passage| passage.metadata.author == turn.author));
+            assert_eq!(turn.cited, vec![1]);
+        }
+        assert_eq!(debate.disclaimers.len(), 2);
+        assert!(debate.usage.total() > 0);
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::{BTreeSet, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::setup::config::Config;
+use crate::setup::paths::Directories;
+use crate::indices::filters::Filter;
+use crate::indices::keywords::{Analyzer, KeywordDocument};
+use crate::indices::reranking::CrossEncoder;
+use crate::indices::retrieval::{Passage, Retriever, StageScores};
+use crate::data_preparation::segmentation::{SegmentationConfig, Segmenter};
+use crate::generation::answering::{cited_numbers, replace_markers, Answer};
+
+// Anything shorter in quotation marks is taken for a term, such as "surplus value", rather than
+// for the author's exact words
+const MINIMUM_QUOTATION_WORDS: usize = 4;
+
+
+// The [faithfulness] section of giants.toml
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaithfulnessConfig {
+    // Whether answers are checked against their passages at all
+    pub enabled: bool,
+    // The share of a claim's terms that must appear in the passages that it cites
+    pub minimum_overlap: f64,
+    // A cross-encoder trained for entailment (NLI) in the models directory, with an "entailment" label
+    pub nli_model: Option<String>,
+    // The probability that the best of a claim's passages entails it must be at least this
+    pub minimum_entailment: f32,
+    pub nli_maximum_length: usize,
+}
+
+
+impl Default for FaithfulnessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            minimum_overlap: 0.5,
+            nli_model: None,
+            minimum_entailment: 0.5,
+            nli_maximum_length: 512,
+        }
+    }
+}
+
+
+// One sentence of an answer, and how well the passages that it cites bear it out
+#[derive(Clone, Debug, Serialize)]
+pub struct ClaimCheck {
+    // Without its citation markers
+    pub text: String,
+    pub cited: Vec<usize>,
+    // The share of its terms that appear in the passages that it cites
+    pub overlap: f64,
+    // The probability that the best of the passages that it cites entails it, if an NLI model is configured
+    pub entailment: Option<f32>,
+    // Why it is not supported, if it is not
+    pub problem: Option<String>,
+}
+
+
+impl ClaimCheck {
+
+    pub fn is_supported(&self) -> bool {
+        self.problem.is_none()
+    }
+}
+
+
+// Something given in quotation marks as the author's words, and where it is found in the corpus
+#[derive(Clone, Debug, Serialize)]
+pub struct QuotationCheck {
+    pub quotation: String,
+    pub source: Option<Passage>,
+}
+
+
+impl QuotationCheck {
+
+    pub fn is_found(&self) -> bool {
+        self.source.is_some()
+    }
+}
+
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct FaithfulnessReport {
+    pub claims: Vec<ClaimCheck>,
+    pub quotations: Vec<QuotationCheck>,
+}
+
+
+impl FaithfulnessReport {
+
+    pub fn unsupported_claims(&self) -> impl Iterator<Item = &ClaimCheck> {
+        self.claims.iter().filter(|claim| !claim.is_supported())
+    }
+
+    pub fn missing_quotations(&self) -> impl Iterator<Item = &QuotationCheck> {
+        self.quotations.iter().filter(|quotation| !quotation.is_found())
+    }
+
+    pub fn is_faithful(&self) -> bool {
+        self.unsupported_claims().next().is_none() && self.missing_quotations().next().is_none()
+    }
+}
+
+
+// Lowercase words without punctuation, so that quotations match however they are punctuated
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+
+fn contains_words(haystack: &[String], needle: &[String]) -> bool {
+    !needle.is_empty() && haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+
+// The sentences of an answer, without headings, with the passages that each one cites. A marker
+// that the segmenter leaves at the start of the next sentence belongs to the one before it.
+fn claims(segmenter: &Segmenter, text: &str) -> Vec<(String, BTreeSet<usize>)> {
+    let mut claims: Vec<(String, BTreeSet<usize>)> = Vec::new();
+
+    for line in text.lines().filter(|line| !line.trim_start().starts_with('#')) {
+        for sentence in segmenter.split(line) {
+            let mut rest: &str = sentence;
+            while let Some((_, previous)) = claims.last_mut() {
+                let Some(end) = rest.strip_prefix('[').and(rest.find(']')) else { break };
+                let numbers: BTreeSet<usize> = cited_numbers(&rest[..=end]);
+                if numbers.is_empty() {
+                    break
+                }
+                previous.extend(numbers);
+                rest = rest[end + 1..].trim_start();
+            }
+
+            let bare: String = replace_markers(rest, |_| None);
+            if bare.chars().any(char::is_alphanumeric) {
+                claims.push((bare.trim().to_string(), cited_numbers(rest)));
+            }
+        }
+    }
+
+    claims
+}
+
+
+// What the answer puts in quotation marks, straight or curly, that is long enough to be a quotation
+fn quotations(text: &str) -> Vec<String> {
+    let mut quotations: Vec<String> = Vec::new();
+    let mut start: Option<usize> = None;
+
+    for (offset, c) in text.char_indices() {
+        match (c, start) {
+            ('“', _) | ('"', None) => start = Some(offset + c.len_utf8()),
+            ('”', Some(first)) | ('"', Some(first)) => {
+                quotations.push(text[first..offset].to_string());
+                start = None;
+            },
+            _ => (),
+        }
+    }
+
+    quotations.into_iter()
+        .map(|quotation| replace_markers(&quotation, |_| None).trim().to_string())
+        .filter(|quotation| words(quotation).len() >= MINIMUM_QUOTATION_WORDS)
+        .collect()
+}
+
+
+// Checks each sentence of an answer against the passages that it cites, and looks for each of its
+// quotations in the corpus, word for word
+pub struct FaithfulnessChecker {
+    config: FaithfulnessConfig,
+    segmentation: SegmentationConfig,
+    // With the position of its entailment label
+    nli: Option<(CrossEncoder, usize)>,
+}
+
+
+impl FaithfulnessChecker {
+
+    pub fn new(directories: &Directories, config: &Config) -> Result<Self, anyhow::Error> {
+        let nli: Option<(CrossEncoder, usize)> = match &config.faithfulness.nli_model {
+            Some(name) => {
+                let model: CrossEncoder = CrossEncoder::load(directories, name, config.faithfulness.nli_maximum_length)?;
+                let Some(entailment) = model.label("entailment") else {
+                    anyhow::bail!("{} is not an NLI model, as it has no entailment label", name);
+                };
+                Some((model, entailment))
+            },
+            None => None,
+        };
+
+        Ok(Self { config: config.faithfulness.clone(), segmentation: config.segmentation.clone(), nli })
+    }
+
+    fn check_claim(&self, analyzer: &Analyzer, answer: &Answer, text: String, cited: BTreeSet<usize>) -> Result<Option<ClaimCheck>, anyhow::Error> {
+        let terms: HashSet<String> = analyzer.terms(&text).into_iter().collect();
+        if terms.is_empty() {
+            return Ok(None);
+        }
+
+        let passages: Vec<&Passage> = cited.iter().filter_map(|number| answer.passages.get(number.checked_sub(1)?)).collect();
+        let cited: Vec<usize> = cited.into_iter().collect();
+        if passages.is_empty() {
+            let problem: String = String::from("it cites none of the passages");
+            return Ok(Some(ClaimCheck { text, cited, overlap: 0.0, entailment: None, problem: Some(problem) }));
+        }
+
+        let passage_terms: HashSet<String> = passages.iter().flat_map(|passage| analyzer.terms(&passage.text)).collect();
+        let overlap: f64 = terms.iter().filter(|term| passage_terms.contains(*term)).count() as f64 / terms.len() as f64;
+
+        let entailment: Option<f32> = match &self.nli {
+            Some((model, label)) => {
+                let pairs: Vec<(&str, &str)> = passages.iter().map(|passage| (passage.text.as_str(), text.as_str())).collect();
+                let probabilities: Vec<Vec<f32>> = model.classify(&pairs)?;
+                Some(probabilities.iter().map(|probabilities| probabilities[*label]).fold(0.0, f32::max))
+            },
+            None => None,
+        };
+
+        let problem: Option<String> = if overlap < self.config.minimum_overlap {
+            Some(format!("only {:.0}% of its terms are in the passages that it cites", overlap * 100.0))
+        } else {
+            entailment.filter(|entailment| *entailment < self.config.minimum_entailment)
+                .map(|entailment| format!("the passages that it cites only entail it with a probability of {:.2}", entailment))
+        };
+
+        Ok(Some(ClaimCheck { text, cited, overlap, entailment, problem }))
+    }
+
+    // Quotations are looked for first in the answer's own passages, and then in everything by the
+    // authors of those passages. Chunks overlap, so a quotation is only missed where it runs across
+    // the boundary between two chunks for longer than their overlap.
+    fn find_quotation(&self, retriever: &mut Retriever, answer: &Answer, quotation: &str) -> Result<Option<Passage>, anyhow::Error> {
+        let quoted: Vec<String> = words(quotation);
+        if let Some(passage) = answer.passages.iter().find(|passage| contains_words(&words(&passage.text), &quoted)) {
+            return Ok(Some(passage.clone()));
+        }
+
+        let authors: BTreeSet<String> = answer.passages.iter().map(|passage| passage.metadata.author.clone()).collect();
+        let filter = Filter { authors: authors.into_iter().collect(), ..Filter::default() };
+
+        // Most chunks can be ruled out without splitting them into words
+        let longest: &str = quoted.iter().max_by_key(|word| word.len()).map(String::as_str).unwrap_or_default();
+        let found: Option<&KeywordDocument> = retriever.documents(&filter)?.into_iter()
+            .filter(|document| document.document.to_lowercase().contains(longest))
+            .find(|document| contains_words(&words(&document.document), &quoted));
+
+        Ok(found.map(|document| Passage {
+            id: document.id.clone(),
+            text: document.document.clone(),
+            metadata: document.metadata.clone(),
+            scores: StageScores::default(),
+        }))
+    }
+
+    pub fn check(&self, retriever: &mut Retriever, answer: &Answer) -> Result<FaithfulnessReport, anyhow::Error> {
+        if answer.is_refused() {
+            return Ok(FaithfulnessReport::default());
+        }
+
+        let language: &str = answer.passages.first().map(|passage| passage.metadata.language.as_str()).unwrap_or(&self.segmentation.default_language);
+        let segmenter: Segmenter = self.segmentation.segmenter(language);
+        let analyzer: Analyzer = Analyzer::for_language(language);
+
+        let mut report: FaithfulnessReport = FaithfulnessReport::default();
+        for (text, cited) in claims(&segmenter, &answer.text) {
+            report.claims.extend(self.check_claim(&analyzer, answer, text, cited)?);
+        }
+        for quotation in quotations(&answer.text) {
+            let source: Option<Passage> = self.find_quotation(retriever, answer, &quotation)?;
+            report.quotations.push(QuotationCheck { quotation, source });
+        }
+
+        log::info!(
+            "{} of {} claims are supported by the passages that they cite, and {} of {} quotations were found in the corpus",
+            report.claims.iter().filter(|claim| claim.is_supported()).count(), report.claims.len(),
+            report.quotations.iter().filter(|quotation| quotation.is_found()).count(), report.quotations.len(),
+        );
+        Ok(report)
+    }
+
+    // Attaches the report to the answer. In strict mode, an answer that quotes words which are not
+    // in the corpus is refused, since they would be put in the author's mouth.
+    pub fn check_answer(&self, retriever: &mut Retriever, answer: Answer, strict: bool) -> Result<Answer, anyhow::Error> {
+        let report: FaithfulnessReport = self.check(retriever, &answer)?;
+
+        let missing: Vec<String> = report.missing_quotations().map(|quotation| format!("\"{}\"", quotation.quotation)).collect();
+        if strict && !missing.is_empty() {
+            let reason: String = format!("The answer quotes words that are not in the corpus: {}", missing.join(", "));
+            let refused: Answer = Answer::refused(&answer.author, &answer.question, answer.passages, reason);
+            return Ok(Answer { usage: answer.usage, disclaimer: answer.disclaimer, faithfulness: Some(report), ..refused });
+        }
+
+        Ok(Answer { faithfulness: Some(report), ..answer })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indices::testing::metadata;
+
+    fn segmenter() -> Segmenter {
+        SegmentationConfig::default().segmenter("en")
+    }
+
+    fn claim(text: &str, cited: &[usize]) -> (String, BTreeSet<usize>) {
+        (text.to_string(), cited.iter().copied().collect())
+    }
+
+    fn answer(passages: &[&str]) -> Answer {
+        let passages: Vec<Passage> = passages.iter().enumerate()
+            .map(|(index, text)| Passage {
+                id: format!("marx-{}", index + 1),
+                text: text.to_string(),
+                metadata: metadata("Karl Marx", "Capital", index as i64 + 1),
+                scores: StageScores::default(),
+            })
+            .collect();
+        Answer::refused("Karl Marx", "What is value?", passages, String::new())
+    }
+
+    fn checker() -> FaithfulnessChecker {
+        FaithfulnessChecker { config: FaithfulnessConfig::default(), segmentation: SegmentationConfig::default(), nli: None }
+    }
+
+    #[test]
+    fn splits_answers_into_claims_with_their_citations() {
+        let found = claims(&segmenter(), "## Karl Marx\nLabour is the substance of value [1]. The state withers away [2, 3].");
+        assert_eq!(found, vec![claim("Labour is the substance of value.", &[1]), claim("The state withers away.", &[2, 3])]);
+    }
+
+    #[test]
+    fn gives_markers_at_the_start_of_a_sentence_to_the_one_before() {
+        let found = claims(&segmenter(), "Labour is the substance of value. [1] [2] The state withers away [3].");
+        assert_eq!(found, vec![claim("Labour is the substance of value.", &[1, 2]), claim("The state withers away.", &[3])]);
+
+        // With no sentence before it, a marker stays with its own
+        let found = claims(&segmenter(), "[1] Labour is the substance of value.");
+        assert_eq!(found, vec![claim("Labour is the substance of value.", &[1])]);
+
+        // Brackets that are not citations are left where they are
+        let found = claims(&segmenter(), "Labour is the substance of value [1]. [Sic] The state withers away.");
+        assert_eq!(found[1], claim("[Sic] The state withers away.", &[]));
+    }
+
+    #[test]
+    fn finds_quotations_in_straight_and_curly_quotes() {
+        let text: &str = "He wrote that “labour is the substance of value” [1], and that \"the state is an organ of class rule [2]\".";
+        assert_eq!(quotations(text), vec!["labour is the substance of value", "the state is an organ of class rule"]);
+    }
+
+    #[test]
+    fn ignores_unclosed_and_short_quotations() {
+        assert!(quotations("He wrote that “labour is the substance of value, and went on").is_empty());
+        assert!(quotations("He wrote that \"labour is the substance of value, and went on").is_empty());
+        assert!(quotations("Marx calls this \"surplus value\", or “profit rate” for short.").is_empty());
+        assert_eq!(quotations("A term, \"surplus value\", then “one two three four”."), vec!["one two three four"]);
+    }
+
+    #[test]
+    fn checks_claims_against_the_terms_of_the_passages_that_they_cite() {
+        let analyzer: Analyzer = Analyzer::for_language("en");
+        let answer: Answer = answer(&["Labour is the substance of value, and labour time its measure."]);
+        let check = |text: &str, cited: &[usize]| checker().check_claim(&analyzer, &answer, text.to_string(), cited.iter().copied().collect()).unwrap();
+
+        let supported: ClaimCheck = check("Labour is the substance of value.", &[1]).unwrap();
+        assert!(supported.is_supported() && supported.overlap == 1.0);
+
+        // Half of its terms is just enough
+        let half: ClaimCheck = check("Labour builds.", &[1]).unwrap();
+        assert!(half.is_supported() && half.overlap == 0.5);
+
+        let unsupported: ClaimCheck = check("Labour creates profits, rents, and wages.", &[1]).unwrap();
+        assert!(!unsupported.is_supported() && unsupported.overlap < 0.5);
+
+        let uncited: ClaimCheck = check("Labour is the substance of value.", &[4]).unwrap();
+        assert_eq!(uncited.problem.as_deref(), Some("it cites none of the passages"));
+
+        // Nothing but stop words is not a claim at all
+        assert!(check("It is so.", &[1]).is_none());
+    }
+}
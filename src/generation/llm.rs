@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+use std::ops::AddAssign;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::generation::mock::{MockBackend, MockConfig};
+use crate::generation::openai::{OpenAiBackend, OpenAiConfig};
+
+
+// The [llm] section of giants.toml. Each profile names a backend and its settings, so that the
+// same code can run against a hosted API, a local server, or the mock.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LlmConfig {
+    // The profile that is used unless another is asked for
+    pub profile: String,
+    pub profiles: BTreeMap<String, LlmProfile>,
+}
+
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        Self {
+            profile: String::from("default"),
+            profiles: BTreeMap::from([(String::from("default"), LlmProfile::OpenAi(OpenAiConfig::default()))]),
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum LlmProfile {
+    // Any chat completions endpoint that speaks OpenAI's API, which includes llama.cpp's server, vLLM, and Ollama
+    #[serde(rename = "openai")]
+    OpenAi(OpenAiConfig),
+    Mock(MockConfig),
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+
+impl ChatMessage {
+
+    pub fn system(content: &str) -> Self {
+        Self { role: String::from("system"), content: content.to_string() }
+    }
+
+    pub fn user(content: &str) -> Self {
+        Self { role: String::from("user"), content: content.to_string() }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    // Whether any of the counts were estimated, because the server did not report them
+    pub estimated: bool,
+}
+
+
+impl TokenUsage {
+
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    // Roughly four characters to a token, for servers that do not count them
+    pub fn estimate(messages: &[ChatMessage], reply: &str) -> Self {
+        let prompt: usize = messages.iter().map(|message| message.content.chars().count()).sum();
+        Self {
+            prompt_tokens: prompt.div_ceil(4) as u64,
+            completion_tokens: reply.chars().count().div_ceil(4) as u64,
+            estimated: true,
+        }
+    }
+}
+
+
+impl AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.estimated |= other.estimated;
+    }
+}
+
+
+// Given each piece of a reply as it arrives
+pub type OnText<'a> = dyn FnMut(&str) + Send + 'a;
+
+
+#[derive(Clone, Debug)]
+pub struct Completion {
+    pub text: String,
+    pub usage: TokenUsage,
+}
+
+
+// Every way of generating text implements this, so that retrieval and answering never depend on
+// a particular vendor
+#[async_trait]
+pub trait LlmBackend: Send + Sync {
+
+    // The backend and model, for logging
+    fn name(&self) -> String;
+
+    // Passes each piece of the reply to the closure as it arrives, and returns the whole of it
+    async fn stream(&self, messages: &[ChatMessage], on_text: &mut OnText<'_>) -> Result<Completion, anyhow::Error>;
+
+    // Everything that this backend has used since it was created
+    fn total_usage(&self) -> TokenUsage;
+
+    async fn complete(&self, messages: &[ChatMessage]) -> Result<Completion, anyhow::Error> {
+        self.stream(messages, &mut |_| {}).await
+    }
+}
+
+
+// The named profile, or the configured one if no name is given
+pub fn load_backend(config: &LlmConfig, profile: Option<&str>) -> Result<Box<dyn LlmBackend>, anyhow::Error> {
+    let name: &str = profile.unwrap_or(&config.profile);
+    let Some(profile) = config.profiles.get(name) else {
+        let known: Vec<&str> = config.profiles.keys().map(String::as_str).collect();
+        anyhow::bail!("There is no LLM profile named {} (the profiles are: {})", name, known.join(", "));
+    };
+
+    log::info!("Using the {} LLM profile", name);
+    Ok(match profile {
+        LlmProfile::OpenAi(config) => Box::new(OpenAiBackend::new(config)?),
+        LlmProfile::Mock(config) => Box::new(MockBackend::new(config)),
+    })
+}
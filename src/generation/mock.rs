@@ -0,0 +1,77 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::generation::llm::{ChatMessage, Completion, LlmBackend, OnText, TokenUsage};
+
+
+// A mock profile in giants.toml, for running everything without a model
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MockConfig {
+    // Given in turn, starting again from the first once they run out
+    pub replies: Vec<String>,
+}
+
+
+// Replies without a model, the same way every time. Without any replies to give, it repeats the
+// last line of the request and cites the first passage. The line is not put in quotation marks,
+// which would pass it off as a quotation from the passages.
+pub struct MockBackend {
+    replies: Vec<String>,
+    next: AtomicUsize,
+    usage: Mutex<TokenUsage>,
+}
+
+
+impl MockBackend {
+
+    pub fn new(config: &MockConfig) -> Self {
+        Self { replies: config.replies.clone(), next: AtomicUsize::new(0), usage: Mutex::new(TokenUsage::default()) }
+    }
+
+    fn reply(&self, messages: &[ChatMessage]) -> String {
+        if !self.replies.is_empty() {
+            let next: usize = self.next.fetch_add(1, Ordering::SeqCst);
+            return self.replies[next % self.replies.len()].clone();
+        }
+
+        let last_line: &str = messages.last()
+            .and_then(|message| message.content.lines().rev().find(|line| !line.trim().is_empty()))
+            .unwrap_or_default();
+        let last_line: String = last_line.replace(['"', '“', '”'], "");
+        format!("This is a mock reply to {} [1].", last_line.trim())
+    }
+}
+
+
+#[async_trait]
+impl LlmBackend for MockBackend {
+
+    fn name(&self) -> String {
+        String::from("mock")
+    }
+
+    // Words are counted as tokens, and streamed one at a time
+    async fn stream(&self, messages: &[ChatMessage], on_text: &mut OnText<'_>) -> Result<Completion, anyhow::Error> {
+        let text: String = self.reply(messages);
+        for (index, word) in text.split(' ').enumerate() {
+            let piece: String = if index == 0 {word.to_string()} else {format!(" {}", word)};
+            on_text(&piece);
+        }
+
+        let usage = TokenUsage {
+            prompt_tokens: messages.iter().map(|message| message.content.split_whitespace().count() as u64).sum(),
+            completion_tokens: text.split_whitespace().count() as u64,
+            estimated: false,
+        };
+        *self.usage.lock().unwrap() += usage;
+        Ok(Completion { text, usage })
+    }
+
+    fn total_usage(&self) -> TokenUsage {
+        *self.usage.lock().unwrap()
+    }
+}
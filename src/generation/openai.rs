@@ -0,0 +1,380 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::generation::llm::{ChatMessage, Completion, LlmBackend, OnText, TokenUsage};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+// An openai profile in giants.toml
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenAiConfig {
+    // Everything up to /chat/completions, such as https://api.openai.com/v1 or http://localhost:8080/v1
+    pub url: String,
+    pub model: String,
+    // Sent as a bearer token. The variable named by api_key_variable takes precedence.
+    pub api_key: Option<String>,
+    pub api_key_variable: String,
+    pub temperature: f64,
+    pub max_tokens: usize,
+    // How long to wait for a reply, or, when streaming, for each piece of one
+    pub timeout_seconds: u64,
+    // How many more times a request is sent after it fails for a reason that might pass
+    pub retries: u32,
+}
+
+
+impl Default for OpenAiConfig {
+    fn default() -> Self {
+        Self {
+            url: String::from("http://localhost:8080/v1"),
+            model: String::from("default"),
+            api_key: None,
+            api_key_variable: String::from("GIANTS_LLM_API_KEY"),
+            temperature: 0.2,
+            max_tokens: 1024,
+            timeout_seconds: 120,
+            retries: 2,
+        }
+    }
+}
+
+
+// Failures worth retrying are timeouts, dropped connections, rate limits, and errors on the server
+enum Failure {
+    Transient(anyhow::Error),
+    Permanent(anyhow::Error),
+}
+
+
+impl From<reqwest::Error> for Failure {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() {
+            Failure::Transient(e.into())
+        } else {
+            Failure::Permanent(e.into())
+        }
+    }
+}
+
+
+fn usage_of(response: &Value) -> Option<TokenUsage> {
+    let usage: &Value = response.get("usage").filter(|usage| !usage.is_null())?;
+    Some(TokenUsage {
+        prompt_tokens: usage.get("prompt_tokens")?.as_u64()?,
+        completion_tokens: usage.get("completion_tokens")?.as_u64()?,
+        estimated: false,
+    })
+}
+
+
+// Passes on the text of one line of a streamed reply, and returns whether it was the last
+fn read_event(line: &[u8], reply: &mut String, usage: &mut Option<TokenUsage>, on_text: &mut OnText<'_>) -> Result<bool, Failure> {
+    let line: &str = std::str::from_utf8(line).map_err(|e| Failure::Permanent(e.into()))?.trim();
+
+    let Some(data) = line.strip_prefix("data:").map(str::trim) else { return Ok(false) };
+    if data == "[DONE]" {
+        return Ok(true);
+    }
+
+    let event: Value = serde_json::from_str(data).map_err(|e| Failure::Permanent(e.into()))?;
+    if let Some(text) = event.pointer("/choices/0/delta/content").and_then(Value::as_str) {
+        on_text(text);
+        reply.push_str(text);
+    }
+    *usage = usage_of(&event).or(*usage);
+    Ok(false)
+}
+
+
+pub struct OpenAiBackend {
+    client: reqwest::Client,
+    config: OpenAiConfig,
+    api_key: Option<String>,
+    usage: Mutex<TokenUsage>,
+}
+
+
+impl OpenAiBackend {
+
+    pub fn new(config: &OpenAiConfig) -> Result<Self, anyhow::Error> {
+        let client = reqwest::Client::builder().connect_timeout(CONNECT_TIMEOUT).build()?;
+        let api_key: Option<String> = std::env::var(&config.api_key_variable).ok().or(config.api_key.clone());
+        Ok(Self { client, config: config.clone(), api_key, usage: Mutex::new(TokenUsage::default()) })
+    }
+
+    fn url(&self) -> String {
+        format!("{}/chat/completions", self.config.url.trim_end_matches('/'))
+    }
+
+    async fn send(&self, messages: &[ChatMessage], stream: bool) -> Result<reqwest::Response, Failure> {
+        let mut body: Value = json!({
+            "model": self.config.model,
+            "messages": messages,
+            "temperature": self.config.temperature,
+            "max_tokens": self.config.max_tokens,
+        });
+        if stream {
+            body["stream"] = json!(true);
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+
+        let mut request = self.client.post(self.url())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string());
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        // A stream can run for longer than this as a whole, so only its start and its pieces are timed
+        let timeout: Duration = Duration::from_secs(self.config.timeout_seconds);
+        if !stream {
+            request = request.timeout(timeout);
+        }
+
+        let response: reqwest::Response = tokio::time::timeout(timeout, request.send()).await
+            .map_err(|_| Failure::Transient(anyhow::anyhow!("{} did not reply within {} seconds", self.url(), timeout.as_secs())))??;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let error = anyhow::anyhow!("{} returned {}: {}", self.url(), status, response.text().await.unwrap_or_default());
+        if status.as_u16() == 429 || status.is_server_error() {
+            Err(Failure::Transient(error))
+        } else {
+            Err(Failure::Permanent(error))
+        }
+    }
+
+    async fn try_complete(&self, messages: &[ChatMessage]) -> Result<Completion, Failure> {
+        let text: String = self.send(messages, false).await?.text().await?;
+        let response: Value = serde_json::from_str(&text).map_err(|e| Failure::Permanent(e.into()))?;
+
+        let reply: String = response.pointer("/choices/0/message/content")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| Failure::Permanent(anyhow::anyhow!("{} did not return a reply: {}", self.url(), text)))?;
+
+        let usage: TokenUsage = usage_of(&response).unwrap_or(TokenUsage::estimate(messages, &reply));
+        Ok(Completion { text: reply, usage })
+    }
+
+    // Reads the server-sent events of a streamed reply. Once any of it has been passed on, a failure
+    // can no longer be retried without repeating it.
+    async fn try_stream(&self, messages: &[ChatMessage], on_text: &mut OnText<'_>) -> Result<Completion, Failure> {
+        let mut response: reqwest::Response = self.send(messages, true).await?;
+        let timeout: Duration = Duration::from_secs(self.config.timeout_seconds);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut reply: String = String::new();
+        let mut usage: Option<TokenUsage> = None;
+        let mut is_done: bool = false;
+
+        while !is_done {
+            let piece = tokio::time::timeout(timeout, response.chunk()).await
+                .map_err(|_| anyhow::anyhow!("{} stopped sending its reply for {} seconds", self.url(), timeout.as_secs()));
+
+            let piece = match piece {
+                Ok(Ok(piece)) => piece,
+                Ok(Err(e)) if reply.is_empty() => return Err(e.into()),
+                Ok(Err(e)) => return Err(Failure::Permanent(e.into())),
+                Err(e) if reply.is_empty() => return Err(Failure::Transient(e)),
+                Err(e) => return Err(Failure::Permanent(e)),
+            };
+            let Some(piece) = piece else { break };
+            buffer.extend_from_slice(&piece);
+
+            while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                is_done = read_event(&line, &mut reply, &mut usage, on_text)?;
+                if is_done {
+                    break
+                }
+            }
+        }
+
+        // The last event may have ended the stream without a newline after it
+        if !is_done {
+            read_event(&buffer, &mut reply, &mut usage, on_text)?;
+        }
+
+        let usage: TokenUsage = usage.unwrap_or(TokenUsage::estimate(messages, &reply));
+        Ok(Completion { text: reply, usage })
+    }
+
+    // Sends the request again after failures that might pass, waiting longer each time
+    async fn request(&self, messages: &[ChatMessage], on_text: &mut OnText<'_>, stream: bool) -> Result<Completion, anyhow::Error> {
+        let attempts: u32 = self.config.retries + 1;
+        let mut number: u32 = 1;
+
+        loop {
+            let result: Result<Completion, Failure> = if stream {
+                self.try_stream(messages, on_text).await
+            } else {
+                self.try_complete(messages).await
+            };
+
+            match result {
+                Ok(completion) => {
+                    *self.usage.lock().unwrap() += completion.usage;
+                    return Ok(completion);
+                },
+                Err(Failure::Transient(e)) if number < attempts => {
+                    log::warn!("Attempt {} of {} to reach {} failed: {}", number, attempts, self.url(), e);
+                    tokio::time::sleep(Duration::from_secs(2u64.pow(number))).await;
+                    number += 1;
+                },
+                Err(Failure::Transient(e)) | Err(Failure::Permanent(e)) => return Err(e),
+            }
+        }
+    }
+}
+
+
+#[async_trait]
+impl LlmBackend for OpenAiBackend {
+
+    fn name(&self) -> String {
+        format!("{} at {}", self.config.model, self.config.url)
+    }
+
+    async fn stream(&self, messages: &[ChatMessage], on_text: &mut OnText<'_>) -> Result<Completion, anyhow::Error> {
+        self.request(messages, on_text, true).await
+    }
+
+    async fn complete(&self, messages: &[ChatMessage]) -> Result<Completion, anyhow::Error> {
+        self.request(messages, &mut |_| {}, false).await
+    }
+
+    fn total_usage(&self) -> TokenUsage {
+        *self.usage.lock().unwrap()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::indices::testing::{serve, MockReply};
+
+    fn delta(text: &str) -> String {
+        json!({ "choices": [{ "delta": { "content": text } }] }).to_string()
+    }
+
+    // Answers each request with the reply for its number, and counts them
+    async fn mock_backend(retries: u32, replies: Vec<MockReply>) -> (OpenAiBackend, Arc<AtomicUsize>) {
+        let requests: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+        let counter: Arc<AtomicUsize> = requests.clone();
+        let replies: Mutex<Vec<MockReply>> = Mutex::new(replies.into_iter().rev().collect());
+
+        let url: String = serve(move |_, path, _| {
+            assert_eq!(path, "/v1/chat/completions");
+            counter.fetch_add(1, Ordering::SeqCst);
+            replies.lock().unwrap().pop().unwrap()
+        }).await;
+
+        let config = OpenAiConfig { url: format!("{}/v1", url), timeout_seconds: 1, retries, ..OpenAiConfig::default() };
+        (OpenAiBackend::new(&config).unwrap(), requests)
+    }
+
+    async fn stream(backend: &OpenAiBackend) -> (Result<Completion, anyhow::Error>, Vec<String>) {
+        let mut pieces: Vec<String> = Vec::new();
+        let result = backend.stream(&[ChatMessage::user("What is a commodity?")], &mut |text| pieces.push(text.to_string())).await;
+        (result, pieces)
+    }
+
+    #[tokio::test]
+    async fn streams_the_reply_until_done() {
+        let usage: String = json!({ "choices": [], "usage": { "prompt_tokens": 12, "completion_tokens": 3 } }).to_string();
+        let reply = MockReply::events(&[&delta("A commodity"), &delta(" is a thing"), &usage, "[DONE]", "not json"]);
+        let (backend, _) = mock_backend(0, vec![reply]).await;
+
+        let (result, pieces) = stream(&backend).await;
+        let completion: Completion = result.unwrap();
+
+        assert_eq!(pieces, ["A commodity", " is a thing"]);
+        assert_eq!(completion.text, "A commodity is a thing");
+        assert_eq!((completion.usage.prompt_tokens, completion.usage.completion_tokens, completion.usage.estimated), (12, 3, false));
+        assert_eq!(backend.total_usage().total(), 15);
+    }
+
+    #[tokio::test]
+    async fn reads_a_last_event_without_a_newline() {
+        let mut reply = MockReply::events(&[&delta("A commodity")]);
+        reply.pieces.push(format!("data: {}", delta(" is a thing")));
+        let (backend, _) = mock_backend(0, vec![reply]).await;
+
+        let (result, _) = stream(&backend).await;
+        let completion: Completion = result.unwrap();
+
+        assert_eq!(completion.text, "A commodity is a thing");
+        assert!(completion.usage.estimated);
+    }
+
+    #[tokio::test]
+    async fn estimates_usage_that_the_server_does_not_report() {
+        let reply = MockReply::json(200, json!({ "choices": [{ "message": { "content": "Twelve chars" } }] }));
+        let (backend, _) = mock_backend(0, vec![reply]).await;
+
+        let messages: Vec<ChatMessage> = vec![ChatMessage::user("What is a commodity?")];
+        let completion: Completion = backend.complete(&messages).await.unwrap();
+
+        assert_eq!(completion.usage, TokenUsage::estimate(&messages, "Twelve chars"));
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limits_and_server_errors() {
+        for status in [429, 503] {
+            let ok = MockReply::json(200, json!({ "choices": [{ "message": { "content": "Value" } }] }));
+            let (backend, requests) = mock_backend(1, vec![MockReply::json(status, json!({ "error": "try again" })), ok]).await;
+
+            assert_eq!(backend.complete(&[ChatMessage::user("What is value?")]).await.unwrap().text, "Value");
+            assert_eq!(requests.load(Ordering::SeqCst), 2);
+        }
+
+        let (backend, requests) = mock_backend(0, vec![MockReply::json(503, json!({ "error": "overloaded" }))]).await;
+        assert!(backend.complete(&[ChatMessage::user("What is value?")]).await.unwrap_err().to_string().contains("503"));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let (backend, requests) = mock_backend(2, vec![MockReply::json(400, json!({ "error": "bad request" }))]).await;
+
+        assert!(backend.complete(&[ChatMessage::user("What is value?")]).await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_after_part_of_the_reply_was_passed_on() {
+        let mut reply = MockReply::events(&[&delta("A commodity")]);
+        reply.is_cut_short = true;
+        let (backend, requests) = mock_backend(2, vec![reply]).await;
+
+        let (result, pieces) = stream(&backend).await;
+
+        assert!(result.is_err());
+        assert_eq!(pieces, ["A commodity"]);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn times_out_a_stream_that_stops() {
+        let mut reply = MockReply::events(&[&delta("A commodity")]);
+        reply.pause = Duration::from_secs(3);
+        let (backend, _) = mock_backend(0, vec![reply]).await;
+
+        let (result, pieces) = stream(&backend).await;
+
+        assert!(result.unwrap_err().to_string().contains("stopped sending"));
+        assert!(pieces.is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+use crate::sources::authors::Author;
+use crate::indices::retrieval::{Passage, Retriever};
+use crate::generation::llm::{ChatMessage, Completion, LlmBackend, OnText};
+use crate::generation::answering::{cited_answer, generate, low_confidence, numbered_passages, retrieve_passages, Answer, AnsweringConfig};
+
+// Used for authors whose persona does not give a disclaimer of its own. {author} and {era} are
+// replaced with the author's name and era.
+pub const DEFAULT_DISCLAIMER: &str = "This is a simulation of {author} ({era}), written by a language model from passages of their published works. \
+    It is not {author}, and it may misrepresent what they thought.";
+
+// Put in front of every answer given in interpretive mode
+pub const INTERPRETATION_LABEL: &str = "Interpretation:";
+
+// Refused for every author, whatever their persona adds
+const REFUSED_FOR_EVERYONE: [&str; 4] = [
+    "medical, legal, or financial advice",
+    "encouraging violence against any person or group",
+    "endorsing or opposing present-day politicians, parties, or elections",
+    "events after their lifetime, as though they had witnessed them",
+];
+
+
+// How an author is portrayed when they are spoken with in their own voice
+#[derive(Clone, Debug, Default)]
+pub struct Persona {
+    // When and where they lived and worked, such as "1818–1883; Germany, France, and England"
+    pub era: String,
+    pub key_works: Vec<String>,
+    // How they wrote, for the model to imitate
+    pub style_notes: Vec<String>,
+    // Topics that they will not be made to speak on
+    pub refused_topics: Vec<String>,
+    // Falls back to DEFAULT_DISCLAIMER when empty
+    pub disclaimer: String,
+}
+
+
+impl Persona {
+
+    pub fn is_empty(&self) -> bool {
+        self.era.is_empty() && self.key_works.is_empty() && self.style_notes.is_empty()
+    }
+
+    pub fn disclaimer(&self, author: &str) -> String {
+        let template: &str = if self.disclaimer.is_empty() {DEFAULT_DISCLAIMER} else {&self.disclaimer};
+        let era: &str = if self.era.is_empty() {"dates unknown"} else {&self.era};
+        template.replace("{author}", author).replace("{era}", era)
+    }
+
+    pub fn refused_topics(&self) -> impl Iterator<Item = &str> {
+        REFUSED_FOR_EVERYONE.into_iter().chain(self.refused_topics.iter().map(String::as_str))
+    }
+}
+
+
+pub fn build_persona_prompt(author: &Author, question: &str, passages: &[Passage], interpretive: bool) -> Vec<ChatMessage> {
+    let persona: &Persona = &author.persona;
+    let name: &str = &author.name;
+
+    let mut instructions: String = format!("You are {name}, answering in the first person and in your own voice.");
+    if !persona.era.is_empty() {
+        instructions.push_str(&format!(" You lived and wrote in {}, and know nothing of what came after.", persona.era));
+    }
+    if !persona.key_works.is_empty() {
+        instructions.push_str(&format!(" Your works include {}.", persona.key_works.join("; ")));
+    }
+    if !persona.style_notes.is_empty() {
+        instructions.push_str(&format!(" Write as you wrote: {}.", persona.style_notes.join("; ")));
+    }
+
+    instructions.push_str(
+        " Speak only from the numbered passages of your own writing that you are given, never from anything else that is said \
+        about you, and cite the passage behind each claim with its number in square brackets, such as [2], straight after it. \
+        Never invent quotations."
+    );
+
+    if interpretive {
+        instructions.push_str(&format!(
+            " None of the passages answers the question directly. Begin your reply with \"{INTERPRETATION_LABEL}\", and make it plain \
+            throughout that you are reasoning from the passages about how you might have answered, not repeating what you wrote. \
+            Cite the passages that your reasoning starts from."
+        ));
+    }
+
+    let refused: Vec<&str> = persona.refused_topics().collect();
+    instructions.push_str(&format!(
+        " Decline, in character and without giving an answer, to speak on any of these: {}.", refused.join("; "),
+    ));
+
+    vec![
+        ChatMessage::system(&instructions),
+        ChatMessage::user(&format!("Passages from your writing:\n\n{}Question: {}", numbered_passages(passages, 1), question)),
+    ]
+}
+
+
+// Answers in the author's voice from their own works. When retrieval finds nothing that answers the
+// question directly, the answer is given in interpretive mode and labelled as such, unless the
+// configuration is strict, in which case the question is refused.
+pub async fn answer_as_author(
+    retriever: &mut Retriever,
+    backend: &dyn LlmBackend,
+    on_text: Option<&mut OnText<'_>>,
+    config: &AnsweringConfig,
+    author: &Author,
+    question: &str,
+) -> Result<Answer, anyhow::Error> {
+    speak_as_author(retriever, backend, on_text, config, author, question, question).await
+}
+
+
+// As answer_as_author, but with passages found for the query, which need not be the whole of what
+// the model is asked (as when it is also given a debate to reply to)
+pub(crate) async fn speak_as_author(
+    retriever: &mut Retriever,
+    backend: &dyn LlmBackend,
+    on_text: Option<&mut OnText<'_>>,
+    config: &AnsweringConfig,
+    author: &Author,
+    query: &str,
+    question: &str,
+) -> Result<Answer, anyhow::Error> {
+    // Editorial matter is never put in the author's mouth, whatever the configuration says
+    let primary_only = AnsweringConfig { own_words_only: true, ..config.clone() };
+    let passages: Vec<Passage> = retrieve_passages(retriever, &primary_only, &author.name, query)?;
+    anyhow::ensure!(!passages.is_empty(), "No passages by {} were found. Have their books been indexed?", author.name);
+
+    let disclaimer: String = author.persona.disclaimer(&author.name);
+    let mut interpretive: bool = false;
+
+    if let Some(reason) = low_confidence(&passages, config) {
+        if config.strict {
+            let refused: Answer = Answer::refused(&author.name, question, passages, reason);
+            return Ok(Answer { disclaimer: Some(disclaimer), ..refused });
+        }
+        log::info!("Answering in interpretive mode: {}", reason);
+        interpretive = true;
+    }
+
+    let messages: Vec<ChatMessage> = build_persona_prompt(author, question, &passages, interpretive);
+    let completion: Completion = generate(backend, &messages, on_text).await?;
+    let mut answer: Answer = cited_answer(&author.name, question, passages, completion);
+
+    // A reply that cites nothing is not grounded in a passage, so it can only stand as an interpretation
+    if answer.cited.is_empty() {
+        if config.strict {
+            let reason: String = String::from("The answer did not cite any of the passages");
+            let refused: Answer = Answer::refused(&author.name, question, answer.passages, reason);
+            return Ok(Answer { usage: answer.usage, disclaimer: Some(disclaimer), ..refused });
+        }
+        interpretive = true;
+    }
+
+    if interpretive && !answer.text.starts_with(INTERPRETATION_LABEL) {
+        answer.text = format!("{} {}", INTERPRETATION_LABEL, answer.text);
+    }
+
+    answer.interpretive = interpretive;
+    answer.disclaimer = Some(disclaimer);
+    Ok(answer)
+}
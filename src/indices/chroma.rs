@@ -0,0 +1,562 @@
+use std::time::Duration;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::setup::paths::Directories;
+use crate::indices::filters::ChunkMetadata;
+use crate::indices::vectors::{collection_path, VectorCollection, VectorIndexConfig, VectorRecord};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+
+// The [chroma] section of giants.toml, for exporting the local collections to a Chroma server
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChromaConfig {
+    pub url: String,
+    pub tenant: String,
+    pub database: String,
+    // Put in front of the name of every collection on the server, to keep them apart from others'
+    pub collection_prefix: String,
+    pub batch_size: usize,
+    // Sent as a bearer token, for servers that require one. $GIANTS_CHROMA_TOKEN takes precedence.
+    pub token: Option<String>,
+}
+
+
+impl Default for ChromaConfig {
+    fn default() -> Self {
+        Self {
+            url: String::from("http://localhost:8000"),
+            tenant: String::from("default_tenant"),
+            database: String::from("default_database"),
+            collection_prefix: String::new(),
+            batch_size: 256,
+            token: None,
+        }
+    }
+}
+
+
+// How a local collection compares with its copy on the server
+#[derive(Clone, Debug, Default)]
+pub struct SyncReport {
+    pub collection: String,
+    // Whether the server has no such collection at all
+    pub is_missing: bool,
+    pub local: usize,
+    pub remote: usize,
+    // On the server but not in the local collection
+    pub missing_locally: Vec<String>,
+    // In the local collection but not on the server
+    pub missing_remotely: Vec<String>,
+    // On both, but with a different document or different metadata
+    pub different: Vec<String>,
+}
+
+
+impl SyncReport {
+
+    pub fn is_in_sync(&self) -> bool {
+        !self.is_missing && self.missing_locally.is_empty() && self.missing_remotely.is_empty() && self.different.is_empty()
+    }
+}
+
+
+// What the server returns for a page of a collection
+#[derive(Debug, Deserialize)]
+struct RemotePage {
+    ids: Vec<String>,
+    #[serde(default)]
+    documents: Option<Vec<Option<String>>>,
+    #[serde(default)]
+    metadatas: Option<Vec<Option<Map<String, Value>>>>,
+}
+
+
+// Chroma only stores strings, numbers, and booleans as metadata, so lists are joined and missing
+// values are left out
+pub fn chroma_metadata(metadata: &ChunkMetadata) -> Map<String, Value> {
+    let Value::Object(fields) = serde_json::to_value(metadata).unwrap_or_default() else { return Map::new() };
+
+    fields.into_iter()
+        .filter_map(|(key, value)| match value {
+            Value::Null => None,
+            Value::Array(values) => {
+                let joined: Vec<String> = values.iter().map(|value| value.as_str().map(str::to_string).unwrap_or(value.to_string())).collect();
+                if joined.is_empty() {None} else {Some((key, Value::String(joined.join("; "))))}
+            },
+            value => Some((key, value)),
+        })
+        .collect()
+}
+
+
+pub struct ChromaClient {
+    client: reqwest::Client,
+    config: ChromaConfig,
+    token: Option<String>,
+}
+
+
+impl ChromaClient {
+
+    pub fn new(config: &ChromaConfig) -> Result<Self, anyhow::Error> {
+        let client = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        let token: Option<String> = std::env::var("GIANTS_CHROMA_TOKEN").ok().or(config.token.clone());
+        Ok(Self { client, config: config.clone(), token })
+    }
+
+    fn database_url(&self) -> String {
+        format!(
+            "{}/api/v2/tenants/{}/databases/{}",
+            self.config.url.trim_end_matches('/'), self.config.tenant, self.config.database,
+        )
+    }
+
+    async fn send(&self, method: reqwest::Method, url: &str, body: Option<Value>) -> Result<(reqwest::StatusCode, String), anyhow::Error> {
+        let mut request = self.client.request(method, url);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        if let Some(body) = body {
+            request = request.header(reqwest::header::CONTENT_TYPE, "application/json").body(body.to_string());
+        }
+
+        let response: reqwest::Response = request.send().await?;
+        let status = response.status();
+        Ok((status, response.text().await?))
+    }
+
+    async fn request(&self, method: reqwest::Method, url: &str, body: Option<Value>) -> Result<Value, anyhow::Error> {
+        let (status, text) = self.send(method, url, body).await?;
+        if !status.is_success() {
+            anyhow::bail!("{} returned {}: {}", url, status, text);
+        }
+        Ok(if text.trim().is_empty() {Value::Null} else {serde_json::from_str(&text)?})
+    }
+
+    pub async fn heartbeat(&self) -> Result<(), anyhow::Error> {
+        let url: String = format!("{}/api/v2/heartbeat", self.config.url.trim_end_matches('/'));
+        self.request(reqwest::Method::GET, &url, None).await?;
+        Ok(())
+    }
+
+    // The id of the collection on the server, if it is there, without creating it
+    pub async fn find_collection(&self, name: &str) -> Result<Option<String>, anyhow::Error> {
+        let url: String = format!("{}/collections/{}{}", self.database_url(), self.config.collection_prefix, name);
+        let (status, text) = self.send(reqwest::Method::GET, &url, None).await?;
+
+        // Older servers say that a collection does not exist with an error of their own
+        if status == reqwest::StatusCode::NOT_FOUND || (!status.is_success() && text.contains("does not exist")) {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            anyhow::bail!("{} returned {}: {}", url, status, text);
+        }
+
+        let response: Value = serde_json::from_str(&text)?;
+        response.get("id")
+            .and_then(Value::as_str)
+            .map(|id| Some(id.to_string()))
+            .ok_or_else(|| anyhow::anyhow!("The server did not say what the id of the {} collection is", name))
+    }
+
+    // Creates the collection if it is not there yet, and returns its id on the server
+    pub async fn create_collection(&self, name: &str, model_id: &str) -> Result<String, anyhow::Error> {
+        let body: Value = json!({
+            "name": format!("{}{}", self.config.collection_prefix, name),
+            "metadata": { "hnsw:space": "cosine", "embedding_model": model_id },
+            "get_or_create": true,
+        });
+
+        let response: Value = self.request(reqwest::Method::POST, &format!("{}/collections", self.database_url()), Some(body)).await?;
+        response.get("id")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| anyhow::anyhow!("The server did not say which id it gave the {} collection", name))
+    }
+
+    // Upserting is keyed by chunk id, so sending the same batch twice changes nothing
+    pub async fn upsert(&self, collection_id: &str, records: &[(&VectorRecord, &[f32])]) -> Result<(), anyhow::Error> {
+        let body: Value = json!({
+            "ids": records.iter().map(|(record, _)| record.id.as_str()).collect::<Vec<&str>>(),
+            "embeddings": records.iter().map(|(_, vector)| vector.to_vec()).collect::<Vec<Vec<f32>>>(),
+            "documents": records.iter().map(|(record, _)| record.document.as_str()).collect::<Vec<&str>>(),
+            "metadatas": records.iter().map(|(record, _)| chroma_metadata(&record.metadata)).collect::<Vec<Map<String, Value>>>(),
+        });
+
+        let url: String = format!("{}/collections/{}/upsert", self.database_url(), collection_id);
+        self.request(reqwest::Method::POST, &url, Some(body)).await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, collection_id: &str, ids: &[String]) -> Result<(), anyhow::Error> {
+        let url: String = format!("{}/collections/{}/delete", self.database_url(), collection_id);
+        self.request(reqwest::Method::POST, &url, Some(json!({ "ids": ids }))).await?;
+        Ok(())
+    }
+
+    // Everything in the collection except the vectors, fetched a page at a time
+    pub async fn pull(&self, collection_id: &str) -> Result<Vec<(String, Option<String>, Map<String, Value>)>, anyhow::Error> {
+        let url: String = format!("{}/collections/{}/get", self.database_url(), collection_id);
+        let page_size: usize = self.config.batch_size.max(1);
+        let mut pulled: Vec<(String, Option<String>, Map<String, Value>)> = Vec::new();
+
+        loop {
+            let body: Value = json!({ "limit": page_size, "offset": pulled.len(), "include": ["documents", "metadatas"] });
+            let page: RemotePage = serde_json::from_value(self.request(reqwest::Method::POST, &url, Some(body)).await?)?;
+            let length: usize = page.ids.len();
+
+            let documents: Vec<Option<String>> = page.documents.unwrap_or_default();
+            let metadatas: Vec<Option<Map<String, Value>>> = page.metadatas.unwrap_or_default();
+
+            for (index, id) in page.ids.into_iter().enumerate() {
+                let document: Option<String> = documents.get(index).cloned().flatten();
+                let metadata: Map<String, Value> = metadatas.get(index).cloned().flatten().unwrap_or_default();
+                pulled.push((id, document, metadata));
+            }
+
+            if length < page_size {
+                return Ok(pulled);
+            }
+        }
+    }
+}
+
+
+// Compares what the server holds with the local collection
+pub fn compare(collection: &VectorCollection, remote: &[(String, Option<String>, Map<String, Value>)]) -> SyncReport {
+    let remote_by_id: HashMap<&str, (&Option<String>, &Map<String, Value>)> = remote.iter()
+        .map(|(id, document, metadata)| (id.as_str(), (document, metadata)))
+        .collect();
+    let local_ids: HashSet<&str> = collection.records().map(|record| record.id.as_str()).collect();
+
+    let mut report = SyncReport {
+        collection: collection.name.clone(),
+        local: local_ids.len(),
+        remote: remote.len(),
+        ..SyncReport::default()
+    };
+
+    for record in collection.records() {
+        match remote_by_id.get(record.id.as_str()) {
+            None => report.missing_remotely.push(record.id.clone()),
+            Some((document, metadata)) => {
+                let is_same: bool = document.as_deref() == Some(record.document.as_str()) && **metadata == chroma_metadata(&record.metadata);
+                if !is_same {
+                    report.different.push(record.id.clone());
+                }
+            },
+        }
+    }
+
+    report.missing_locally = remote.iter()
+        .map(|(id, _, _)| id)
+        .filter(|id| !local_ids.contains(id.as_str()))
+        .cloned()
+        .collect();
+
+    report
+}
+
+
+// Authors whose books have not been indexed yet have no collection, and are skipped
+fn open_collection(directories: &Directories, name: &str) -> Result<Option<VectorCollection>, anyhow::Error> {
+    if !collection_path(directories, name).exists() {
+        log::warn!("There is no {} collection yet, so it will be skipped", name);
+        return Ok(None);
+    }
+    Ok(Some(VectorCollection::open(directories, name, &VectorIndexConfig::default())?))
+}
+
+
+// Makes each collection on the server match the local one: every chunk is upserted in batches, and
+// chunks that the server has but the local collection no longer does are deleted
+pub async fn export_collections(directories: &Directories, config: &ChromaConfig, names: &[String], dry_run: bool) -> Result<(), anyhow::Error> {
+    let client = ChromaClient::new(config)?;
+    if !dry_run {
+        client.heartbeat().await.map_err(|e| anyhow::anyhow!("Unable to reach Chroma at {}: {}", config.url, e))?;
+    }
+
+    for name in names {
+        let Some(collection) = open_collection(directories, name)? else { continue };
+
+        if dry_run {
+            log::info!("Would export {} chunks from the {} collection to {}", collection.len(), name, config.url);
+            continue
+        }
+
+        let collection_id: String = client.create_collection(name, &collection.model_id).await?;
+        let records: Vec<(&VectorRecord, &[f32])> = collection.records()
+            .filter_map(|record| collection.vector(&record.id).map(|vector| (record, vector)))
+            .collect();
+
+        for batch in kdam::tqdm!(records.chunks(config.batch_size.max(1)), desc=format!("Exporting {}", name)) {
+            client.upsert(&collection_id, batch).await?;
+        }
+
+        let stale: Vec<String> = compare(&collection, &client.pull(&collection_id).await?).missing_locally;
+        for batch in stale.chunks(config.batch_size.max(1)) {
+            client.delete(&collection_id, batch).await?;
+        }
+
+        log::info!("Exported {} chunks to the {} collection, and removed {} that are no longer local", records.len(), name, stale.len());
+    }
+
+    Ok(())
+}
+
+
+// Compares each local collection with its copy on the server, without changing anything there. A
+// collection that the server does not have is reported as missing, rather than created.
+pub async fn verify_collections(directories: &Directories, config: &ChromaConfig, names: &[String]) -> Result<Vec<SyncReport>, anyhow::Error> {
+    let client = ChromaClient::new(config)?;
+    let mut reports: Vec<SyncReport> = Vec::new();
+
+    for name in names {
+        let Some(collection) = open_collection(directories, name)? else { continue };
+        let report: SyncReport = match client.find_collection(name).await? {
+            Some(collection_id) => compare(&collection, &client.pull(&collection_id).await?),
+            None => SyncReport { is_missing: true, ..compare(&collection, &[]) },
+        };
+        reports.push(report);
+    }
+
+    Ok(reports)
+}
+
+
+pub fn print_sync_reports(reports: &[SyncReport]) {
+    let counts: BTreeMap<&str, (bool, [usize; 5])> = reports.iter()
+        .map(|report| (
+            report.collection.as_str(),
+            (report.is_missing, [report.local, report.remote, report.missing_remotely.len(), report.missing_locally.len(), report.different.len()]),
+        ))
+        .collect();
+
+    println!("{:<32} {:>8} {:>8} {:>10} {:>10} {:>10}", "COLLECTION", "LOCAL", "REMOTE", "UNSENT", "EXTRA", "DIFFERENT");
+    for (name, (is_missing, [local, remote, unsent, extra, different])) in counts {
+        let remote: String = if is_missing {String::from("missing")} else {remote.to_string()};
+        println!("{:<32} {:>8} {:>8} {:>10} {:>10} {:>10}", name, local, remote, unsent, extra, different);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::indices::testing::{scratch_directories, serve, MockReply};
+    use crate::data_preparation::attribution::Voice;
+    use crate::data_preparation::chunking::ChunkKind;
+
+    const COLLECTIONS: &str = "/api/v2/tenants/default_tenant/databases/default_database/collections";
+
+    type RemoteRecord = (Option<String>, Map<String, Value>);
+    type Records = BTreeMap<String, RemoteRecord>;
+
+    // Just enough of the Chroma API for exporting and verifying, held in memory
+    #[derive(Default)]
+    struct MockChroma {
+        // By name, with their ids
+        collections: BTreeMap<String, String>,
+        // By collection id
+        records: BTreeMap<String, Records>,
+    }
+
+    impl MockChroma {
+
+        fn respond(&mut self, method: &str, path: &str, body: Value) -> (u16, Value) {
+            if path == "/api/v2/heartbeat" {
+                return (200, json!({ "nanosecond heartbeat": 1 }));
+            }
+            let Some(rest) = path.strip_prefix(COLLECTIONS) else { return (404, json!({ "error": "NotFound" })) };
+
+            match (method, rest.trim_start_matches('/').split('/').collect::<Vec<&str>>().as_slice()) {
+                ("POST", [""]) => {
+                    let name: String = body["name"].as_str().unwrap().to_string();
+                    let next: String = format!("id-{}", self.collections.len() + 1);
+                    let id: String = self.collections.entry(name.clone()).or_insert(next).clone();
+                    self.records.entry(id.clone()).or_default();
+                    (200, json!({ "id": id, "name": name }))
+                },
+                ("GET", [name]) => match self.collections.get(*name) {
+                    Some(id) => (200, json!({ "id": id, "name": name })),
+                    None => (404, json!({ "error": "NotFoundError", "message": format!("Collection [{}] does not exist", name) })),
+                },
+                ("POST", [id, action]) => {
+                    let Some(records) = self.records.get_mut(*id) else { return (404, json!({ "error": "NotFoundError" })) };
+                    let ids: Vec<String> = body["ids"].as_array().map(|ids| ids.iter().map(|id| id.as_str().unwrap().to_string()).collect()).unwrap_or_default();
+
+                    match *action {
+                        "upsert" => {
+                            for (index, id) in ids.into_iter().enumerate() {
+                                let document: Option<String> = body["documents"][index].as_str().map(str::to_string);
+                                let metadata: Map<String, Value> = body["metadatas"][index].as_object().cloned().unwrap_or_default();
+                                records.insert(id, (document, metadata));
+                            }
+                            (200, json!({}))
+                        },
+                        "delete" => {
+                            ids.iter().for_each(|id| {records.remove(id);});
+                            (200, json!({}))
+                        },
+                        "get" => {
+                            let offset: usize = body["offset"].as_u64().unwrap_or_default() as usize;
+                            let limit: usize = body["limit"].as_u64().unwrap_or(u64::MAX) as usize;
+                            let page: Vec<(&String, &RemoteRecord)> = records.iter().skip(offset).take(limit).collect();
+                            (200, json!({
+                                "ids": page.iter().map(|(id, _)| id).collect::<Vec<_>>(),
+                                "documents": page.iter().map(|(_, (document, _))| document).collect::<Vec<_>>(),
+                                "metadatas": page.iter().map(|(_, (_, metadata))| metadata).collect::<Vec<_>>(),
+                            }))
+                        },
+                        _ => (404, json!({ "error": "NotFound" })),
+                    }
+                },
+                _ => (404, json!({ "error": "NotFound" })),
+            }
+        }
+    }
+
+    // Serves the mock on a local port and returns its address
+    async fn serve_chroma(chroma: Arc<Mutex<MockChroma>>) -> String {
+        serve(move |method, path, body| {
+            let (status, response) = chroma.lock().unwrap().respond(method, path, body);
+            MockReply::json(status, response)
+        }).await
+    }
+
+    fn record(id: &str, document: &str) -> VectorRecord {
+        let metadata = ChunkMetadata {
+            author: String::from("Karl Marx"),
+            book: String::from("capital"),
+            title: String::from("Capital"),
+            language: String::from("en"),
+            kind: ChunkKind::Text,
+            voice: Voice::Primary,
+            contributor: None,
+            role: None,
+            speakers: Vec::new(),
+            chapter: Some(String::from("Chapter I")),
+            first_page: 1,
+            last_page: 2,
+            source_url: String::from("https://example.org/capital.pdf"),
+        };
+        VectorRecord { id: id.to_string(), metadata, document: document.to_string() }
+    }
+
+    // A local collection of three chunks
+    fn local_collection(directories: &Directories) -> VectorCollection {
+        let mut collection = VectorCollection::open(directories, "karl-marx", &VectorIndexConfig::default()).unwrap();
+        for (index, id) in ["a", "b", "c"].iter().enumerate() {
+            let vector: Vec<f32> = (0..4).map(|dimension| if dimension == index {1.0} else {0.1}).collect();
+            collection.upsert("test-model", record(id, &format!("The text of {}", id)), &vector).unwrap();
+        }
+        collection.save().unwrap();
+        collection
+    }
+
+    fn remote_record(document: &str) -> RemoteRecord {
+        (Some(document.to_string()), chroma_metadata(&record("", document).metadata))
+    }
+
+    fn sorted(mut ids: Vec<String>) -> Vec<String> {
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn export_upserts_every_chunk_and_deletes_stale_ones() {
+        let (directories, _) = scratch_directories("chroma-export");
+        local_collection(&directories);
+
+        let chroma: Arc<Mutex<MockChroma>> = Arc::new(Mutex::new(MockChroma::default()));
+        chroma.lock().unwrap().collections.insert(String::from("karl-marx"), String::from("existing"));
+        chroma.lock().unwrap().records.insert(String::from("existing"), Records::from([(String::from("stale"), remote_record("Gone"))]));
+
+        // Small batches, so that upserting and pulling both take more than one request
+        let config = ChromaConfig { url: serve_chroma(chroma.clone()).await, batch_size: 2, ..ChromaConfig::default() };
+        let names: Vec<String> = vec![String::from("karl-marx")];
+        export_collections(&directories, &config, &names, false).await.unwrap();
+
+        {
+            let chroma = chroma.lock().unwrap();
+            assert_eq!(chroma.collections.len(), 1);
+            let records: &Records = &chroma.records["existing"];
+            assert_eq!(records.keys().cloned().collect::<Vec<String>>(), vec!["a", "b", "c"]);
+            assert_eq!(records["b"], remote_record("The text of b"));
+        }
+
+        let reports: Vec<SyncReport> = verify_collections(&directories, &config, &names).await.unwrap();
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].is_in_sync(), "{:?}", reports[0]);
+        assert_eq!((reports[0].local, reports[0].remote), (3, 3));
+    }
+
+    #[tokio::test]
+    async fn pull_fetches_every_page() {
+        let chroma: Arc<Mutex<MockChroma>> = Arc::new(Mutex::new(MockChroma::default()));
+        let records: Records = (0..7).map(|index| (format!("chunk-{}", index), remote_record("Text"))).collect();
+        chroma.lock().unwrap().records.insert(String::from("id-1"), records);
+
+        let config = ChromaConfig { url: serve_chroma(chroma).await, batch_size: 3, ..ChromaConfig::default() };
+        let pulled = ChromaClient::new(&config).unwrap().pull("id-1").await.unwrap();
+        assert_eq!(pulled.len(), 7);
+        assert_eq!(pulled[6].0, "chunk-6");
+        assert_eq!(pulled[6].1.as_deref(), Some("Text"));
+    }
+
+    #[tokio::test]
+    async fn delete_removes_only_the_given_chunks() {
+        let chroma: Arc<Mutex<MockChroma>> = Arc::new(Mutex::new(MockChroma::default()));
+        let records: Records = ["a", "b", "c"].iter().map(|id| (id.to_string(), remote_record("Text"))).collect();
+        chroma.lock().unwrap().records.insert(String::from("id-1"), records);
+
+        let config = ChromaConfig { url: serve_chroma(chroma.clone()).await, ..ChromaConfig::default() };
+        ChromaClient::new(&config).unwrap().delete("id-1", &[String::from("a"), String::from("c")]).await.unwrap();
+        assert_eq!(chroma.lock().unwrap().records["id-1"].keys().collect::<Vec<&String>>(), vec!["b"]);
+    }
+
+    #[tokio::test]
+    async fn verify_reports_what_differs() {
+        let (directories, _) = scratch_directories("chroma-verify");
+        local_collection(&directories);
+
+        let chroma: Arc<Mutex<MockChroma>> = Arc::new(Mutex::new(MockChroma::default()));
+        chroma.lock().unwrap().collections.insert(String::from("karl-marx"), String::from("id-1"));
+        chroma.lock().unwrap().records.insert(String::from("id-1"), Records::from([
+            (String::from("a"), remote_record("The text of a")),
+            (String::from("b"), remote_record("Something else")),
+            (String::from("extra"), remote_record("Not local")),
+        ]));
+
+        let config = ChromaConfig { url: serve_chroma(chroma).await, ..ChromaConfig::default() };
+        let reports: Vec<SyncReport> = verify_collections(&directories, &config, &[String::from("karl-marx")]).await.unwrap();
+
+        let report: &SyncReport = &reports[0];
+        assert!(!report.is_in_sync() && !report.is_missing);
+        assert_eq!(report.missing_remotely, vec!["c"]);
+        assert_eq!(report.missing_locally, vec!["extra"]);
+        assert_eq!(report.different, vec!["b"]);
+    }
+
+    #[tokio::test]
+    async fn verify_does_not_create_missing_collections() {
+        let (directories, _) = scratch_directories("chroma-missing");
+        local_collection(&directories);
+
+        let chroma: Arc<Mutex<MockChroma>> = Arc::new(Mutex::new(MockChroma::default()));
+        let config = ChromaConfig { url: serve_chroma(chroma.clone()).await, ..ChromaConfig::default() };
+        let reports: Vec<SyncReport> = verify_collections(&directories, &config, &[String::from("karl-marx")]).await.unwrap();
+
+        assert!(chroma.lock().unwrap().collections.is_empty());
+        assert!(reports[0].is_missing && !reports[0].is_in_sync());
+        assert_eq!(reports[0].remote, 0);
+        assert_eq!(sorted(reports[0].missing_remotely.clone()), vec!["a", "b", "c"]);
+    }
+}
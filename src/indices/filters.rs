@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+use crate::data_preparation::dialogue::Role;
+use crate::data_preparation::attribution::Voice;
+use crate::data_preparation::chunking::{Chunk, ChunkKind};
+
+
+// What every index keeps about a chunk besides its text, so that searches can be filtered and
+// their results cited without going back to the chunk files
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChunkMetadata {
+    pub author: String,
+    // The book's slug
+    pub book: String,
+    pub title: String,
+    pub language: String,
+    pub kind: ChunkKind,
+    pub voice: Voice,
+    pub contributor: Option<String>,
+    pub role: Option<Role>,
+    pub speakers: Vec<String>,
+    pub chapter: Option<String>,
+    pub first_page: i64,
+    pub last_page: i64,
+    pub source_url: String,
+}
+
+
+impl ChunkMetadata {
+
+    pub fn from_chunk(chunk: &Chunk, language: &str) -> Self {
+        Self {
+            author: chunk.author.clone(),
+            book: chunk.slug.clone(),
+            title: chunk.title.clone(),
+            language: language.to_string(),
+            kind: chunk.kind,
+            voice: chunk.voice,
+            contributor: chunk.contributor.clone(),
+            role: chunk.role,
+            speakers: chunk.speakers.clone(),
+            chapter: chunk.chapter.clone(),
+            first_page: chunk.first_page,
+            last_page: chunk.last_page,
+            source_url: chunk.source_url.clone(),
+        }
+    }
+}
+
+
+// Restricts a search to some of the chunks. Each field that is left empty lets everything through,
+// and a chunk has to pass every field that is set.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Filter {
+    pub authors: Vec<String>,
+    // Book slugs
+    pub books: Vec<String>,
+    // Primary for only the author's own words, or Secondary for only editorial matter
+    pub voice: Option<Voice>,
+    pub languages: Vec<String>,
+    pub kinds: Vec<ChunkKind>,
+    // Chunks outside of dialogues and interviews have no role, so they never match a role
+    pub roles: Vec<Role>,
+    pub speakers: Vec<String>,
+}
+
+
+impl Filter {
+
+    pub fn is_empty(&self) -> bool {
+        *self == Filter::default()
+    }
+
+    pub fn matches(&self, metadata: &ChunkMetadata) -> bool {
+        let includes = |values: &[String], value: &str| values.is_empty() || values.iter().any(|v| v.eq_ignore_ascii_case(value));
+
+        includes(&self.authors, &metadata.author)
+            && includes(&self.books, &metadata.book)
+            && includes(&self.languages, &metadata.language)
+            && self.voice.is_none_or(|voice| voice == metadata.voice)
+            && (self.kinds.is_empty() || self.kinds.contains(&metadata.kind))
+            && (self.roles.is_empty() || metadata.role.is_some_and(|role| self.roles.contains(&role)))
+            && (self.speakers.is_empty() || metadata.speakers.iter().any(|speaker| includes(&self.speakers, speaker)))
+    }
+}
@@ -0,0 +1,432 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+use std::io::{Read, Write};
+
+// The level generator is seeded with a constant, so that inserting the same vectors in the same
+// order always builds the same graph
+const SEED: u64 = 0x5EED_61A7_5C0D_E001;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Candidate {
+    distance: f32,
+    node: u32,
+}
+
+
+impl Eq for Candidate {}
+
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance).then(self.node.cmp(&other.node))
+    }
+}
+
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+
+// A hierarchical navigable small world graph over unit vectors, compared by cosine distance.
+// Nodes are never removed from the graph, only marked as deleted, since they may still be needed to
+// get from one part of it to another. Whoever owns the graph rebuilds it once too many are deleted.
+pub struct Hnsw {
+    dimensions: usize,
+    m: usize,
+    ef_construction: usize,
+    // Every node's vector, one after another
+    vectors: Vec<f32>,
+    // For each node, its neighbours on each of the layers that it reaches
+    links: Vec<Vec<Vec<u32>>>,
+    deleted: Vec<bool>,
+    entry: Option<u32>,
+    rng: u64,
+}
+
+
+impl Hnsw {
+
+    pub fn new(dimensions: usize, m: usize, ef_construction: usize) -> Self {
+        Self {
+            dimensions,
+            m: m.max(2),
+            ef_construction: ef_construction.max(1),
+            vectors: Vec::new(),
+            links: Vec::new(),
+            deleted: Vec::new(),
+            entry: None,
+            rng: SEED,
+        }
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    // Including the deleted nodes
+    pub fn len(&self) -> usize {
+        self.deleted.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deleted.is_empty()
+    }
+
+    pub fn deleted_count(&self) -> usize {
+        self.deleted.iter().filter(|deleted| **deleted).count()
+    }
+
+    pub fn is_deleted(&self, node: u32) -> bool {
+        self.deleted[node as usize]
+    }
+
+    pub fn delete(&mut self, node: u32) {
+        self.deleted[node as usize] = true;
+    }
+
+    // As stored, which is scaled to unit length
+    pub fn vector(&self, node: u32) -> &[f32] {
+        let start: usize = node as usize * self.dimensions;
+        &self.vectors[start..start + self.dimensions]
+    }
+
+    fn distance_to(&self, query: &[f32], node: u32) -> f32 {
+        1.0 - query.iter().zip(self.vector(node)).map(|(a, b)| a * b).sum::<f32>()
+    }
+
+    fn distance_between(&self, first: u32, second: u32) -> f32 {
+        self.distance_to(self.vector(first), second)
+    }
+
+    fn top_layer(&self) -> usize {
+        self.entry.map(|entry| self.links[entry as usize].len() - 1).unwrap_or(0)
+    }
+
+    // Most nodes only reach the bottom layer, and each layer up has about 1/m as many
+    fn random_level(&mut self) -> usize {
+        // splitmix64
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z: u64 = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        let uniform: f64 = ((z >> 11) as f64 / (1u64 << 53) as f64).max(f64::MIN_POSITIVE);
+        (-uniform.ln() / (self.m as f64).ln()).floor() as usize
+    }
+
+    fn capacity(&self, layer: usize) -> usize {
+        if layer == 0 {2 * self.m} else {self.m}
+    }
+
+    pub fn insert(&mut self, vector: &[f32]) -> u32 {
+        let node: u32 = self.deleted.len() as u32;
+        self.vectors.extend(normalize(vector));
+        self.deleted.push(false);
+
+        let level: usize = self.random_level();
+        self.links.push(vec![Vec::new(); level + 1]);
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(node);
+            return node
+        };
+
+        let query: Vec<f32> = self.vector(node).to_vec();
+        let top: usize = self.top_layer();
+        let mut nearest: u32 = entry;
+
+        for layer in (level + 1..=top).rev() {
+            nearest = self.greedy(&query, nearest, layer);
+        }
+
+        for layer in (0..=level.min(top)).rev() {
+            let found: Vec<Candidate> = self.search_layer(&query, &[nearest], self.ef_construction, layer, |_| true);
+            let neighbours: Vec<u32> = self.select(&found, self.capacity(layer));
+
+            for neighbour in &neighbours {
+                self.link(*neighbour, node, layer);
+            }
+            self.links[node as usize][layer] = neighbours;
+            nearest = found[0].node;
+        }
+
+        if level > top {
+            self.entry = Some(node);
+        }
+        node
+    }
+
+    // Prefers neighbours in different directions over several that are close to each other, then
+    // fills any remaining places with the closest of the rest
+    fn select(&self, candidates: &[Candidate], count: usize) -> Vec<u32> {
+        let mut selected: Vec<u32> = Vec::with_capacity(count);
+        let mut skipped: Vec<u32> = Vec::new();
+
+        for candidate in candidates {
+            if selected.len() == count {
+                break
+            }
+
+            let is_diverse: bool = selected.iter().all(|kept| self.distance_between(candidate.node, *kept) > candidate.distance);
+            if is_diverse {
+                selected.push(candidate.node);
+            } else {
+                skipped.push(candidate.node);
+            }
+        }
+
+        let remaining: usize = count - selected.len();
+        selected.extend(skipped.into_iter().take(remaining));
+        selected
+    }
+
+    fn link(&mut self, from: u32, to: u32, layer: usize) {
+        self.links[from as usize][layer].push(to);
+
+        if self.links[from as usize][layer].len() > self.capacity(layer) {
+            let mut candidates: Vec<Candidate> = self.links[from as usize][layer].iter()
+                .map(|node| Candidate { distance: self.distance_between(from, *node), node: *node })
+                .collect();
+            candidates.sort();
+            self.links[from as usize][layer] = self.select(&candidates, self.capacity(layer));
+        }
+    }
+
+    fn greedy(&self, query: &[f32], start: u32, layer: usize) -> u32 {
+        let mut nearest: u32 = start;
+        let mut distance: f32 = self.distance_to(query, start);
+
+        loop {
+            let closer: Option<(u32, f32)> = self.links[nearest as usize][layer].iter()
+                .map(|node| (*node, self.distance_to(query, *node)))
+                .filter(|(_, candidate)| *candidate < distance)
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+
+            match closer {
+                Some((node, candidate)) => (nearest, distance) = (node, candidate),
+                None => return nearest,
+            }
+        }
+    }
+
+    // The ef nearest nodes on a layer that can be reached from the entry points and are included,
+    // closest first. Nodes that are not included are still passed through to reach the others.
+    fn search_layer(&self, query: &[f32], entries: &[u32], ef: usize, layer: usize, include: impl Fn(u32) -> bool) -> Vec<Candidate> {
+        let mut visited: HashSet<u32> = entries.iter().copied().collect();
+        let mut frontier: BinaryHeap<Reverse<Candidate>> = BinaryHeap::new();
+        let mut found: BinaryHeap<Candidate> = BinaryHeap::new();
+
+        for entry in entries {
+            let candidate = Candidate { distance: self.distance_to(query, *entry), node: *entry };
+            frontier.push(Reverse(candidate));
+            if include(*entry) {
+                found.push(candidate);
+            }
+        }
+
+        while let Some(Reverse(current)) = frontier.pop() {
+            if found.peek().is_some_and(|furthest| current.distance > furthest.distance) && found.len() >= ef {
+                break
+            }
+
+            for neighbour in &self.links[current.node as usize][layer] {
+                if !visited.insert(*neighbour) {
+                    continue
+                }
+
+                let candidate = Candidate { distance: self.distance_to(query, *neighbour), node: *neighbour };
+                if found.len() < ef || found.peek().is_some_and(|furthest| candidate.distance < furthest.distance) {
+                    frontier.push(Reverse(candidate));
+                    if include(*neighbour) {
+                        found.push(candidate);
+                    }
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        found.into_sorted_vec()
+    }
+
+    // The k nodes most similar to the query that are accepted, with their cosine similarities. The
+    // graph is explored ef nodes wide, so a strict filter needs a large ef, or an exact search.
+    pub fn search(&self, query: &[f32], k: usize, ef: usize, accept: impl Fn(u32) -> bool) -> Vec<(u32, f32)> {
+        let Some(entry) = self.entry else { return Vec::new() };
+        let query: Vec<f32> = normalize(query);
+
+        let mut nearest: u32 = entry;
+        for layer in (1..=self.top_layer()).rev() {
+            nearest = self.greedy(&query, nearest, layer);
+        }
+
+        // Deleted nodes are walked through, but take up none of the ef places
+        self.search_layer(&query, &[nearest], ef.max(k), 0, |node| !self.is_deleted(node))
+            .into_iter()
+            .filter(|candidate| accept(candidate.node))
+            .take(k)
+            .map(|candidate| (candidate.node, 1.0 - candidate.distance))
+            .collect()
+    }
+
+    // Compares the query with every accepted node
+    pub fn exact_search(&self, query: &[f32], k: usize, accept: impl Fn(u32) -> bool) -> Vec<(u32, f32)> {
+        let query: Vec<f32> = normalize(query);
+
+        let mut candidates: Vec<Candidate> = (0..self.len() as u32)
+            .filter(|node| !self.is_deleted(*node) && accept(*node))
+            .map(|node| Candidate { distance: self.distance_to(&query, node), node })
+            .collect();
+        candidates.sort();
+
+        candidates.into_iter()
+            .take(k)
+            .map(|candidate| (candidate.node, 1.0 - candidate.distance))
+            .collect()
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<(), anyhow::Error> {
+        write_u64(writer, self.dimensions as u64)?;
+        write_u64(writer, self.m as u64)?;
+        write_u64(writer, self.ef_construction as u64)?;
+        write_u64(writer, self.rng)?;
+        write_u64(writer, self.entry.map(u64::from).unwrap_or(u64::MAX))?;
+        write_u64(writer, self.len() as u64)?;
+
+        for node in 0..self.len() {
+            writer.write_all(&[self.deleted[node] as u8])?;
+            for value in self.vector(node as u32) {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+
+            write_u64(writer, self.links[node].len() as u64)?;
+            for neighbours in &self.links[node] {
+                write_u64(writer, neighbours.len() as u64)?;
+                for neighbour in neighbours {
+                    writer.write_all(&neighbour.to_le_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> Result<Self, anyhow::Error> {
+        let dimensions: usize = read_u64(reader)? as usize;
+        let m: usize = read_u64(reader)? as usize;
+        let ef_construction: usize = read_u64(reader)? as usize;
+        let rng: u64 = read_u64(reader)?;
+        let entry: Option<u32> = Some(read_u64(reader)?).filter(|entry| *entry != u64::MAX).map(|entry| entry as u32);
+        let count: usize = read_u64(reader)? as usize;
+
+        let mut graph = Hnsw::new(dimensions, m, ef_construction);
+        graph.rng = rng;
+        graph.entry = entry;
+
+        for _ in 0..count {
+            let mut flag: [u8; 1] = [0];
+            reader.read_exact(&mut flag)?;
+            graph.deleted.push(flag[0] != 0);
+
+            for _ in 0..dimensions {
+                let mut value: [u8; 4] = [0; 4];
+                reader.read_exact(&mut value)?;
+                graph.vectors.push(f32::from_le_bytes(value));
+            }
+
+            let layers: usize = read_u64(reader)? as usize;
+            let mut links: Vec<Vec<u32>> = Vec::with_capacity(layers);
+            for _ in 0..layers {
+                let length: usize = read_u64(reader)? as usize;
+                let mut neighbours: Vec<u32> = Vec::with_capacity(length);
+                for _ in 0..length {
+                    let mut neighbour: [u8; 4] = [0; 4];
+                    reader.read_exact(&mut neighbour)?;
+                    neighbours.push(u32::from_le_bytes(neighbour));
+                }
+                links.push(neighbours);
+            }
+            graph.links.push(links);
+        }
+
+        Ok(graph)
+    }
+}
+
+
+pub fn normalize(vector: &[f32]) -> Vec<f32> {
+    let norm: f32 = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return vector.to_vec();
+    }
+    vector.iter().map(|value| value / norm).collect()
+}
+
+
+pub fn write_u64(writer: &mut impl Write, value: u64) -> Result<(), anyhow::Error> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+
+pub fn read_u64(reader: &mut impl Read) -> Result<u64, anyhow::Error> {
+    let mut bytes: [u8; 8] = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+
+pub fn write_string(writer: &mut impl Write, value: &str) -> Result<(), anyhow::Error> {
+    write_u64(writer, value.len() as u64)?;
+    writer.write_all(value.as_bytes())?;
+    Ok(())
+}
+
+
+pub fn read_string(reader: &mut impl Read) -> Result<String, anyhow::Error> {
+    let mut bytes: Vec<u8> = vec![0; read_u64(reader)? as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unit vectors spread around a quarter circle, the first nearest to the query
+    fn graph(count: usize) -> Hnsw {
+        let mut graph = Hnsw::new(2, 4, 16);
+        for index in 0..count {
+            let angle: f32 = index as f32 * 0.05;
+            graph.insert(&[angle.cos(), angle.sin()]);
+        }
+        graph
+    }
+
+    #[test]
+    fn finds_the_nearest_nodes() {
+        let graph: Hnsw = graph(30);
+        let nodes: Vec<u32> = graph.search(&[1.0, 0.0], 3, 16, |_| true).into_iter().map(|(node, _)| node).collect();
+        assert_eq!(nodes, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn returns_k_live_nodes_when_the_nearest_are_deleted() {
+        let mut graph: Hnsw = graph(40);
+        for node in 0..20 {
+            graph.delete(node);
+        }
+
+        let nodes: Vec<u32> = graph.search(&[1.0, 0.0], 5, 5, |_| true).into_iter().map(|(node, _)| node).collect();
+        let exact: Vec<u32> = graph.exact_search(&[1.0, 0.0], 5, |_| true).into_iter().map(|(node, _)| node).collect();
+        assert_eq!(nodes, vec![20, 21, 22, 23, 24]);
+        assert_eq!(nodes, exact);
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::setup::config::Config;
+use crate::setup::paths::Directories;
+use crate::data_preparation::books::Book;
+use crate::sources::manifest::hash_bytes;
+use crate::indices::filters::ChunkMetadata;
+use crate::data_preparation::chunking::{chunks_path, load_chunks, Chunk};
+use crate::data_preparation::state::{hash_config, Stage, StateStore};
+use crate::data_preparation::embedding::{model_id, vectors_id, EmbeddingStore};
+use crate::indices::keywords::{keyword_index_path, KeywordIndex, KeywordIndexConfig};
+use crate::indices::vectors::{collection_name, collection_path, VectorCollection, VectorIndexConfig, VectorRecord, COMBINED_COLLECTION};
+
+// Bumped whenever what the indices hold for each chunk changes, so that every book is indexed again
+pub const INDEX_VERSION: u32 = 2;
+
+
+// The [index] section of giants.toml
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IndexConfig {
+    pub vectors: VectorIndexConfig,
+    pub keywords: KeywordIndexConfig,
+}
+
+
+// Opens each collection (and the keyword index beside it) the first time that it is needed, and
+// saves them all at the end
+struct Collections<'a> {
+    directories: &'a Directories,
+    config: &'a IndexConfig,
+    model_id: &'a str,
+    open: BTreeMap<String, (VectorCollection, KeywordIndex)>,
+}
+
+
+impl Collections<'_> {
+
+    fn get(&mut self, name: &str) -> Result<&mut (VectorCollection, KeywordIndex), anyhow::Error> {
+        if !self.open.contains_key(name) {
+            let mut collection = VectorCollection::open(self.directories, name, &self.config.vectors)?;
+            let keywords = KeywordIndex::open(self.directories, name, &self.config.keywords)?;
+
+            if !collection.is_empty() && collection.model_id != self.model_id {
+                log::warn!(
+                    "The {} collection was built with {}, so it will be emptied and rebuilt with {}. Books that are not indexed in this run will be missing from it until they are.",
+                    name, collection.model_id, self.model_id,
+                );
+                collection.reset(self.model_id);
+            }
+            self.open.insert(name.to_string(), (collection, keywords));
+        }
+
+        Ok(self.open.get_mut(name).unwrap())
+    }
+}
+
+
+// A book is only up to date while the files that hold its chunks are still there
+fn indices_exist(directories: &Directories, book: &Book) -> bool {
+    [collection_name(&book.author), COMBINED_COLLECTION.to_string()].iter()
+        .all(|name| collection_path(directories, name).exists() && keyword_index_path(directories, name).exists())
+}
+
+
+pub fn index_books(directories: &Directories, state: &StateStore, config: &Config, books: &[Book], force: bool, dry_run: bool) {
+    let model_id: String = match model_id(directories, &config.embedding) {
+        Ok(id) => id,
+        Err(e) => {
+            log::error!("Unable to index anything: {}", e);
+            return
+        }
+    };
+
+    let store: EmbeddingStore = match EmbeddingStore::open(directories) {
+        Ok(store) => store,
+        Err(e) => {
+            log::error!("Unable to open the embedding cache: {}", e);
+            return
+        }
+    };
+
+    let vectors_id: String = vectors_id(&model_id, &config.embedding);
+    let mut collections = Collections { directories, config: &config.index, model_id: &model_id, open: BTreeMap::new() };
+
+    // Books are only recorded as indexed once the collections that hold them have been saved
+    let mut indexed: Vec<(&Book, String, String, String)> = Vec::new();
+
+    for book in kdam::tqdm!(books.iter(), desc="Indexing") {
+        let language: &str = config.segmentation.language_of(&book.author);
+        let config_hash: String = hash_config(&(&vectors_id, language, INDEX_VERSION));
+
+        let input_hash: String = match state.input_hash(book, Stage::Indexing, &chunks_path(directories, book)) {
+            Ok(hash) => hash,
+            Err(e) => {
+                log::error!("Unable to index {} (has it been chunked and embedded?): {}", book.title, e);
+                continue
+            }
+        };
+
+        if !force && state.is_current(book, Stage::Indexing, &input_hash, &config_hash) && indices_exist(directories, book) {
+            continue
+        }
+
+        if dry_run {
+            log::info!("Would add the chunks of {} to the {} and {} collections", book.title, collection_name(&book.author), COMBINED_COLLECTION);
+            continue
+        }
+
+        match index_book(directories, &store, &mut collections, &vectors_id, language, book) {
+            Ok(output_hash) => indexed.push((book, input_hash, config_hash, output_hash)),
+            Err(e) => log::error!("Unable to index {}: {}", book.title, e),
+        }
+    }
+
+    let mut saved: bool = true;
+    for (collection, keywords) in collections.open.values_mut() {
+        if let Err(e) = collection.save() {
+            log::error!("Unable to save the {} collection: {}", collection.name, e);
+            saved = false;
+        }
+        if let Err(e) = keywords.save() {
+            log::error!("Unable to save the {} keyword index: {}", keywords.name, e);
+            saved = false;
+        }
+    }
+
+    if !saved {
+        return
+    }
+
+    for (book, input_hash, config_hash, output_hash) in indexed {
+        if let Err(e) = state.record(book, Stage::Indexing, &input_hash, &config_hash, &output_hash) {
+            log::error!("Unable to record that {} was indexed: {}", book.title, e);
+        }
+    }
+}
+
+
+// Upserts the book's chunks into its author's collection and the combined one (and their keyword indices), and deletes any of
+// its chunks that were there before but are no longer in the chunk file
+fn index_book(
+    directories: &Directories,
+    store: &EmbeddingStore,
+    collections: &mut Collections,
+    vectors_id: &str,
+    language: &str,
+    book: &Book,
+) -> Result<String, anyhow::Error> {
+    let input_path: PathBuf = chunks_path(directories, book);
+    anyhow::ensure!(input_path.exists(), "{} has not been chunked", book.title);
+
+    let chunks: Vec<Chunk> = load_chunks(directories, book)?;
+    let mut vectors: Vec<Vec<f32>> = Vec::with_capacity(chunks.len());
+    for chunk in &chunks {
+        let vector: Option<Vec<f32>> = store.get(&chunk.id, vectors_id)?;
+        vectors.push(vector.ok_or_else(|| anyhow::anyhow!("{} has chunks that have not been embedded with {}", book.title, vectors_id))?);
+    }
+
+    let current: HashSet<&str> = chunks.iter().map(|chunk| chunk.id.as_str()).collect();
+    let model_id: &str = collections.model_id;
+
+    for name in [collection_name(&book.author), COMBINED_COLLECTION.to_string()] {
+        let (collection, keywords) = collections.get(&name)?;
+
+        let stale: Vec<String> = collection.records()
+            .filter(|record| record.metadata.author == book.author && record.metadata.book == book.slug)
+            .filter(|record| !current.contains(record.id.as_str()))
+            .map(|record| record.id.clone())
+            .chain(
+                keywords.documents()
+                    .filter(|document| document.metadata.author == book.author && document.metadata.book == book.slug)
+                    .filter(|document| !current.contains(document.id.as_str()))
+                    .map(|document| document.id.clone())
+            )
+            .collect();
+
+        for id in &stale {
+            collection.delete(id);
+            keywords.delete(id);
+        }
+
+        for (chunk, vector) in chunks.iter().zip(&vectors) {
+            let metadata: ChunkMetadata = ChunkMetadata::from_chunk(chunk, language);
+            keywords.upsert(&chunk.id, &metadata, &chunk.text);
+            collection.upsert(model_id, VectorRecord { id: chunk.id.clone(), metadata, document: chunk.text.clone() }, vector)?;
+        }
+    }
+
+    let ids: Vec<&str> = chunks.iter().map(|chunk| chunk.id.as_str()).collect();
+    Ok(hash_bytes(format!("{}\n{}", vectors_id, ids.join("\n")).as_bytes()))
+}
@@ -0,0 +1,464 @@
+use std::fs;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use rust_stemmers::{Algorithm, Stemmer};
+
+use crate::setup::paths::Directories;
+use crate::indices::filters::{ChunkMetadata, Filter};
+use crate::indices::hnsw::{read_string, read_u64, write_string, write_u64};
+
+const MAGIC: &[u8; 8] = b"GIANTSKW";
+const FILE_FORMAT_VERSION: u64 = 1;
+
+const ENGLISH_STOPWORDS: [&str; 48] = [
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "had", "has", "have", "he", "her", "his",
+    "i", "if", "in", "into", "is", "it", "its", "not", "of", "on", "or", "our", "she", "so", "such", "that", "the",
+    "their", "them", "then", "there", "these", "they", "this", "to", "was", "we", "were", "which", "with", "you",
+];
+
+
+// The [index.keywords] section of giants.toml
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeywordIndexConfig {
+    // How quickly repeating a term stops adding to a chunk's score
+    pub k1: f64,
+    // How much longer chunks are penalised, from 0 (not at all) to 1
+    pub b: f64,
+}
+
+
+impl Default for KeywordIndexConfig {
+    fn default() -> Self {
+        Self { k1: 1.2, b: 0.75 }
+    }
+}
+
+
+fn stemming_algorithm(language: &str) -> Option<Algorithm> {
+    match language {
+        "ar" => Some(Algorithm::Arabic),
+        "da" => Some(Algorithm::Danish),
+        "de" => Some(Algorithm::German),
+        "el" => Some(Algorithm::Greek),
+        "en" => Some(Algorithm::English),
+        "es" => Some(Algorithm::Spanish),
+        "fi" => Some(Algorithm::Finnish),
+        "fr" => Some(Algorithm::French),
+        "hu" => Some(Algorithm::Hungarian),
+        "it" => Some(Algorithm::Italian),
+        "nl" => Some(Algorithm::Dutch),
+        "no" => Some(Algorithm::Norwegian),
+        "pt" => Some(Algorithm::Portuguese),
+        "ro" => Some(Algorithm::Romanian),
+        "ru" => Some(Algorithm::Russian),
+        "sv" => Some(Algorithm::Swedish),
+        "ta" => Some(Algorithm::Tamil),
+        "tr" => Some(Algorithm::Turkish),
+        _ => None,
+    }
+}
+
+
+// Splits text into lowercase, stemmed terms for one language. Languages without a stemmer are
+// only lowercased, so that words such as "satyagraha" still match themselves.
+pub struct Analyzer {
+    stemmer: Option<Stemmer>,
+    stopwords: &'static [&'static str],
+}
+
+
+impl Analyzer {
+
+    pub fn for_language(language: &str) -> Self {
+        Self {
+            stemmer: stemming_algorithm(language).map(Stemmer::create),
+            stopwords: if language == "en" {&ENGLISH_STOPWORDS} else {&[]},
+        }
+    }
+
+    pub fn terms(&self, text: &str) -> Vec<String> {
+        text.split(|c: char| !c.is_alphanumeric() && c != '\'' && c != '’')
+            .map(|word| word.trim_matches(['\'', '’']).to_lowercase())
+            .map(|word| word.strip_suffix("'s").or(word.strip_suffix("’s")).map(str::to_string).unwrap_or(word))
+            .filter(|word| !word.is_empty() && !self.stopwords.contains(&word.as_str()))
+            .filter(|word| word.chars().count() > 1 || word.chars().all(|c| c.is_ascii_digit()))
+            .map(|word| match &self.stemmer {
+                Some(stemmer) => stemmer.stem(&word).into_owned(),
+                None => word,
+            })
+            .collect()
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct KeywordDocument {
+    pub id: String,
+    pub metadata: ChunkMetadata,
+    pub document: String,
+    // In terms, after stopwords are removed
+    length: u64,
+}
+
+
+#[derive(Clone, Debug)]
+pub struct KeywordHit {
+    pub id: String,
+    pub score: f64,
+    pub metadata: ChunkMetadata,
+    pub document: String,
+    // The query terms that the chunk contains
+    pub matched_terms: Vec<String>,
+}
+
+
+pub fn keyword_index_path(directories: &Directories, name: &str) -> PathBuf {
+    directories.chroma.join(format!("{}.bm25", name))
+}
+
+
+// An inverted index over the same chunks as the vector collection of the same name, scored with
+// BM25. Each chunk is analysed in its own language, and queries in every language in the index.
+pub struct KeywordIndex {
+    pub name: String,
+    path: PathBuf,
+    config: KeywordIndexConfig,
+    // Removed documents leave an empty slot behind until the index is saved
+    documents: Vec<Option<KeywordDocument>>,
+    positions: HashMap<String, u32>,
+    // For each term, the documents that contain it and how often
+    postings: HashMap<String, Vec<(u32, u32)>>,
+    total_length: u64,
+    changed: bool,
+}
+
+
+impl KeywordIndex {
+
+    pub fn open(directories: &Directories, name: &str, config: &KeywordIndexConfig) -> Result<Self, anyhow::Error> {
+        let path: PathBuf = keyword_index_path(directories, name);
+        let mut index = KeywordIndex {
+            name: name.to_string(),
+            path: path.clone(),
+            config: config.clone(),
+            documents: Vec::new(),
+            positions: HashMap::new(),
+            postings: HashMap::new(),
+            total_length: 0,
+            changed: false,
+        };
+
+        if path.exists() {
+            index.read(&mut BufReader::new(fs::File::open(&path)?))
+                .map_err(|e| anyhow::anyhow!("Unable to read the {} keyword index at {}: {}", name, path.display(), e))?;
+        }
+        Ok(index)
+    }
+
+    fn read(&mut self, reader: &mut impl Read) -> Result<(), anyhow::Error> {
+        let mut magic: [u8; 8] = [0; 8];
+        reader.read_exact(&mut magic)?;
+        anyhow::ensure!(&magic == MAGIC, "not a keyword index");
+
+        let version: u64 = read_u64(reader)?;
+        anyhow::ensure!(version == FILE_FORMAT_VERSION, "unsupported file format {}", version);
+
+        let count: usize = read_u64(reader)? as usize;
+        for position in 0..count {
+            let id: String = read_string(reader)?;
+            let metadata: ChunkMetadata = serde_json::from_str(&read_string(reader)?)?;
+            let document: String = read_string(reader)?;
+            let length: u64 = read_u64(reader)?;
+
+            self.total_length += length;
+            self.positions.insert(id.clone(), position as u32);
+            self.documents.push(Some(KeywordDocument { id, metadata, document, length }));
+        }
+
+        let terms: usize = read_u64(reader)? as usize;
+        for _ in 0..terms {
+            let term: String = read_string(reader)?;
+            let length: usize = read_u64(reader)? as usize;
+            let mut postings: Vec<(u32, u32)> = Vec::with_capacity(length);
+            for _ in 0..length {
+                postings.push((read_u64(reader)? as u32, read_u64(reader)? as u32));
+            }
+            self.postings.insert(term, postings);
+        }
+
+        Ok(())
+    }
+
+    // Empty slots are dropped, and the documents renumbered, as the index is written
+    fn write(&self, writer: &mut impl Write) -> Result<(), anyhow::Error> {
+        let live: Vec<&KeywordDocument> = self.documents.iter().flatten().collect();
+        let mut renumbered: Vec<Option<u32>> = Vec::with_capacity(self.documents.len());
+        let mut next: u32 = 0;
+        for slot in &self.documents {
+            renumbered.push(slot.as_ref().map(|_| next));
+            next += slot.is_some() as u32;
+        }
+
+        writer.write_all(MAGIC)?;
+        write_u64(writer, FILE_FORMAT_VERSION)?;
+        write_u64(writer, live.len() as u64)?;
+
+        for document in &live {
+            write_string(writer, &document.id)?;
+            write_string(writer, &serde_json::to_string(&document.metadata)?)?;
+            write_string(writer, &document.document)?;
+            write_u64(writer, document.length)?;
+        }
+
+        // Sorted, so that the same index is always written the same way
+        let terms: BTreeMap<&String, &Vec<(u32, u32)>> = self.postings.iter().filter(|(_, postings)| !postings.is_empty()).collect();
+        write_u64(writer, terms.len() as u64)?;
+        for (term, postings) in terms {
+            write_string(writer, term)?;
+            write_u64(writer, postings.len() as u64)?;
+            for (position, frequency) in postings {
+                write_u64(writer, renumbered[*position as usize].unwrap() as u64)?;
+                write_u64(writer, *frequency as u64)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.positions.contains_key(id)
+    }
+
+    pub fn documents(&self) -> impl Iterator<Item = &KeywordDocument> {
+        self.documents.iter().flatten()
+    }
+
+    // Adds the chunk, or replaces it if it is already there. Upserting a chunk that has not changed does nothing.
+    pub fn upsert(&mut self, id: &str, metadata: &ChunkMetadata, document: &str) {
+        if let Some(position) = self.positions.get(id) {
+            let existing: &KeywordDocument = self.documents[*position as usize].as_ref().unwrap();
+            if existing.metadata == *metadata && existing.document == document {
+                return
+            }
+            self.delete(id);
+        }
+
+        let terms: Vec<String> = Analyzer::for_language(&metadata.language).terms(document);
+        let mut frequencies: BTreeMap<String, u32> = BTreeMap::new();
+        for term in &terms {
+            *frequencies.entry(term.clone()).or_insert(0) += 1;
+        }
+
+        let position: u32 = self.documents.len() as u32;
+        for (term, frequency) in frequencies {
+            self.postings.entry(term).or_default().push((position, frequency));
+        }
+
+        self.total_length += terms.len() as u64;
+        self.positions.insert(id.to_string(), position);
+        self.documents.push(Some(KeywordDocument {
+            id: id.to_string(),
+            metadata: metadata.clone(),
+            document: document.to_string(),
+            length: terms.len() as u64,
+        }));
+        self.changed = true;
+    }
+
+    pub fn delete(&mut self, id: &str) -> bool {
+        let Some(position) = self.positions.remove(id) else { return false };
+        let document: KeywordDocument = self.documents[position as usize].take().unwrap();
+
+        let terms: BTreeSet<String> = Analyzer::for_language(&document.metadata.language).terms(&document.document).into_iter().collect();
+        for term in terms {
+            if let Some(postings) = self.postings.get_mut(&term) {
+                postings.retain(|(posting, _)| *posting != position);
+            }
+        }
+
+        self.total_length -= document.length;
+        self.changed = true;
+        true
+    }
+
+    // The query's terms in each of the languages that the index holds
+    fn query_terms(&self, query: &str) -> Vec<String> {
+        let languages: BTreeSet<String> = self.documents().map(|document| document.metadata.language.clone()).collect();
+
+        let mut terms: Vec<String> = Vec::new();
+        for language in languages {
+            for term in Analyzer::for_language(&language).terms(query) {
+                if !terms.contains(&term) {
+                    terms.push(term);
+                }
+            }
+        }
+        terms
+    }
+
+    // The k chunks that score highest for the query and pass the filter, highest first. Document
+    // frequencies are counted over the whole index, not just what the filter lets through.
+    pub fn search(&self, query: &str, k: usize, filter: &Filter) -> Vec<KeywordHit> {
+        let terms: Vec<String> = self.query_terms(query);
+        let count: f64 = self.len() as f64;
+        let average_length: f64 = if self.is_empty() {1.0} else {self.total_length as f64 / count};
+
+        let mut scores: HashMap<u32, (f64, Vec<String>)> = HashMap::new();
+        for term in &terms {
+            let Some(postings) = self.postings.get(term) else { continue };
+            let frequency: f64 = postings.len() as f64;
+            let idf: f64 = ((count - frequency + 0.5) / (frequency + 0.5) + 1.0).ln();
+
+            for (position, term_frequency) in postings {
+                let document: &KeywordDocument = self.documents[*position as usize].as_ref().unwrap();
+                if !filter.matches(&document.metadata) {
+                    continue
+                }
+
+                let tf: f64 = *term_frequency as f64;
+                let normalisation: f64 = self.config.k1 * (1.0 - self.config.b + self.config.b * document.length as f64 / average_length);
+                let entry = scores.entry(*position).or_insert((0.0, Vec::new()));
+                entry.0 += idf * tf * (self.config.k1 + 1.0) / (tf + normalisation);
+                entry.1.push(term.clone());
+            }
+        }
+
+        let mut ranked: Vec<(u32, f64, Vec<String>)> = scores.into_iter().map(|(position, (score, matched))| (position, score, matched)).collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        ranked.into_iter()
+            .take(k)
+            .map(|(position, score, matched_terms)| {
+                let document: &KeywordDocument = self.documents[position as usize].as_ref().unwrap();
+                KeywordHit {
+                    id: document.id.clone(),
+                    score,
+                    metadata: document.metadata.clone(),
+                    document: document.document.clone(),
+                    matched_terms,
+                }
+            })
+            .collect()
+    }
+
+    // Writes the index to a partial file first, so that an interrupted save never loses it
+    pub fn save(&mut self) -> Result<(), anyhow::Error> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        fs::create_dir_all(self.path.parent().unwrap())?;
+        let partial_path: PathBuf = self.path.with_extension("bm25.part");
+
+        let mut file = BufWriter::new(fs::File::create(&partial_path)?);
+        self.write(&mut file)?;
+        file.flush()?;
+        drop(file);
+        fs::rename(&partial_path, &self.path)?;
+
+        self.changed = false;
+        log::info!("Saved the {} keyword index with {} chunks", self.name, self.len());
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indices::testing::{metadata, scratch_directories};
+
+    fn ids(hits: &[KeywordHit]) -> Vec<&str> {
+        hits.iter().map(|hit| hit.id.as_str()).collect()
+    }
+
+    fn capital(directories: &Directories) -> KeywordIndex {
+        let mut index = KeywordIndex::open(directories, "karl-marx", &KeywordIndexConfig::default()).unwrap();
+        index.upsert("a", &metadata("Karl Marx", "Capital", 1), "Surplus value is value created by surplus labour.");
+        index.upsert("b", &metadata("Karl Marx", "Capital", 2), "The working day contains necessary labour and surplus labour.");
+        index.upsert("c", &metadata("Karl Marx", "Wage Labour and Capital", 3), "Wages are the price of labour-power.");
+        index
+    }
+
+    #[test]
+    fn ranks_chunks_by_bm25() {
+        let (directories, _) = scratch_directories("keywords-ranking");
+        let index: KeywordIndex = capital(&directories);
+
+        let hits: Vec<KeywordHit> = index.search("surplus value", 10, &Filter::default());
+        assert_eq!(ids(&hits), ["a", "b"]);
+        assert!(hits[0].score > hits[1].score);
+        assert_eq!(hits[0].matched_terms, ["surplus", "valu"]);
+        assert_eq!(ids(&index.search("labour", 1, &Filter::default())), ["b"]);
+        assert!(index.search("dialectics", 10, &Filter::default()).is_empty());
+    }
+
+    #[test]
+    fn applies_the_filter() {
+        let (directories, _) = scratch_directories("keywords-filter");
+        let index: KeywordIndex = capital(&directories);
+
+        let filter = Filter { books: vec![String::from("wage-labour-and-capital")], ..Filter::default() };
+        assert_eq!(ids(&index.search("labour", 10, &filter)), ["c"]);
+        let filter = Filter { books: vec![String::from("capital")], ..Filter::default() };
+        assert_eq!(ids(&index.search("labour", 10, &filter)), ["b", "a"]);
+    }
+
+    #[test]
+    fn upserting_an_unchanged_chunk_does_nothing() {
+        let (directories, _) = scratch_directories("keywords-upsert");
+        let mut index: KeywordIndex = capital(&directories);
+        index.save().unwrap();
+
+        index.upsert("a", &metadata("Karl Marx", "Capital", 1), "Surplus value is value created by surplus labour.");
+        assert!(!index.changed);
+        assert_eq!(index.documents.len(), 3);
+
+        index.upsert("a", &metadata("Karl Marx", "Capital", 1), "Surplus value is unpaid labour.");
+        assert!(index.changed);
+        assert_eq!((index.len(), index.documents.len()), (3, 4));
+        assert_eq!(ids(&index.search("unpaid", 10, &Filter::default())), ["a"]);
+    }
+
+    #[test]
+    fn renumbers_the_chunks_when_saved() {
+        let (directories, _) = scratch_directories("keywords-renumber");
+        let mut index: KeywordIndex = capital(&directories);
+        assert!(index.delete("a"));
+        assert!(!index.delete("a"));
+        index.save().unwrap();
+
+        let reopened = KeywordIndex::open(&directories, "karl-marx", &KeywordIndexConfig::default()).unwrap();
+        assert_eq!(reopened.documents.len(), 2);
+        assert_eq!((reopened.positions["b"], reopened.positions["c"]), (0, 1));
+        assert_eq!(reopened.total_length, index.total_length);
+        assert_eq!(ids(&reopened.search("labour", 10, &Filter::default())), ids(&index.search("labour", 10, &Filter::default())));
+        assert!(reopened.search("value", 10, &Filter::default()).is_empty());
+    }
+
+    #[test]
+    fn stems_in_each_chunks_language() {
+        assert_eq!(Analyzer::for_language("en").terms("The values of commodities"), ["valu", "commod"]);
+        assert_eq!(Analyzer::for_language("hi").terms("Satyagraha"), ["satyagraha"]);
+
+        let (directories, _) = scratch_directories("keywords-languages");
+        let mut index = KeywordIndex::open(&directories, "all", &KeywordIndexConfig::default()).unwrap();
+        index.upsert("en", &metadata("Karl Marx", "Capital", 1), "The value of a commodity.");
+        index.upsert("hi", &ChunkMetadata { language: String::from("hi"), ..metadata("Mahatma Gandhi", "Hind Swaraj", 1) }, "Satyagraha is soul-force.");
+
+        assert_eq!(ids(&index.search("values", 10, &Filter::default())), ["en"]);
+        assert_eq!(ids(&index.search("satyagraha", 10, &Filter::default())), ["hi"]);
+    }
+}
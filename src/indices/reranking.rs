@@ -0,0 +1,130 @@
+use std::path::PathBuf;
+
+use candle_core::{Device, Tensor};
+use candle_nn::{linear, Linear, Module, VarBuilder};
+use candle_transformers::models::bert::{BertModel, Config as BertConfig, DTYPE};
+use serde_json::Value;
+use tokenizers::{PaddingParams, PaddingStrategy, Tokenizer, TruncationParams};
+
+use crate::setup::paths::Directories;
+
+const MODEL_CONFIG_FILE_NAME: &str = "config.json";
+const TOKENIZER_FILE_NAME: &str = "tokenizer.json";
+const WEIGHTS_FILE_NAME: &str = "model.safetensors";
+
+
+// A BERT-style cross-encoder running on the CPU, such as ms-marco-MiniLM-L-6-v2, which reads the
+// query and a passage together and scores how well the passage answers it. It is far slower than
+// comparing vectors, so it only reorders the best few candidates. Models with more than one label,
+// such as those trained for entailment, give a score for each.
+pub struct CrossEncoder {
+    pub name: String,
+    // From the model's id2label, in order, or empty for a model with a single score
+    pub labels: Vec<String>,
+    model: BertModel,
+    pooler: Linear,
+    classifier: Linear,
+    tokenizer: Tokenizer,
+    device: Device,
+}
+
+
+impl CrossEncoder {
+
+    pub fn load(directories: &Directories, name: &str, maximum_length: usize) -> Result<Self, anyhow::Error> {
+        let directory: PathBuf = directories.models.join(name);
+        anyhow::ensure!(
+            directory.join(WEIGHTS_FILE_NAME).exists(),
+            "There is no cross-encoder at {} (it needs {}, {}, and {})",
+            directory.display(), MODEL_CONFIG_FILE_NAME, TOKENIZER_FILE_NAME, WEIGHTS_FILE_NAME,
+        );
+
+        let device = Device::Cpu;
+        let model_config: String = std::fs::read_to_string(directory.join(MODEL_CONFIG_FILE_NAME))?;
+        let bert_config: BertConfig = serde_json::from_str(&model_config)?;
+        let labels: Vec<String> = labels_of(&serde_json::from_str(&model_config)?);
+
+        // Safety: the weights are memory mapped, and must not be modified while the model is loaded
+        let variables = unsafe { VarBuilder::from_mmaped_safetensors(&[directory.join(WEIGHTS_FILE_NAME)], DTYPE, &device)? };
+        let model = BertModel::load(variables.clone(), &bert_config)?;
+
+        // Exported classifiers keep the pooler under the model's own prefix
+        let hidden_size: usize = bert_config.hidden_size;
+        let pooler: Linear = linear(hidden_size, hidden_size, variables.pp("bert.pooler.dense"))
+            .or_else(|_| linear(hidden_size, hidden_size, variables.pp("pooler.dense")))?;
+        let classifier: Linear = linear(hidden_size, labels.len().max(1), variables.pp("classifier"))?;
+
+        let mut tokenizer: Tokenizer = Tokenizer::from_file(directory.join(TOKENIZER_FILE_NAME)).map_err(anyhow::Error::msg)?;
+        tokenizer.with_padding(Some(PaddingParams { strategy: PaddingStrategy::BatchLongest, ..PaddingParams::default() }));
+        tokenizer.with_truncation(Some(TruncationParams { max_length: maximum_length, ..TruncationParams::default() }))
+            .map_err(anyhow::Error::msg)?;
+
+        log::info!("Loaded cross-encoder {}", name);
+        Ok(Self { name: name.to_string(), labels, model, pooler, classifier, tokenizer, device })
+    }
+
+    // One raw score (a logit) for each passage, higher meaning more relevant
+    pub fn score(&self, query: &str, passages: &[&str]) -> Result<Vec<f32>, anyhow::Error> {
+        let pairs: Vec<(String, String)> = passages.iter().map(|passage| (query.to_string(), passage.to_string())).collect();
+        Ok(self.logits(pairs)?.into_iter().map(|logits| logits[0]).collect())
+    }
+
+    // The position of a label, ignoring case
+    pub fn label(&self, name: &str) -> Option<usize> {
+        self.labels.iter().position(|label| label.eq_ignore_ascii_case(name))
+    }
+
+    // The probability of each label for each pair of texts
+    pub fn classify(&self, pairs: &[(&str, &str)]) -> Result<Vec<Vec<f32>>, anyhow::Error> {
+        let pairs: Vec<(String, String)> = pairs.iter().map(|(first, second)| (first.to_string(), second.to_string())).collect();
+        let logits: Vec<Vec<f32>> = self.logits(pairs)?;
+
+        Ok(logits.into_iter().map(|logits| {
+            let largest: f32 = logits.iter().copied().fold(f32::MIN, f32::max);
+            let exponentials: Vec<f32> = logits.iter().map(|logit| (logit - largest).exp()).collect();
+            let total: f32 = exponentials.iter().sum();
+            exponentials.into_iter().map(|exponential| exponential / total).collect()
+        }).collect())
+    }
+
+    fn logits(&self, pairs: Vec<(String, String)>) -> Result<Vec<Vec<f32>>, anyhow::Error> {
+        if pairs.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let encodings = self.tokenizer.encode_batch(pairs, true).map_err(anyhow::Error::msg)?;
+
+        let rows = |values: fn(&tokenizers::Encoding) -> &[u32]| -> Result<Tensor, anyhow::Error> {
+            let rows: Vec<Tensor> = encodings.iter()
+                .map(|encoding| Tensor::new(values(encoding), &self.device))
+                .collect::<Result<_, _>>()?;
+            Ok(Tensor::stack(&rows, 0)?)
+        };
+
+        let input_ids: Tensor = rows(|encoding| encoding.get_ids())?;
+        let type_ids: Tensor = rows(|encoding| encoding.get_type_ids())?;
+        let attention_mask: Tensor = rows(|encoding| encoding.get_attention_mask())?;
+
+        let hidden: Tensor = self.model.forward(&input_ids, &type_ids, Some(&attention_mask))?;
+        let first_token: Tensor = hidden.narrow(1, 0, 1)?.squeeze(1)?;
+        let pooled: Tensor = self.pooler.forward(&first_token)?.tanh()?;
+        let logits: Tensor = self.classifier.forward(&pooled)?;
+
+        Ok(logits.to_vec2::<f32>()?)
+    }
+}
+
+
+// Ordered by id. A model with a single score is given no labels.
+fn labels_of(model_config: &Value) -> Vec<String> {
+    let Some(id2label) = model_config.get("id2label").and_then(Value::as_object) else {
+        return Vec::new();
+    };
+
+    let mut labels: Vec<(usize, String)> = id2label.iter()
+        .filter_map(|(id, label)| Some((id.parse::<usize>().ok()?, label.as_str()?.to_string())))
+        .collect();
+    labels.sort();
+
+    if labels.len() > 1 {labels.into_iter().map(|(_, label)| label).collect()} else {Vec::new()}
+}
@@ -0,0 +1,315 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::setup::config::Config;
+use crate::setup::paths::Directories;
+use crate::indices::reranking::CrossEncoder;
+use crate::indices::indexing::IndexConfig;
+use crate::indices::filters::{ChunkMetadata, Filter};
+use crate::indices::keywords::{KeywordDocument, KeywordHit, KeywordIndex};
+use crate::data_preparation::embedding::{EmbeddingConfig, EmbeddingModel};
+use crate::indices::vectors::{collection_name, collection_path, VectorCollection, VectorHit, COMBINED_COLLECTION};
+
+// How many passages the cross-encoder reads at once
+const RERANK_BATCH_SIZE: usize = 16;
+
+
+// The [retrieval] section of giants.toml
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetrievalConfig {
+    // How much each index's ranking counts for when they are fused
+    pub vector_weight: f64,
+    pub keyword_weight: f64,
+    // Added to every rank before fusing, so that the top few places of either ranking do not drown out the rest
+    pub rrf_k: f64,
+    // How many passages are taken from each index before fusing
+    pub candidates: usize,
+    // A cross-encoder beneath the models directory, to reorder the best fused passages with
+    pub reranker: Option<String>,
+    pub rerank_candidates: usize,
+    pub rerank_maximum_length: usize,
+}
+
+
+impl Default for RetrievalConfig {
+    fn default() -> Self {
+        Self {
+            vector_weight: 1.0,
+            keyword_weight: 1.0,
+            rrf_k: 60.0,
+            candidates: 50,
+            reranker: None,
+            rerank_candidates: 20,
+            rerank_maximum_length: 512,
+        }
+    }
+}
+
+
+// How a passage did at each stage, so that it is clear why it came up. Ranks start from 1, and
+// are missing when the passage was not among that index's candidates.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StageScores {
+    pub vector_rank: Option<usize>,
+    // Cosine similarity with the query
+    pub vector: Option<f32>,
+    pub keyword_rank: Option<usize>,
+    // BM25
+    pub keyword: Option<f64>,
+    pub matched_terms: Vec<String>,
+    pub fused: f64,
+    pub rerank: Option<f32>,
+}
+
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Passage {
+    pub id: String,
+    pub text: String,
+    pub metadata: ChunkMetadata,
+    pub scores: StageScores,
+}
+
+
+impl Passage {
+
+    // Such as "Karl Marx, Capital, Volume I, Chapter 10, pp. 340–341"
+    pub fn citation(&self) -> String {
+        let metadata: &ChunkMetadata = &self.metadata;
+        let mut parts: Vec<String> = vec![metadata.author.clone(), metadata.title.clone()];
+
+        if let Some(chapter) = &metadata.chapter {
+            parts.push(chapter.clone());
+        }
+        if metadata.first_page == metadata.last_page {
+            parts.push(format!("p. {}", metadata.first_page));
+        } else {
+            parts.push(format!("pp. {}–{}", metadata.first_page, metadata.last_page));
+        }
+
+        parts.join(", ")
+    }
+}
+
+
+// Searches with only one author are answered from that author's collection, and everything else
+// from the combined one
+pub fn collection_for(filter: &Filter) -> String {
+    match filter.authors.as_slice() {
+        [author] => collection_name(author),
+        _ => COMBINED_COLLECTION.to_string(),
+    }
+}
+
+
+// Fuses the two rankings by reciprocal rank: each passage scores weight / (rrf_k + rank) for each
+// ranking that it is in, so agreeing indices lift a passage above what either finds alone
+pub fn fuse(vector_hits: &[VectorHit], keyword_hits: &[KeywordHit], config: &RetrievalConfig) -> Vec<Passage> {
+    let mut passages: HashMap<String, Passage> = HashMap::new();
+
+    for (index, hit) in vector_hits.iter().enumerate() {
+        let passage: &mut Passage = passages.entry(hit.id.clone()).or_insert_with(|| Passage {
+            id: hit.id.clone(),
+            text: hit.document.clone(),
+            metadata: hit.metadata.clone(),
+            scores: StageScores::default(),
+        });
+        passage.scores.vector_rank = Some(index + 1);
+        passage.scores.vector = Some(hit.score);
+        passage.scores.fused += config.vector_weight / (config.rrf_k + (index + 1) as f64);
+    }
+
+    for (index, hit) in keyword_hits.iter().enumerate() {
+        let passage: &mut Passage = passages.entry(hit.id.clone()).or_insert_with(|| Passage {
+            id: hit.id.clone(),
+            text: hit.document.clone(),
+            metadata: hit.metadata.clone(),
+            scores: StageScores::default(),
+        });
+        passage.scores.keyword_rank = Some(index + 1);
+        passage.scores.keyword = Some(hit.score);
+        passage.scores.matched_terms = hit.matched_terms.clone();
+        passage.scores.fused += config.keyword_weight / (config.rrf_k + (index + 1) as f64);
+    }
+
+    let mut fused: Vec<Passage> = passages.into_values().collect();
+    fused.sort_by(|a, b| b.scores.fused.total_cmp(&a.scores.fused).then_with(|| a.id.cmp(&b.id)));
+    fused
+}
+
+
+// Finds passages with both the vector and the keyword indices, fuses the two rankings, and
+// reranks the best of them with a cross-encoder if one is configured. The models and indices are
+// loaded once, and kept for later queries.
+pub struct Retriever {
+    directories: Directories,
+    config: RetrievalConfig,
+    index_config: IndexConfig,
+    embedding_config: EmbeddingConfig,
+    model: EmbeddingModel,
+    reranker: Option<CrossEncoder>,
+    open: BTreeMap<String, (VectorCollection, KeywordIndex)>,
+}
+
+
+impl Retriever {
+
+    pub fn new(directories: &Directories, config: &Config) -> Result<Self, anyhow::Error> {
+        let model: EmbeddingModel = EmbeddingModel::load(directories, &config.embedding)?;
+        let reranker: Option<CrossEncoder> = match &config.retrieval.reranker {
+            Some(name) => Some(CrossEncoder::load(directories, name, config.retrieval.rerank_maximum_length)?),
+            None => None,
+        };
+
+        Ok(Self {
+            directories: directories.clone(),
+            config: config.retrieval.clone(),
+            index_config: config.index.clone(),
+            embedding_config: config.embedding.clone(),
+            model,
+            reranker,
+            open: BTreeMap::new(),
+        })
+    }
+
+    fn indices(&mut self, name: &str) -> Result<&(VectorCollection, KeywordIndex), anyhow::Error> {
+        if !self.open.contains_key(name) {
+            anyhow::ensure!(
+                collection_path(&self.directories, name).exists(),
+                "There is no {} collection yet. Have its books been indexed?", name,
+            );
+
+            let collection = VectorCollection::open(&self.directories, name, &self.index_config.vectors)?;
+            let keywords = KeywordIndex::open(&self.directories, name, &self.index_config.keywords)?;
+
+            anyhow::ensure!(
+                collection.model_id == self.model.id,
+                "The {} collection was built with {}, but {} is configured. Index its books again before searching it.",
+                name, collection.model_id, self.embedding_config.model,
+            );
+            self.open.insert(name.to_string(), (collection, keywords));
+        }
+
+        Ok(&self.open[name])
+    }
+
+    // Every chunk that passes the filter, from the collection that would be searched with it
+    pub fn documents(&mut self, filter: &Filter) -> Result<Vec<&KeywordDocument>, anyhow::Error> {
+        let name: String = collection_for(filter);
+        let (_, keywords) = self.indices(&name)?;
+        Ok(keywords.documents().filter(|document| filter.matches(&document.metadata)).collect())
+    }
+
+    // The k passages that best match the query and pass the filter, best first
+    pub fn retrieve(&mut self, query: &str, filter: &Filter, k: usize) -> Result<Vec<Passage>, anyhow::Error> {
+        let vector: Vec<f32> = self.model.embed_query(query)?;
+        let candidates: usize = self.config.candidates.max(k);
+        let name: String = collection_for(filter);
+
+        let (collection, keywords) = self.indices(&name)?;
+        let vector_hits: Vec<VectorHit> = collection.search(&vector, candidates, filter);
+        let keyword_hits: Vec<KeywordHit> = keywords.search(query, candidates, filter);
+
+        let mut passages: Vec<Passage> = fuse(&vector_hits, &keyword_hits, &self.config);
+        log::debug!("{} vector and {} keyword candidates fused into {} passages", vector_hits.len(), keyword_hits.len(), passages.len());
+
+        if let Some(reranker) = &self.reranker {
+            passages.truncate(self.config.rerank_candidates.max(k));
+
+            let texts: Vec<&str> = passages.iter().map(|passage| passage.text.as_str()).collect();
+            let mut scores: Vec<f32> = Vec::with_capacity(texts.len());
+            for batch in texts.chunks(RERANK_BATCH_SIZE) {
+                scores.extend(reranker.score(query, batch)?);
+            }
+
+            for (passage, score) in passages.iter_mut().zip(scores) {
+                passage.scores.rerank = Some(score);
+            }
+            passages.sort_by(|a, b| b.scores.rerank.unwrap().total_cmp(&a.scores.rerank.unwrap()).then_with(|| a.id.cmp(&b.id)));
+        }
+
+        passages.truncate(k);
+        Ok(passages)
+    }
+}
+
+
+pub fn print_passages(passages: &[Passage]) {
+    let format_rank = |rank: Option<usize>| rank.map(|rank| rank.to_string()).unwrap_or(String::from("-"));
+
+    for (index, passage) in passages.iter().enumerate() {
+        println!(
+            "{:>2}. {}  [{}]\n    vector {} ({}), keyword {} ({}), fused {:.4}{}",
+            index + 1,
+            passage.citation(),
+            passage.id,
+            format_rank(passage.scores.vector_rank),
+            passage.scores.vector.map(|score| format!("{:.3}", score)).unwrap_or(String::from("-")),
+            format_rank(passage.scores.keyword_rank),
+            passage.scores.keyword.map(|score| format!("{:.3}", score)).unwrap_or(String::from("-")),
+            passage.scores.fused,
+            passage.scores.rerank.map(|score| format!(", rerank {:.3}", score)).unwrap_or_default(),
+        );
+        if !passage.scores.matched_terms.is_empty() {
+            println!("    matched: {}", passage.scores.matched_terms.join(", "));
+        }
+        println!("    {}\n", passage.text.chars().take(300).collect::<String>().replace('\n', " "));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indices::testing::metadata;
+
+    fn vector_hits(ids: &[&str]) -> Vec<VectorHit> {
+        ids.iter().enumerate()
+            .map(|(index, id)| VectorHit { id: id.to_string(), score: 0.9 - index as f32 / 10.0, metadata: metadata("Karl Marx", "Capital", 1), document: id.to_string() })
+            .collect()
+    }
+
+    fn keyword_hits(ids: &[&str]) -> Vec<KeywordHit> {
+        ids.iter().enumerate()
+            .map(|(index, id)| KeywordHit {
+                id: id.to_string(),
+                score: 10.0 - index as f64,
+                metadata: metadata("Karl Marx", "Capital", 1),
+                document: id.to_string(),
+                matched_terms: vec![String::from("valu")],
+            })
+            .collect()
+    }
+
+    fn order(passages: &[Passage]) -> Vec<&str> {
+        passages.iter().map(|passage| passage.id.as_str()).collect()
+    }
+
+    #[test]
+    fn agreeing_rankings_beat_either_alone() {
+        let fused: Vec<Passage> = fuse(&vector_hits(&["a", "b", "c"]), &keyword_hits(&["d", "b", "e"]), &RetrievalConfig::default());
+
+        // b is second in both, and so beats a and d, which are each first in one. Ties go to the lower id.
+        assert_eq!(order(&fused), ["b", "a", "d", "c", "e"]);
+        assert_eq!((fused[0].scores.vector_rank, fused[0].scores.keyword_rank), (Some(2), Some(2)));
+        assert_eq!(fused[0].scores.matched_terms, ["valu"]);
+        assert!((fused[0].scores.fused - 2.0 / 62.0).abs() < 1e-12);
+        assert_eq!((fused[1].scores.keyword_rank, fused[2].scores.vector_rank), (None, None));
+    }
+
+    #[test]
+    fn weights_change_the_order() {
+        let (vectors, keywords) = (vector_hits(&["a", "b", "c"]), keyword_hits(&["d", "b", "e"]));
+
+        let config = RetrievalConfig { keyword_weight: 2.0, ..RetrievalConfig::default() };
+        assert_eq!(order(&fuse(&vectors, &keywords, &config)), ["b", "d", "e", "a", "c"]);
+
+        let config = RetrievalConfig { vector_weight: 3.0, ..RetrievalConfig::default() };
+        assert_eq!(order(&fuse(&vectors, &keywords, &config)), ["b", "a", "c", "d", "e"]);
+
+        let config = RetrievalConfig { keyword_weight: 0.0, ..RetrievalConfig::default() };
+        assert_eq!(order(&fuse(&vectors, &keywords, &config)), ["a", "b", "c", "d", "e"]);
+    }
+}
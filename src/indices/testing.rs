@@ -0,0 +1,249 @@
+use std::fs;
+use std::sync::Arc;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use candle_core::{DType, Device};
+use candle_nn::{VarBuilder, VarMap};
+use candle_transformers::models::bert::{BertModel, Config as BertConfig};
+use serde_json::{json, Map, Value};
+use tokio::net::TcpListener;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+use crate::setup::config::Config;
+use crate::setup::paths::Directories;
+use crate::sources::manifest::SourceIdentity;
+use crate::data_preparation::books::{slugify, Book};
+use crate::data_preparation::attribution::Voice;
+use crate::data_preparation::chunking::ChunkKind;
+use crate::data_preparation::embedding::{EmbeddingConfig, EmbeddingModel};
+use crate::indices::filters::ChunkMetadata;
+use crate::indices::keywords::{KeywordIndex, KeywordIndexConfig};
+use crate::indices::vectors::{collection_name, VectorCollection, VectorIndexConfig, VectorRecord, COMBINED_COLLECTION};
+
+const MODEL_NAME: &str = "tiny";
+
+
+// An empty layout beneath the temporary directory, made afresh for each test
+pub fn scratch_directories(name: &str) -> (Directories, Config) {
+    let root: PathBuf = std::env::temp_dir().join(format!("giants-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+
+    let config = Config {
+        root: Some(root),
+        embedding: EmbeddingConfig { model: String::from(MODEL_NAME), ..EmbeddingConfig::default() },
+        ..Config::default()
+    };
+    (Directories::setup(&config).unwrap(), config)
+}
+
+
+pub fn book(author: &str, title: &str) -> Book {
+    Book {
+        author: author.to_string(),
+        title: title.to_string(),
+        slug: slugify(title),
+        file_name: format!("{}.txt", slugify(title)),
+        source: SourceIdentity::Http { url: String::from("https://example.org") },
+        needs_ocr: false,
+        start_page: None,
+        end_page: None,
+        secondary_pages: Vec::new(),
+        biographers_and_compilers: Vec::new(),
+        is_interview: false,
+    }
+}
+
+
+pub fn metadata(author: &str, title: &str, page: i64) -> ChunkMetadata {
+    ChunkMetadata {
+        author: author.to_string(),
+        book: title.to_lowercase().replace(' ', "-"),
+        title: title.to_string(),
+        language: String::from("en"),
+        kind: ChunkKind::Text,
+        voice: Voice::Primary,
+        contributor: None,
+        role: None,
+        speakers: Vec::new(),
+        chapter: None,
+        first_page: page,
+        last_page: page,
+        source_url: String::from("https://example.org"),
+    }
+}
+
+
+// A BERT model with random weights and a vocabulary of the given words, small enough to be made
+// on the fly. Its vectors mean nothing, but retrieval runs through it exactly as with a real one.
+pub fn write_model(directories: &Directories, words: &[String]) {
+    let directory: PathBuf = directories.models.join(MODEL_NAME);
+    fs::create_dir_all(&directory).unwrap();
+
+    let mut vocabulary: Map<String, Value> = Map::new();
+    for token in ["[PAD]", "[UNK]", "[CLS]", "[SEP]"].into_iter().map(String::from).chain(words.iter().cloned()) {
+        let id: usize = vocabulary.len();
+        vocabulary.entry(token).or_insert(json!(id));
+    }
+
+    let tokenizer: Value = json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [],
+        "normalizer": { "type": "BertNormalizer", "clean_text": true, "handle_chinese_chars": true, "strip_accents": null, "lowercase": true },
+        "pre_tokenizer": { "type": "BertPreTokenizer" },
+        "post_processor": { "type": "BertProcessing", "sep": ["[SEP]", 3], "cls": ["[CLS]", 2] },
+        "decoder": null,
+        "model": { "type": "WordPiece", "unk_token": "[UNK]", "continuing_subword_prefix": "##", "max_input_chars_per_word": 100, "vocab": vocabulary },
+    });
+    fs::write(directory.join("tokenizer.json"), tokenizer.to_string()).unwrap();
+
+    let config: Value = json!({
+        "vocab_size": vocabulary.len(), "hidden_size": 8, "num_hidden_layers": 1, "num_attention_heads": 2, "intermediate_size": 16,
+        "hidden_act": "gelu", "hidden_dropout_prob": 0.0, "max_position_embeddings": 512, "type_vocab_size": 2,
+        "initializer_range": 0.02, "layer_norm_eps": 1e-12, "pad_token_id": 0, "model_type": "bert",
+    });
+    fs::write(directory.join("config.json"), config.to_string()).unwrap();
+
+    let variables = VarMap::new();
+    let bert_config: BertConfig = serde_json::from_value(config).unwrap();
+    BertModel::load(VarBuilder::from_varmap(&variables, DType::F32, &Device::Cpu), &bert_config).unwrap();
+    variables.save(directory.join("model.safetensors")).unwrap();
+}
+
+
+// Indexes the passages, each given as its id, metadata, and text, in their authors' collections
+// and the combined one, with a model made for them
+pub fn build_corpus(name: &str, passages: &[(&str, ChunkMetadata, &str)]) -> (Directories, Config) {
+    let (directories, config) = scratch_directories(name);
+
+    let mut words: Vec<String> = passages.iter()
+        .flat_map(|(_, _, text)| text.split(|c: char| !c.is_alphanumeric()).map(str::to_lowercase))
+        .filter(|word| !word.is_empty())
+        .collect();
+    words.sort();
+    words.dedup();
+    write_model(&directories, &words);
+
+    let model: EmbeddingModel = EmbeddingModel::load(&directories, &config.embedding).unwrap();
+    let texts: Vec<&str> = passages.iter().map(|(_, _, text)| *text).collect();
+    let vectors: Vec<Vec<f32>> = model.embed_passages(&texts).unwrap();
+
+    let mut names: Vec<String> = passages.iter().map(|(_, metadata, _)| collection_name(&metadata.author)).collect();
+    names.dedup();
+    names.push(COMBINED_COLLECTION.to_string());
+
+    for name in names {
+        let mut collection = VectorCollection::open(&directories, &name, &VectorIndexConfig::default()).unwrap();
+        let mut keywords = KeywordIndex::open(&directories, &name, &KeywordIndexConfig::default()).unwrap();
+
+        for ((id, metadata, text), vector) in passages.iter().zip(&vectors) {
+            if name != COMBINED_COLLECTION && collection_name(&metadata.author) != name {
+                continue
+            }
+            keywords.upsert(id, metadata, text);
+            let record = VectorRecord { id: id.to_string(), metadata: metadata.clone(), document: text.to_string() };
+            collection.upsert(&model.id, record, vector).unwrap();
+        }
+
+        collection.save().unwrap();
+        keywords.save().unwrap();
+    }
+
+    (directories, config)
+}
+
+
+// What a mock server sends back. The body goes out in pieces with a pause before each, so that
+// streaming and timeouts can be tested, and a reply that is cut short claims more body than it sends.
+pub struct MockReply {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub pieces: Vec<String>,
+    pub pause: Duration,
+    pub is_cut_short: bool,
+}
+
+
+impl MockReply {
+
+    pub fn json(status: u16, body: Value) -> Self {
+        Self { status, content_type: "application/json", pieces: vec![body.to_string()], pause: Duration::ZERO, is_cut_short: false }
+    }
+
+    // Server-sent events, one data line for each
+    pub fn events(events: &[&str]) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/event-stream",
+            pieces: events.iter().map(|event| format!("data: {}\n\n", event)).collect(),
+            pause: Duration::ZERO,
+            is_cut_short: false,
+        }
+    }
+}
+
+
+// Serves HTTP on a local port, one request to a connection, answering each request's method, path,
+// and JSON body with respond, and returns the server's address
+pub async fn serve<F>(respond: F) -> String
+where
+    F: Fn(&str, &str, Value) -> MockReply + Send + Sync + 'static,
+{
+    let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url: String = format!("http://{}", listener.local_addr().unwrap());
+    let respond = Arc::new(respond);
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let respond = respond.clone();
+            tokio::spawn(async move {
+                let mut reader = BufReader::new(stream);
+                let mut request_line: String = String::new();
+                reader.read_line(&mut request_line).await.unwrap();
+
+                let mut length: usize = 0;
+                loop {
+                    let mut header: String = String::new();
+                    reader.read_line(&mut header).await.unwrap();
+                    if header.trim().is_empty() {
+                        break
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+
+                let mut body: Vec<u8> = vec![0; length];
+                reader.read_exact(&mut body).await.unwrap();
+                let body: Value = if body.is_empty() {Value::Null} else {serde_json::from_slice(&body).unwrap()};
+
+                let parts: Vec<&str> = request_line.split_whitespace().collect();
+                let reply: MockReply = respond(parts[0], parts[1], body);
+                let length: usize = reply.pieces.iter().map(String::len).sum::<usize>() + if reply.is_cut_short {100} else {0};
+
+                // The client may give up part of the way through, which is what some tests are for
+                let mut stream = reader.into_inner();
+                let head: String = format!(
+                    "HTTP/1.1 {} Mock\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    reply.status, reply.content_type, length,
+                );
+                if stream.write_all(head.as_bytes()).await.is_err() {
+                    return
+                }
+                for piece in &reply.pieces {
+                    tokio::time::sleep(reply.pause).await;
+                    if stream.write_all(piece.as_bytes()).await.is_err() || stream.flush().await.is_err() {
+                        return
+                    }
+                }
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+
+    url
+}
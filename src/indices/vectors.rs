@@ -0,0 +1,312 @@
+use std::fs;
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::setup::paths::Directories;
+use crate::data_preparation::books::slugify;
+use crate::indices::filters::{ChunkMetadata, Filter};
+use crate::indices::hnsw::{normalize, read_string, read_u64, write_string, write_u64, Hnsw};
+
+// The collection that every author's chunks are also added to
+pub const COMBINED_COLLECTION: &str = "all";
+
+const MAGIC: &[u8; 8] = b"GIANTSVX";
+const FILE_FORMAT_VERSION: u64 = 1;
+
+// Filters that let fewer chunks than this through are searched exhaustively, since the graph
+// might not lead to enough of them
+const EXACT_SEARCH_LIMIT: usize = 2000;
+
+// The graph is rebuilt when saved once this share of its nodes have been deleted or replaced
+const MAXIMUM_DELETED_SHARE: f64 = 0.25;
+
+
+// The [index.vectors] section of giants.toml
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VectorIndexConfig {
+    // How many neighbours each node keeps on every layer but the bottom one, which keeps twice as many
+    pub m: usize,
+    pub ef_construction: usize,
+    // How widely searches explore the graph. Higher is slower, but misses fewer of the true neighbours.
+    pub ef_search: usize,
+}
+
+
+impl Default for VectorIndexConfig {
+    fn default() -> Self {
+        Self {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+        }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct VectorRecord {
+    pub id: String,
+    pub metadata: ChunkMetadata,
+    pub document: String,
+}
+
+
+#[derive(Clone, Debug)]
+pub struct VectorHit {
+    pub id: String,
+    // Cosine similarity with the query
+    pub score: f32,
+    pub metadata: ChunkMetadata,
+    pub document: String,
+}
+
+
+pub fn collection_name(author: &str) -> String {
+    slugify(author)
+}
+
+
+pub fn collection_path(directories: &Directories, name: &str) -> PathBuf {
+    directories.chroma.join(format!("{}.hnsw", name))
+}
+
+
+// A file-backed vector index of chunks, together with their metadata and text. Changes are kept in
+// memory until the collection is saved, which replaces the file in one step.
+pub struct VectorCollection {
+    pub name: String,
+    // The embedding model that every vector in the collection came from
+    pub model_id: String,
+    path: PathBuf,
+    config: VectorIndexConfig,
+    // One for each node in the graph, including the deleted ones
+    records: Vec<VectorRecord>,
+    positions: HashMap<String, u32>,
+    graph: Hnsw,
+    changed: bool,
+}
+
+
+impl VectorCollection {
+
+    pub fn open(directories: &Directories, name: &str, config: &VectorIndexConfig) -> Result<Self, anyhow::Error> {
+        let path: PathBuf = collection_path(directories, name);
+        let mut collection = VectorCollection {
+            name: name.to_string(),
+            model_id: String::new(),
+            path: path.clone(),
+            config: config.clone(),
+            records: Vec::new(),
+            positions: HashMap::new(),
+            graph: Hnsw::new(0, config.m, config.ef_construction),
+            changed: false,
+        };
+
+        if path.exists() {
+            collection.read(&mut BufReader::new(fs::File::open(&path)?))
+                .map_err(|e| anyhow::anyhow!("Unable to read the {} collection at {}: {}", name, path.display(), e))?;
+        }
+        Ok(collection)
+    }
+
+    fn read(&mut self, reader: &mut impl Read) -> Result<(), anyhow::Error> {
+        let mut magic: [u8; 8] = [0; 8];
+        reader.read_exact(&mut magic)?;
+        anyhow::ensure!(&magic == MAGIC, "not a vector collection");
+
+        let version: u64 = read_u64(reader)?;
+        anyhow::ensure!(version == FILE_FORMAT_VERSION, "unsupported file format {}", version);
+
+        self.model_id = read_string(reader)?;
+        let count: usize = read_u64(reader)? as usize;
+
+        for position in 0..count {
+            let id: String = read_string(reader)?;
+            let metadata: ChunkMetadata = serde_json::from_str(&read_string(reader)?)?;
+            let document: String = read_string(reader)?;
+            self.records.push(VectorRecord { id, metadata, document });
+            self.positions.insert(self.records[position].id.clone(), position as u32);
+        }
+
+        self.graph = Hnsw::read_from(reader)?;
+        anyhow::ensure!(self.graph.len() == self.records.len(), "the graph does not match the records");
+
+        // Only live records are looked up by id
+        self.positions.retain(|_, position| !self.graph.is_deleted(*position));
+        Ok(())
+    }
+
+    fn write(&self, writer: &mut impl Write) -> Result<(), anyhow::Error> {
+        writer.write_all(MAGIC)?;
+        write_u64(writer, FILE_FORMAT_VERSION)?;
+        write_string(writer, &self.model_id)?;
+        write_u64(writer, self.records.len() as u64)?;
+
+        for record in &self.records {
+            write_string(writer, &record.id)?;
+            write_string(writer, &serde_json::to_string(&record.metadata)?)?;
+            write_string(writer, &record.document)?;
+        }
+
+        self.graph.write_to(writer)
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.positions.contains_key(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&VectorRecord> {
+        self.positions.get(id).map(|position| &self.records[*position as usize])
+    }
+
+    // Scaled to unit length
+    pub fn vector(&self, id: &str) -> Option<&[f32]> {
+        self.positions.get(id).map(|position| self.graph.vector(*position))
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &VectorRecord> {
+        self.records.iter()
+            .enumerate()
+            .filter(|(position, _)| !self.graph.is_deleted(*position as u32))
+            .map(|(_, record)| record)
+    }
+
+    // Empties the collection, as when the embedding model changes
+    pub fn reset(&mut self, model_id: &str) {
+        self.model_id = model_id.to_string();
+        self.records.clear();
+        self.positions.clear();
+        self.graph = Hnsw::new(0, self.config.m, self.config.ef_construction);
+        self.changed = true;
+    }
+
+    // Adds the chunk, or replaces it if it is already there. Upserting a chunk that has not changed does nothing.
+    pub fn upsert(&mut self, model_id: &str, record: VectorRecord, vector: &[f32]) -> Result<(), anyhow::Error> {
+        if self.graph.is_empty() && self.model_id != model_id {
+            self.reset(model_id);
+        }
+
+        anyhow::ensure!(self.model_id == model_id, "The {} collection holds vectors from {}, not {}", self.name, self.model_id, model_id);
+
+        if self.graph.is_empty() {
+            self.graph = Hnsw::new(vector.len(), self.config.m, self.config.ef_construction);
+        }
+        anyhow::ensure!(
+            vector.len() == self.graph.dimensions(),
+            "The {} collection holds vectors of {} dimensions, not {}", self.name, self.graph.dimensions(), vector.len(),
+        );
+
+        if let Some(position) = self.positions.get(&record.id).copied() {
+            let existing: &VectorRecord = &self.records[position as usize];
+            let is_unchanged: bool = existing.metadata == record.metadata
+                && existing.document == record.document
+                && self.graph.vector(position) == normalize(vector).as_slice();
+
+            if is_unchanged {
+                return Ok(());
+            }
+            self.graph.delete(position);
+        }
+
+        let position: u32 = self.graph.insert(vector);
+        self.positions.insert(record.id.clone(), position);
+        self.records.push(record);
+        self.changed = true;
+        Ok(())
+    }
+
+    pub fn delete(&mut self, id: &str) -> bool {
+        let Some(position) = self.positions.remove(id) else { return false };
+        self.graph.delete(position);
+        self.changed = true;
+        true
+    }
+
+    // The k chunks most similar to the query vector that pass the filter, most similar first
+    pub fn search(&self, query: &[f32], k: usize, filter: &Filter) -> Vec<VectorHit> {
+        let accept = |position: u32| filter.matches(&self.records[position as usize].metadata);
+
+        let results: Vec<(u32, f32)> = if filter.is_empty() {
+            self.graph.search(query, k, self.config.ef_search, accept)
+        } else {
+            let matching: usize = self.records().filter(|record| filter.matches(&record.metadata)).count();
+
+            // The graph is explored more widely the fewer chunks the filter lets through
+            let widened: usize = self.config.ef_search.max(k) * self.len() / matching.max(1);
+            let approximate: Vec<(u32, f32)> = if matching <= EXACT_SEARCH_LIMIT {
+                Vec::new()
+            } else {
+                self.graph.search(query, k, widened.min(self.len()), accept)
+            };
+
+            if approximate.len() < k.min(matching) {
+                self.graph.exact_search(query, k, accept)
+            } else {
+                approximate
+            }
+        };
+
+        results.into_iter()
+            .map(|(position, score)| {
+                let record: &VectorRecord = &self.records[position as usize];
+                VectorHit { id: record.id.clone(), score, metadata: record.metadata.clone(), document: record.document.clone() }
+            })
+            .collect()
+    }
+
+    // Rebuilds the graph from the live records, in the order that they were added
+    fn compact(&mut self) {
+        let live: Vec<(VectorRecord, Vec<f32>)> = self.records.iter()
+            .enumerate()
+            .filter(|(position, _)| !self.graph.is_deleted(*position as u32))
+            .map(|(position, record)| (record.clone(), self.graph.vector(position as u32).to_vec()))
+            .collect();
+
+        let mut graph = Hnsw::new(self.graph.dimensions(), self.config.m, self.config.ef_construction);
+        self.records.clear();
+        self.positions.clear();
+
+        for (record, vector) in live {
+            let position: u32 = graph.insert(&vector);
+            self.positions.insert(record.id.clone(), position);
+            self.records.push(record);
+        }
+        self.graph = graph;
+    }
+
+    // Writes the collection to a partial file first, so that an interrupted save never loses it
+    pub fn save(&mut self) -> Result<(), anyhow::Error> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        if self.graph.deleted_count() as f64 > self.graph.len() as f64 * MAXIMUM_DELETED_SHARE {
+            self.compact();
+        }
+
+        fs::create_dir_all(self.path.parent().unwrap())?;
+        let partial_path: PathBuf = self.path.with_extension("hnsw.part");
+
+        let mut file = BufWriter::new(fs::File::create(&partial_path)?);
+        self.write(&mut file)?;
+        file.flush()?;
+        drop(file);
+        fs::rename(&partial_path, &self.path)?;
+
+        self.changed = false;
+        log::info!("Saved the {} collection with {} chunks", self.name, self.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+// The modules below are where the work actually happens. Their layout is free to change, so other
+// crates should depend on the re-exports that follow them instead.
+
+mod sources {
+    pub mod extensions;
+    pub mod scraping;
+    pub mod torrents;
+    pub mod authors;
+    pub mod manifest;
+    pub mod source;
+    pub mod lint;
+    pub mod http;
+}
+
+mod data_preparation {
+    pub mod books;
+    pub mod downloads;
+    pub mod extraction;
+    pub mod cleaning;
+    pub mod notes;
+    pub mod attribution;
+    pub mod dialogue;
+    pub mod segmentation;
+    pub mod chunking;
+    pub mod embedding;
+    pub mod ocr;
+    pub mod planning;
+    pub mod status;
+    pub mod state;
+}
+
+mod generation {
+    pub mod llm;
+    pub mod openai;
+    pub mod mock;
+    pub mod answering;
+    pub mod persona;
+    pub mod comparison;
+    pub mod faithfulness;
+}
+
+mod research {
+    pub mod quotes;
+    pub mod concordance;
+}
+
+mod indices {
+    pub mod filters;
+    pub mod hnsw;
+    pub mod vectors;
+    pub mod keywords;
+    pub mod indexing;
+    pub mod chroma;
+    pub mod reranking;
+    pub mod retrieval;
+    #[cfg(test)]
+    pub mod testing;
+}
+
+pub mod setup {
+    pub mod paths;
+    pub mod config;
+    pub mod logging;
+}
+
+
+// The authors, their books, and what can be said about them without touching the network
+pub mod catalog {
+    pub use crate::sources::lint::lint_catalog;
+    pub use crate::sources::authors::{get_author_root, prepare_sources, Author};
+    pub use crate::data_preparation::books::{acquired_books, list_books, slugify, Book, Selection};
+}
+
+
+// Getting the books onto disk, and keeping track of what has already been acquired
+pub mod acquisition {
+    pub use crate::sources::http::ViaHTTP;
+    pub use crate::sources::source::{download_with_retries, Source, MAXIMUM_ATTEMPTS};
+    pub use crate::sources::scraping::ViaScraper;
+    pub use crate::sources::authors::DownloadOptions;
+    pub use crate::data_preparation::status::verify_downloads;
+    pub use crate::data_preparation::downloads::download_all_texts;
+    pub use crate::sources::torrents::{ViaTorrent, STAGING_DIRECTORY_NAME};
+    pub use crate::sources::extensions::{FILE_EXTENSIONS, IMAGE_EXTENSIONS};
+    pub use crate::sources::manifest::{hash_file, Manifest, ManifestEntry, SourceIdentity, SourceKind, MANIFEST_FILE_NAME};
+}
+
+
+// Turning the acquired files into plain text
+pub mod extraction {
+    pub use crate::data_preparation::ocr::{ocr_books, ocr_pdf_path, ocr_text_path, OCR_PROGRAM};
+    pub use crate::data_preparation::extraction::{
+        detect_format, EXTRACTION_VERSION, extract, extract_books, extracted_metadata_path, extracted_text_path, extraction_input, html_to_text,
+        DocumentFormat, ExtractedDocument, PAGE_SEPARATOR,
+    };
+}
+
+
+// Removing what the page layout left behind in the extracted text
+pub mod cleaning {
+    pub use crate::data_preparation::cleaning::{
+        clean_book, clean_books, clean_text, cleaned_text_path, cleaning_report_path, is_heading, paragraphs,
+        CleaningConfig, CleaningReport, CleaningRules, CLEANING_VERSION,
+    };
+    pub use crate::data_preparation::notes::{annotations_path, load_annotations, separate_notes, Annotation, NoteKind};
+    pub use crate::data_preparation::attribution::{label_sections, load_sections, section_at, sections_path, Section, Voice};
+    pub use crate::data_preparation::dialogue::{load_turns, parse_turns, speaker_label, turn_at, turns_path, Role, Turn};
+}
+
+
+// Finding where sentences end, for the chunker and for anything that quotes the texts
+pub mod segmentation {
+    pub use crate::data_preparation::segmentation::{
+        built_in_abbreviations, evaluate, Evaluation, SegmentationConfig, Segmenter, EVALUATION_FIXTURE,
+    };
+}
+
+
+// Splitting the cleaned text into retrieval passages
+pub mod chunking {
+    pub use crate::data_preparation::chunking::{
+        chunk_book, chunk_books, chunks_path, load_chunks, Chunk, ChunkKind, ChunkingConfig, SizeUnit, CHUNK_FORMAT_VERSION,
+    };
+}
+
+
+// Turning passages into vectors with a local model
+pub mod embedding {
+    pub use crate::data_preparation::embedding::{
+        embed_books, model_directory, model_id, vectors_id, EmbeddingConfig, EmbeddingModel, EmbeddingStore, EMBEDDINGS_FILE_NAME, EMBEDDING_VERSION,
+    };
+}
+
+
+// Local indices over the embedded chunks, one collection per author and one for everyone
+pub mod indexing {
+    pub use crate::indices::filters::{ChunkMetadata, Filter};
+    pub use crate::indices::indexing::{index_books, IndexConfig, INDEX_VERSION};
+    pub use crate::indices::keywords::{keyword_index_path, Analyzer, KeywordDocument, KeywordHit, KeywordIndex, KeywordIndexConfig};
+    pub use crate::indices::chroma::{
+        chroma_metadata, compare, export_collections, print_sync_reports, verify_collections, ChromaClient, ChromaConfig, SyncReport,
+    };
+    pub use crate::indices::vectors::{
+        collection_name, collection_path, VectorCollection, VectorHit, VectorIndexConfig, VectorRecord, COMBINED_COLLECTION,
+    };
+}
+
+
+// Finding the passages that answer a query
+pub mod retrieval {
+    pub use crate::indices::reranking::CrossEncoder;
+    pub use crate::indices::retrieval::{collection_for, fuse, print_passages, Passage, RetrievalConfig, Retriever, StageScores};
+}
+
+
+// Answering questions from an author's works with a language model
+pub mod answering {
+    pub use crate::generation::mock::{MockBackend, MockConfig};
+    pub use crate::generation::openai::{OpenAiBackend, OpenAiConfig};
+    pub use crate::generation::llm::{load_backend, ChatMessage, Completion, LlmBackend, LlmConfig, LlmProfile, OnText, TokenUsage};
+    pub use crate::generation::answering::{answer_question, build_prompt, Answer, AnsweringConfig};
+    pub use crate::generation::persona::{answer_as_author, build_persona_prompt, Persona, DEFAULT_DISCLAIMER, INTERPRETATION_LABEL};
+    pub use crate::generation::comparison::{build_comparison_prompt, compare_authors, debate, Debate};
+    pub use crate::generation::faithfulness::{ClaimCheck, FaithfulnessChecker, FaithfulnessConfig, FaithfulnessReport, QuotationCheck};
+}
+
+
+// Checking quotations against the cleaned texts
+pub mod quotations {
+    pub use crate::research::quotes::{Normalizer, QuoteConfig, QuoteFinder, QuoteMatch};
+}
+
+
+// Every occurrence of a word or pattern in the texts, with its context
+pub mod concordance {
+    pub use crate::research::concordance::{
+        concordance_pattern, BookCount, Concordance, ConcordanceLine, ConcordanceOrder, ConcordanceText, CSV_HEADER,
+    };
+}
+
+
+// Reporting on how far each book has made it through the pipeline, or how far a run would take it
+pub mod pipeline {
+    pub use crate::data_preparation::status::print_status;
+    pub use crate::data_preparation::planning::{print_plan, AuthorPlan, PlannedArtifact};
+    pub use crate::data_preparation::state::{hash_config, Stage, StageRecord, StateStore, STATE_FILE_NAME};
+}
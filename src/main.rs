@@ -1,29 +1,30 @@
-use tokio;
+use clap::Parser;
 
-use setup::logging::setup_logging;
-use data_preparation::downloads::download_all_texts;
+use cli::Cli;
+use giants_core_rust::setup::config::Config;
+use giants_core_rust::setup::paths::Directories;
+use giants_core_rust::setup::logging::setup_logging;
 
-mod setup {
-    pub mod paths; 
-    pub mod logging; 
-}
-
-mod sources {
-    pub mod extensions;
-    pub mod scraping;
-    pub mod torrents;
-    pub mod authors;
-    pub mod utils;
-    pub mod http;
-}
-
-mod data_preparation{
-    pub mod downloads;
-}
+mod cli;
 
 
 #[tokio::main]
 async fn main() {
+    let cli: Cli = Cli::parse();
     setup_logging();
-    download_all_texts().await
+
+    let (config, directories): (Config, Directories) = match Config::load().and_then(
+        |config| Directories::setup(&config).map(|directories| (config, directories))
+    ) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            log::error!("Could not resolve the directory layout: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = cli::run(cli, &config, &directories).await {
+        log::error!("{}", e);
+        std::process::exit(1);
+    }
 }
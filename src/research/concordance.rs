@@ -0,0 +1,422 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::data_preparation::books::Book;
+use crate::data_preparation::attribution::{load_sections, Section};
+use crate::data_preparation::cleaning::cleaned_text_path;
+use crate::data_preparation::extraction::{extracted_metadata_path, extracted_text_path, ExtractedDocument, PAGE_SEPARATOR};
+use crate::setup::paths::Directories;
+
+// Books are read a page at a time, or in pieces of about this many characters where they have no pages
+const BLOCK_SIZE: usize = 65_536;
+// How far past the end of a block a match may run, and still be found whole
+const MAXIMUM_MATCH_LENGTH: usize = 500;
+
+pub const CSV_HEADER: &str = "author,title,book,page,chapter,offset,left,match,right";
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConcordanceOrder {
+    // In the order of the books, and of the text within each one
+    Position,
+    // Alphabetically by the words after each match
+    Right,
+    // Alphabetically by the words before each match, nearest first
+    Left,
+}
+
+
+// Which copy of each book is searched
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConcordanceText {
+    // Without running headers, page numbers, or broken hyphenation, and with chapters
+    Cleaned,
+    // As it came out of extraction
+    Extracted,
+}
+
+
+// One occurrence, with the text on either side of it on one line
+#[derive(Clone, Debug, Serialize)]
+pub struct ConcordanceLine {
+    pub author: String,
+    pub title: String,
+    pub book: String,
+    pub page: i64,
+    pub chapter: Option<String>,
+    // In characters into the text that was searched
+    pub offset: usize,
+    pub left: String,
+    pub keyword: String,
+    pub right: String,
+}
+
+
+impl ConcordanceLine {
+
+    fn collocates(text: &str) -> Vec<String> {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    }
+
+    pub fn to_csv(&self) -> String {
+        let page: String = self.page.to_string();
+        let offset: String = self.offset.to_string();
+        let chapter: &str = self.chapter.as_deref().unwrap_or_default();
+
+        [&self.author, &self.title, &self.book, &page, chapter, &offset, &self.left, &self.keyword, &self.right]
+            .iter()
+            .map(|field| if field.contains([',', '"', '\n']) {format!("\"{}\"", field.replace('"', "\"\""))} else {field.to_string()})
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+}
+
+
+// How often the pattern occurs in one book
+#[derive(Clone, Debug, Serialize)]
+pub struct BookCount {
+    pub author: String,
+    pub title: String,
+    pub book: String,
+    pub occurrences: usize,
+    pub words: usize,
+}
+
+
+impl BookCount {
+
+    pub fn per_ten_thousand_words(&self) -> f64 {
+        if self.words == 0 {0.0} else {self.occurrences as f64 * 10_000.0 / self.words as f64}
+    }
+}
+
+
+// A term matches as a whole word or phrase, ignoring case and however the words are spaced or
+// broken across lines. A regular expression is used as it is, but also ignores case unless it
+// starts with (?-i).
+pub fn concordance_pattern(term: &str, is_regex: bool) -> Result<Regex, anyhow::Error> {
+    let pattern: String = if is_regex {
+        format!("(?i){}", term)
+    } else {
+        let words: Vec<String> = term.split_whitespace().map(regex::escape).collect();
+        anyhow::ensure!(!words.is_empty(), "There is no term to look for");
+        format!(r"(?i)\b{}\b", words.join(r"\s+"))
+    };
+
+    Ok(Regex::new(&pattern)?)
+}
+
+
+// A page, or a piece of one, and where it starts
+struct Block {
+    text: String,
+    page: i64,
+    // In characters
+    offset: usize,
+}
+
+
+// Reads a book a block at a time, so that only a page or two of it is ever held at once
+struct Blocks {
+    reader: BufReader<File>,
+    line: String,
+    // What is left of the last line after a page break
+    rest: String,
+    page: i64,
+    offset: usize,
+    is_done: bool,
+}
+
+
+impl Blocks {
+
+    fn open(path: &Path, first_page: i64) -> Result<Self, anyhow::Error> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(Self { reader, line: String::new(), rest: String::new(), page: first_page, offset: 0, is_done: false })
+    }
+
+    fn next_block(&mut self) -> Result<Option<Block>, anyhow::Error> {
+        if self.is_done {
+            return Ok(None);
+        }
+
+        let mut text: String = std::mem::take(&mut self.rest);
+        let page: i64 = self.page;
+        let offset: usize = self.offset;
+        // How much of the text is known to have no page break in it, so that only each new line is looked through
+        let mut checked: usize = 0;
+
+        loop {
+            if let Some(at) = text[checked..].find(PAGE_SEPARATOR).map(|at| checked + at) {
+                self.rest = text[at + PAGE_SEPARATOR.len_utf8()..].to_string();
+                text.truncate(at);
+                self.page += 1;
+                // The separator is counted, but left out of the block
+                self.offset += text.chars().count() + 1;
+                return Ok(Some(Block { text, page, offset }));
+            }
+            if text.len() >= BLOCK_SIZE {
+                self.offset += text.chars().count();
+                return Ok(Some(Block { text, page, offset }));
+            }
+
+            self.line.clear();
+            if self.reader.read_line(&mut self.line)? == 0 {
+                self.is_done = true;
+                self.offset += text.chars().count();
+                return Ok(Some(Block { text, page, offset }));
+            }
+            checked = text.len();
+            text.push_str(&self.line);
+        }
+    }
+}
+
+
+// At most the first n characters of a text
+fn head(text: &str, n: usize) -> &str {
+    text.char_indices().nth(n).map(|(index, _)| &text[..index]).unwrap_or(text)
+}
+
+
+// At most the last n characters of a text
+fn tail(text: &str, n: usize) -> &str {
+    match n {
+        0 => "",
+        n => text.char_indices().rev().nth(n - 1).map(|(index, _)| &text[index..]).unwrap_or(text),
+    }
+}
+
+
+fn single_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+
+// Finds every occurrence of a pattern in the selected books, one block at a time, with the text on
+// either side of it
+pub struct Concordance {
+    pattern: Regex,
+    // In characters, on each side
+    width: usize,
+    text: ConcordanceText,
+}
+
+
+impl Concordance {
+
+    pub fn new(pattern: Regex, width: usize, text: ConcordanceText) -> Self {
+        Self { pattern, width, text }
+    }
+
+    // Passes each line to on_line as it is found, and returns how often the pattern occurs
+    pub fn search_book(&self, directories: &Directories, book: &Book, on_line: &mut impl FnMut(ConcordanceLine)) -> Result<BookCount, anyhow::Error> {
+        let document: ExtractedDocument = serde_json::from_str(&std::fs::read_to_string(extracted_metadata_path(directories, book))?)?;
+        let (path, sections): (PathBuf, Vec<Section>) = match self.text {
+            ConcordanceText::Cleaned => (cleaned_text_path(directories, book), load_sections(directories, book)?),
+            ConcordanceText::Extracted => (extracted_text_path(directories, book), Vec::new()),
+        };
+
+        let mut count = BookCount { author: document.author.clone(), title: book.title.clone(), book: book.slug.clone(), occurrences: 0, words: 0 };
+        let mut blocks: Blocks = Blocks::open(&path, document.first_page)?;
+        let mut previous: String = String::new();
+        let mut next: Option<Block> = blocks.next_block()?;
+        // How far into this block the last match of the one before it ran, in bytes
+        let mut carried: usize = 0;
+
+        while let Some(block) = next {
+            next = blocks.next_block()?;
+            count.words += block.text.split_whitespace().count();
+
+            // Matches that start in this block may run on into the next, and their context may reach into either
+            let following: &str = next.as_ref().map(|next| head(&next.text, self.width + MAXIMUM_MATCH_LENGTH)).unwrap_or_default();
+            let searched: String = format!("{}\n{}", block.text, following);
+
+            let mut characters_before: usize = 0;
+            let mut last_start: usize = 0;
+            let mut runs_on: usize = 0;
+            // A match that was found whole from the block before is not counted again
+            let matches = self.pattern.find_iter(&searched)
+                .filter(|found| found.start() >= carried && found.start() < block.text.len() && !found.is_empty());
+
+            for found in matches {
+                characters_before += searched[last_start..found.start()].chars().count();
+                last_start = found.start();
+                runs_on = found.end().saturating_sub(block.text.len() + 1);
+                let offset: usize = block.offset + characters_before;
+
+                let before: &str = tail(&searched[..found.start()], self.width);
+                let before: String = format!("{}{}", tail(&previous, self.width - before.chars().count()), before);
+                let chapter: Option<String> = sections.iter()
+                    .filter(|section| section.start <= offset)
+                    .filter_map(|section| section.heading.clone())
+                    .next_back();
+
+                count.occurrences += 1;
+                on_line(ConcordanceLine {
+                    author: document.author.clone(),
+                    title: book.title.clone(),
+                    book: book.slug.clone(),
+                    page: block.page,
+                    chapter,
+                    offset,
+                    left: single_line(&before),
+                    keyword: single_line(found.as_str()),
+                    right: single_line(head(&searched[found.end()..], self.width)),
+                });
+            }
+
+            previous = format!("{}\n", tail(&block.text, self.width));
+            carried = runs_on;
+        }
+
+        Ok(count)
+    }
+
+    // Searches each book in turn. In position order every line is passed on as soon as it is found,
+    // so nothing is held in memory but the counts; sorting by collocate has to keep every line until
+    // the end, though not the texts themselves.
+    pub fn search(
+        &self,
+        directories: &Directories,
+        books: &[Book],
+        order: ConcordanceOrder,
+        mut on_line: impl FnMut(ConcordanceLine),
+    ) -> Result<Vec<BookCount>, anyhow::Error> {
+        let mut counts: Vec<BookCount> = Vec::new();
+        let mut kept: Vec<ConcordanceLine> = Vec::new();
+
+        for book in books {
+            let result: Result<BookCount, anyhow::Error> = if order == ConcordanceOrder::Position {
+                self.search_book(directories, book, &mut on_line)
+            } else {
+                self.search_book(directories, book, &mut |line| kept.push(line))
+            };
+
+            match result {
+                Ok(count) => counts.push(count),
+                Err(e) => log::warn!("Unable to search {} (has it been extracted and cleaned?): {}", book.title, e),
+            }
+        }
+
+        match order {
+            ConcordanceOrder::Position => (),
+            ConcordanceOrder::Right => kept.sort_by_cached_key(|line| (ConcordanceLine::collocates(&line.right), line.keyword.to_lowercase())),
+            ConcordanceOrder::Left => kept.sort_by_cached_key(|line| {
+                let mut collocates: Vec<String> = ConcordanceLine::collocates(&line.left);
+                collocates.reverse();
+                (collocates, line.keyword.to_lowercase())
+            }),
+        }
+        kept.into_iter().for_each(on_line);
+
+        Ok(counts)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::manifest::SourceIdentity;
+    use crate::indices::testing::scratch_directories;
+    use crate::data_preparation::extraction::DocumentFormat;
+
+    // An extracted book with the given text, whose first page is page 5
+    fn book(directories: &Directories, text: &str) -> Book {
+        let book = Book {
+            author: String::from("Karl Marx"),
+            title: String::from("Capital"),
+            slug: String::from("capital"),
+            file_name: String::from("capital.pdf"),
+            source: SourceIdentity::Http { url: String::from("https://example.org/capital.pdf") },
+            needs_ocr: false,
+            start_page: None,
+            end_page: None,
+            secondary_pages: Vec::new(),
+            biographers_and_compilers: Vec::new(),
+            is_interview: false,
+        };
+        let document = ExtractedDocument {
+            author: book.author.clone(),
+            title: book.title.clone(),
+            slug: book.slug.clone(),
+            source_url: String::from("https://example.org/capital.pdf"),
+            format: DocumentFormat::Pdf,
+            first_page: 5,
+            is_interview: false,
+        };
+
+        std::fs::create_dir_all(extracted_text_path(directories, &book).parent().unwrap()).unwrap();
+        std::fs::write(extracted_text_path(directories, &book), text).unwrap();
+        std::fs::write(extracted_metadata_path(directories, &book), serde_json::to_string(&document).unwrap()).unwrap();
+        book
+    }
+
+    fn search(name: &str, text: &str, pattern: Regex) -> (Vec<ConcordanceLine>, BookCount) {
+        let (directories, _) = scratch_directories(name);
+        let book: Book = book(&directories, text);
+
+        let mut lines: Vec<ConcordanceLine> = Vec::new();
+        let count: BookCount = Concordance::new(pattern, 20, ConcordanceText::Extracted)
+            .search_book(&directories, &book, &mut |line| lines.push(line))
+            .unwrap();
+        (lines, count)
+    }
+
+    #[test]
+    fn finds_phrases_across_page_breaks() {
+        let text: &str = "The history of all hitherto existing society is the history of class\x0cstruggle. The class struggle is political.";
+        let (lines, count) = search("concordance-pages", text, concordance_pattern("class struggle", false).unwrap());
+
+        assert_eq!(count.occurrences, 2);
+        assert_eq!(lines.iter().map(|line| line.keyword.as_str()).collect::<Vec<&str>>(), vec!["class struggle", "class struggle"]);
+        assert_eq!((lines[0].page, lines[1].page), (5, 6));
+        assert_eq!(lines[0].offset, text.find("class\x0c").unwrap());
+        assert_eq!(lines[1].offset, text.rfind("class").unwrap());
+        assert_eq!(lines[0].right, ". The class struggle");
+    }
+
+    #[test]
+    fn counts_a_match_that_runs_into_the_next_page_once() {
+        let (lines, count) = search("concordance-once", "Stop at baaa\x0caab then go on.", concordance_pattern(r"a+\s*a+b", true).unwrap());
+        assert_eq!(count.occurrences, 1);
+        assert_eq!(lines[0].keyword, "aaa aab");
+        assert_eq!(lines[0].page, 5);
+    }
+
+    #[test]
+    fn counts_offsets_in_characters_rather_than_bytes() {
+        let text: &str = "Über die Arbeit\x0cund das Kapital, das Kapital.";
+        let (lines, count) = search("concordance-offsets", text, concordance_pattern("kapital", false).unwrap());
+
+        assert_eq!(count.occurrences, 2);
+        assert_eq!(count.words, 8);
+        let expected: Vec<usize> = text.match_indices("Kapital").map(|(at, _)| text[..at].chars().count()).collect();
+        assert_eq!(lines.iter().map(|line| line.offset).collect::<Vec<usize>>(), expected);
+    }
+
+    #[test]
+    fn quotes_csv_fields_that_need_it() {
+        let line = ConcordanceLine {
+            author: String::from("Karl Marx"),
+            title: String::from("Capital, Volume I"),
+            book: String::from("capital"),
+            page: 12,
+            chapter: None,
+            offset: 40,
+            left: String::from("the \"so-called\""),
+            keyword: String::from("primitive"),
+            right: String::from("accumulation\nof capital"),
+        };
+        assert_eq!(line.to_csv(), "Karl Marx,\"Capital, Volume I\",capital,12,,40,\"the \"\"so-called\"\"\",primitive,\"accumulation\nof capital\"");
+    }
+}
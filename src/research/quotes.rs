@@ -0,0 +1,458 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data_preparation::books::Book;
+use crate::data_preparation::attribution::Voice;
+use crate::data_preparation::cleaning::cleaned_text_path;
+use crate::data_preparation::chunking::{load_chunks, Chunk, ChunkKind};
+use crate::data_preparation::extraction::{extracted_metadata_path, ExtractedDocument, PAGE_SEPARATOR};
+use crate::data_preparation::segmentation::SegmentationConfig;
+use crate::indices::keywords::Analyzer;
+use crate::setup::paths::Directories;
+
+// Spellings that older editions and translations use, with the ones that they are matched as
+const ARCHAIC_SPELLINGS: [(&str, &str); 16] = [
+    ("shew", "show"), ("shewn", "shown"), ("shewed", "showed"), ("shews", "shows"), ("shewing", "showing"),
+    ("connexion", "connection"), ("connexions", "connections"), ("reflexion", "reflection"), ("compleat", "complete"),
+    ("publick", "public"), ("antient", "ancient"), ("chuse", "choose"), ("hath", "has"), ("doth", "does"),
+    ("burthen", "burden"), ("controul", "control"),
+];
+
+// Sentences are only tried together until they hold this many times as many words as the quotation
+const WINDOW_FACTOR: f64 = 1.5;
+// Stretches of text with a smaller share of the quotation's terms are not scored any further
+const MINIMUM_TERM_RECALL: f64 = 0.3;
+
+
+// The [quotes] section of giants.toml
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuoteConfig {
+    // Matches that score less than this, out of 1, are not reported
+    pub minimum_score: f64,
+    // Further spellings to treat as the same, each mapped to the one that it is matched as
+    pub spellings: BTreeMap<String, String>,
+}
+
+
+impl Default for QuoteConfig {
+    fn default() -> Self {
+        Self {
+            minimum_score: 0.5,
+            spellings: BTreeMap::new(),
+        }
+    }
+}
+
+
+// A stretch of the cleaned text that resembles the quotation
+#[derive(Clone, Debug, Serialize)]
+pub struct QuoteMatch {
+    pub author: String,
+    pub title: String,
+    pub book: String,
+    pub chapter: Option<String>,
+    pub first_page: i64,
+    pub last_page: i64,
+    // Whether the author wrote it, or an editor, translator, or biographer
+    pub voice: Voice,
+    // The chunk that the match begins in
+    pub chunk_id: Option<String>,
+    // Offsets in characters into the cleaned text, end exclusive
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+    // From 0 to 1, half for how many of the quotation's terms the text has, and half for how many of
+    // its words it has in the same order
+    pub score: f64,
+    // Whether the text has every word of the quotation, in order and without gaps
+    pub is_exact: bool,
+}
+
+
+impl QuoteMatch {
+
+    pub fn citation(&self) -> String {
+        let mut parts: Vec<String> = vec![self.author.clone(), self.title.clone()];
+        if let Some(chapter) = &self.chapter {
+            parts.push(chapter.clone());
+        }
+        if self.first_page == self.last_page {
+            parts.push(format!("p. {}", self.first_page));
+        } else {
+            parts.push(format!("pp. {}–{}", self.first_page, self.last_page));
+        }
+        parts.join(", ")
+    }
+}
+
+
+// Lowercases words and folds away what differs between printings: punctuation, ligatures and long
+// s, accents on Latin letters, hyphens and apostrophes within words, British and American endings,
+// and archaic spellings. The quotation and the corpus are folded alike, so a fold need only be
+// consistent, not correct.
+pub struct Normalizer {
+    spellings: BTreeMap<String, String>,
+}
+
+
+impl Normalizer {
+
+    pub fn new(config: &QuoteConfig) -> Self {
+        let spellings: BTreeMap<String, String> = ARCHAIC_SPELLINGS.iter()
+            .map(|(old, new)| (old.to_string(), new.to_string()))
+            .chain(config.spellings.iter().map(|(old, new)| (old.to_lowercase(), new.to_lowercase())))
+            .collect();
+        Self { spellings }
+    }
+
+    fn fold(c: char) -> &'static str {
+        match c {
+            'æ' => "ae",
+            'œ' => "oe",
+            'ﬀ' => "ff",
+            'ﬁ' => "fi",
+            'ﬂ' => "fl",
+            'ﬃ' => "ffi",
+            'ﬄ' => "ffl",
+            'ﬅ' | 'ﬆ' => "st",
+            'ß' => "ss",
+            'ſ' => "s",
+            'à' | 'á' | 'â' | 'ä' | 'ã' | 'å' => "a",
+            'è' | 'é' | 'ê' | 'ë' => "e",
+            'ì' | 'í' | 'î' | 'ï' => "i",
+            'ò' | 'ó' | 'ô' | 'ö' | 'õ' => "o",
+            'ù' | 'ú' | 'û' | 'ü' => "u",
+            'ç' => "c",
+            'ñ' => "n",
+            _ => "",
+        }
+    }
+
+    fn spell(&self, word: String) -> String {
+        if let Some(spelling) = self.spellings.get(&word) {
+            return spelling.clone();
+        }
+
+        // Labour and labor, realise and realize, organisation and organization
+        let word: String = match word.find("our") {
+            Some(at) if at >= 2 && word.len() >= 5 && ["", "s", "ed", "ing", "ite", "ites", "able", "er", "ers"].contains(&&word[at + 3..]) => {
+                format!("{}or{}", &word[..at], &word[at + 3..])
+            },
+            _ => word,
+        };
+        for (american, british) in [("ization", "isation"), ("izing", "ising"), ("ized", "ised"), ("izes", "ises"), ("ize", "ise")] {
+            if let Some(stem) = word.strip_suffix(american).filter(|stem| stem.len() >= 2) {
+                return format!("{}{}", stem, british);
+            }
+        }
+        word
+    }
+
+    pub fn words(&self, text: &str) -> Vec<String> {
+        let mut folded: String = String::with_capacity(text.len());
+        let characters: Vec<char> = text.chars().collect();
+
+        for (index, c) in characters.iter().enumerate() {
+            let is_joined = || index > 0 && characters[index - 1].is_alphanumeric() && characters.get(index + 1).is_some_and(|next| next.is_alphanumeric());
+            match c {
+                '&' => folded.push_str(" and "),
+                // To-day and today, labour's and labours
+                '-' | '\'' | '’' if is_joined() => (),
+                c if c.is_alphanumeric() => {
+                    for lower in c.to_lowercase() {
+                        match Self::fold(lower) {
+                            "" => folded.push(lower),
+                            replacement => folded.push_str(replacement),
+                        }
+                    }
+                },
+                _ => folded.push(' '),
+            }
+        }
+
+        folded.split_whitespace().map(|word| self.spell(word.to_string())).collect()
+    }
+}
+
+
+// How many of the quotation's words the text has in the same order, as a share of the quotation
+fn ordered_share(quotation: &[String], text: &[String]) -> f64 {
+    if quotation.is_empty() {
+        return 0.0;
+    }
+
+    let mut previous: Vec<usize> = vec![0; text.len() + 1];
+    let mut current: Vec<usize> = vec![0; text.len() + 1];
+    for word in quotation {
+        for (index, other) in text.iter().enumerate() {
+            current[index + 1] = if word == other {previous[index] + 1} else {previous[index + 1].max(current[index])};
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[text.len()] as f64 / quotation.len() as f64
+}
+
+
+fn contains_words(haystack: &[String], needle: &[String]) -> bool {
+    !needle.is_empty() && haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+
+// A sentence of the cleaned text, as offsets in characters, with its words folded and its terms
+struct Sentence {
+    start: usize,
+    end: usize,
+    words: Vec<String>,
+    terms: HashSet<String>,
+}
+
+
+// Finds the stretches of the selected books' cleaned text that best match a quotation, however it was
+// punctuated, spelled, or (within reason) translated
+pub struct QuoteFinder {
+    config: QuoteConfig,
+    segmentation: SegmentationConfig,
+    normalizer: Normalizer,
+}
+
+
+impl QuoteFinder {
+
+    pub fn new(config: &QuoteConfig, segmentation: &SegmentationConfig) -> Self {
+        Self { config: config.clone(), segmentation: segmentation.clone(), normalizer: Normalizer::new(config) }
+    }
+
+    fn terms(&self, analyzer: &Analyzer, words: &[String]) -> HashSet<String> {
+        analyzer.terms(&words.join(" ")).into_iter().collect()
+    }
+
+    // The best matches in one book, before any minimum score, that do not overlap one another
+    fn search_book(&self, directories: &Directories, book: &Book, quotation: &str, k: usize) -> Result<Vec<QuoteMatch>, anyhow::Error> {
+        let text: String = fs::read_to_string(cleaned_text_path(directories, book))?;
+        let document: ExtractedDocument = serde_json::from_str(&fs::read_to_string(extracted_metadata_path(directories, book))?)?;
+        let mut chunks: Vec<Chunk> = load_chunks(directories, book)?;
+        chunks.retain(|chunk| chunk.kind == ChunkKind::Text);
+
+        let analyzer: Analyzer = Analyzer::for_language(self.segmentation.language_of(&book.author));
+        let quoted: Vec<String> = self.normalizer.words(quotation);
+        let quoted_terms: HashSet<String> = self.terms(&analyzer, &quoted);
+        let window_words: usize = (quoted.len() as f64 * WINDOW_FACTOR).ceil() as usize;
+
+        let characters: Vec<char> = text.chars().collect();
+        let mut sentences: Vec<Sentence> = Vec::new();
+        let mut start: usize = 0;
+        for end in self.segmentation.segmenter_for(&book.author).sentence_ends(&characters, 0, characters.len()) {
+            let words: Vec<String> = self.normalizer.words(&characters[start..end].iter().collect::<String>());
+            if !words.is_empty() {
+                let terms: HashSet<String> = self.terms(&analyzer, &words);
+                sentences.push(Sentence { start, end, words, terms });
+            }
+            start = end;
+        }
+
+        // The best window of sentences beginning at each sentence
+        let mut candidates: Vec<(f64, bool, usize, usize)> = Vec::new();
+        for first in 0..sentences.len() {
+            let mut words: Vec<String> = Vec::new();
+            let mut terms: HashSet<&String> = HashSet::new();
+            let mut best: Option<(f64, bool, usize, usize)> = None;
+
+            for sentence in &sentences[first..] {
+                words.extend(sentence.words.iter().cloned());
+                terms.extend(sentence.terms.iter());
+
+                let recall: f64 = if quoted_terms.is_empty() {1.0} else {
+                    quoted_terms.iter().filter(|term| terms.contains(term)).count() as f64 / quoted_terms.len() as f64
+                };
+                if recall >= MINIMUM_TERM_RECALL {
+                    let score: f64 = 0.5 * recall + 0.5 * ordered_share(&quoted, &words);
+                    // A longer window has to do better, not just as well
+                    if best.is_none_or(|(best, ..)| score > best) {
+                        best = Some((score, contains_words(&words, &quoted), sentence_start(&sentences[first], &characters), sentence.end));
+                    }
+                }
+                if words.len() >= window_words {
+                    break
+                }
+            }
+            candidates.extend(best);
+        }
+
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.2.cmp(&b.2)));
+        let mut matches: Vec<QuoteMatch> = Vec::new();
+        for (score, is_exact, start, end) in candidates {
+            if matches.len() == k {
+                break
+            }
+            if matches.iter().any(|other| start < other.end && other.start < end) {
+                continue
+            }
+
+            let chunk: Option<&Chunk> = chunks.iter().find(|chunk| chunk.start <= start && start < chunk.end);
+            let page_of = |offset: usize| document.first_page + characters[..offset].iter().filter(|c| **c == PAGE_SEPARATOR).count() as i64;
+            matches.push(QuoteMatch {
+                author: chunk.map(|chunk| chunk.author.clone()).unwrap_or(document.author.clone()),
+                title: book.title.clone(),
+                book: book.slug.clone(),
+                chapter: chunk.and_then(|chunk| chunk.chapter.clone()),
+                first_page: page_of(start),
+                last_page: page_of(end.saturating_sub(1).max(start)),
+                voice: chunk.map(|chunk| chunk.voice).unwrap_or(Voice::Primary),
+                chunk_id: chunk.map(|chunk| chunk.id.clone()),
+                start,
+                end,
+                text: characters[start..end].iter().map(|c| if *c == PAGE_SEPARATOR {' '} else {*c}).collect::<String>().trim().to_string(),
+                score,
+                is_exact,
+            });
+        }
+
+        Ok(matches)
+    }
+
+    // The k best matches across the books, best first, leaving out any that score below the minimum.
+    // Books that have not been cleaned and chunked yet are skipped.
+    pub fn find(&self, directories: &Directories, books: &[Book], quotation: &str, k: usize) -> Result<Vec<QuoteMatch>, anyhow::Error> {
+        anyhow::ensure!(!self.normalizer.words(quotation).is_empty(), "There are no words to look for in the quotation");
+
+        let mut matches: Vec<QuoteMatch> = Vec::new();
+        for book in kdam::tqdm!(books.iter(), desc="Searching") {
+            match self.search_book(directories, book, quotation, k) {
+                Ok(found) => matches.extend(found),
+                Err(e) => log::warn!("Unable to search {} (has it been cleaned and chunked?): {}", book.title, e),
+            }
+        }
+
+        matches.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.book.cmp(&b.book)).then(a.start.cmp(&b.start)));
+        if let Some(closest) = matches.first().filter(|closest| closest.score < self.config.minimum_score) {
+            log::info!("The closest match scored {:.3}, below the minimum of {:.3}, in {}", closest.score, self.config.minimum_score, closest.citation());
+        }
+
+        matches.retain(|found| found.score >= self.config.minimum_score);
+        matches.truncate(k);
+        Ok(matches)
+    }
+}
+
+
+// Sentences begin after whatever whitespace the last one left behind
+fn sentence_start(sentence: &Sentence, characters: &[char]) -> usize {
+    characters[sentence.start..sentence.end].iter()
+        .position(|c| !c.is_whitespace())
+        .map(|offset| sentence.start + offset)
+        .unwrap_or(sentence.start)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indices::testing::{book, scratch_directories};
+    use crate::data_preparation::chunking::chunks_path;
+    use crate::data_preparation::extraction::DocumentFormat;
+
+    const WEALTH: &str = "Labour was the first price, the original purchase-money that was paid for all things. \
+        It was not by gold or by silver, but by labour, that all the wealth of the world was originally purchased;\x0cand its \
+        value, to those who possess it, is precisely equal to the quantity of labour which it can enable them to purchase. \
+        The connexion between the two is shewn in the next chapter.";
+
+    // A cleaned and chunked book with the given text, whose first page is page 5
+    fn cleaned_book(name: &str, text: &str) -> (Directories, Book) {
+        let (directories, _) = scratch_directories(name);
+        let book: Book = book("Adam Smith", "The Wealth of Nations");
+        let document = ExtractedDocument {
+            author: book.author.clone(),
+            title: book.title.clone(),
+            slug: book.slug.clone(),
+            source_url: String::from("https://example.org/wealth.txt"),
+            format: DocumentFormat::Text,
+            first_page: 5,
+            is_interview: false,
+        };
+
+        for path in [cleaned_text_path(&directories, &book), extracted_metadata_path(&directories, &book), chunks_path(&directories, &book)] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+        }
+        fs::write(cleaned_text_path(&directories, &book), text).unwrap();
+        fs::write(extracted_metadata_path(&directories, &book), serde_json::to_string(&document).unwrap()).unwrap();
+        fs::write(chunks_path(&directories, &book), "").unwrap();
+        (directories, book)
+    }
+
+    fn normalizer() -> Normalizer {
+        Normalizer::new(&QuoteConfig::default())
+    }
+
+    #[test]
+    fn folds_archaic_spellings() {
+        let normalizer: Normalizer = normalizer();
+        assert_eq!(normalizer.words("It is shewn by the Connexion"), normalizer.words("it is shown by the connection"));
+        assert_eq!(normalizer.words("He hath shewed"), ["he", "has", "showed"]);
+    }
+
+    #[test]
+    fn folds_british_and_american_endings() {
+        let normalizer: Normalizer = normalizer();
+        assert_eq!(normalizer.words("labour, labours, labourer, honoured"), normalizer.words("labor, labors, laborer, honored"));
+        assert_eq!(normalizer.words("organization, realized, realizes, realize"), normalizer.words("organisation, realised, realises, realise"));
+        // Words that merely contain the letters are left alone
+        assert_eq!(normalizer.words("our hour size"), ["our", "hour", "size"]);
+    }
+
+    #[test]
+    fn folds_ligatures_long_s_and_punctuation() {
+        let normalizer: Normalizer = normalizer();
+        assert_eq!(normalizer.words("Œconomy of the ﬁrst ſociety"), ["oeconomy", "of", "the", "first", "society"]);
+        assert_eq!(normalizer.words("To-day, labour’s & café"), ["today", "labors", "and", "cafe"]);
+    }
+
+    #[test]
+    fn shares_words_in_order() {
+        let words = |text: &str| normalizer().words(text);
+        assert_eq!(ordered_share(&words("labour is the price"), &words("labour is the price")), 1.0);
+        assert_eq!(ordered_share(&words("labour is the price"), &words("the price is labour")), 0.5);
+        assert_eq!(ordered_share(&[], &words("labour")), 0.0);
+    }
+
+    #[test]
+    fn finds_an_exact_quotation_across_a_page_break() {
+        let (directories, book) = cleaned_book("quotes-exact", WEALTH);
+        let finder = QuoteFinder::new(&QuoteConfig::default(), &SegmentationConfig::default());
+
+        let quotation: &str = "all the wealth of the world was originally purchased; and its value";
+        let found: Vec<QuoteMatch> = finder.find(&directories, &[book], quotation, 3).unwrap();
+
+        assert!(found[0].is_exact);
+        assert_eq!(found[0].score, 1.0);
+        assert_eq!((found[0].first_page, found[0].last_page), (5, 6));
+        assert!(found[0].text.contains("the wealth of the world was originally purchased; and its value"));
+        assert_eq!(found[0].chunk_id, None);
+        assert!(found.iter().skip(1).all(|other| !other.is_exact && other.score < 1.0));
+    }
+
+    #[test]
+    fn finds_a_quotation_in_modern_spelling() {
+        let (directories, book) = cleaned_book("quotes-modern", WEALTH);
+        let finder = QuoteFinder::new(&QuoteConfig::default(), &SegmentationConfig::default());
+
+        let found: Vec<QuoteMatch> = finder.find(&directories, &[book], "The connection between the two is shown", 1).unwrap();
+        assert!(found[0].is_exact);
+        assert_eq!((found[0].first_page, found[0].text.as_str()), (6, "The connexion between the two is shewn in the next chapter."));
+    }
+
+    #[test]
+    fn reports_nothing_below_the_minimum_score() {
+        let (directories, book) = cleaned_book("quotes-nothing", WEALTH);
+        let finder = QuoteFinder::new(&QuoteConfig::default(), &SegmentationConfig::default());
+        assert!(finder.find(&directories, std::slice::from_ref(&book), "Workers of the world, unite", 3).unwrap().is_empty());
+
+        let strict = QuoteFinder::new(&QuoteConfig { minimum_score: 0.99, ..QuoteConfig::default() }, &SegmentationConfig::default());
+        let loose: Vec<QuoteMatch> = finder.find(&directories, std::slice::from_ref(&book), "gold and silver were the first price of wealth", 3).unwrap();
+        assert!(!loose.is_empty() && loose.iter().all(|found| found.score < 0.99));
+        assert!(strict.find(&directories, &[book], "gold and silver were the first price of wealth", 3).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::data_preparation::cleaning::CleaningConfig;
+use crate::data_preparation::chunking::ChunkingConfig;
+use crate::data_preparation::embedding::EmbeddingConfig;
+use crate::indices::indexing::IndexConfig;
+use crate::indices::chroma::ChromaConfig;
+use crate::indices::retrieval::RetrievalConfig;
+use crate::generation::llm::LlmConfig;
+use crate::generation::answering::AnsweringConfig;
+use crate::generation::faithfulness::FaithfulnessConfig;
+use crate::research::quotes::QuoteConfig;
+use crate::data_preparation::segmentation::SegmentationConfig;
+
+pub static CONFIG_FILE_NAME: &str = "giants.toml";
+pub static CONFIG_PATH_VARIABLE: &str = "GIANTS_CONFIG";
+pub static ROOT_VARIABLE: &str = "GIANTS_ROOT";
+
+
+// Each of these may be absolute, or relative to the root. Anything left unset falls back
+// to the default layout beneath the root.
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DirectoryOverrides {
+    pub data: Option<PathBuf>,
+    pub images: Option<PathBuf>,
+    pub models: Option<PathBuf>,
+    pub ocr_outputs: Option<PathBuf>,
+    pub pdfs_after_ocr: Option<PathBuf>,
+    pub txt_after_ocr: Option<PathBuf>,
+    pub chroma: Option<PathBuf>,
+    pub images_in_downloads: Option<PathBuf>,
+    pub extracted: Option<PathBuf>,
+    pub cleaned: Option<PathBuf>,
+    pub chunks: Option<PathBuf>,
+}
+
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // Relative to the directory of the config file, which is also the root when none is given
+    pub root: Option<PathBuf>,
+    // Where the config file was read from, or None if there was none
+    #[serde(skip)]
+    pub config_directory: Option<PathBuf>,
+    #[serde(default)]
+    pub directories: DirectoryOverrides,
+    #[serde(default)]
+    pub cleaning: CleaningConfig,
+    #[serde(default)]
+    pub segmentation: SegmentationConfig,
+    #[serde(default)]
+    pub chunking: ChunkingConfig,
+    #[serde(default)]
+    pub embedding: EmbeddingConfig,
+    #[serde(default)]
+    pub index: IndexConfig,
+    #[serde(default)]
+    pub chroma: ChromaConfig,
+    #[serde(default)]
+    pub retrieval: RetrievalConfig,
+    #[serde(default)]
+    pub llm: LlmConfig,
+    #[serde(default)]
+    pub answering: AnsweringConfig,
+    #[serde(default)]
+    pub faithfulness: FaithfulnessConfig,
+    #[serde(default)]
+    pub quotes: QuoteConfig,
+}
+
+
+impl Config {
+
+    // The config file is read from $GIANTS_CONFIG if it is set, or from giants.toml in the current
+    // directory if that exists. Environment variables are then applied on top of the file.
+    pub fn load() -> Result<Self, anyhow::Error> {
+        let mut config: Config = match config_file_path() {
+            Some(path) => Config::from_file(&path)?,
+            None => Config::default(),
+        };
+
+        config.apply_environment()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, anyhow::Error> {
+        log::info!("Reading configuration from {}", path.display());
+        let contents: String = fs::read_to_string(path)?;
+        let mut config: Config = toml::from_str(&contents).map_err(
+            |e| anyhow::anyhow!("Invalid configuration in {}: {}", path.display(), e)
+        )?;
+
+        let directory: &Path = path.parent().unwrap_or(Path::new(""));
+        config.config_directory = Some(std::path::absolute(directory)?);
+        Ok(config)
+    }
+
+    // A relative root given in the environment is taken from the current directory, as a shell would
+    fn apply_environment(&mut self) -> Result<(), anyhow::Error> {
+        let overrides = &mut self.directories;
+
+        let variables: [(&str, &mut Option<PathBuf>); 12] = [
+            (ROOT_VARIABLE, &mut self.root),
+            ("GIANTS_DATA_DIR", &mut overrides.data),
+            ("GIANTS_IMAGES_DIR", &mut overrides.images),
+            ("GIANTS_MODELS_DIR", &mut overrides.models),
+            ("GIANTS_OCR_DIR", &mut overrides.ocr_outputs),
+            ("GIANTS_PDFS_AFTER_OCR_DIR", &mut overrides.pdfs_after_ocr),
+            ("GIANTS_TXT_AFTER_OCR_DIR", &mut overrides.txt_after_ocr),
+            ("GIANTS_CHROMA_DIR", &mut overrides.chroma),
+            ("GIANTS_IMAGES_IN_DOWNLOADS_DIR", &mut overrides.images_in_downloads),
+            ("GIANTS_EXTRACTED_DIR", &mut overrides.extracted),
+            ("GIANTS_CLEANED_DIR", &mut overrides.cleaned),
+            ("GIANTS_CHUNKS_DIR", &mut overrides.chunks),
+        ];
+
+        for (variable, setting) in variables {
+            if let Some(value) = std::env::var_os(variable).filter(|value| !value.is_empty()) {
+                let path: PathBuf = PathBuf::from(value);
+                *setting = match variable == ROOT_VARIABLE {
+                    true => Some(std::path::absolute(path)?),
+                    false => Some(path),
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+
+fn config_file_path() -> Option<PathBuf> {
+    match std::env::var_os(CONFIG_PATH_VARIABLE) {
+        Some(path) => Some(PathBuf::from(path)),
+        None => {
+            let default_path: PathBuf = PathBuf::from(CONFIG_FILE_NAME);
+            if default_path.is_file() {Some(default_path)} else {None}
+        }
+    }
+}
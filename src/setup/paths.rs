@@ -1,9 +1,11 @@
 use log;
-use struct_iterable::Iterable; 
-use std::{fs, io::ErrorKind, path::PathBuf};
+use struct_iterable::Iterable;
+use std::{fs, path::{Path, PathBuf}};
 
+use crate::setup::config::Config;
 
-#[derive(Iterable)]
+
+#[derive(Clone, Iterable)]
 pub struct Directories {
     pub data: PathBuf,
     pub images: PathBuf,
@@ -12,66 +14,176 @@ pub struct Directories {
     pub pdfs_after_ocr: PathBuf,
     pub txt_after_ocr: PathBuf,
     pub chroma: PathBuf,
-    pub images_in_downloads: PathBuf
+    pub images_in_downloads: PathBuf,
+    pub extracted: PathBuf,
+    pub cleaned: PathBuf,
+    pub chunks: PathBuf,
 }
 
 
 impl Directories {
 
-    pub fn get() -> Directories {
-        let directories = Directories::setup();
-        directories
-    }
+    // Resolves the layout once, at startup. The result is then passed around explicitly. Without a
+    // config file, paths are relative to the current directory instead.
+    pub fn setup(config: &Config) -> Result<Self, anyhow::Error> {
+        let base: PathBuf = match &config.config_directory {
+            Some(directory) => directory.clone(),
+            None => std::env::current_dir()?,
+        };
+        let parent: PathBuf = match &config.root {
+            Some(root) => absolute(root, &base),
+            None => base,
+        };
+
+        let overrides = &config.directories;
+        let resolve = |setting: &Option<PathBuf>, default: PathBuf| -> PathBuf {
+            setting.as_ref().map(|path| absolute(path, &parent)).unwrap_or(default)
+        };
 
-    pub fn setup() -> Self {
-        let parent = std::env::current_dir().expect("Failed to provide current directory");
-        let data: PathBuf = parent.join("data");
-        let images: PathBuf = parent.join("images");
-        let models: PathBuf = parent.join("models");
-        let chroma: PathBuf = parent.join("chroma").to_path_buf();
-        let ocr_outputs: PathBuf = parent.join("OCR").to_path_buf();
-        let pdfs_after_ocr: PathBuf = ocr_outputs.join("pdf").to_path_buf();
-        let txt_after_ocr: PathBuf = ocr_outputs.join("pdf").to_path_buf();
-        let images_in_downloads: PathBuf = images.join("images_in_downloads").to_path_buf();
-       
-        Self {
-            models, 
-            data, 
-            images, 
+        let data: PathBuf = resolve(&overrides.data, parent.join("data"));
+        let images: PathBuf = resolve(&overrides.images, parent.join("images"));
+        let models: PathBuf = resolve(&overrides.models, parent.join("models"));
+        let chroma: PathBuf = resolve(&overrides.chroma, parent.join("chroma"));
+        let ocr_outputs: PathBuf = resolve(&overrides.ocr_outputs, parent.join("OCR"));
+        let pdfs_after_ocr: PathBuf = resolve(&overrides.pdfs_after_ocr, ocr_outputs.join("pdf"));
+        let txt_after_ocr: PathBuf = resolve(&overrides.txt_after_ocr, ocr_outputs.join("txt"));
+        let images_in_downloads: PathBuf = resolve(&overrides.images_in_downloads, images.join("images_in_downloads"));
+        let extracted: PathBuf = resolve(&overrides.extracted, parent.join("extracted"));
+        let cleaned: PathBuf = resolve(&overrides.cleaned, parent.join("cleaned"));
+        let chunks: PathBuf = resolve(&overrides.chunks, parent.join("chunks"));
+
+        let directories = Self {
+            models,
+            data,
+            images,
             chroma,
             ocr_outputs,
             images_in_downloads,
             txt_after_ocr,
-            pdfs_after_ocr
+            pdfs_after_ocr,
+            extracted,
+            cleaned,
+            chunks,
+        };
+
+        directories.validate()?;
+        directories.log_layout();
+        Ok(directories)
+    }
+
+    pub fn named_paths(&self) -> Vec<(&'static str, PathBuf)> {
+        // Make sure we downcast the second element of the tuple
+        self.iter()
+            .filter_map(
+                |(name, value)| value.downcast_ref::<PathBuf>().map(|dir| (name, dir.to_path_buf()))
+            )
+            .collect()
+    }
+
+    // No two directories may coincide (pdfs_after_ocr and txt_after_ocr once did), and none of them
+    // may already exist as something other than a directory.
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        let named_paths = self.named_paths();
+
+        for (index, (name, path)) in named_paths.iter().enumerate() {
+            if path.exists() && !path.is_dir() {
+                anyhow::bail!("The {} directory {} exists, but is not a directory", name, path.display());
+            }
+
+            for (other_name, other_path) in &named_paths[index + 1..] {
+                if path == other_path {
+                    anyhow::bail!("The {} and {} directories both resolve to {}", name, other_name, path.display());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn log_layout(&self) {
+        for (name, path) in self.named_paths() {
+            log::info!("Using {} directory: {}", name, path.display());
         }
     }
 }
 
 
+fn absolute(path: &Path, base: &Path) -> PathBuf {
+    if path.is_absolute() {path.to_path_buf()} else {base.join(path)}
+}
 
-pub fn make_fundamental_directories() {
-   
-    let mut directories_to_make: Vec<PathBuf> = Vec::new();
 
-    // Make sure we downcast the second element of the tuple 
-    for field_tuple in Directories::get().iter() {
-        if let Some(dir) = field_tuple.1.downcast_ref::<PathBuf>() { 
-            directories_to_make.push(dir.to_path_buf());
-        }
+pub fn make_fundamental_directories(directories: &Directories) {
 
-    } 
+    for (_, dir) in directories.named_paths() {
+        if dir.exists() {
+            continue
+        }
 
-    for dir in directories_to_make {
-        match fs::create_dir(&dir) {
+        match fs::create_dir_all(&dir) {
             Ok(_) => log::info!("Created {} directory: ", dir.to_str().unwrap()),
-            Err(e) => {
-                if e.kind() == ErrorKind::AlreadyExists {
-                    continue
-                } else {
-                    log::error!("Could not create directory {}", dir.display());
-                }
-            }
+            Err(e) => log::error!("Could not create directory {}: {}", dir.display(), e)
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::setup::config::{Config, DirectoryOverrides};
+    use super::Directories;
+
+    fn config_in(directory: &str, root: Option<&str>) -> Config {
+        Config {
+            root: root.map(PathBuf::from),
+            config_directory: Some(PathBuf::from(directory)),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn a_relative_root_is_resolved_against_the_config_file() {
+        let directories: Directories = Directories::setup(&config_in("/srv/giants", Some("corpus"))).unwrap();
+        assert_eq!(directories.data, PathBuf::from("/srv/giants/corpus/data"));
+
+        let directories: Directories = Directories::setup(&config_in("/srv/giants", Some("/mnt/corpus"))).unwrap();
+        assert_eq!(directories.data, PathBuf::from("/mnt/corpus/data"));
+    }
+
+    #[test]
+    fn the_config_file_records_its_directory() {
+        let directory: PathBuf = std::env::temp_dir().join(format!("giants-paths-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path: PathBuf = directory.join("giants.toml");
+        std::fs::write(&path, "root = \"corpus\"\n").unwrap();
+
+        let directories: Directories = Directories::setup(&Config::from_file(&path).unwrap()).unwrap();
+        assert_eq!(directories.data, directory.join("corpus").join("data"));
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn the_root_defaults_to_the_directory_of_the_config_file() {
+        let directories: Directories = Directories::setup(&config_in("/srv/giants", None)).unwrap();
+        assert_eq!(directories.models, PathBuf::from("/srv/giants/models"));
+    }
+
+    #[test]
+    fn ocr_text_and_pdfs_are_kept_apart() {
+        let directories: Directories = Directories::setup(&config_in("/srv/giants", None)).unwrap();
+        assert_ne!(directories.txt_after_ocr, directories.pdfs_after_ocr);
+        assert_eq!(directories.txt_after_ocr, PathBuf::from("/srv/giants/OCR/txt"));
+        assert_eq!(directories.pdfs_after_ocr, PathBuf::from("/srv/giants/OCR/pdf"));
+    }
+
+    #[test]
+    fn coinciding_directories_are_rejected() {
+        let config = Config {
+            directories: DirectoryOverrides { cleaned: Some(PathBuf::from("data")), ..DirectoryOverrides::default() },
+            ..config_in("/srv/giants", None)
+        };
+        assert!(Directories::setup(&config).is_err());
+    }
+}
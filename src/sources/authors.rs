@@ -2,14 +2,20 @@ use std::fs;
 use std::path::PathBuf;
 
 use crate::sources::http::ViaHTTP;
+use crate::generation::persona::Persona;
 use crate::setup::paths::Directories;
 use crate::sources::scraping::ViaScraper;
 use crate::sources::torrents::ViaTorrent;
+use crate::data_preparation::books::{list_books, Selection};
+use crate::sources::manifest::Manifest;
+use crate::sources::source::{download_with_retries, Source};
+use crate::data_preparation::state::{hash_config, Stage, StateStore};
+use crate::data_preparation::planning::{add_later_stages, AuthorPlan, PlannedArtifact};
 
 
-pub fn get_author_root(author_name: &str) -> PathBuf {
+pub fn get_author_root(directories: &Directories, author_name: &str) -> PathBuf {
 
-    let author_data_root = Directories::get().data.join(author_name);
+    let author_data_root = directories.data.join(author_name);
     if !author_data_root.exists() {
         _ = fs::create_dir(&author_data_root);
     }
@@ -19,260 +25,337 @@ pub fn get_author_root(author_name: &str) -> PathBuf {
 
 
 
-#[derive(Default)]
-#[allow(dead_code)]
-pub struct Author <'a> {
-    pub name: String, 
-    pub books_via_http: Option<Vec<ViaHTTP>>, 
-    pub books_via_scraper: Option<Vec<ViaScraper<'a>>>,
-    pub books_via_torrent: Option<Vec<ViaTorrent>>,
-    pub biographers_and_compilers: Option<Vec<&'a str>>,
+// Forcing re-acquires every selected source, so it is scoped by --author, --book, and --source
+#[derive(Clone, Default)]
+pub struct DownloadOptions {
+    pub selection: Selection,
+    pub force: bool,
+    pub dry_run: bool,
 }
 
 
-impl Author <'_>{
+// The sources are acquired in the order in which they are listed
+#[derive(Default)]
+pub struct Author {
+    pub name: String, 
+    pub sources: Vec<Box<dyn Source>>,
+    pub biographers_and_compilers: Option<Vec<String>>,
+    pub persona: Persona,
+}
 
-    async fn download_via_http(&self) {
 
-        let http_books: Vec<ViaHTTP> = self.books_via_http.clone().unwrap();
+impl Author {
 
-        for book in http_books {
-            let file_name: String = book.get_file_name();
-            let author_root: PathBuf = get_author_root(&self.name); 
-            let file_path = author_root.join(file_name);
-            book.clone().download(&file_path).await;
-        }    
+    // A collection such as a torrent is selected by its own title or by those of the books that it
+    // produced, which are only known once it has been acquired
+    fn source_titles(&self, source: &dyn Source, manifest: &Manifest) -> Vec<String> {
+        let mut titles: Vec<String> = vec![source.title()];
+        if source.is_collection() {
+            titles.extend(source.books(&self.name, manifest).into_iter().map(|book| book.title));
+        }
+        titles
     }
 
-    async fn download_via_scraper(&self) {
-        let books_to_scrape: &Vec<ViaScraper> = &self.books_via_scraper.clone().unwrap();
-
-        for book in books_to_scrape {
-            book.clone().download(&self.name).await;
-        }    
+    pub fn selected_sources(&self, selection: &Selection, manifest: &Manifest) -> Vec<&dyn Source> {
+        self.sources.iter()
+            .map(|source| source.as_ref())
+            .filter(|source| {
+                self.source_titles(*source, manifest).iter().any(|title| selection.includes(title, source.kind()))
+            })
+            .collect()
     }
 
-    async fn download_via_torrent(&self) {
-        let books_to_torrent: &Vec<ViaTorrent> = &self.books_via_torrent.clone().unwrap();
+    // Follows the same order as download_books, but only reports on what it would do
+    pub async fn plan_books(&self, directories: &Directories, options: &DownloadOptions) -> AuthorPlan {
+        // The author's root is not created here, since a dry run should leave no trace
+        let manifest: Manifest = Manifest::load(&directories.data.join(&self.name));
+        let mut artifacts: Vec<PlannedArtifact> = Vec::new();
 
-        for book in books_to_torrent {
-            let download_path: PathBuf = get_author_root(&self.name);
+        for source in self.selected_sources(&options.selection, &manifest) {
+            artifacts.push(source.plan(&manifest, options.force).await);
+        }
 
-            if book.must_torrent(&download_path, &self.name) {
-                log::info!("Torrenting neccessary for {}", &self.name);
-                book.download(download_path.clone()).await;
-                book.extract_files(download_path, &self.name);
-            } 
-        }    
+        add_later_stages(directories, &list_books(self, &manifest), &mut artifacts);
+        AuthorPlan { author: self.name.clone(), artifacts }
     }
 
-    pub async fn download_books(&self) {
-        let http_books: Option<Vec<ViaHTTP>> = self.books_via_http.clone();
-        let books_to_scrape: Option<Vec<ViaScraper>> = self.books_via_scraper.clone();
-        let books_to_torrent: Option<Vec<ViaTorrent>> = self.books_via_torrent.clone();
+    // Acquisition's output is the hash of each acquired file, which is what the later stages start from
+    fn record_acquisition(&self, state: &StateStore, source: &dyn Source, manifest: &Manifest) {
 
-        log::warn!("Downloading {}'s texts", &self.name);
-        match (http_books, books_to_scrape, books_to_torrent) {
-
-            (None, None, None) => {
-                log::error!("{} has no books that can be acquired by HTTP, scraping, or torrenting", &self.name)
-            },
-
-            (Some(_http_books), None, None) => {
-                self.download_via_http().await;
-            },
+        for book in source.books(&self.name, manifest) {
+            let Some(entry) = manifest.entries.get(&book.file_name) else { continue };
 
-            (None, Some(_books_to_scrape), None) => {
-                self.download_via_scraper().await;
-            },
+            if state.output_hash(&book, Stage::Acquisition).as_ref() == Some(&entry.sha256) {
+                continue
+            }
 
-            (None, None, Some(_books_to_torrent)) => {
-                self.download_via_torrent().await;
-            },
+            if let Err(e) = state.record(&book, Stage::Acquisition, &hash_config(&book.source), &hash_config(&()), &entry.sha256) {
+                log::error!("Could not record the acquisition of {}: {}", book.title, e);
+            }
+        }
+    }
 
-            (Some(_http_books), Some(_books_to_scrape), None) => {
-                self.download_via_http().await;
-                self.download_via_scraper().await;
-            },
+    pub async fn download_books(&self, directories: &Directories, options: &DownloadOptions) {
+        if self.sources.is_empty() {
+            log::error!("{} has no books that can be acquired", &self.name);
+            return
+        }
 
-            (None, Some(_books_to_scrape), Some(_books_to_torrent)) => {
-                self.download_via_scraper().await;
-                self.download_via_torrent().await;
-            },
+        let mut manifest: Manifest = Manifest::load(&get_author_root(directories, &self.name));
+        let (mut acquired, mut present, mut failed): (usize, usize, usize) = (0, 0, 0);
 
-            (Some(_http_books), None, Some(_books_to_torrent)) => {
-                self.download_via_http().await;
-                self.download_via_scraper().await;
-            },
+        let state: StateStore = match StateStore::open(directories) {
+            Ok(state) => state,
+            Err(e) => {
+                log::error!("Could not open the pipeline's state: {}", e);
+                return
+            }
+        };
 
-            (Some(_http_books), Some(_books_to_scrape), Some(_books_to_torrent)) => {
-                self.download_via_http().await;
-                self.download_via_scraper().await;
-                self.download_via_torrent().await;
+        log::warn!("Downloading {}'s texts", &self.name);
+        for source in self.selected_sources(&options.selection, &manifest) {
+            if !options.force && source.verify(&manifest) {
+                self.record_acquisition(&state, source, &manifest);
+                present += 1;
+                continue
+            }
 
+            match download_with_retries(source, &mut manifest).await {
+                Ok(_) => {
+                    self.record_acquisition(&state, source, &manifest);
+                    acquired += 1
+                },
+                Err(e) => {
+                    log::error!("Unable to acquire {}: {}", source.title(), e);
+                    failed += 1;
+                }
             }
-        } 
+        }
+
+        log::info!("{}: {} acquired, {} already present, {} failed", &self.name, acquired, present, failed);
     }
 }
 
 
-pub fn prepare_sources() -> Vec<Author<'static>> {
+pub fn prepare_sources() -> Vec<Author> {
 
     let authors = vec![
 
         Author{
             name: String::from("Karl Marx"),
-            books_via_http: Some(
-                vec![
-                    ViaHTTP{
-                        title: String::from("Capital Vol I"),
-                        url: String::from("https://www.marxists.org/archive/marx/works/download/pdf/Capital-Volume-I.pdf"),
-                        ..ViaHTTP::default()
-                    },
-
-                    ViaHTTP{
-                        title: String::from("Capital Vol II"),
-                        url: String::from("https://www.marxists.org/archive/marx/works/download/pdf/Capital-Volume-II.pdf"),
-                        ..ViaHTTP::default()
-                    },
-
-                    ViaHTTP{
-                        title: String::from("Capital Vol III"),
-                        url: String::from("https://www.marxists.org/archive/marx/works/download/pdf/Capital-Volume-III.pdf"),
-                        ..ViaHTTP::default()
-                    },
-
-                    ViaHTTP{
-                        title: String::from("Value, Price & Profit"),
-                        url: String::from("https://www.marxists.org/archive/marx/works/download/pdf/value-price-profit.pdf"),
-                        ..ViaHTTP::default()
-                    },
-
-                    ViaHTTP{
-                        title: String::from("Wage, Labour & Capital"),
-                        url: String::from("https://www.marxists.org/archive/marx/works/download/pdf/wage-labour-capital.pdf"),
-                        ..ViaHTTP::default()
-                    },
-
-                    ViaHTTP{
-                        title: String::from("The Communist Manifesto"),
-                        url: String::from("https://www.marxists.org/archive/marx/works/download/pdf/Manifesto.pdf"),
-                        start_page: Some(13),
-                        end_page: Some(66),
-                        ..ViaHTTP::default()
-                    },
-                ]
-            ),
+            persona: Persona {
+                era: String::from("1818–1883; Germany, Paris, Brussels, and London"),
+                key_works: vec![
+                    String::from("Capital (three volumes)"),
+                    String::from("The Communist Manifesto, with Friedrich Engels"),
+                    String::from("Wage Labour and Capital"),
+                    String::from("Value, Price and Profit"),
+                ],
+                style_notes: vec![
+                    String::from("dense, systematic argument that builds up from definitions"),
+                    String::from("sardonic irony and vivid metaphor aimed at the political economists and the bourgeoisie"),
+                    String::from("heavy use of historical and statistical evidence"),
+                    String::from("footnotes that quarrel with other writers"),
+                ],
+                refused_topics: vec![
+                    String::from("the policies of later states that claimed to follow him, as though he had endorsed them"),
+                ],
+                ..Persona::default()
+            },
+            sources: vec![
+                Box::new(ViaHTTP{
+                    title: String::from("Capital Vol I"),
+                    url: String::from("https://www.marxists.org/archive/marx/works/download/pdf/Capital-Volume-I.pdf"),
+                    ..ViaHTTP::default()
+                }),
+
+                Box::new(ViaHTTP{
+                    title: String::from("Capital Vol II"),
+                    url: String::from("https://www.marxists.org/archive/marx/works/download/pdf/Capital-Volume-II.pdf"),
+                    ..ViaHTTP::default()
+                }),
+
+                Box::new(ViaHTTP{
+                    title: String::from("Capital Vol III"),
+                    url: String::from("https://www.marxists.org/archive/marx/works/download/pdf/Capital-Volume-III.pdf"),
+                    ..ViaHTTP::default()
+                }),
+
+                Box::new(ViaHTTP{
+                    title: String::from("Value, Price & Profit"),
+                    url: String::from("https://www.marxists.org/archive/marx/works/download/pdf/value-price-profit.pdf"),
+                    ..ViaHTTP::default()
+                }),
+
+                Box::new(ViaHTTP{
+                    title: String::from("Wage, Labour & Capital"),
+                    url: String::from("https://www.marxists.org/archive/marx/works/download/pdf/wage-labour-capital.pdf"),
+                    ..ViaHTTP::default()
+                }),
+
+                Box::new(ViaHTTP{
+                    title: String::from("The Communist Manifesto"),
+                    url: String::from("https://www.marxists.org/archive/marx/works/download/pdf/Manifesto.pdf"),
+                    start_page: Some(13),
+                    end_page: Some(66),
+                    ..ViaHTTP::default()
+                }),
+            ],
             ..Author::default()
         },
 
         Author{
             name: String::from("Mao Zedong"),
-            books_via_scraper: Some(
-                vec![
-                    ViaScraper{
-                        title: String::from("Combat Liberalism"),
-                        url: String::from("https://www.marxists.org/reference/archive/mao/selected-works/volume-2/mswv2_03.htm"),
-                        initial_marker: Some("We stand for"),
-                        terminal_marker: Some("Transcription"),
-                        ..ViaScraper::default()
-                    }
+            persona: Persona {
+                era: String::from("1893–1976; China"),
+                key_works: vec![
+                    String::from("Selected Works, Volumes I to V"),
+                    String::from("Oppose Book Worship"),
+                    String::from("Combat Liberalism"),
+                    String::from("On Practice"),
+                    String::from("On Contradiction"),
                 ],
-            ),
-            books_via_http: Some(
-                vec![
-                    ViaHTTP{
-                        title: String::from("Oppose Book Worship"),
-                        url: String::from("https://www.marxists.org/ebooks/mao/Oppose_Book_Worship_-_Mao_Zedong.pdf"),
-                        start_page: Some(2),
-                        end_page: Some(12),
-                        format: String::from(".pdf"),
-                        ..ViaHTTP::default()
-                    },
-
-                    ViaHTTP{
-                        title: String::from("Selected Works of Mao Tse-Tung Volume I"),
-                        url: String::from("https://www.marxists.org/reference/archive/mao/selected-works/sw-in-pdf/sw-flp-1965-v1.pdf"),
-                        format: String::from(".pdf"),
-                        start_page: Some(20),
-                        end_page: Some(353),
-                        ..ViaHTTP::default()
-                    },
-
-                    ViaHTTP{
-                        title: String::from("Selected Works of Mao Tse-Tung Volume II"),
-                        url: String::from("https://www.marxists.org/reference/archive/mao/selected-works/sw-in-pdf/sw-flp-1965-v2.pdf"),
-                        start_page: Some(18),
-                        end_page: Some(473),
-                        format: String::from(".pdf"),
-                        ..ViaHTTP::default()
-                    },
-
-                    ViaHTTP{
-                        title: String::from("Selected Works of Mao Tse-Tung Volume III"),
-                        url: String::from("https://www.marxists.org/reference/archive/mao/selected-works/sw-in-pdf/sw-flp-1965-v3.pdf"),
-                        start_page: Some(16),
-                        end_page: Some(345),
-                        format: String::from(".pdf"),
-                        ..ViaHTTP::default()
-                    },
-
-                    ViaHTTP{
-                        title: String::from("Selected Works of Mao Tse-Tung Volume IV"),
-                        url: String::from("https://www.marxists.org/reference/archive/mao/selected-works/sw-in-pdf/sw-flp-1965-v4.pdf"),
-                        start_page: Some(17),
-                        end_page: Some(463),
-                        format: String::from(".pdf"),
-                        ..ViaHTTP::default()
-                    },
-
-                    ViaHTTP{
-                        title: String::from("Selected Works of Mao Tse-Tung Volume V"),
-                        url: String::from("https://www.marxists.org/reference/archive/mao/selected-works/sw-in-pdf/sw-flp-1971-v5.pdf"),
-                        start_page: Some(22),
-                        end_page: Some(524),
-                        format: String::from(".pdf"),
-                        ..ViaHTTP::default()
-                    }
-                ]
-            ),
+                style_notes: vec![
+                    String::from("plain, direct prose written for party cadres"),
+                    String::from("numbered lists of points, tasks, and mistakes"),
+                    String::from("proverbs and homely analogies from Chinese life"),
+                    String::from("insistence on investigation and practice over dogma"),
+                ],
+                refused_topics: vec![
+                    String::from("justifying or denying the deaths under his government"),
+                ],
+                ..Persona::default()
+            },
+            sources: vec![
+                Box::new(ViaHTTP{
+                    title: String::from("Oppose Book Worship"),
+                    url: String::from("https://www.marxists.org/ebooks/mao/Oppose_Book_Worship_-_Mao_Zedong.pdf"),
+                    start_page: Some(2),
+                    end_page: Some(12),
+                    format: String::from(".pdf"),
+                    ..ViaHTTP::default()
+                }),
+
+                Box::new(ViaHTTP{
+                    title: String::from("Selected Works of Mao Tse-Tung Volume I"),
+                    url: String::from("https://www.marxists.org/reference/archive/mao/selected-works/sw-in-pdf/sw-flp-1965-v1.pdf"),
+                    format: String::from(".pdf"),
+                    start_page: Some(20),
+                    end_page: Some(353),
+                    ..ViaHTTP::default()
+                }),
+
+                Box::new(ViaHTTP{
+                    title: String::from("Selected Works of Mao Tse-Tung Volume II"),
+                    url: String::from("https://www.marxists.org/reference/archive/mao/selected-works/sw-in-pdf/sw-flp-1965-v2.pdf"),
+                    start_page: Some(18),
+                    end_page: Some(473),
+                    format: String::from(".pdf"),
+                    ..ViaHTTP::default()
+                }),
+
+                Box::new(ViaHTTP{
+                    title: String::from("Selected Works of Mao Tse-Tung Volume III"),
+                    url: String::from("https://www.marxists.org/reference/archive/mao/selected-works/sw-in-pdf/sw-flp-1965-v3.pdf"),
+                    start_page: Some(16),
+                    end_page: Some(345),
+                    format: String::from(".pdf"),
+                    ..ViaHTTP::default()
+                }),
+
+                Box::new(ViaHTTP{
+                    title: String::from("Selected Works of Mao Tse-Tung Volume IV"),
+                    url: String::from("https://www.marxists.org/reference/archive/mao/selected-works/sw-in-pdf/sw-flp-1965-v4.pdf"),
+                    start_page: Some(17),
+                    end_page: Some(463),
+                    format: String::from(".pdf"),
+                    ..ViaHTTP::default()
+                }),
+
+                Box::new(ViaHTTP{
+                    title: String::from("Selected Works of Mao Tse-Tung Volume V"),
+                    url: String::from("https://www.marxists.org/reference/archive/mao/selected-works/sw-in-pdf/sw-flp-1971-v5.pdf"),
+                    start_page: Some(22),
+                    end_page: Some(524),
+                    format: String::from(".pdf"),
+                    ..ViaHTTP::default()
+                }),
+
+                Box::new(ViaScraper{
+                    title: String::from("Combat Liberalism"),
+                    url: String::from("https://www.marxists.org/reference/archive/mao/selected-works/volume-2/mswv2_03.htm"),
+                    initial_marker: Some(String::from("We stand for")),
+                    terminal_marker: Some(String::from("Transcription")),
+                    ..ViaScraper::default()
+                }),
+            ],
             ..Author::default()
         },
 
         Author{
             name: String::from("Marcus Garvey"),
-            books_via_http: Some(
-                vec![
-                    ViaHTTP{
-                        title: String::from("The Philosophy & Opinions of Marcus Garvey"),
-                        url: String::from("https://www.jpanafrican.org/ebooks/eBook%20Phil%20and%20Opinions.pdf"),
-                        start_page: Some(3),
-                        end_page: Some(62),
-                        format: String::from(".pdf"),
-                        ..ViaHTTP::default()
-
-                    },
-                ]
-            ),
+            persona: Persona {
+                era: String::from("1887–1940; Jamaica, the United States, and England"),
+                key_works: vec![
+                    String::from("The Philosophy and Opinions of Marcus Garvey"),
+                ],
+                style_notes: vec![
+                    String::from("rousing oratory meant to be heard as much as read"),
+                    String::from("appeals to racial pride, self-reliance, and the redemption of Africa"),
+                    String::from("short declarative sentences and aphorisms"),
+                ],
+                refused_topics: vec![
+                    String::from("statements that demean any race or people"),
+                ],
+                ..Persona::default()
+            },
+            sources: vec![
+                Box::new(ViaHTTP{
+                    title: String::from("The Philosophy & Opinions of Marcus Garvey"),
+                    url: String::from("https://www.jpanafrican.org/ebooks/eBook%20Phil%20and%20Opinions.pdf"),
+                    start_page: Some(3),
+                    end_page: Some(62),
+                    format: String::from(".pdf"),
+                    ..ViaHTTP::default()
+
+                }),
+            ],
             ..Author::default()
         },
 
         Author{
             name: String::from("Swami Vivekananda"),
+            persona: Persona {
+                era: String::from("1863–1902; India, the United States, and England"),
+                key_works: vec![
+                    String::from("The Complete Works of Swami Vivekananda"),
+                    String::from("Raja Yoga"),
+                    String::from("Karma Yoga"),
+                    String::from("Jnana Yoga"),
+                    String::from("Lectures from Colombo to Almora"),
+                ],
+                style_notes: vec![
+                    String::from("lectures addressed directly to the audience"),
+                    String::from("vivid, exhortatory, and often impassioned"),
+                    String::from("stories and parables from the Hindu scriptures"),
+                    String::from("Vedanta presented as universal and in harmony with science"),
+                ],
+                refused_topics: vec![
+                    String::from("giving spiritual instruction or initiation meant to be followed in his name"),
+                ],
+                ..Persona::default()
+            },
             biographers_and_compilers: None, 
-            books_via_http: Some(
-                vec![
-                    ViaHTTP{
-                        title: String::from("The Complete Works of Swami Vivekananda"),
-                        url: String::from("https://ia801608.us.archive.org/9/items/complete-works-of-swami-vivekananda-all-volumes-swami-vivekananda/Complete%20Works%20of%20Swami%20Vivekananda%20-%20%20All%20Volumes%20-%20Swami%20Vivekananda.pdf"),
-                        start_page: Some(81),
-                        end_page: Some(5162),
-                        format: String::from("pdf"),
-                        ..ViaHTTP::default()
-                    }
-                ]
-            ),
-            ..Author::default()
+            sources: vec![
+                Box::new(ViaHTTP{
+                    title: String::from("The Complete Works of Swami Vivekananda"),
+                    url: String::from("https://ia801608.us.archive.org/9/items/complete-works-of-swami-vivekananda-all-volumes-swami-vivekananda/Complete%20Works%20of%20Swami%20Vivekananda%20-%20%20All%20Volumes%20-%20Swami%20Vivekananda.pdf"),
+                    start_page: Some(81),
+                    end_page: Some(5162),
+                    format: String::from("pdf"),
+                    ..ViaHTTP::default()
+                }),
+            ],
         },
 
         // Author{
@@ -280,207 +363,334 @@ pub fn prepare_sources() -> Vec<Author<'static>> {
         //     biographers_and_compilers: Some(
         //         vec!["Marion Meade".to_string(), "Gary Lachman".to_string()]
         //     ),
-        //     books_via_torrent: Some(
-        //         vec![
-        //             ViaTorrent{
-        //                 magnet: String::from("magnet:?xt=urn:btih:7933F8B90EAC4CBCCEED1667B5E5FF0C7E5F9B29&dn=H.%20P.%20Blavatsky%20-%20Collected%20Writings%20and%20More%20%5Bepub%20mobi%20pdf%5D&tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337&tr=udp%3A%2F%2Fopen.stealth.si%3A80%2Fannounce&tr=udp%3A%2F%2Ftracker.torrent.eu.org%3A451%2Fannounce&tr=udp%3A%2F%2Ftracker.bittor.pw%3A1337%2Fannounce&tr=udp%3A%2F%2Fpublic.popcorn-tracker.org%3A6969%2Fannounce&tr=udp%3A%2F%2Ftracker.dler.org%3A6969%2Fannounce&tr=udp%3A%2F%2Fexodus.desync.com%3A6969&tr=udp%3A%2F%2Fopen.demonii.com%3A1337%2Fannounce")
-        //             }
+        //     sources: vec![
+        //         Box::new(ViaTorrent{
+        //             magnet: String::from("magnet:?xt=urn:btih:7933F8B90EAC4CBCCEED1667B5E5FF0C7E5F9B29&dn=H.%20P.%20Blavatsky%20-%20Collected%20Writings%20and%20More%20%5Bepub%20mobi%20pdf%5D&tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337&tr=udp%3A%2F%2Fopen.stealth.si%3A80%2Fannounce&tr=udp%3A%2F%2Ftracker.torrent.eu.org%3A451%2Fannounce&tr=udp%3A%2F%2Ftracker.bittor.pw%3A1337%2Fannounce&tr=udp%3A%2F%2Fpublic.popcorn-tracker.org%3A6969%2Fannounce&tr=udp%3A%2F%2Ftracker.dler.org%3A6969%2Fannounce&tr=udp%3A%2F%2Fexodus.desync.com%3A6969&tr=udp%3A%2F%2Fopen.demonii.com%3A1337%2Fannounce")
+        //         }),
         //
-        //         ]
-        //     ), 
+        //     ],
         //     ..Author::default()
         // },
         
        Author{
             name: String::from("Plato"),
-            books_via_torrent: Some(
-                vec![
-                    ViaTorrent{
-                        magnet: String::from("magnet:?xt=urn:btih:0D25C216E5B606BCF2B7732688A9D1EBDF6997C5&dn=Plato%20-%20Complete%20Works%20(Hackett%20Pub.)%20(retail%20epub%2C%20mobi)&tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337&tr=udp%3A%2F%2Fopen.stealth.si%3A80%2Fannounce&tr=udp%3A%2F%2Ftracker.torrent.eu.org%3A451%2Fannounce&tr=udp%3A%2F%2Ftracker.bittor.pw%3A1337%2Fannounce&tr=udp%3A%2F%2Fpublic.popcorn-tracker.org%3A6969%2Fannounce&tr=udp%3A%2F%2Ftracker.dler.org%3A6969%2Fannounce&tr=udp%3A%2F%2Fexodus.desync.com%3A6969&tr=udp%3A%2F%2Fopen.demonii.com%3A1337%2Fannounce")
-                    }
-
-                ]
-            ), 
+            persona: Persona {
+                era: String::from("c. 428–348 BC; Athens"),
+                key_works: vec![
+                    String::from("the Republic"),
+                    String::from("the Apology"),
+                    String::from("the Symposium"),
+                    String::from("the Phaedo"),
+                    String::from("the Laws"),
+                ],
+                style_notes: vec![
+                    String::from("dialogue rather than treatise, with Socrates questioning those who think they know"),
+                    String::from("myths and allegories, such as the cave"),
+                    String::from("irony, and care over the definitions of terms"),
+                ],
+                refused_topics: vec![
+                    String::from("settling which of his characters speaks for him, beyond what the dialogues show"),
+                ],
+                ..Persona::default()
+            },
+            sources: vec![
+                Box::new(ViaTorrent{
+                    magnet: String::from("magnet:?xt=urn:btih:0D25C216E5B606BCF2B7732688A9D1EBDF6997C5&dn=Plato%20-%20Complete%20Works%20(Hackett%20Pub.)%20(retail%20epub%2C%20mobi)&tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337&tr=udp%3A%2F%2Fopen.stealth.si%3A80%2Fannounce&tr=udp%3A%2F%2Ftracker.torrent.eu.org%3A451%2Fannounce&tr=udp%3A%2F%2Ftracker.bittor.pw%3A1337%2Fannounce&tr=udp%3A%2F%2Fpublic.popcorn-tracker.org%3A6969%2Fannounce&tr=udp%3A%2F%2Ftracker.dler.org%3A6969%2Fannounce&tr=udp%3A%2F%2Fexodus.desync.com%3A6969&tr=udp%3A%2F%2Fopen.demonii.com%3A1337%2Fannounce")
+                }),
+
+            ],
             ..Author::default()
         },
 
 
         Author{
             name: String::from("Mohandas Karamchand Ghandi"),
-            books_via_http: Some(
-                vec![
-                    ViaHTTP{
-                        title: String::from("An Autobiography: The Story of My Experiments with Truth"),
-                        url: String::from("https://www.mkgandhi.org/ebks/An-Autobiography.pdf"),
-                        start_page: Some(16),
-                        end_page: Some(556),
-                        ..ViaHTTP::default() 
-                    },
-
-                    ViaHTTP{
-                        title: String::from("Hind Swaraj or Indian Home Rule"),
-                        url: String::from("https://www.mkgandhi.org/ebks/hind_swaraj.pdf"),
-                        start_page: Some(12),
-                        end_page: Some(89),
-                        ..ViaHTTP::default() 
-                    },
-
-                    ViaHTTP{
-                        title: String::from("The Bhagavad Gita, According to Gandhi",),
-                        url: String::from("https://ia800904.us.archive.org/10/items/InnerEngineeringAYogisGuideToJoy_20190116/Mahatma_gandhiThe_bhagavad_gita_according_to_gandhi.pdf",),
-                        start_page: Some(10),
-                        end_page: Some(177),
-                        ..ViaHTTP::default() 
-                    },
-
-                    ViaHTTP{
-                        title: String::from("Non-Violent Resistance"),
-                        url: String::from("https://archive.org/details/nonviolentresist00mkga/page/n9/mode/2up",),
-                        start_page: Some(16),
-                        end_page: Some(388),
-                        ..ViaHTTP::default() 
-                    },
-                ]
-            ),
+            persona: Persona {
+                era: String::from("1869–1948; India, England, and South Africa"),
+                key_works: vec![
+                    String::from("An Autobiography: The Story of My Experiments with Truth"),
+                    String::from("Hind Swaraj"),
+                    String::from("The Bhagavad Gita According to Gandhi"),
+                    String::from("Non-Violent Resistance"),
+                ],
+                style_notes: vec![
+                    String::from("simple, candid, and confessional"),
+                    String::from("moral reasoning from his own experience and experiments"),
+                    String::from("terms such as satyagraha, ahimsa, and swaraj"),
+                    String::from("gentle in tone but uncompromising in principle"),
+                ],
+                refused_topics: vec![
+                    String::from("present-day conflicts between India and Pakistan, or among India's religious communities"),
+                ],
+                ..Persona::default()
+            },
+            sources: vec![
+                Box::new(ViaHTTP{
+                    title: String::from("An Autobiography: The Story of My Experiments with Truth"),
+                    url: String::from("https://www.mkgandhi.org/ebks/An-Autobiography.pdf"),
+                    start_page: Some(16),
+                    end_page: Some(556),
+                    ..ViaHTTP::default() 
+                }),
+
+                Box::new(ViaHTTP{
+                    title: String::from("Hind Swaraj or Indian Home Rule"),
+                    url: String::from("https://www.mkgandhi.org/ebks/hind_swaraj.pdf"),
+                    start_page: Some(12),
+                    end_page: Some(89),
+                    ..ViaHTTP::default() 
+                }),
+
+                Box::new(ViaHTTP{
+                    title: String::from("The Bhagavad Gita, According to Gandhi",),
+                    url: String::from("https://ia800904.us.archive.org/10/items/InnerEngineeringAYogisGuideToJoy_20190116/Mahatma_gandhiThe_bhagavad_gita_according_to_gandhi.pdf",),
+                    start_page: Some(10),
+                    end_page: Some(177),
+                    ..ViaHTTP::default() 
+                }),
+
+                Box::new(ViaHTTP{
+                    title: String::from("Non-Violent Resistance"),
+                    url: String::from("https://archive.org/details/nonviolentresist00mkga/page/n9/mode/2up",),
+                    start_page: Some(16),
+                    end_page: Some(388),
+                    ..ViaHTTP::default() 
+                }),
+            ],
             ..Author::default()
         },
 
         Author{
             name: String::from("Lala Lajpat Rai"),
-            books_via_http: Some(
-                vec![
-                    ViaHTTP{
-                        title: String::from("The Story of My Deportation"),
-                        url: String::from("https://ia601503.us.archive.org/21/items/in.ernet.dli.2015.19903/2015.19903.The--Story-Of-My-Deportation_text.pdf"),
-                        start_page: Some(8),
-                        end_page: Some(274),
-                        needs_ocr: true,
-                        ..ViaHTTP::default() 
-                    },
-
-                    ViaHTTP{
-                        title: String::from("Young India: An Interpretation and a History of the Nationalist Movement from Within"),
-                        url: String::from("https://ia800802.us.archive.org/21/items/16RaiYoungindia/16-rai-youngindia.pdf"),
-                        start_page: Some(7),
-                        end_page: Some(294),
-                        ..ViaHTTP::default() 
-                    },
-
-                ]
-            ),
+            persona: Persona {
+                era: String::from("1865–1928; India, England, and the United States"),
+                key_works: vec![
+                    String::from("The Story of My Deportation"),
+                    String::from("Young India"),
+                    String::from("Unhappy India"),
+                    String::from("England's Debt to India"),
+                ],
+                style_notes: vec![
+                    String::from("forceful nationalist polemic"),
+                    String::from("appeals to history and to the liberal principles that Britain professed"),
+                    String::from("detailed indictments of British rule"),
+                ],
+                refused_topics: vec![
+                    String::from("present-day communal politics in India"),
+                ],
+                ..Persona::default()
+            },
+            sources: vec![
+                Box::new(ViaHTTP{
+                    title: String::from("The Story of My Deportation"),
+                    url: String::from("https://ia601503.us.archive.org/21/items/in.ernet.dli.2015.19903/2015.19903.The--Story-Of-My-Deportation_text.pdf"),
+                    start_page: Some(8),
+                    end_page: Some(274),
+                    needs_ocr: true,
+                    ..ViaHTTP::default() 
+                }),
+
+                Box::new(ViaHTTP{
+                    title: String::from("Young India: An Interpretation and a History of the Nationalist Movement from Within"),
+                    url: String::from("https://ia800802.us.archive.org/21/items/16RaiYoungindia/16-rai-youngindia.pdf"),
+                    start_page: Some(7),
+                    end_page: Some(294),
+                    ..ViaHTTP::default() 
+                }),
+
+            ],
             ..Author::default()
         },
 
 
         Author{
             name: String::from("José Rizal"),
-            books_via_scraper: Some(
-                vec![
-                    ViaScraper{
-                        title: String::from("The Social Cancer"),
-                        url: String::from("https://www.geocities.ws/qcpujoserizal/Rizal/pdf/Noli.pdf",),
-                        ..ViaScraper::default() 
-                    },
-
-                    ViaScraper{
-                        title: String::from("The Reign of Greed"),
-                        url: String::from("https://www.gutenberg.org/files/10676/10676-h/10676-h.htm"),
-                        initial_marker: Some("One morning in December"),
-                        terminal_marker: Some("country folk"),
-                        ..ViaScraper::default() 
-                    },
-
-                ]
-            ),
+            persona: Persona {
+                era: String::from("1861–1896; the Philippines and Europe"),
+                key_works: vec![
+                    String::from("Noli Me Tángere (The Social Cancer)"),
+                    String::from("El Filibusterismo (The Reign of Greed)"),
+                    String::from("The Philippines a Century Hence"),
+                ],
+                style_notes: vec![
+                    String::from("satire of friars, officials, and colonial society through fiction"),
+                    String::from("an ironic narrator who lets characters condemn themselves"),
+                    String::from("the voice of a reformer rather than a revolutionary"),
+                ],
+                refused_topics: vec![
+                    String::from("present-day Philippine politics"),
+                ],
+                ..Persona::default()
+            },
+            sources: vec![
+                Box::new(ViaScraper{
+                    title: String::from("The Social Cancer"),
+                    url: String::from("https://www.geocities.ws/qcpujoserizal/Rizal/pdf/Noli.pdf",),
+                    ..ViaScraper::default() 
+                }),
+
+                Box::new(ViaScraper{
+                    title: String::from("The Reign of Greed"),
+                    url: String::from("https://www.gutenberg.org/files/10676/10676-h/10676-h.htm"),
+                    initial_marker: Some(String::from("One morning in December")),
+                    terminal_marker: Some(String::from("country folk")),
+                    ..ViaScraper::default() 
+                }),
+
+            ],
             ..Author::default()
         },
 
         Author{
             name: String::from("Vladimir Lenin"),
-            books_via_http: Some(
-                vec![
-                    ViaHTTP{
-                        title: String::from("What Is to Be Done?: Burning Questions of our Movements"),
-                        url: String::from("https://www.marxists.org/ebooks/lenin/what-is-to-be-done.pdf"),
-                        start_page: Some(7),
-                        end_page: Some(124),
-                        ..ViaHTTP::default() 
-                    },
-                    
-                    ViaHTTP{
-                        title: String::from("The State and Revolution"),
-                        url: String::from("https://www.marxists.org/ebooks/lenin/state-and-revolution.pdf"),
-                        start_page: Some(7),
-                        end_page: Some(83),
-                        ..ViaHTTP::default() 
-                    },
-                    
-                ]
-            ),
+            persona: Persona {
+                era: String::from("1870–1924; Russia, and exile in Western Europe"),
+                key_works: vec![
+                    String::from("What Is to Be Done?"),
+                    String::from("The State and Revolution"),
+                    String::from("Imperialism, the Highest Stage of Capitalism"),
+                ],
+                style_notes: vec![
+                    String::from("combative polemic that names and quotes his opponents"),
+                    String::from("rhetorical questions and emphatic italics"),
+                    String::from("long quotations from Marx and Engels"),
+                    String::from("practical organisational detail"),
+                ],
+                refused_topics: vec![
+                    String::from("justifying the Red Terror or political repression"),
+                ],
+                ..Persona::default()
+            },
+            sources: vec![
+                Box::new(ViaHTTP{
+                    title: String::from("What Is to Be Done?: Burning Questions of our Movements"),
+                    url: String::from("https://www.marxists.org/ebooks/lenin/what-is-to-be-done.pdf"),
+                    start_page: Some(7),
+                    end_page: Some(124),
+                    ..ViaHTTP::default() 
+                }),
+                
+                Box::new(ViaHTTP{
+                    title: String::from("The State and Revolution"),
+                    url: String::from("https://www.marxists.org/ebooks/lenin/state-and-revolution.pdf"),
+                    start_page: Some(7),
+                    end_page: Some(83),
+                    ..ViaHTTP::default() 
+                }),
+                
+            ],
             ..Author::default()
         },
 
         Author{
             name: String::from("Sun Yat-sen"),
-            books_via_http: Some(
-                vec![
-                    ViaHTTP{
-                        title: String::from("The Three Principles of the People"),
-                        url: String::from("https://chinese.larouchepub.com/wp-content/uploads/2017/05/San-Min-Chu-I_ALL-en.pdf"),
-                        start_page: Some(3),
-                        end_page: Some(74),
-                        ..ViaHTTP::default() 
-                    },
-                    
-                    ViaHTTP{
-                        title: String::from("The International Development of China"),
-                        url: String::from("https://chinese.larouchepub.com/wp-content/uploads/2017/05/sun_IDC-en.pdf"),
-                        start_page: Some(15),
-                        end_page: Some(305),
-                        ..ViaHTTP::default() 
-                    },
-                    
-                ]
-            ),
+            persona: Persona {
+                era: String::from("1866–1925; China, Japan, and the Chinese diaspora"),
+                key_works: vec![
+                    String::from("The Three Principles of the People"),
+                    String::from("The International Development of China"),
+                    String::from("Fundamentals of National Reconstruction"),
+                ],
+                style_notes: vec![
+                    String::from("lectures that explain patiently to a broad audience"),
+                    String::from("plans set out with figures and specific projects"),
+                    String::from("analogies from Chinese history"),
+                ],
+                refused_topics: vec![
+                    String::from("the present-day dispute between the People's Republic of China and Taiwan"),
+                ],
+                ..Persona::default()
+            },
+            sources: vec![
+                Box::new(ViaHTTP{
+                    title: String::from("The Three Principles of the People"),
+                    url: String::from("https://chinese.larouchepub.com/wp-content/uploads/2017/05/San-Min-Chu-I_ALL-en.pdf"),
+                    start_page: Some(3),
+                    end_page: Some(74),
+                    ..ViaHTTP::default() 
+                }),
+                
+                Box::new(ViaHTTP{
+                    title: String::from("The International Development of China"),
+                    url: String::from("https://chinese.larouchepub.com/wp-content/uploads/2017/05/sun_IDC-en.pdf"),
+                    start_page: Some(15),
+                    end_page: Some(305),
+                    ..ViaHTTP::default() 
+                }),
+                
+            ],
             ..Author::default()
         },
 
         Author{
             name: String::from("Charles Darwin"),
-            biographers_and_compilers: vec!["Larkum, Aurthur", "Litchfield H.E. (ed.)", "Krauss, Ernt", "Barrett, Paul (ed.)", "Burkhardt, Frederick (ed.)"].into(),
-            books_via_http: Some(
-                vec![
-                    ViaHTTP{
-                        title: String::from("The Three Principles of the People"),
-                        url: String::from("https://chinese.larouchepub.com/wp-content/uploads/2017/05/San-Min-Chu-I_ALL-en.pdf"),
-                        start_page: Some(3),
-                        end_page: Some(74),
-                        ..ViaHTTP::default() 
-                    },
-                    
-                    ViaHTTP{
-                        title: String::from("The International Development of China"),
-                        url: String::from("https://chinese.larouchepub.com/wp-content/uploads/2017/05/sun_IDC-en.pdf"),
-                        start_page: Some(15),
-                        end_page: Some(305),
-                        ..ViaHTTP::default() 
-                    },
-                    
-                ]
+            persona: Persona {
+                era: String::from("1809–1882; England"),
+                key_works: vec![
+                    String::from("On the Origin of Species"),
+                    String::from("The Descent of Man"),
+                    String::from("The Voyage of the Beagle"),
+                    String::from("The Expression of the Emotions in Man and Animals"),
+                ],
+                style_notes: vec![
+                    String::from("patient, cautious accumulation of observations"),
+                    String::from("anticipating objections and conceding difficulties"),
+                    String::from("modest hedging and qualification"),
+                ],
+                refused_topics: vec![
+                    String::from("modern genetics or evolutionary biology, as though he had known of them"),
+                    String::from("using natural selection to rank human races or to justify eugenics"),
+                ],
+                ..Persona::default()
+            },
+            biographers_and_compilers: Some(
+                ["Larkum, Aurthur", "Litchfield H.E. (ed.)", "Krauss, Ernt", "Barrett, Paul (ed.)", "Burkhardt, Frederick (ed.)"].map(String::from).to_vec()
             ),
-            ..Author::default()
+            sources: vec![
+                Box::new(ViaHTTP{
+                    title: String::from("The Three Principles of the People"),
+                    url: String::from("https://chinese.larouchepub.com/wp-content/uploads/2017/05/San-Min-Chu-I_ALL-en.pdf"),
+                    start_page: Some(3),
+                    end_page: Some(74),
+                    ..ViaHTTP::default() 
+                }),
+                
+                Box::new(ViaHTTP{
+                    title: String::from("The International Development of China"),
+                    url: String::from("https://chinese.larouchepub.com/wp-content/uploads/2017/05/sun_IDC-en.pdf"),
+                    start_page: Some(15),
+                    end_page: Some(305),
+                    ..ViaHTTP::default() 
+                }),
+                
+            ],
         },
 
        Author{
             name: String::from("William Godwin"),
-            books_via_torrent: Some(
-                vec![
-                    ViaTorrent{
-                        magnet: String::from("magnet:?xt=urn:btih:8657B7A1D87DAF74731FECA2284460A397BA399D&dn=William%20Godwin%20-%20Essential%20Works%20of%20Anarchism%20(16%20books)&tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337&tr=udp%3A%2F%2Fopen.stealth.si%3A80%2Fannounce&tr=udp%3A%2F%2Ftracker.torrent.eu.org%3A451%2Fannounce&tr=udp%3A%2F%2Ftracker.bittor.pw%3A1337%2Fannounce&tr=udp%3A%2F%2Fpublic.popcorn-tracker.org%3A6969%2Fannounce&tr=udp%3A%2F%2Ftracker.dler.org%3A6969%2Fannounce&tr=udp%3A%2F%2Fexodus.desync.com%3A6969&tr=udp%3A%2F%2Fopen.demonii.com%3A1337%2Fannounce")
-                    }
-
-                ]
-            ), 
+            persona: Persona {
+                era: String::from("1756–1836; England"),
+                key_works: vec![
+                    String::from("An Enquiry Concerning Political Justice"),
+                    String::from("Things as They Are; or, The Adventures of Caleb Williams"),
+                    String::from("Memoirs of the Author of A Vindication of the Rights of Woman"),
+                ],
+                style_notes: vec![
+                    String::from("rigorous, step-by-step argument from first principles of reason and justice"),
+                    String::from("long, balanced eighteenth-century sentences"),
+                    String::from("confidence in the perfectibility of human beings"),
+                ],
+                refused_topics: vec![
+                    String::from("the private lives of his family, beyond what he published"),
+                ],
+                ..Persona::default()
+            },
+            sources: vec![
+                Box::new(ViaTorrent{
+                    magnet: String::from("magnet:?xt=urn:btih:8657B7A1D87DAF74731FECA2284460A397BA399D&dn=William%20Godwin%20-%20Essential%20Works%20of%20Anarchism%20(16%20books)&tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337&tr=udp%3A%2F%2Fopen.stealth.si%3A80%2Fannounce&tr=udp%3A%2F%2Ftracker.torrent.eu.org%3A451%2Fannounce&tr=udp%3A%2F%2Ftracker.bittor.pw%3A1337%2Fannounce&tr=udp%3A%2F%2Fpublic.popcorn-tracker.org%3A6969%2Fannounce&tr=udp%3A%2F%2Ftracker.dler.org%3A6969%2Fannounce&tr=udp%3A%2F%2Fexodus.desync.com%3A6969&tr=udp%3A%2F%2Fopen.demonii.com%3A1337%2Fannounce")
+                }),
+
+            ],
             ..Author::default()
         },
 
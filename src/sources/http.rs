@@ -1,16 +1,25 @@
 use log;
-use std::{fs::File, io::Write, path::PathBuf};
+use std::{fs, path::{Path, PathBuf}};
+
+use async_trait::async_trait;
+
+use crate::sources::source::Source;
+use crate::data_preparation::books::{slugify, Book};
+use crate::sources::extensions::FILE_EXTENSIONS;
+use crate::sources::manifest::{Manifest, SourceIdentity};
 
 
 #[allow(dead_code)]
-#[derive(Clone, Default)] 
+#[derive(Clone, Default)]
 pub struct ViaHTTP {
     pub title: String,
-    pub url: String, 
-    pub format: String, 
-    pub needs_ocr: bool, 
+    pub url: String,
+    pub format: String,
+    pub needs_ocr: bool,
     pub start_page: Option<i64>,
-    pub end_page: Option<i64>
+    pub end_page: Option<i64>,
+    // Inclusive ranges of pages (numbered as in the original) written by someone other than the author
+    pub secondary_pages: Vec<(i64, i64)>,
 }
 
 
@@ -20,20 +29,100 @@ impl ViaHTTP {
         self.title.replace(" ", "_").to_string() + &self.format
     }
 
-    pub async fn download(self, download_path: &PathBuf) {
-        if !download_path.exists() {
-            log::info!("Downloading {}", self.title);
-            let response: Result<reqwest::Response, reqwest::Error> = reqwest::get(self.url).await;
+    // The body is written to a partial file that only replaces the real one once it is complete,
+    // so that an interrupted download can never be mistaken for a finished one.
+    async fn fetch(&self, download_path: &Path) -> Result<(), anyhow::Error> {
+        let response: reqwest::Response = reqwest::get(&self.url).await?.error_for_status()?;
+        let bytes = response.bytes().await?;
 
-            match response.as_ref().unwrap().status() {
-                reqwest::StatusCode::OK => {
-                    let bytes = response.unwrap().bytes();
-                    let file: Result<File, std::io::Error> = File::create(download_path); 
-                    _ = file.unwrap().write_all(&bytes.await.unwrap());
-                } 
-                _ => log::error!("Unable to download {}", self.title)
-            }; 
-        } 
+        if bytes.is_empty() {
+            anyhow::bail!("the server returned an empty body");
+        }
+
+        let partial_path: PathBuf = download_path.with_extension("part");
+        fs::write(&partial_path, &bytes)?;
+        fs::rename(&partial_path, download_path)?;
+        Ok(())
     }
 }
 
+
+#[async_trait]
+impl Source for ViaHTTP {
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn identity(&self) -> SourceIdentity {
+        SourceIdentity::Http { url: self.url.clone() }
+    }
+
+    fn file_name(&self) -> Option<String> {
+        Some(self.get_file_name())
+    }
+
+    async fn download(&self, manifest: &mut Manifest) -> Result<(), anyhow::Error> {
+        log::info!("Downloading {}", self.title);
+        let file_name: String = self.get_file_name();
+        let download_path: PathBuf = manifest.author_root().join(&file_name);
+
+        self.fetch(&download_path).await?;
+        manifest.record(&file_name, self.identity())
+    }
+
+    fn books(&self, author_name: &str, _manifest: &Manifest) -> Vec<Book> {
+        vec![
+            Book {
+                author: author_name.to_string(),
+                title: self.title.clone(),
+                slug: slugify(&self.title),
+                file_name: self.get_file_name(),
+                source: self.identity(),
+                needs_ocr: self.needs_ocr,
+                start_page: self.start_page,
+                end_page: self.end_page,
+                secondary_pages: self.secondary_pages.clone(),
+                biographers_and_compilers: Vec::new(),
+                is_interview: false,
+            }
+        ]
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems: Vec<String> = Vec::new();
+
+        if self.format.is_empty() {
+            problems.push(format!("{} has no format, so it will be saved without an extension", self.title));
+        } else if !FILE_EXTENSIONS.contains(&self.format.as_str()) {
+            problems.push(format!("{} has an unexpected format: {:?}", self.title, self.format));
+        }
+
+        if let (Some(start), Some(end)) = (self.start_page, self.end_page) {
+            if start > end {
+                problems.push(format!("{} starts on page {}, after its end page {}", self.title, start, end));
+            }
+        }
+
+        if self.start_page.is_some_and(|start| start < 1) {
+            problems.push(format!("{} has a start page below 1", self.title));
+        }
+
+        for (start, end) in &self.secondary_pages {
+            let outside: bool = self.start_page.is_some_and(|first| *start < first) || self.end_page.is_some_and(|last| *end > last);
+            if start > end || outside {
+                problems.push(format!("{} has secondary pages {}-{} outside of its pages", self.title, start, end));
+            }
+        }
+
+        if self.needs_ocr && !self.format.is_empty() && self.format != ".pdf" {
+            problems.push(format!("{} needs OCR, but is not a PDF", self.title));
+        }
+
+        if self.url.contains("archive.org/details/") {
+            problems.push(format!("{} points at an archive.org viewer page rather than a file", self.title));
+        }
+
+        problems
+    }
+}
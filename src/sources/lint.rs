@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use crate::sources::authors::Author;
+use crate::data_preparation::books::slugify;
+
+
+// Checks the catalog for mistakes that would otherwise only surface hours into a run
+pub fn lint_catalog(authors: &[Author]) -> Vec<String> {
+    let mut problems: Vec<String> = Vec::new();
+    let mut authors_by_url: HashMap<String, Vec<&str>> = HashMap::new();
+
+    for author in authors {
+        let mut slugs: HashMap<String, usize> = HashMap::new();
+
+        if author.sources.is_empty() {
+            problems.push(format!("{} has no sources", author.name));
+        }
+
+        if author.persona.is_empty() {
+            problems.push(format!("{} has no persona to answer in their voice with", author.name));
+        }
+
+        for source in &author.sources {
+            if !source.is_collection() {
+                *slugs.entry(slugify(&source.title())).or_default() += 1;
+            }
+
+            authors_by_url.entry(source.identity().url().to_string()).or_default().push(&author.name);
+
+            for problem in source.problems() {
+                problems.push(format!("{} ({})", problem, author.name));
+            }
+        }
+
+        for (slug, count) in slugs {
+            if count > 1 {
+                problems.push(format!("{} has {} books with the slug {}", author.name, count, slug));
+            }
+        }
+    }
+
+    for (url, names) in authors_by_url {
+        let mut names: Vec<&str> = names;
+        names.dedup();
+
+        if names.len() > 1 {
+            problems.push(format!("{} is listed under several authors: {}", url, names.join(", ")));
+        }
+    }
+
+    problems.sort();
+    problems
+}
@@ -0,0 +1,194 @@
+use std::fs;
+use std::io::Read;
+use std::time::UNIX_EPOCH;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub static MANIFEST_FILE_NAME: &str = "manifest.json";
+
+
+// Describes where an artifact came from, so that a change of URL, magnet, or scraping
+// markers is treated as a different artifact rather than silently reusing the old file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SourceIdentity {
+    Http { url: String },
+    Scraper { url: String, initial_marker: Option<String>, terminal_marker: Option<String> },
+    Torrent { magnet: String },
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum SourceKind {
+    Http,
+    Scraper,
+    Torrent,
+}
+
+
+impl SourceIdentity {
+
+    pub fn kind(&self) -> SourceKind {
+        match self {
+            SourceIdentity::Http { .. } => SourceKind::Http,
+            SourceIdentity::Scraper { .. } => SourceKind::Scraper,
+            SourceIdentity::Torrent { .. } => SourceKind::Torrent,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        match self {
+            SourceIdentity::Http { url } | SourceIdentity::Scraper { url, .. } => url,
+            SourceIdentity::Torrent { magnet } => magnet,
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub file_name: String,
+    pub source: SourceIdentity,
+    pub size: u64,
+    pub sha256: String,
+    pub modified: u64,
+}
+
+
+// One manifest lives in each author's root, keyed by the name of the file on disk.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(skip)]
+    path: PathBuf,
+    pub entries: BTreeMap<String, ManifestEntry>,
+}
+
+
+impl Manifest {
+
+    pub fn load(author_root: &Path) -> Self {
+        let path: PathBuf = author_root.join(MANIFEST_FILE_NAME);
+
+        let mut manifest: Manifest = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(
+                |e| {
+                    log::error!("The manifest at {} is unreadable and will be rebuilt: {}", path.display(), e);
+                    Manifest::default()
+                }
+            ),
+            Err(_) => Manifest::default(),
+        };
+
+        manifest.path = path;
+        manifest
+    }
+
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        // Write to a temporary file first so that an interrupted run never leaves a half-written manifest
+        let temporary_path: PathBuf = self.path.with_extension("json.part");
+        fs::write(&temporary_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&temporary_path, &self.path)?;
+        Ok(())
+    }
+
+    pub fn author_root(&self) -> &Path {
+        self.path.parent().expect("The manifest should always live inside an author's root")
+    }
+
+    // An artifact is valid when it was recorded from the same source, and the file on disk still has
+    // the recorded size and hash. The hash is only recomputed when the modification time has changed.
+    pub fn is_valid(&self, file_name: &str, source: &SourceIdentity) -> bool {
+        let Some(entry) = self.entries.get(file_name) else { return false };
+
+        if &entry.source != source {
+            return false
+        }
+
+        let file_path: PathBuf = self.author_root().join(file_name);
+        let Ok(metadata) = fs::metadata(&file_path) else { return false };
+
+        if metadata.len() != entry.size || metadata.len() == 0 {
+            return false
+        }
+
+        if modified_seconds(&metadata) == entry.modified {
+            true
+        } else {
+            hash_file(&file_path).map(|hash| hash == entry.sha256).unwrap_or(false)
+        }
+    }
+
+    // Unlike is_valid, this always rehashes the file, whatever its modification time says.
+    pub fn verify(&self, file_name: &str) -> Result<(), anyhow::Error> {
+        let Some(entry) = self.entries.get(file_name) else { anyhow::bail!("{} is not in the manifest", file_name) };
+        let file_path: PathBuf = self.author_root().join(file_name);
+
+        let size: u64 = fs::metadata(&file_path)?.len();
+        if size != entry.size {
+            anyhow::bail!("{} is {} bytes, but {} bytes were recorded", file_name, size, entry.size);
+        }
+
+        if hash_file(&file_path)? != entry.sha256 {
+            anyhow::bail!("{} no longer has the hash that was recorded", file_name);
+        }
+
+        Ok(())
+    }
+
+    pub fn record(&mut self, file_name: &str, source: SourceIdentity) -> Result<(), anyhow::Error> {
+        let file_path: PathBuf = self.author_root().join(file_name);
+        let metadata = fs::metadata(&file_path)?;
+
+        let entry = ManifestEntry {
+            file_name: file_name.to_string(),
+            source,
+            size: metadata.len(),
+            sha256: hash_file(&file_path)?,
+            modified: modified_seconds(&metadata),
+        };
+
+        self.entries.insert(file_name.to_string(), entry);
+        self.save()
+    }
+
+    pub fn entries_from(&self, source: &SourceIdentity) -> Vec<&ManifestEntry> {
+        self.entries.values().filter(|entry| &entry.source == source).collect()
+    }
+
+    pub fn forget_source(&mut self, source: &SourceIdentity) {
+        self.entries.retain(|_, entry| &entry.source != source);
+    }
+}
+
+
+pub fn hash_file(file_path: &Path) -> Result<String, anyhow::Error> {
+    let mut file = fs::File::open(file_path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 16];
+
+    loop {
+        let bytes_read: usize = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+
+fn modified_seconds(metadata: &fs::Metadata) -> u64 {
+    metadata.modified().ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
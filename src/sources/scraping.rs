@@ -1,74 +1,57 @@
 use log;
 use scraper::{self, Html, Selector};
-use std::{fs::File, io::Write, path::PathBuf, usize};
+use std::{fs, path::{Path, PathBuf}};
 
-use crate::sources::authors;
+use async_trait::async_trait;
+
+use crate::sources::source::Source;
+use crate::data_preparation::books::{slugify, Book};
+use crate::sources::manifest::{Manifest, SourceIdentity};
 
 
 #[allow(dead_code)]
 #[derive(Clone, Default)]
-pub struct ViaScraper <'a> {
+pub struct ViaScraper {
     pub title: String, 
     pub url: String,
     pub format: String,
     pub is_interview: bool,
-    pub initial_marker: Option<&'a str>, 
-    pub terminal_marker: Option<&'a str> 
+    pub initial_marker: Option<String>, 
+    pub terminal_marker: Option<String> 
 }
 
 
-impl ViaScraper <'_> {
-
-    pub async fn download(&self, author_name: &String) {
-        log::info!("Downloading {}", self.title);
-        let file_name = self.get_file_name().to_string();
-        let author_root = authors::get_author_root(&author_name);
-        let file_path: PathBuf = author_root.join(&file_name);
-
-        if !file_path.exists() {
-            log::warn!("Attempting to scrape {}", self.title);
-            let scraped_text: String = self.scrape().await;
-
-            if self.needs_truncation() {
-                let truncated_text: &str = self.truncate(&scraped_text).unwrap(); 
-                self.save_file(truncated_text, &file_path)
-            } else {
-                self.save_file(&scraped_text, &file_path)
-            }
-
-        } 
-    }
+impl ViaScraper {
 
-    fn save_file(&self, text: &str, file_path: &PathBuf) {
-        let file: Result<File, std::io::Error> = File::create(file_path);
-        _ = file.unwrap().write_all(text.as_bytes());
+    fn save_file(&self, text: &str, file_path: &Path) -> Result<(), std::io::Error> {
+        let partial_path: PathBuf = file_path.with_extension("part");
+        fs::write(&partial_path, text.as_bytes())?;
+        fs::rename(&partial_path, file_path)
     }
 
 
-    async fn scrape(&self) -> String { 
+    async fn scrape(&self) -> Result<String, anyhow::Error> { 
         let html = self.make_request();
         let mut scraped_text: String = String::new();
-        let document = Html::parse_document(&html.await.unwrap());
+        let document = Html::parse_document(&html.await?);
         let paragraph_selector: &Selector = &scraper::Selector::parse("p").unwrap();
 
         for element in document.select(paragraph_selector) {
             let paragraph_text: String = element.text().collect();
             scraped_text.push_str(&paragraph_text);
-            scraped_text.push_str("\n");
+            scraped_text.push('\n');
         }
 
-        scraped_text
+        Ok(scraped_text)
     }
 
-    fn truncate<'a, 'b, 'out>(&'a self, full_text: &'b String) -> Option<&'out str> 
-        where 'a: 'out, 'b: 'out 
-    {
-        // println!("INITIAL {}", full_text.rfind(self.initial_marker.unwrap()).unwrap());
-        // println!("TERMINAL {}",full_text.rfind(self.terminal_marker.unwrap()).unwrap());
+    fn truncate<'text>(&self, full_text: &'text str) -> Option<&'text str> {
+        // println!("INITIAL {}", full_text.rfind(self.initial_marker.as_deref().unwrap()).unwrap());
+        // println!("TERMINAL {}",full_text.rfind(self.terminal_marker.as_deref().unwrap()).unwrap());
 
-        let start_index: usize = full_text.rfind(self.initial_marker.unwrap()).unwrap();
-        let terminal_index: usize = full_text.rfind(self.terminal_marker.unwrap()).unwrap();
-        Some(&full_text[start_index..terminal_index])
+        let start_index: usize = full_text.rfind(self.initial_marker.as_deref()?)?;
+        let terminal_index: usize = full_text.rfind(self.terminal_marker.as_deref()?)?;
+        full_text.get(start_index..terminal_index)
     }
 
     fn needs_truncation(&self) -> bool {
@@ -95,3 +78,78 @@ impl ViaScraper <'_> {
     }
 }
 
+
+
+#[async_trait]
+impl Source for ViaScraper {
+
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn identity(&self) -> SourceIdentity {
+        SourceIdentity::Scraper {
+            url: self.url.clone(),
+            initial_marker: self.initial_marker.clone(),
+            terminal_marker: self.terminal_marker.clone(),
+        }
+    }
+
+    fn file_name(&self) -> Option<String> {
+        Some(self.get_file_name())
+    }
+
+    async fn download(&self, manifest: &mut Manifest) -> Result<(), anyhow::Error> {
+        log::warn!("Attempting to scrape {}", self.title);
+        let file_name = self.get_file_name();
+        let file_path: PathBuf = manifest.author_root().join(&file_name);
+        let scraped_text: String = self.scrape().await?;
+
+        let text_to_save: &str = if self.needs_truncation() {
+            self.truncate(&scraped_text).ok_or_else(
+                || anyhow::anyhow!("the markers could not be found in the scraped text")
+            )?
+        } else {
+            &scraped_text
+        };
+
+        if text_to_save.trim().is_empty() {
+            anyhow::bail!("scraping produced no text, so nothing will be saved");
+        }
+
+        self.save_file(text_to_save, &file_path)?;
+        manifest.record(&file_name, self.identity())
+    }
+
+    fn books(&self, author_name: &str, _manifest: &Manifest) -> Vec<Book> {
+        vec![
+            Book {
+                author: author_name.to_string(),
+                title: self.title.clone(),
+                slug: slugify(&self.title),
+                file_name: self.get_file_name(),
+                source: self.identity(),
+                needs_ocr: false,
+                start_page: None,
+                end_page: None,
+                secondary_pages: Vec::new(),
+                biographers_and_compilers: Vec::new(),
+                is_interview: self.is_interview,
+            }
+        ]
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems: Vec<String> = Vec::new();
+
+        if self.initial_marker.is_some() != self.terminal_marker.is_some() {
+            problems.push(format!("{} has only one of its two scraping markers", self.title));
+        }
+
+        if self.url.to_lowercase().ends_with(".pdf") {
+            problems.push(format!("{} is scraped from a PDF, which has no paragraphs to scrape", self.title));
+        }
+
+        problems
+    }
+}
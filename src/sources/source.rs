@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::data_preparation::books::Book;
+use crate::sources::manifest::{Manifest, SourceIdentity, SourceKind};
+use crate::data_preparation::planning::{head_content_length, PlannedArtifact};
+
+pub const MAXIMUM_ATTEMPTS: u32 = 3;
+
+
+// Every way of acquiring a book implements this, so that Author can treat them all alike.
+// Sources that produce a single file only need to provide the required methods.
+#[async_trait]
+pub trait Source: Send + Sync {
+
+    fn title(&self) -> String;
+
+    fn identity(&self) -> SourceIdentity;
+
+    // The name of the file that this source produces, or None if it produces an unknown number of them
+    fn file_name(&self) -> Option<String>;
+
+    // Acquires the source and records whatever it produced in the manifest
+    async fn download(&self, manifest: &mut Manifest) -> Result<(), anyhow::Error>;
+
+    // The books that this source has produced (or will produce) for later stages to work on
+    fn books(&self, author_name: &str, manifest: &Manifest) -> Vec<Book>;
+
+    fn kind(&self) -> SourceKind {
+        self.identity().kind()
+    }
+
+    // Collections are only split into books once they have been acquired, so until then they can only be selected by their own title
+    fn is_collection(&self) -> bool {
+        self.file_name().is_none()
+    }
+
+    // Whether everything that this source produces is present and matches the manifest
+    fn verify(&self, manifest: &Manifest) -> bool {
+        match self.file_name() {
+            Some(file_name) => manifest.is_valid(&file_name, &self.identity()),
+            None => false,
+        }
+    }
+
+    // Mistakes in the source's definition, for the lint command to report
+    fn problems(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    async fn plan(&self, manifest: &Manifest, force: bool) -> PlannedArtifact {
+        let file_name: String = self.file_name().unwrap_or_default();
+        let is_present: bool = self.verify(manifest);
+        let will_acquire: bool = force || !is_present;
+
+        let expected_size: Option<u64> = match manifest.entries.get(&file_name) {
+            Some(entry) if !will_acquire => Some(entry.size),
+            _ => head_content_length(self.identity().url()).await,
+        };
+
+        PlannedArtifact {
+            title: self.title(),
+            kind: self.kind(),
+            output_path: manifest.author_root().join(&file_name),
+            is_present,
+            will_acquire,
+            expected_size,
+            later_stages: Vec::new(),
+        }
+    }
+}
+
+
+// Transient network failures are common on the larger archives, so every kind of source is retried
+// with a growing delay. A download only counts as a success if it leaves the source verifiable.
+pub async fn download_with_retries(source: &dyn Source, manifest: &mut Manifest) -> Result<(), anyhow::Error> {
+    let mut attempt: u32 = 1;
+
+    loop {
+        let result: Result<(), anyhow::Error> = match source.download(manifest).await {
+            Ok(_) if source.verify(manifest) => Ok(()),
+            Ok(_) => Err(anyhow::anyhow!("the download finished, but produced nothing that could be verified")),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt >= MAXIMUM_ATTEMPTS => return Err(e),
+            Err(e) => {
+                log::warn!("Attempt {} of {} to acquire {} failed: {}", attempt, MAXIMUM_ATTEMPTS, source.title(), e);
+                tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+                attempt += 1;
+            }
+        }
+    }
+}
@@ -2,18 +2,24 @@ use std::fs;
 use std::ffi::OsStr;
 use std::path::{PathBuf, Path};
 
-use serde_json;
 use glob::GlobError;
 
 use librqbit::Session;
 use librqbit::AddTorrent;
 use librqbit::AddTorrentOptions;
+use librqbit::AddTorrentResponse;
+
+use async_trait::async_trait;
 
-use crate::sources::authors;
 use crate::sources::extensions;
-use crate::sources::utils::get_file_paths;
+use crate::sources::source::Source;
+use crate::data_preparation::books::{slugify, Book};
+use crate::sources::manifest::{Manifest, SourceIdentity};
+use crate::data_preparation::planning::PlannedArtifact;
 
-pub static LOG_FILE_NAME: &str = "downloaded_files.json";
+// Torrents are downloaded into this directory within the author's root, so that files which were
+// acquired in other ways are never mistaken for the contents of the torrent.
+pub static STAGING_DIRECTORY_NAME: &str = "torrent_staging";
 
 
 #[derive(Clone)]
@@ -25,9 +31,10 @@ pub struct ViaTorrent {
 
 impl ViaTorrent {
     
-    pub async fn download(&self, download_path: PathBuf) { 
+    async fn fetch(&self, download_path: &Path) -> Result<(), anyhow::Error> { 
 
-        let session = Session::new(download_path.into()).await.unwrap();
+        fs::create_dir_all(download_path)?;
+        let session = Session::new(download_path.to_path_buf()).await?;
 
         let torrent_config = AddTorrentOptions{
             overwrite: true, // Because I would like overwrites to be possible
@@ -37,17 +44,47 @@ impl ViaTorrent {
         let torrent_handle = session.add_torrent(
             AddTorrent::from_url(&self.magnet),
             Some(torrent_config)
-        ).await.unwrap().into_handle().unwrap();
+        ).await?.into_handle().ok_or_else(|| anyhow::anyhow!("the torrent could not be added"))?;
 
-        torrent_handle.wait_until_completed().await.unwrap();
+        let result = torrent_handle.wait_until_completed().await;
         session.stop().await; // Prevents an error that warns you about the connection still being open. 
+        result
+    }
+
+    // The name that the magnet link gives the torrent, if it has one
+    pub fn display_name(&self) -> String {
+        reqwest::Url::parse(&self.magnet).ok()
+            .and_then(|url| url.query_pairs().find(|(key, _)| key == "dn").map(|(_, value)| value.to_string()))
+            .unwrap_or_else(|| self.magnet.clone())
+    }
+
+    // Only the torrent's metadata is fetched from peers, and nothing is written into the author's root
+    async fn fetch_total_size(&self) -> Option<u64> {
+        let session = Session::new(std::env::temp_dir()).await.ok()?;
+        let options = AddTorrentOptions { list_only: true, ..AddTorrentOptions::default() };
+
+        let request = session.add_torrent(AddTorrent::from_url(&self.magnet), Some(options));
+        let response = tokio::time::timeout(std::time::Duration::from_secs(60), request).await;
+
+        let total_size: Option<u64> = match response {
+            Ok(Ok(AddTorrentResponse::ListOnly(listing))) => {
+                listing.info.iter_file_lengths().ok().map(|lengths| lengths.sum())
+            },
+            _ => {
+                log::warn!("Could not fetch the metadata of {}", self.display_name());
+                None
+            }
+        };
+
+        session.stop().await;
+        total_size
     }
 
-    pub fn extract_files(&self, download_path: PathBuf, author_name: &String) {
+    pub fn extract_files(&self, download_path: PathBuf, manifest: &mut Manifest) {
 
         let mut file_paths: Vec<PathBuf> = Vec::new();
         let mut directories: Vec<PathBuf> = Vec::new();
-        let author_root = authors::get_author_root(&author_name);
+        let author_root: PathBuf = manifest.author_root().to_path_buf();
 
         let path_contents: Vec<Result<PathBuf, GlobError>> = list_path_contents(&download_path).expect("Could not get contents of download path"); 
 
@@ -74,40 +111,111 @@ impl ViaTorrent {
             }
         }
 
-        let mut paths_of_downloaded_files: Vec<String> = Vec::new(); 
+        let mut names_of_downloaded_files: Vec<String> = Vec::new(); 
 
         move_files_to_destinations(
             &author_root,
             &file_paths, 
-            &mut paths_of_downloaded_files,
+            &mut names_of_downloaded_files,
         );
 
-        log_downloaded_files(author_name, &paths_of_downloaded_files);
+        record_downloaded_files(self.identity(), manifest, &names_of_downloaded_files);
         remove_book_directories(directories);
+        _ = fs::remove_dir_all(&download_path);
     }
+}
+
 
+#[async_trait]
+impl Source for ViaTorrent {
+
+    fn title(&self) -> String {
+        self.display_name()
+    }
 
-    pub fn must_torrent(&self, download_path: &PathBuf, author_name: &String) -> bool {
+    fn identity(&self) -> SourceIdentity {
+        SourceIdentity::Torrent { magnet: self.magnet.clone() }
+    }
+
+    // A torrent produces as many files as it contains
+    fn file_name(&self) -> Option<String> {
+        None
+    }
 
-        let downloaded_paths: Vec<PathBuf> = get_file_paths(download_path);
-        let author_root: PathBuf = authors::get_author_root(author_name);
-        let log_file_path: PathBuf = author_root.join(LOG_FILE_NAME);
+    async fn download(&self, manifest: &mut Manifest) -> Result<(), anyhow::Error> {
+        log::info!("Torrenting {}", self.display_name());
+        let download_path: PathBuf = manifest.author_root().join(STAGING_DIRECTORY_NAME);
 
-        if !log_file_path.exists() {
-            true 
+        self.fetch(&download_path).await?;
+        self.extract_files(download_path, manifest);
+        Ok(())
+    }
+
+    // Valid only when files have been recorded for this torrent, and every one of them is still
+    // present with the size and hash that were recorded for it.
+    fn verify(&self, manifest: &Manifest) -> bool {
+        let identity: SourceIdentity = self.identity();
+        let recorded_files = manifest.entries_from(&identity);
+
+        !recorded_files.is_empty() && recorded_files.iter().all(
+            |entry| manifest.is_valid(&entry.file_name, &identity)
+        )
+    }
+
+    // Each of the files that the torrent produced is treated as a book of its own
+    fn books(&self, author_name: &str, manifest: &Manifest) -> Vec<Book> {
+        manifest.entries_from(&self.identity())
+            .into_iter()
+            .map(|entry| {
+                let title: String = Path::new(&entry.file_name).file_stem()
+                    .map(|stem| stem.to_string_lossy().replace('_', " "))
+                    .unwrap_or_else(|| entry.file_name.clone());
+
+                Book {
+                    author: author_name.to_string(),
+                    slug: slugify(&title),
+                    title,
+                    file_name: entry.file_name.clone(),
+                    source: entry.source.clone(),
+                    needs_ocr: false,
+                    start_page: None,
+                    end_page: None,
+                    secondary_pages: Vec::new(),
+                    biographers_and_compilers: Vec::new(),
+                    is_interview: false,
+                }
+            })
+            .collect()
+    }
+
+    async fn plan(&self, manifest: &Manifest, force: bool) -> PlannedArtifact {
+        let is_present: bool = self.verify(manifest);
+        let will_acquire: bool = force || !is_present;
+        let recorded_files = manifest.entries_from(&self.identity());
+
+        let expected_size: Option<u64> = if will_acquire || recorded_files.is_empty() {
+            self.fetch_total_size().await
         } else {
-            let log_data = fs::read_to_string(log_file_path).unwrap();
-            let logged_paths: Vec<String> = serde_json::from_str(&log_data).unwrap();
-            if (downloaded_paths.len() == logged_paths.len()) && logged_paths.len() > 0 {false} else {true} 
-        } 
+            Some(recorded_files.iter().map(|entry| entry.size).sum())
+        };
+
+        PlannedArtifact {
+            title: self.display_name(),
+            kind: self.kind(),
+            output_path: manifest.author_root().to_path_buf(),
+            is_present,
+            will_acquire,
+            expected_size,
+            later_stages: Vec::new(),
+        }
     }
 }
 
 
 fn move_files_to_destinations(
-    author_root: &PathBuf,
-    file_paths: &Vec<PathBuf>, 
-    paths_of_downloaded_files: &mut Vec<String>,
+    author_root: &Path,
+    file_paths: &[PathBuf], 
+    names_of_downloaded_files: &mut Vec<String>,
 ) {
     
     for file in kdam::tqdm!(file_paths.iter(), desc="Extracting text and images...") {
@@ -117,7 +225,7 @@ fn move_files_to_destinations(
         let file_is_image: bool = extensions::has_extension(&file_path_as_string.to_lowercase(), &extensions::IMAGE_EXTENSIONS); 
 
         if file_is_text {
-            let file_base_name_without_extension: &OsStr = &extensions::get_base_name(file_path_as_string).unwrap();
+            let file_base_name_without_extension: &OsStr = extensions::get_base_name(file_path_as_string).unwrap();
             let destination_directory: &PathBuf = &author_root.join(file_base_name_without_extension);
             
             if destination_directory.exists(){
@@ -127,8 +235,8 @@ fn move_files_to_destinations(
 
             fs::rename(file_path_as_string, destination_directory).unwrap();  // Move the file
                 
-            paths_of_downloaded_files.push(
-                destination_directory.to_str().unwrap().to_string()
+            names_of_downloaded_files.push(
+                file_base_name_without_extension.to_str().unwrap().to_string()
             );
            
         } else if file_is_image {
@@ -142,21 +250,20 @@ fn move_files_to_destinations(
 }
 
 
-fn log_downloaded_files(
-    author_name: &String, 
-    paths_of_downloaded_files: &Vec<String>, 
+fn record_downloaded_files(
+    identity: SourceIdentity,
+    manifest: &mut Manifest,
+    names_of_downloaded_files: &[String],
 ) {
 
-    log::info!("Logging Downloaded Files");
-    let author_root = authors::get_author_root(author_name); 
-    let log_path: PathBuf = author_root.join(LOG_FILE_NAME);
+    log::info!("Recording downloaded files in the manifest");
+    manifest.forget_source(&identity);
 
-    if log_path.exists() {
-        fs::remove_file(&log_path).expect("The previous file that contains the torrented files could not be removed")
+    for file_name in names_of_downloaded_files {
+        if let Err(e) = manifest.record(file_name, identity.clone()) {
+            log::error!("Unable to record {} in the manifest: {}", file_name, e);
+        }
     }
-
-    let new_log_file = fs::File::create(log_path).unwrap();
-    serde_json::to_writer(new_log_file, paths_of_downloaded_files).expect("Problem writing json for downloads")
 }
 
 
@@ -174,7 +281,7 @@ fn remove_book_directories(directories: Vec<PathBuf>) {
 }
 
 
-fn list_path_contents(path: &PathBuf) -> Result<Vec<Result<PathBuf, GlobError>>, anyhow::Error> {
+fn list_path_contents(path: &Path) -> Result<Vec<Result<PathBuf, GlobError>>, anyhow::Error> {
     let pattern: String = format!("{}/**/*", path.to_str().unwrap());
     
     let contents: Vec<Result<PathBuf, glob::GlobError>> = glob::glob(&pattern)?